@@ -0,0 +1,18 @@
+//! Keeps include/fs.h in step with the API in src/lib.rs.
+
+use std::path::Path;
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(dir.join("cbindgen.toml"))
+        .expect("cbindgen.toml should be readable");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(dir.join("src/lib.rs"))
+        .generate()
+        .expect("the API should be one cbindgen can translate")
+        .write_to_file(dir.join("include/fs.h"));
+}
@@ -0,0 +1,122 @@
+#ifndef FS_H
+#define FS_H
+
+/* Generated by cbindgen from fs-capi/src/lib.rs; don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * What `fs_search_poll` came back with.
+ */
+typedef enum FsPoll {
+  /**
+   * The next item is in `out`
+   */
+  FS_POLL_ITEM,
+  /**
+   * Nothing yet, within the timeout
+   */
+  FS_POLL_PENDING,
+  /**
+   * The search is over: everything has been handed out, or it was cancelled
+   */
+  FS_POLL_DONE,
+} FsPoll;
+
+/**
+ * A search under way, on threads of its own.
+ */
+typedef struct FsSearch FsSearch;
+
+/**
+ * A result, or an error the search carried on past. What it points to is
+ * the search's, and good until the next `fs_search_poll` or `fs_search_free`.
+ */
+typedef struct FsItem {
+  /**
+   * The path as the OS has it (on Windows, as UTF-8), not NUL-terminated;
+   * an error not about any one path has none
+   */
+  const uint8_t *path;
+  size_t path_len;
+  /**
+   * The path as NUL-terminated UTF-8, anything that isn't replaced by U+FFFD
+   */
+  const char *path_utf8;
+  /**
+   * What went wrong, as NUL-terminated UTF-8; NULL for a result
+   */
+  const char *error;
+} FsItem;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Start searching under `root` on threads of the search's own.
+ *
+ * `config_json` is NULL, or a JSON document setting up the search as
+ * `Search::from_config` reads it (`{"extensions": ["pdf"], "crawl":
+ * {"threads": 4}}`), whose `root-path` `root` overrides. On failure, returns
+ * NULL and, unless `error` is NULL, points it at a message to free with
+ * `fs_string_free`.
+ *
+ * # Safety
+ *
+ * `root`, and `config_json` unless it's NULL, must be NUL-terminated
+ * strings. `error` must be NULL or writable.
+ */
+struct FsSearch *fs_search_new(const char *root, const char *config_json, char **error);
+
+/**
+ * Take the next item into `out`, waiting up to `timeout_ms` milliseconds for
+ * one: 0 only takes one that's ready, and a negative timeout waits as long
+ * as it takes. `out` is left alone unless it returns `FS_POLL_ITEM`.
+ *
+ * # Safety
+ *
+ * `search` must come from `fs_search_new` and not have been freed; `out`
+ * must be writable. Polls of one search from several threads take turns.
+ */
+enum FsPoll fs_search_poll(const struct FsSearch *search, int64_t timeout_ms, struct FsItem *out);
+
+/**
+ * Stop the search: no more directories are read, and once what was already
+ * found has been polled, `fs_search_poll` returns `FS_POLL_DONE`. Safe to
+ * call from any thread, including while another is polling.
+ *
+ * # Safety
+ *
+ * `search` must come from `fs_search_new` and not have been freed.
+ */
+void fs_search_cancel(const struct FsSearch *search);
+
+/**
+ * Stop the search if it's still going, and free it. NULL is ignored.
+ *
+ * # Safety
+ *
+ * `search` must be NULL or come from `fs_search_new`, and not be used again,
+ * nor by any other thread.
+ */
+void fs_search_free(struct FsSearch *search);
+
+/**
+ * Free a message `fs_search_new` made. NULL is ignored.
+ *
+ * # Safety
+ *
+ * `s` must be NULL or a message from this library, not already freed.
+ */
+void fs_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FS_H */
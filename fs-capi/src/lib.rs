@@ -0,0 +1,319 @@
+//! fs's search for C and C++ programs, so a desktop application can embed the
+//! walk instead of running `fs` and parsing what it prints. `fs_search_new`
+//! starts a search, `fs_search_poll` takes its results one at a time,
+//! `fs_search_cancel` stops it early from any thread, and `fs_search_free`
+//! lets it go. include/fs.h, generated from this file, declares it all.
+
+use fs::{CancellationToken, Error, FileEntry, Results, Search, SearchConfig};
+use std::ffi::{c_char, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// A search under way, on threads of its own.
+pub struct FsSearch {
+    polled: Mutex<Polled>,
+    cancel: CancellationToken,
+    runtime: Runtime,
+}
+
+/// The results still to come, and the one `fs_search_poll` last handed out,
+/// which its `FsItem` points into.
+struct Polled {
+    results: Results,
+    path: Vec<u8>,
+    path_utf8: CString,
+    error: Option<CString>,
+}
+
+/// A result, or an error the search carried on past. What it points to is
+/// the search's, and good until the next `fs_search_poll` or `fs_search_free`.
+#[repr(C)]
+pub struct FsItem {
+    /// The path as the OS has it (on Windows, as UTF-8), not NUL-terminated;
+    /// an error not about any one path has none
+    pub path: *const u8,
+    pub path_len: usize,
+    /// The path as NUL-terminated UTF-8, anything that isn't replaced by U+FFFD
+    pub path_utf8: *const c_char,
+    /// What went wrong, as NUL-terminated UTF-8; NULL for a result
+    pub error: *const c_char,
+}
+
+/// What `fs_search_poll` came back with.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsPoll {
+    /// The next item is in `out`
+    Item,
+    /// Nothing yet, within the timeout
+    Pending,
+    /// The search is over: everything has been handed out, or it was cancelled
+    Done,
+}
+
+/// Start searching under `root` on threads of the search's own.
+///
+/// `config_json` is NULL, or a JSON document setting up the search as
+/// `Search::from_config` reads it (`{"extensions": ["pdf"], "crawl":
+/// {"threads": 4}}`), whose `root-path` `root` overrides. On failure, returns
+/// NULL and, unless `error` is NULL, points it at a message to free with
+/// `fs_string_free`.
+///
+/// # Safety
+///
+/// `root`, and `config_json` unless it's NULL, must be NUL-terminated
+/// strings. `error` must be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn fs_search_new(
+    root: *const c_char,
+    config_json: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut FsSearch {
+    let root = (!root.is_null()).then(|| CStr::from_ptr(root));
+    let config = (!config_json.is_null()).then(|| CStr::from_ptr(config_json));
+    match start(root, config) {
+        Ok(search) => Box::into_raw(Box::new(search)),
+        Err(message) => {
+            if !error.is_null() {
+                *error = to_c_string(message).into_raw();
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+fn start(root: Option<&CStr>, config: Option<&CStr>) -> Result<FsSearch, String> {
+    let root = root.ok_or("the root is NULL")?;
+    let mut config = match config {
+        Some(json) => {
+            let json = json.to_str().map_err(|_| "the config isn't UTF-8")?;
+            serde_json::from_str(json).map_err(|e| format!("bad config: {e}"))?
+        }
+        None => SearchConfig::default(),
+    };
+    config.root_path = path_from_c(root)?;
+    let cancel = config.crawl.cancel.clone();
+    let search = Search::new(config);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("can't start the search's threads: {e}"))?;
+    let results = runtime.block_on(search.run());
+    Ok(FsSearch {
+        polled: Mutex::new(Polled {
+            results,
+            path: Vec::new(),
+            path_utf8: CString::default(),
+            error: None,
+        }),
+        cancel,
+        runtime,
+    })
+}
+
+/// Take the next item into `out`, waiting up to `timeout_ms` milliseconds for
+/// one: 0 only takes one that's ready, and a negative timeout waits as long
+/// as it takes. `out` is left alone unless it returns `FS_POLL_ITEM`.
+///
+/// # Safety
+///
+/// `search` must come from `fs_search_new` and not have been freed; `out`
+/// must be writable. Polls of one search from several threads take turns.
+#[no_mangle]
+pub unsafe extern "C" fn fs_search_poll(
+    search: *const FsSearch,
+    timeout_ms: i64,
+    out: *mut FsItem,
+) -> FsPoll {
+    let search = &*search;
+    let mut polled = search.polled.lock().unwrap_or_else(|e| e.into_inner());
+    let results = &mut polled.results;
+    let next = search.runtime.block_on(async {
+        match u64::try_from(timeout_ms) {
+            Ok(ms) => tokio::time::timeout(Duration::from_millis(ms), results.recv())
+                .await
+                .ok(),
+            Err(_) => Some(results.recv().await),
+        }
+    });
+    match next {
+        None => FsPoll::Pending,
+        Some(None) => FsPoll::Done,
+        Some(Some(item)) => {
+            polled.hold(item);
+            *out = polled.item();
+            FsPoll::Item
+        }
+    }
+}
+
+impl Polled {
+    fn hold(&mut self, item: Result<FileEntry, Error>) {
+        let (path, error) = match item {
+            Ok(entry) => (entry.into_path(), None),
+            Err(e) => {
+                let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                (path, Some(to_c_string(e.to_string())))
+            }
+        };
+        self.path_utf8 = to_c_string(path.to_string_lossy().into_owned());
+        self.path = path_bytes(path);
+        self.error = error;
+    }
+
+    fn item(&self) -> FsItem {
+        FsItem {
+            path: self.path.as_ptr(),
+            path_len: self.path.len(),
+            path_utf8: self.path_utf8.as_ptr(),
+            error: self.error.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+        }
+    }
+}
+
+/// Stop the search: no more directories are read, and once what was already
+/// found has been polled, `fs_search_poll` returns `FS_POLL_DONE`. Safe to
+/// call from any thread, including while another is polling.
+///
+/// # Safety
+///
+/// `search` must come from `fs_search_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fs_search_cancel(search: *const FsSearch) {
+    (*search).cancel.cancel();
+}
+
+/// Stop the search if it's still going, and free it. NULL is ignored.
+///
+/// # Safety
+///
+/// `search` must be NULL or come from `fs_search_new`, and not be used again,
+/// nor by any other thread.
+#[no_mangle]
+pub unsafe extern "C" fn fs_search_free(search: *mut FsSearch) {
+    if search.is_null() {
+        return;
+    }
+    let search = Box::from_raw(search);
+    search.cancel.cancel();
+    // Without waiting for a read that may be stuck on a dead mount
+    search.runtime.shutdown_background();
+}
+
+/// Free a message `fs_search_new` made. NULL is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or a message from this library, not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn fs_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// `s` for C, whose strings end at the first NUL.
+fn to_c_string(s: impl Into<Vec<u8>>) -> CString {
+    let mut bytes = s.into();
+    bytes.retain(|&b| b != 0);
+    CString::new(bytes).expect("the NULs are gone")
+}
+
+#[cfg(unix)]
+fn path_from_c(s: &CStr) -> Result<PathBuf, String> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(Path::new(std::ffi::OsStr::from_bytes(s.to_bytes())).to_path_buf())
+}
+
+#[cfg(not(unix))]
+fn path_from_c(s: &CStr) -> Result<PathBuf, String> {
+    Ok(s.to_str().map_err(|_| "the root isn't UTF-8")?.into())
+}
+
+#[cfg(unix)]
+fn path_bytes(path: PathBuf) -> Vec<u8> {
+    use std::os::unix::ffi::OsStringExt;
+    path.into_os_string().into_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: PathBuf) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Poll `search` to the end, as the paths found and the errors.
+    unsafe fn drain(search: *const FsSearch) -> (BTreeSet<PathBuf>, Vec<String>) {
+        let (mut found, mut errors) = (BTreeSet::new(), Vec::new());
+        let mut item = FsItem {
+            path: ptr::null(),
+            path_len: 0,
+            path_utf8: ptr::null(),
+            error: ptr::null(),
+        };
+        loop {
+            match fs_search_poll(search, -1, &mut item) {
+                FsPoll::Item if item.error.is_null() => {
+                    let bytes = std::slice::from_raw_parts(item.path, item.path_len);
+                    let utf8 = CStr::from_ptr(item.path_utf8).to_str().unwrap();
+                    assert_eq!(bytes, utf8.as_bytes());
+                    found.insert(PathBuf::from(utf8));
+                }
+                FsPoll::Item => errors.push(CStr::from_ptr(item.error).to_string_lossy().into()),
+                FsPoll::Pending => unreachable!("it waits as long as it takes"),
+                FsPoll::Done => return (found, errors),
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_from_c() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::create_dir(tmp.path().join("sub"))?;
+        for name in ["a.txt", "b.md", "sub/c.txt"] {
+            std::fs::write(tmp.path().join(name), "x")?;
+        }
+        let root = CString::new(tmp.path().to_str().unwrap()).unwrap();
+        let config = CString::new(r#"{"extensions": ["txt"], "crawl": {"threads": 2}}"#).unwrap();
+        unsafe {
+            let search = fs_search_new(root.as_ptr(), config.as_ptr(), ptr::null_mut());
+            assert!(!search.is_null());
+            let (found, errors) = drain(search);
+            let expected = ["a.txt", "sub/c.txt"].map(|name| tmp.path().join(name));
+            assert_eq!(found, BTreeSet::from(expected));
+            assert!(errors.is_empty(), "{errors:?}");
+            // Polling past the end stays done
+            let mut item = std::mem::zeroed();
+            assert_eq!(fs_search_poll(search, 0, &mut item), FsPoll::Done);
+            fs_search_free(search);
+
+            // Cancelled before it's polled, it ends without the rest
+            let search = fs_search_new(root.as_ptr(), ptr::null(), ptr::null_mut());
+            fs_search_cancel(search);
+            let (found, _) = drain(search);
+            assert!(found.len() <= 3);
+            fs_search_free(search);
+
+            // A bad config is an error saying why
+            let mut error = ptr::null_mut();
+            let bad = CString::new(r#"{"threads": 2}"#).unwrap();
+            let search = fs_search_new(root.as_ptr(), bad.as_ptr(), &mut error);
+            assert!(search.is_null());
+            let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+            assert!(
+                message.starts_with("bad config: unknown field `threads`"),
+                "{message}"
+            );
+            fs_string_free(error);
+        }
+        Ok(())
+    }
+}
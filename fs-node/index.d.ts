@@ -0,0 +1,33 @@
+export interface SearchOptions {
+  /** Only names containing this, where `*` matches anything */
+  pattern?: string
+  /** Only files with one of these extensions, compared without case */
+  extensions?: string[]
+  /** Go at most this many directories below the root */
+  maxDepth?: number
+  /** Include hidden files and look inside hidden directories */
+  hidden?: boolean
+  /** Leave out what .gitignore files say to ignore (the default) */
+  gitignore?: boolean
+  /** How many directories to read at once */
+  threads?: number
+  /** Called with each error the walk carries on past */
+  onError?: (error: { path: string | null; error: string }) => void
+}
+
+export interface Entry {
+  path: string
+  /** How many directories below the root it is: 1 directly in it */
+  depth: number
+}
+
+/** Walk the tree under `root`; breaking out of the loop stops the walk. */
+export function search(root: string, options?: SearchOptions): AsyncGenerator<Entry, void, undefined>
+
+/** The addon's own class, which `search` wraps. */
+export class Search {
+  constructor(root: string, options?: Omit<SearchOptions, 'onError'>)
+  /** The next item, or null once the search is over */
+  next(): Promise<{ path?: string; depth?: number; error?: string } | null>
+  cancel(): void
+}
@@ -0,0 +1,36 @@
+'use strict'
+
+// The addon napi build made for this platform, e.g. fs.linux-x64-gnu.node
+const { readdirSync } = require('fs')
+const { join } = require('path')
+
+const prefix = `fs.${process.platform}-${process.arch}`
+const addon = readdirSync(__dirname).find((name) => name.startsWith(prefix) && name.endsWith('.node'))
+if (!addon) {
+  throw new Error(`no build of @wcygan/fs for ${process.platform}-${process.arch}; run \`npm run build\``)
+}
+const { Search } = require(join(__dirname, addon))
+
+/**
+ * Walk the tree under `root`, yielding `{ path, depth }` for each file found.
+ * Errors the walk carries on past go to `options.onError`, if given, as
+ * `{ path, error }`, and are otherwise left out. Breaking out of the loop
+ * stops the walk.
+ */
+async function* search(root, options = {}) {
+  const { onError, ...filters } = options
+  const walk = new Search(root, filters)
+  try {
+    for (let item = await walk.next(); item !== null; item = await walk.next()) {
+      if (item.error == null) {
+        yield { path: item.path, depth: item.depth }
+      } else if (onError) {
+        onError({ path: item.path ?? null, error: item.error })
+      }
+    }
+  } finally {
+    walk.cancel()
+  }
+}
+
+module.exports = { search, Search }
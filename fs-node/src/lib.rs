@@ -0,0 +1,109 @@
+//! fs's search as a native Node.js addon, for editors and build tools that
+//! would otherwise spawn `find` or `fd` and parse what they print. The addon
+//! has a `Search` class whose `next()` resolves to each item in turn;
+//! index.js wraps it in `search(root, options)`, an async iterator.
+
+use fs::crawl::Threads;
+use fs::{CancellationToken, Results, SearchBuilder};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use tokio::sync::Mutex;
+
+/// What to look for, as the `fs` command's flags have it; what isn't given is
+/// as their defaults.
+#[napi(object)]
+#[derive(Default)]
+pub struct SearchOptions {
+    /// Only names containing this, where `*` matches anything
+    pub pattern: Option<String>,
+    /// Only files with one of these extensions, compared without case
+    pub extensions: Option<Vec<String>>,
+    /// Go at most this many directories below the root
+    pub max_depth: Option<u32>,
+    /// Include hidden files and look inside hidden directories
+    pub hidden: Option<bool>,
+    /// Leave out what .gitignore files say to ignore (the default)
+    pub gitignore: Option<bool>,
+    /// How many directories to read at once
+    pub threads: Option<u32>,
+}
+
+/// A file the search found, or an error it carried on past.
+#[napi(object)]
+pub struct Item {
+    /// The file's path, or the path the error is about, if any
+    pub path: Option<String>,
+    /// How many directories below the root the file is: 1 directly in it
+    pub depth: Option<u32>,
+    /// What went wrong, for an error
+    pub error: Option<String>,
+}
+
+/// A search of the tree under a root, started by the first `next()`.
+#[napi]
+pub struct Search {
+    search: fs::Search,
+    cancel: CancellationToken,
+    results: Mutex<Option<Results>>,
+}
+
+#[napi]
+impl Search {
+    #[napi(constructor)]
+    pub fn new(root: String, options: Option<SearchOptions>) -> Result<Self> {
+        let options = options.unwrap_or_default();
+        let cancel = CancellationToken::new();
+        let mut builder = SearchBuilder::new(root)
+            .hidden(options.hidden.unwrap_or(false))
+            .gitignore(options.gitignore.unwrap_or(true))
+            .cancel_token(cancel.clone());
+        if let Some(pattern) = options.pattern {
+            builder = builder.pattern(pattern);
+        }
+        if let Some(extensions) = options.extensions {
+            builder = builder.extensions(extensions);
+        }
+        if let Some(depth) = options.max_depth {
+            builder = builder.max_depth(depth as usize);
+        }
+        match options.threads {
+            Some(0) => return Err(Error::from_reason("threads must be at least 1")),
+            Some(threads) => builder = builder.threads(Threads::Fixed(threads as usize)),
+            None => {}
+        }
+        Ok(Search {
+            search: builder.build(),
+            cancel,
+            results: Mutex::new(None),
+        })
+    }
+
+    /// The next item, or null once the search is over.
+    #[napi]
+    pub async fn next(&self) -> Option<Item> {
+        let mut results = self.results.lock().await;
+        if results.is_none() {
+            *results = Some(self.search.run().await);
+        }
+        let item = results.as_mut()?.recv().await?;
+        Some(match item {
+            Ok(entry) => Item {
+                path: Some(entry.path().to_string_lossy().into_owned()),
+                depth: Some(entry.depth() as u32),
+                error: None,
+            },
+            Err(e) => Item {
+                path: e.path().map(|path| path.to_string_lossy().into_owned()),
+                depth: None,
+                error: Some(e.to_string()),
+            },
+        })
+    }
+
+    /// Stop the search: no more directories are read, and `next()` resolves
+    /// to null once what was already found has been taken.
+    #[napi]
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
@@ -0,0 +1,34 @@
+// Run after `npm run build`: node test.mjs
+import assert from 'node:assert/strict'
+import { mkdtempSync, mkdirSync, writeFileSync } from 'node:fs'
+import { createRequire } from 'node:module'
+import { tmpdir } from 'node:os'
+import { join, relative } from 'node:path'
+
+const { search } = createRequire(import.meta.url)('./index.js')
+
+const root = mkdtempSync(join(tmpdir(), 'fs-node-'))
+mkdirSync(join(root, 'sub'))
+for (const name of ['a.txt', 'b.md', join('sub', 'c.txt')]) {
+  writeFileSync(join(root, name), 'x')
+}
+
+const found = []
+for await (const entry of search(root, { extensions: ['txt'], threads: 2 })) {
+  found.push([relative(root, entry.path), entry.depth])
+}
+found.sort()
+assert.deepEqual(found, [['a.txt', 1], [join('sub', 'c.txt'), 2]])
+
+const errors = []
+for await (const _ of search(join(root, 'gone'), { onError: (e) => errors.push(e) })) {
+  assert.fail('nothing is there')
+}
+assert.equal(errors.length, 1)
+assert.equal(errors[0].path, join(root, 'gone'))
+
+// Breaking out early stops the walk
+for await (const _ of search(root)) {
+  break
+}
+console.log('ok')
@@ -0,0 +1,283 @@
+//! fs's search for Python, without a subprocess in between: `fs_py.Search`
+//! walks a tree as `fs` does, .gitignore and all, and hands back an `Entry`
+//! for each file, to a `for` loop or an `async for`.
+//!
+//! ```python
+//! from fs_py import Search
+//!
+//! for entry in Search("data", extensions=["parquet"], threads=8):
+//!     load(entry.path, entry.size())
+//! ```
+
+use fs::crawl::Threads;
+use fs::{EntryKind, Error, FileEntry, Results};
+use pyo3::exceptions::{
+    PyFileNotFoundError, PyNotADirectoryError, PyOSError, PyPermissionError, PyStopAsyncIteration,
+    PyValueError,
+};
+use pyo3::prelude::*;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::Mutex;
+
+/// A search of the tree under `root`, walked afresh each time it's iterated.
+/// What isn't given is as the `fs` command has it: every file at any depth,
+/// leaving out hidden and gitignored ones. Errors the walk carries on past
+/// are handed to `on_error` as `OSError`s, like `os.walk`'s, if it's given,
+/// and otherwise left out.
+#[pyclass(frozen, module = "fs_py")]
+struct Search {
+    search: fs::Search,
+    on_error: Option<Arc<PyObject>>,
+}
+
+#[pymethods]
+impl Search {
+    #[new]
+    #[pyo3(signature = (
+        root,
+        pattern = "*",
+        extensions = None,
+        max_depth = None,
+        hidden = false,
+        gitignore = true,
+        threads = 1,
+        on_error = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        root: PathBuf,
+        pattern: &str,
+        extensions: Option<Vec<String>>,
+        max_depth: Option<usize>,
+        hidden: bool,
+        gitignore: bool,
+        threads: usize,
+        on_error: Option<PyObject>,
+    ) -> PyResult<Self> {
+        if threads == 0 {
+            return Err(PyValueError::new_err("threads must be at least 1"));
+        }
+        let mut builder = fs::SearchBuilder::new(root)
+            .pattern(pattern)
+            .hidden(hidden)
+            .gitignore(gitignore)
+            .threads(Threads::Fixed(threads));
+        if let Some(extensions) = extensions {
+            builder = builder.extensions(extensions);
+        }
+        if let Some(depth) = max_depth {
+            builder = builder.max_depth(depth);
+        }
+        Ok(Search {
+            search: builder.build(),
+            on_error: on_error.map(Arc::new),
+        })
+    }
+
+    fn __iter__(&self) -> PyResult<Walk> {
+        Ok(Walk {
+            iter: self.search.iter()?,
+            on_error: self.on_error.clone(),
+        })
+    }
+
+    fn __aiter__(&self) -> AsyncWalk {
+        let runtime = pyo3_async_runtimes::tokio::get_runtime();
+        let results = runtime.block_on(self.search.run());
+        AsyncWalk {
+            results: Arc::new(Mutex::new(results)),
+            on_error: self.on_error.clone(),
+        }
+    }
+}
+
+/// A walk under way for a `for` loop, on threads of its own; waiting for the
+/// next entry lets other Python threads run.
+#[pyclass(module = "fs_py")]
+struct Walk {
+    iter: fs::Iter,
+    on_error: Option<Arc<PyObject>>,
+}
+
+#[pymethods]
+impl Walk {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Entry>> {
+        loop {
+            let iter = &mut self.iter;
+            match py.allow_threads(|| iter.next()) {
+                None => return Ok(None),
+                Some(Ok(entry)) => return Ok(Some(Entry::new(entry))),
+                Some(Err(e)) => report(py, self.on_error.as_deref(), &e)?,
+            }
+        }
+    }
+}
+
+/// A walk under way for an `async for`, on tokio's runtime, so the event
+/// loop is never blocked waiting for it.
+#[pyclass(frozen, module = "fs_py")]
+struct AsyncWalk {
+    results: Arc<Mutex<Results>>,
+    on_error: Option<Arc<PyObject>>,
+}
+
+#[pymethods]
+impl AsyncWalk {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let results = Arc::clone(&self.results);
+        let on_error = self.on_error.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let mut results = results.lock().await;
+            loop {
+                match results.recv().await {
+                    None => return Err(PyStopAsyncIteration::new_err(())),
+                    Some(Ok(entry)) => return Ok(Entry::new(entry)),
+                    Some(Err(e)) => Python::with_gil(|py| report(py, on_error.as_deref(), &e))?,
+                }
+            }
+        })
+    }
+}
+
+/// Hand `e` to `on_error`, if there is one.
+fn report(py: Python<'_>, on_error: Option<&PyObject>, e: &Error) -> PyResult<()> {
+    let Some(on_error) = on_error else {
+        return Ok(());
+    };
+    let message = e.to_string();
+    let error = match e {
+        Error::PermissionDenied { .. } => PyPermissionError::new_err(message),
+        Error::NotADirectory { .. } => PyNotADirectoryError::new_err(message),
+        Error::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound => {
+            PyFileNotFoundError::new_err(message)
+        }
+        _ => PyOSError::new_err(message),
+    };
+    let error = error.into_value(py);
+    if let Some(path) = e.path() {
+        error.bind(py).setattr("filename", path.as_os_str())?;
+    }
+    on_error.call1(py, (error,))?;
+    Ok(())
+}
+
+/// A file the search found. Pass it wherever a path goes: it's `os.PathLike`.
+#[pyclass(frozen, module = "fs_py")]
+struct Entry {
+    entry: FileEntry,
+}
+
+impl Entry {
+    fn new(entry: FileEntry) -> Self {
+        Entry { entry }
+    }
+}
+
+#[pymethods]
+impl Entry {
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.entry.path().to_path_buf()
+    }
+
+    /// How many directories below the root it is: 1 directly in it
+    #[getter]
+    fn depth(&self) -> usize {
+        self.entry.depth()
+    }
+
+    /// "file", "dir", "symlink" or "other", from the directory listing where
+    /// the file system records it.
+    fn kind(&self) -> PyResult<&'static str> {
+        Ok(match self.entry.kind()? {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Other => "other",
+        })
+    }
+
+    /// Its size in bytes.
+    fn size(&self) -> PyResult<u64> {
+        Ok(self.entry.size()?)
+    }
+
+    /// When it was last modified, in seconds since the epoch, as `os.stat`
+    /// has it.
+    fn modified(&self) -> PyResult<f64> {
+        let modified = self.entry.modified()?;
+        Ok(match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(before) => -before.duration().as_secs_f64(),
+        })
+    }
+
+    fn __fspath__(&self) -> OsString {
+        self.entry.path().as_os_str().to_os_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Entry({:?})", self.entry.path())
+    }
+}
+
+#[pymodule]
+fn fs_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Search>()?;
+    module.add_class::<Entry>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_search_from_python() -> PyResult<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::create_dir(tmp.path().join("sub"))?;
+        for name in ["a.txt", "b.md", "sub/c.txt"] {
+            std::fs::write(tmp.path().join(name), "x")?;
+        }
+        let script = CString::new(
+            r#"
+import asyncio, os
+
+found = sorted(os.path.relpath(entry, root) for entry in Search(root, extensions=["txt"]))
+assert found == ["a.txt", os.path.join("sub", "c.txt")], found
+shallow = [(entry.depth, entry.kind(), entry.size()) for entry in Search(root, max_depth=0)]
+assert sorted(shallow) == [(1, "file", 1), (1, "file", 1)], shallow
+
+errors = []
+assert list(Search(os.path.join(root, "gone"), on_error=errors.append)) == []
+assert isinstance(errors[0], FileNotFoundError), errors
+assert errors[0].filename == os.path.join(root, "gone"), errors[0].filename
+
+async def collect():
+    return [os.fspath(entry) async for entry in Search(root, pattern="c", threads=2)]
+assert asyncio.run(collect()) == [os.path.join(root, "sub", "c.txt")]
+"#,
+        )?;
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "fs_py")?;
+            fs_py(&module)?;
+            let globals = PyDict::new(py);
+            globals.set_item("Search", module.getattr("Search")?)?;
+            globals.set_item("root", tmp.path())?;
+            py.run(&script, Some(&globals), None)
+        })
+    }
+}
@@ -0,0 +1,360 @@
+//! Streaming search results into a tar or zip archive (`--archive`).
+
+use super::human_size;
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Read};
+use std::path::Path;
+use std::process::ExitCode;
+
+/// Archive format, inferred from the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl Format {
+    /// The format a file name's extension implies, if any.
+    pub(crate) fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+
+        if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+
+    fn from_path(path: &Path) -> Result<Self> {
+        match Self::detect(path) {
+            Some(format) => Ok(format),
+            None => bail!(
+                "can't tell the archive format of {}; use .tar, .tar.gz, .tar.zst or .zip",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// An open archive that files are appended to one at a time.
+enum ArchiveWriter {
+    Tar(tar::Builder<BufWriter<File>>),
+    TarGz(tar::Builder<GzEncoder<BufWriter<File>>>),
+    TarZst(tar::Builder<zstd::Encoder<'static, BufWriter<File>>>),
+    Zip(Box<zip::ZipWriter<BufWriter<File>>>),
+}
+
+impl ArchiveWriter {
+    fn create(out: &Path, format: Format) -> Result<Self> {
+        let file = BufWriter::new(File::create(out)?);
+        Ok(match format {
+            Format::Tar => Self::Tar(tar::Builder::new(file)),
+            Format::TarGz => Self::TarGz(tar::Builder::new(GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            ))),
+            Format::TarZst => Self::TarZst(tar::Builder::new(zstd::Encoder::new(file, 0)?)),
+            Format::Zip => Self::Zip(Box::new(zip::ZipWriter::new(file))),
+        })
+    }
+
+    /// Append `file`, stored as `name`, keeping its mtime and permissions. Its
+    /// entry is started before its contents are read, so an error leaves the
+    /// archive with an entry cut short, and nothing more can be added.
+    fn add(&mut self, file: File, metadata: &Metadata, name: &Path) -> Result<()> {
+        let mut contents = Exact(file.take(metadata.len()));
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(metadata);
+        match self {
+            Self::Tar(b) => b.append_data(&mut header, name, contents)?,
+            Self::TarGz(b) => b.append_data(&mut header, name, contents)?,
+            Self::TarZst(b) => b.append_data(&mut header, name, contents)?,
+            Self::Zip(zip) => {
+                let mut options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(metadata.len() >= u32::MAX as u64);
+                if let Some(mtime) = zip_mtime(metadata) {
+                    options = options.last_modified_time(mtime);
+                }
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    options = options.unix_permissions(metadata.permissions().mode());
+                }
+
+                // Zip entry names always use '/' separators
+                let entry: Vec<_> = name
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect();
+                zip.start_file(entry.join("/"), options)?;
+                io::copy(&mut contents, zip.as_mut())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write trailers and flush every layer down to the file.
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Tar(b) => b.into_inner()?.into_inner().map_err(|e| e.into_error())?,
+            Self::TarGz(b) => b
+                .into_inner()?
+                .finish()?
+                .into_inner()
+                .map_err(|e| e.into_error())?,
+            Self::TarZst(b) => b
+                .into_inner()?
+                .finish()?
+                .into_inner()
+                .map_err(|e| e.into_error())?,
+            Self::Zip(zip) => zip.finish()?.into_inner().map_err(|e| e.into_error())?,
+        };
+        Ok(())
+    }
+}
+
+/// A file's contents, as long as they were when its entry was started: if
+/// it's grown since, the rest is left out, and if it's shrunk, reading fails
+/// rather than leave the entry shorter than its header says.
+struct Exact(io::Take<File>);
+
+impl Read for Exact {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        if n == 0 && !buf.is_empty() && self.0.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the file shrank while it was being archived",
+            ));
+        }
+        Ok(n)
+    }
+}
+
+/// The regular file at `path`, opened, or `None` for a directory (reached
+/// through a symlink), whose contents aren't results.
+fn open(path: &Path) -> Result<Option<(File, Metadata)>> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    // Opening a FIFO would wait for a writer
+    if !metadata.is_file() {
+        bail!("not a regular file");
+    }
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    Ok(Some((file, metadata)))
+}
+
+/// Zip timestamps are a DOS date-time, which can't represent anything before 1980.
+fn zip_mtime(metadata: &std::fs::Metadata) -> Option<zip::DateTime> {
+    let mtime = time::OffsetDateTime::from(metadata.modified().ok()?);
+    zip::DateTime::try_from(time::PrimitiveDateTime::new(mtime.date(), mtime.time())).ok()
+}
+
+/// Appends each result to `out` as it arrives, stored under its path relative to `root`.
+pub async fn run(mut rx: Results, root: &Path, out: &Path, dry_run: bool) -> Result<ExitCode> {
+    let format = Format::from_path(out)?;
+
+    if dry_run {
+        let mut plan = Plan::default();
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => plan.add(
+                    "archive",
+                    format!(
+                        "{} as {}",
+                        entry.path().display(),
+                        entry_name(entry.path(), root).display()
+                    ),
+                ),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let root = root.to_path_buf();
+    let out = out.to_path_buf();
+
+    // tar and zip writers are synchronous, so the whole archive is written on a blocking thread
+    tokio::task::spawn_blocking(move || write_archive(rx, &root, &out, format)).await?
+}
+
+/// The name a file is stored under: its path relative to the search root.
+fn entry_name<'a>(path: &'a Path, root: &Path) -> &'a Path {
+    match path.strip_prefix(root) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel,
+        _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+    }
+}
+
+fn write_archive(mut rx: Results, root: &Path, out: &Path, format: Format) -> Result<ExitCode> {
+    let mut writer = ArchiveWriter::create(out, format)?;
+    // The archive shows up in the results when it's written inside the search root
+    let out_abs = std::path::absolute(out)?;
+    let mut archived = 0usize;
+    let mut bytes = 0u64;
+    let mut failed = 0usize;
+
+    while let Some(item) = rx.blocking_recv() {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+
+        if std::path::absolute(&path).is_ok_and(|p| p == out_abs) {
+            continue;
+        }
+
+        let (file, metadata) = match open(&path) {
+            Ok(Some(opened)) => opened,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                failed += 1;
+                continue;
+            }
+        };
+        if let Err(e) = writer.add(file, &metadata, entry_name(&path, root)) {
+            drop(writer);
+            let _ = std::fs::remove_file(out);
+            return Err(e).with_context(|| {
+                format!(
+                    "can't archive {}, so {} was left unfinished and removed",
+                    path.display(),
+                    out.display()
+                )
+            });
+        }
+        archived += 1;
+        bytes += metadata.len();
+    }
+
+    writer.finish()?;
+    println!(
+        "Archived {archived} file(s) ({}) into {}, {failed} failed",
+        human_size(bytes),
+        out.display()
+    );
+
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs as stdfs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    /// Make root/src/lib.rs and root/README.md and send both paths.
+    async fn sample_tree(root: &Path) -> Result<Results> {
+        stdfs::create_dir_all(root.join("src"))?;
+        stdfs::write(root.join("src").join("lib.rs"), "pub fn f() {}")?;
+        stdfs::write(root.join("README.md"), "# readme")?;
+
+        let (tx, rx) = crate::crawl::channel(4);
+        tx.send(vec![Ok(root.join("src").join("lib.rs").into())])
+            .await?;
+        tx.send(vec![Ok(root.join("README.md").into())]).await?;
+        Ok(rx)
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(Format::from_path(Path::new("a.tar")).unwrap(), Format::Tar);
+        assert_eq!(
+            Format::from_path(Path::new("a.TGZ")).unwrap(),
+            Format::TarGz
+        );
+        assert_eq!(
+            Format::from_path(Path::new("logs.tar.zst")).unwrap(),
+            Format::TarZst
+        );
+        assert_eq!(Format::from_path(Path::new("x.zip")).unwrap(), Format::Zip);
+        assert!(Format::from_path(Path::new("x.rar")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tar_zst_round_trip() -> Result<()> {
+        let src = tempdir()?;
+        let out_dir = tempdir()?;
+        let out = out_dir.path().join("out.tar.zst");
+
+        let rx = sample_tree(src.path()).await?;
+        assert_eq!(run(rx, src.path(), &out, false).await?, ExitCode::SUCCESS);
+
+        let decoder = zstd::Decoder::new(File::open(&out)?)?;
+        let mut archive = tar::Archive::new(decoder);
+        let mut names: Vec<_> = archive
+            .entries()?
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![PathBuf::from("README.md"), PathBuf::from("src/lib.rs")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zip_round_trip() -> Result<()> {
+        let src = tempdir()?;
+        let out_dir = tempdir()?;
+        let out = out_dir.path().join("out.zip");
+
+        let rx = sample_tree(src.path()).await?;
+        assert_eq!(run(rx, src.path(), &out, false).await?, ExitCode::SUCCESS);
+
+        let mut archive = zip::ZipArchive::new(File::open(&out)?)?;
+        let mut contents = String::new();
+        archive
+            .by_name("src/lib.rs")?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "pub fn f() {}");
+        assert!(archive.by_name("README.md").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_file_that_shrank_fails_its_entry() -> Result<()> {
+        let tmp = tempdir()?;
+        let path = tmp.path().join("app.log");
+        stdfs::write(&path, "abc")?;
+
+        // Its header said 5 bytes, but there are only 3 left
+        let mut contents = Exact(File::open(&path)?.take(5));
+        let e = io::copy(&mut contents, &mut io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        // One that grew is cut back to what its header said
+        let mut contents = Exact(File::open(&path)?.take(2));
+        assert_eq!(io::copy(&mut contents, &mut io::sink())?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//! Putting the list of results on the system clipboard (`--clipboard`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Set in the environment of the detached process that keeps serving the
+/// clipboard on X11/Wayland after the main process exits.
+#[cfg(all(unix, not(target_os = "macos")))]
+const HOLDER_ENV: &str = "FS_CLIPBOARD_HOLDER";
+
+/// Waits for the crawl to finish, then copies every result to the clipboard,
+/// separated by newlines or, with `null`, by NUL bytes.
+pub async fn run(mut rx: Results, null: bool, dry_run: bool) -> Result<ExitCode> {
+    let mut paths = Vec::new();
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => paths.push(entry.into_path()),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    let text = join(&paths, null);
+    if dry_run {
+        let mut plan = Plan::default();
+        plan.add(
+            "clipboard",
+            format!("{} path(s), {} bytes", paths.len(), text.len()),
+        );
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    tokio::task::spawn_blocking(move || set_text(text)).await??;
+    println!("Copied {} path(s) to the clipboard", paths.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn join(paths: &[PathBuf], null: bool) -> String {
+    let separator = if null { "\0" } else { "\n" };
+    paths
+        .iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn open() -> Result<arboard::Clipboard> {
+    arboard::Clipboard::new().context("could not open the system clipboard")
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub(crate) fn set_text(text: String) -> Result<()> {
+    open()?.set_text(text)?;
+    Ok(())
+}
+
+/// On X11 and Wayland the clipboard is served by the process that owns it, so the
+/// contents would vanish when fs exits. Hand them to a detached copy of fs that
+/// keeps serving them until something else is copied.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn set_text(text: String) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    // Fail here, with a useful message, if there is no display to talk to
+    drop(open()?);
+
+    let mut holder = Command::new(std::env::current_exe()?)
+        .env(HOLDER_ENV, "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Keep Ctrl-C in the terminal from reaching it
+        .process_group(0)
+        .spawn()
+        .context("could not start the clipboard holder")?;
+    holder
+        .stdin
+        .take()
+        .context("clipboard holder has no stdin")?
+        .write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// If this process was started as the clipboard holder, serve the text from
+/// stdin until the clipboard changes hands, and return the exit code.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn hold_if_requested() -> Option<Result<ExitCode>> {
+    use arboard::SetExtLinux;
+    use std::io::Read;
+
+    std::env::var_os(HOLDER_ENV)?;
+    Some((|| {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        open()?.set().wait().text(text)?;
+        Ok(ExitCode::SUCCESS)
+    })())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn hold_if_requested() -> Option<Result<ExitCode>> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_separators() {
+        let paths = vec![PathBuf::from("a.txt"), PathBuf::from("dir/b c.txt")];
+        assert_eq!(join(&paths, false), "a.txt\ndir/b c.txt");
+        assert_eq!(join(&paths, true), "a.txt\0dir/b c.txt");
+        assert_eq!(join(&[], false), "");
+    }
+}
@@ -0,0 +1,310 @@
+//! Deleting search results (`--delete`, `--delete-dirs`).
+
+use super::plan::Plan;
+use super::{confirm, human_size};
+use crate::crawl::{EntryKind, Results};
+use anyhow::{bail, Result};
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::fs;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeleteOptions {
+    /// Also remove directories that the deletion left empty, and matching
+    /// directories that are empty
+    pub dirs: bool,
+    /// Skip the confirmation prompt
+    pub yes: bool,
+    /// Only report what would be deleted
+    pub dry_run: bool,
+}
+
+/// Collects every result, shows what is about to go, asks for confirmation,
+/// then deletes and prints a summary. With `dirs`, directories among the
+/// results are removed if they're empty once the files are gone.
+pub async fn run(rx: Results, root: &Path, opts: DeleteOptions) -> Result<ExitCode> {
+    run_checked(rx, root, opts, |_| Ok(())).await
+}
+
+/// [`run`], but asking `check` about each file just before deleting it, and
+/// leaving the file alone if it says no.
+pub async fn run_checked(
+    mut rx: Results,
+    root: &Path,
+    opts: DeleteOptions,
+    check: impl Fn(&Path) -> io::Result<()>,
+) -> Result<ExitCode> {
+    let mut targets = Vec::new();
+    let mut matched_dirs = Vec::new();
+    let mut total_bytes = 0u64;
+
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) if opts.dirs && entry.kind().ok() == Some(EntryKind::Dir) => {
+                matched_dirs.push(entry.into_path());
+            }
+            Ok(entry) => {
+                total_bytes += entry.size().unwrap_or(0);
+                targets.push(entry.into_path());
+            }
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    if opts.dry_run {
+        let mut plan = Plan::default();
+        for path in &targets {
+            plan.add("delete", path.display());
+        }
+        if opts.dirs {
+            for dir in would_be_emptied(&targets, &matched_dirs, root).await {
+                plan.add("rmdir", dir.display());
+            }
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if targets.is_empty() && matched_dirs.is_empty() {
+        println!("Nothing to delete.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for path in targets.iter().chain(&matched_dirs) {
+        println!("{}", path.display());
+    }
+    println!(
+        "{} file(s), {} in total",
+        targets.len(),
+        human_size(total_bytes)
+    );
+    if !matched_dirs.is_empty() {
+        println!(
+            "{} director(ies), removed only if empty",
+            matched_dirs.len()
+        );
+    }
+
+    if !opts.yes {
+        if !std::io::stdin().is_terminal() {
+            bail!("refusing to delete without confirmation; pass --yes when not running interactively");
+        }
+        if !confirm("Delete these files?")? {
+            println!("Aborted, nothing was deleted.");
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    let mut deleted = 0usize;
+    let mut failed = 0usize;
+    for path in &targets {
+        let removed = match check(path) {
+            Ok(()) => fs::remove_file(path).await,
+            Err(e) => Err(e),
+        };
+        match removed {
+            Ok(()) => deleted += 1,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    let mut removed_dirs = 0usize;
+    if opts.dirs {
+        for dir in emptied_dir_candidates(&targets, &matched_dirs, root) {
+            match remove_if_empty(&dir).await {
+                Ok(true) => removed_dirs += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Error: {}: {e}", dir.display());
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    print!("Deleted {deleted} file(s)");
+    if opts.dirs {
+        print!(", {removed_dirs} empty director(ies)");
+    }
+    println!(", {failed} failed");
+
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The matched directories and every ancestor directory of them and of the
+/// deleted files below `root`, deepest first, so a child is always tried
+/// before its parent.
+fn emptied_dir_candidates(deleted: &[PathBuf], matched: &[PathBuf], root: &Path) -> Vec<PathBuf> {
+    // A matched directory is a candidate itself, a file only its parents
+    let files = deleted.iter().map(|path| (path, 1));
+    let matched = matched.iter().map(|path| (path, 0));
+    let mut dirs = BTreeSet::new();
+    for (path, skip) in files.chain(matched) {
+        for dir in path.ancestors().skip(skip) {
+            if dir == root || !dir.starts_with(root) {
+                break;
+            }
+            dirs.insert(dir.to_path_buf());
+        }
+    }
+
+    let mut dirs: Vec<_> = dirs.into_iter().collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    dirs
+}
+
+/// The directories `--delete-dirs` would remove: those whose every entry is either
+/// a deleted file or another directory that would be removed.
+async fn would_be_emptied(deleted: &[PathBuf], matched: &[PathBuf], root: &Path) -> Vec<PathBuf> {
+    let mut gone: HashSet<PathBuf> = deleted.iter().cloned().collect();
+    let mut emptied = Vec::new();
+
+    for dir in emptied_dir_candidates(deleted, matched, root) {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        let mut all_gone = true;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if !gone.contains(&entry.path()) {
+                all_gone = false;
+                break;
+            }
+        }
+        if all_gone {
+            gone.insert(dir.clone());
+            emptied.push(dir);
+        }
+    }
+    emptied
+}
+
+/// Remove `dir` if it has no entries left; returns whether it was removed.
+async fn remove_if_empty(dir: &Path) -> std::io::Result<bool> {
+    if fs::read_dir(dir).await?.next_entry().await?.is_some() {
+        return Ok(false);
+    }
+    fs::remove_dir(dir).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs as stdfs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_delete_files_and_emptied_dirs() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+
+        // root/a/b/old.log is the only file under a/, keep.txt stays in c/
+        let deep = root.join("a").join("b");
+        let kept_dir = root.join("c");
+        stdfs::create_dir_all(&deep)?;
+        stdfs::create_dir_all(&kept_dir)?;
+        let old_log = deep.join("old.log");
+        let other_log = kept_dir.join("other.log");
+        let keep = kept_dir.join("keep.txt");
+        stdfs::write(&old_log, "log")?;
+        stdfs::write(&other_log, "log")?;
+        stdfs::write(&keep, "keep")?;
+
+        let (tx, rx) = crate::crawl::channel(10);
+        tx.send(vec![Ok(old_log.clone().into())]).await?;
+        tx.send(vec![Ok(other_log.clone().into())]).await?;
+        drop(tx);
+
+        let opts = DeleteOptions {
+            dirs: true,
+            yes: true,
+            dry_run: false,
+        };
+        let code = run(rx, root, opts).await?;
+
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert!(!old_log.exists() && !other_log.exists());
+        assert!(
+            !root.join("a").exists(),
+            "emptied directories should be removed"
+        );
+        assert!(keep.exists() && kept_dir.exists());
+        assert!(root.exists(), "the search root is never removed");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_matched_empty_dirs_are_removed() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+
+        // cache/ matches and is empty; logs/ matches but still has a file
+        let cache = root.join("build").join("cache");
+        let logs = root.join("logs");
+        stdfs::create_dir_all(&cache)?;
+        stdfs::create_dir_all(&logs)?;
+        stdfs::write(logs.join("keep.txt"), "keep")?;
+
+        let (tx, rx) = crate::crawl::channel(10);
+        tx.send(vec![Ok(cache.clone().into()), Ok(logs.clone().into())])
+            .await?;
+        drop(tx);
+        let opts = DeleteOptions {
+            dirs: true,
+            yes: true,
+            dry_run: false,
+        };
+        assert_eq!(run(rx, root, opts).await?, ExitCode::SUCCESS);
+
+        assert!(!cache.exists(), "a matched empty directory is removed");
+        assert!(
+            !root.join("build").exists(),
+            "and so is the parent it emptied"
+        );
+        assert!(logs.join("keep.txt").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_deletes_nothing() -> Result<()> {
+        let tmp = tempdir()?;
+        let dir = tmp.path().join("only");
+        stdfs::create_dir_all(&dir)?;
+        let file = dir.join("x.log");
+        stdfs::write(&file, "x")?;
+
+        assert_eq!(
+            would_be_emptied(std::slice::from_ref(&file), &[], tmp.path()).await,
+            vec![dir.clone()]
+        );
+
+        let (tx, rx) = crate::crawl::channel(1);
+        tx.send(vec![Ok(file.clone().into())]).await?;
+        drop(tx);
+        let opts = DeleteOptions {
+            dirs: true,
+            yes: false,
+            dry_run: true,
+        };
+        assert_eq!(run(rx, tmp.path(), opts).await?, ExitCode::SUCCESS);
+        assert!(file.exists() && dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_emptied_dir_candidates_deepest_first() {
+        let root = Path::new("/r");
+        let files = vec![PathBuf::from("/r/a/b/c.txt"), PathBuf::from("/r/a/d.txt")];
+        let dirs = emptied_dir_candidates(&files, &[], root);
+        assert_eq!(dirs, vec![PathBuf::from("/r/a/b"), PathBuf::from("/r/a")]);
+    }
+}
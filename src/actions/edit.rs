@@ -0,0 +1,110 @@
+//! Opening search results in the user's editor (`--edit`).
+
+use super::exec::{self, Batcher, CommandTemplate};
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[cfg(unix)]
+const FALLBACK_EDITOR: &str = "vi";
+#[cfg(windows)]
+const FALLBACK_EDITOR: &str = "notepad";
+
+/// The editor to run, from `$VISUAL`, then `$EDITOR`, then a platform default.
+/// The value is split on whitespace, so e.g. `EDITOR="code --wait"` works.
+pub fn editor_command(visual: Option<String>, editor: Option<String>) -> Result<CommandTemplate> {
+    let value = [visual, editor]
+        .into_iter()
+        .flatten()
+        .find(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_EDITOR.into());
+
+    let words: Vec<String> = value.split_whitespace().map(String::from).collect();
+    if words.is_empty() {
+        bail!("no editor configured; set $VISUAL or $EDITOR");
+    }
+    CommandTemplate::for_batch(&words)
+}
+
+/// Waits for the crawl to finish, then opens every result in the editor, as many
+/// per invocation as fit on a command line. Invocations run one after another.
+pub async fn run(mut rx: Results, editor: &CommandTemplate, dry_run: bool) -> Result<ExitCode> {
+    let mut paths = Vec::new();
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => paths.push(entry.into_path()),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    if paths.is_empty() {
+        println!("No files to edit");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut batcher = Batcher::new(editor, exec::arg_max());
+    let mut batches: Vec<Vec<PathBuf>> =
+        paths.into_iter().filter_map(|p| batcher.push(p)).collect();
+    batches.extend(batcher.finish());
+
+    let mut plan = Plan::default();
+    let mut failed = false;
+    for batch in &batches {
+        let cmd = editor.build_batch(batch);
+        if dry_run {
+            plan.add("edit", exec::render(&cmd));
+        } else {
+            failed |= !exec::run_command(cmd, false).await;
+        }
+    }
+
+    if dry_run {
+        plan.finish();
+    }
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_precedence() -> Result<()> {
+        let render = |t: CommandTemplate| exec::render(&t.build_batch(&[PathBuf::from("a")]));
+
+        let both = editor_command(Some("code --wait".into()), Some("nano".into()))?;
+        assert_eq!(render(both), "code --wait a");
+        let blank_visual = editor_command(Some(" ".into()), Some("nano".into()))?;
+        assert_eq!(render(blank_visual), "nano a");
+        let neither = editor_command(None, None)?;
+        assert_eq!(render(neither), format!("{FALLBACK_EDITOR} a"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_edit_runs_editor_on_all_results() -> Result<()> {
+        // `touch` stands in for the editor: every file it is given gets created
+        let tmp = tempfile::tempdir()?;
+        let files: Vec<PathBuf> = (0..3)
+            .map(|i| tmp.path().join(format!("{i}.txt")))
+            .collect();
+
+        let (tx, rx) = crate::crawl::channel(files.len());
+        for f in &files {
+            tx.send(vec![Ok(f.clone().into())]).await?;
+        }
+        drop(tx);
+
+        let editor = editor_command(Some("touch".into()), None)?;
+        assert_eq!(run(rx, &editor, false).await?, ExitCode::SUCCESS);
+        assert!(files.iter().all(|f| f.exists()));
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+//! Running external commands over search results (`--exec`, `--exec-batch`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Result};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+
+/// The default placeholder, standing for the whole path.
+const PLACEHOLDER: &str = "{}";
+
+/// Everything that marks where a path goes in a command template, longest first
+/// so that `{/.}` isn't read as `{/}` followed by text:
+/// `{/.}` basename without extension, `{//}` parent directory, `{/}` basename,
+/// `{.}` path without extension, `{}` the full path.
+const PLACEHOLDERS: [&str; 5] = ["{/.}", "{//}", "{/}", "{.}", "{}"];
+
+/// A command line as given on the CLI, with `{}` placeholders for paths.
+#[derive(Debug, Clone)]
+pub struct CommandTemplate {
+    args: Vec<String>,
+}
+
+impl CommandTemplate {
+    /// Parse a template; if it has no placeholder, the path is appended at the end.
+    pub fn new(args: &[String]) -> Result<Self> {
+        if args.is_empty() {
+            bail!("no command given to execute");
+        }
+
+        let mut args = args.to_vec();
+        if !args[1..].iter().any(|a| has_placeholder(a)) {
+            args.push(PLACEHOLDER.into());
+        }
+        Ok(Self { args })
+    }
+
+    /// Like `new`, but for `--exec-batch`, where a placeholder stands for many
+    /// arguments and so can't be embedded in a larger one.
+    pub fn for_batch(args: &[String]) -> Result<Self> {
+        let template = Self::new(args)?;
+        if template.args[1..]
+            .iter()
+            .any(|a| !is_placeholder(a) && has_placeholder(a))
+        {
+            bail!(
+                "in --exec-batch, placeholders like '{PLACEHOLDER}' must be arguments of their own"
+            );
+        }
+        Ok(template)
+    }
+
+    /// Build one invocation for a single path, substituted wherever a placeholder appears.
+    fn build(&self, path: &Path) -> Command {
+        let mut cmd = Command::new(&self.args[0]);
+        for arg in &self.args[1..] {
+            cmd.arg(substitute(arg, path));
+        }
+        cmd
+    }
+
+    /// Build one invocation with every path of `batch` substituted for each placeholder.
+    pub(super) fn build_batch(&self, batch: &[PathBuf]) -> Command {
+        let mut cmd = Command::new(&self.args[0]);
+        for arg in &self.args[1..] {
+            if is_placeholder(arg) {
+                cmd.args(batch.iter().map(|path| expand(arg, path)));
+            } else {
+                cmd.arg(arg);
+            }
+        }
+        cmd
+    }
+
+    /// Argument-list bytes used by the template itself, placeholders excluded.
+    fn fixed_cost(&self) -> usize {
+        self.args
+            .iter()
+            .filter(|a| !is_placeholder(a))
+            .map(|a| arg_cost(a.len()))
+            .sum()
+    }
+
+    fn placeholder_count(&self) -> usize {
+        self.args[1..].iter().filter(|a| is_placeholder(a)).count()
+    }
+}
+
+fn is_placeholder(arg: &str) -> bool {
+    PLACEHOLDERS.contains(&arg)
+}
+
+fn has_placeholder(arg: &str) -> bool {
+    PLACEHOLDERS.iter().any(|p| arg.contains(p))
+}
+
+/// What a single placeholder stands for, given the result `path`.
+fn expand(placeholder: &str, path: &Path) -> OsString {
+    let name = || path.file_name().unwrap_or(path.as_os_str());
+    match placeholder {
+        "{/}" => name().to_owned(),
+        "{//}" => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.as_os_str().to_owned(),
+            _ => ".".into(),
+        },
+        "{.}" => path.with_extension("").into_os_string(),
+        "{/.}" => path.file_stem().unwrap_or_else(name).to_owned(),
+        _ => path.as_os_str().to_owned(),
+    }
+}
+
+/// Replace every placeholder in `arg`, wherever it appears.
+fn substitute(arg: &str, path: &Path) -> OsString {
+    let mut out = OsString::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        out.push(&rest[..start]);
+        rest = &rest[start..];
+        match PLACEHOLDERS.iter().find(|p| rest.starts_with(*p)) {
+            Some(placeholder) => {
+                out.push(expand(placeholder, path));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                out.push("{");
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push(rest);
+    out
+}
+
+/// Runs the command once per result, keeping up to `jobs` invocations in flight
+/// while the crawl continues to feed the channel.
+pub async fn run_each(
+    mut rx: Results,
+    template: &CommandTemplate,
+    jobs: usize,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    if dry_run {
+        let mut plan = Plan::default();
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => plan.add("run", render(&template.build(entry.path()))),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let jobs = jobs.max(1);
+    // With several commands running at once, buffer each one's output so lines don't interleave
+    let capture = jobs > 1;
+    let mut running = JoinSet::new();
+    let mut failed = false;
+
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => {
+                let path = entry.into_path();
+                while running.len() >= jobs {
+                    failed |= !running.join_next().await.unwrap_or(Ok(true))?;
+                }
+                running.spawn(run_command(template.build(&path), capture));
+            }
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    while let Some(ok) = running.join_next().await {
+        failed |= !ok?;
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Runs the command once per batch of paths, chunking like `xargs` so every
+/// invocation stays under the OS argument-length limit.
+pub async fn run_batch(
+    mut rx: Results,
+    template: &CommandTemplate,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    let mut batcher = Batcher::new(template, arg_max());
+    let mut plan = dry_run.then(Plan::default);
+    let mut failed = false;
+
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => {
+                if let Some(batch) = batcher.push(entry.into_path()) {
+                    failed |= !dispatch(template.build_batch(&batch), &mut plan).await;
+                }
+            }
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    if let Some(batch) = batcher.finish() {
+        failed |= !dispatch(template.build_batch(&batch), &mut plan).await;
+    }
+
+    if let Some(plan) = plan {
+        plan.finish();
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Run one batch command, or under --dry-run-exec just add it to the plan.
+async fn dispatch(cmd: Command, plan: &mut Option<Plan>) -> bool {
+    match plan {
+        Some(plan) => {
+            plan.add("run", render(&cmd));
+            true
+        }
+        None => run_command(cmd, false).await,
+    }
+}
+
+/// A command line as it could be pasted into a POSIX shell.
+pub(super) fn render(cmd: &Command) -> String {
+    let cmd = cmd.as_std();
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "-_./:=,+@%".contains(c));
+            if plain {
+                arg.into_owned()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run a command, returning whether it succeeded. Output is either inherited
+/// or captured and written out in one piece once the command exits.
+pub(super) async fn run_command(mut cmd: Command, capture: bool) -> bool {
+    let status = if capture {
+        cmd.output().await.map(|out| {
+            let _ = std::io::stdout().lock().write_all(&out.stdout);
+            let _ = std::io::stderr().lock().write_all(&out.stderr);
+            out.status
+        })
+    } else {
+        cmd.status().await
+    };
+
+    match status {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!(
+                "Error: failed to run {}: {e}",
+                cmd.as_std().get_program().to_string_lossy()
+            );
+            false
+        }
+    }
+}
+
+/// Accumulates paths until the next one would push the command line over `limit`.
+pub(super) struct Batcher {
+    limit: usize,
+    copies: usize,
+    used: usize,
+    paths: Vec<PathBuf>,
+}
+
+impl Batcher {
+    pub(super) fn new(template: &CommandTemplate, limit: usize) -> Self {
+        Self {
+            limit: limit.saturating_sub(template.fixed_cost()),
+            copies: template.placeholder_count(),
+            used: 0,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Add a path, handing back the current batch first if the path doesn't fit in it.
+    /// A single path larger than the whole budget still gets a batch of its own.
+    pub(super) fn push(&mut self, path: PathBuf) -> Option<Vec<PathBuf>> {
+        let cost = arg_cost(path.as_os_str().len()) * self.copies;
+        let full = if !self.paths.is_empty() && self.used + cost > self.limit {
+            self.used = 0;
+            Some(std::mem::take(&mut self.paths))
+        } else {
+            None
+        };
+
+        self.used += cost;
+        self.paths.push(path);
+        full
+    }
+
+    pub(super) fn finish(self) -> Option<Vec<PathBuf>> {
+        if self.paths.is_empty() {
+            None
+        } else {
+            Some(self.paths)
+        }
+    }
+}
+
+/// Bytes an argument of `len` bytes takes in the argv block: the string, its NUL, and its pointer.
+fn arg_cost(len: usize) -> usize {
+    len + 1 + std::mem::size_of::<usize>()
+}
+
+/// Space left for arguments after the environment, with some headroom.
+#[cfg(unix)]
+pub(super) fn arg_max() -> usize {
+    // SAFETY: sysconf has no preconditions.
+    let raw = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    let limit = if raw > 0 { raw as usize } else { 128 * 1024 };
+    let env: usize = std::env::vars_os()
+        .map(|(k, v)| arg_cost(k.len() + v.len() + 1))
+        .sum();
+    limit.saturating_sub(env).saturating_sub(2048)
+}
+
+/// CreateProcess caps the whole command line at 32767 UTF-16 units.
+#[cfg(windows)]
+pub(super) fn arg_max() -> usize {
+    32 * 1024 - 1 - 2048
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(args: &[&str]) -> CommandTemplate {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        CommandTemplate::new(&args).unwrap()
+    }
+
+    #[test]
+    fn test_template_appends_placeholder() {
+        let t = template(&["rm", "-f"]);
+        assert_eq!(t.args, vec!["rm", "-f", "{}"]);
+
+        let t = template(&["tar", "cf", "out.tar", "{}", "--verbose"]);
+        assert_eq!(t.placeholder_count(), 1);
+        assert!(CommandTemplate::new(&[]).is_err());
+
+        let embedded = vec!["cp".to_string(), "{}".into(), "{}.bak".into()];
+        assert!(CommandTemplate::new(&embedded).is_ok());
+        assert!(CommandTemplate::for_batch(&embedded).is_err());
+    }
+
+    #[test]
+    fn test_build_substitutes_embedded_placeholder() {
+        let cmd = template(&["cp", "{}", "{}.bak"]).build(Path::new("dir/a.txt"));
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, vec!["dir/a.txt", "dir/a.txt.bak"]);
+    }
+
+    #[test]
+    fn test_path_placeholders() {
+        let t = template(&["echo", "{/}", "{//}", "{.}", "{/.}", "{.}.mp3", "{x}{}"]);
+        let cmd = t.build(Path::new("music/live/song.tar.wav"));
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            vec![
+                "song.tar.wav",
+                "music/live",
+                "music/live/song.tar",
+                "song.tar",
+                "music/live/song.tar.mp3",
+                "{x}music/live/song.tar.wav",
+            ]
+        );
+
+        // No directory part means the current one
+        let cmd = template(&["echo", "{//}", "{/.}"]).build(Path::new("README"));
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, vec![".", "README"]);
+
+        // Any placeholder counts, so nothing is appended
+        assert_eq!(template(&["echo", "{/}"]).args, vec!["echo", "{/}"]);
+
+        let batch = CommandTemplate::for_batch(&["echo".into(), "{/}".into()]).unwrap();
+        let cmd = batch.build_batch(&[PathBuf::from("a/x.rs"), PathBuf::from("b/y.rs")]);
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, vec!["x.rs", "y.rs"]);
+        assert!(CommandTemplate::for_batch(&["echo".into(), "{.}.o".into()]).is_err());
+    }
+
+    #[test]
+    fn test_render_quotes_for_the_shell() {
+        let cmd = template(&["echo", "it's here", "{}"]).build(Path::new("a b/c.txt"));
+        assert_eq!(render(&cmd), "echo 'it'\\''s here' 'a b/c.txt'");
+    }
+
+    #[test]
+    fn test_batcher_respects_limit() {
+        let t = template(&["rm"]);
+        let per_path = arg_cost("a/0.txt".len());
+        // Room for exactly three paths after the template itself
+        let mut batcher = Batcher::new(&t, t.fixed_cost() + 3 * per_path);
+
+        let mut batches = Vec::new();
+        for i in 0..7 {
+            if let Some(b) = batcher.push(PathBuf::from(format!("a/{i}.txt"))) {
+                batches.push(b);
+            }
+        }
+        batches.extend(batcher.finish());
+
+        let sizes: Vec<_> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![3, 3, 1]);
+        assert_eq!(batches[2], vec![PathBuf::from("a/6.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_batch_invokes_command() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let a = tmp.path().join("a.txt");
+        let b = tmp.path().join("b.txt");
+        std::fs::write(&a, "a")?;
+        std::fs::write(&b, "b")?;
+
+        let (tx, rx) = crate::crawl::channel(10);
+        tx.send(vec![Ok(a.clone().into())]).await?;
+        tx.send(vec![Ok(b.clone().into())]).await?;
+        drop(tx);
+
+        let code = run_batch(rx, &template(&["rm", "{}"]), false).await?;
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert!(
+            !a.exists() && !b.exists(),
+            "rm should have removed both files"
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_each_parallel() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let (tx, rx) = crate::crawl::channel(10);
+        let mut files = Vec::new();
+        for i in 0..5 {
+            let f = tmp.path().join(format!("{i}.txt"));
+            std::fs::write(&f, "x")?;
+            tx.send(vec![Ok(f.clone().into())]).await?;
+            files.push(f);
+        }
+        drop(tx);
+
+        let code = run_each(rx, &template(&["cp", "{}", "{}.bak"]), 3, false).await?;
+        assert_eq!(code, ExitCode::SUCCESS);
+        for f in files {
+            assert!(PathBuf::from(format!("{}.bak", f.display())).exists());
+        }
+
+        // A failing command turns into a failure exit code
+        let (tx, rx) = crate::crawl::channel(1);
+        tx.send(vec![Ok(tmp.path().join("missing").into())]).await?;
+        drop(tx);
+        let code = run_each(rx, &template(&["cat"]), 2, false).await?;
+        assert_eq!(code, ExitCode::FAILURE);
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Checksums of search results (`--hash`), printed like `sha256sum` does.
+
+use super::plan::Plan;
+use crate::crawl::{FileEntry, Results};
+use crate::hashing::{self, hash_file, Algorithm};
+use crate::Error;
+use anyhow::Result;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+/// Hashes results in the shared hashing pool while the crawl continues, and
+/// prints `<hex>  <path>` lines in the order the hashes complete.
+pub async fn run(
+    mut rx: Results,
+    algorithm: Algorithm,
+    jobs: usize,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    if dry_run {
+        let mut plan = Plan::default();
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => plan.add("hash", entry.path().display()),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut hashed = hashing::spawn_pool(rx, jobs, move |item: &Result<FileEntry, Error>| {
+        let path = item.as_ref().ok()?.path();
+        // Only files have contents, a symlink's being its target's
+        match std::fs::metadata(path) {
+            Ok(metadata) if !metadata.is_file() => None,
+            _ => Some(hash_file(path, algorithm)),
+        }
+    });
+    let mut failed = false;
+    while let Some((item, digest)) = hashed.recv().await {
+        match (item, digest) {
+            (Ok(entry), Some(Ok(digest))) => {
+                io::stdout().write_all(&checksum_line(&digest, entry.path()))?;
+            }
+            (Ok(entry), Some(Err(e))) => {
+                eprintln!("Error: {}: {e}", entry.path().display());
+                failed = true;
+            }
+            (Err(e), _) => eprintln!("Error: {e}"),
+            (Ok(_), None) => {}
+        }
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The line `sha256sum` would print: the path as its bytes, unless it has a
+/// backslash or a line break, when those are escaped and the line starts
+/// with a backslash, for `sha256sum -c` to read it back.
+fn checksum_line(digest: &str, path: &Path) -> Vec<u8> {
+    let name = path.as_os_str().as_encoded_bytes();
+    let escaped = name.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r'));
+    let mut line = Vec::with_capacity(digest.len() + name.len() + 4);
+    if escaped {
+        line.push(b'\\');
+    }
+    line.extend_from_slice(digest.as_bytes());
+    line.extend_from_slice(b"  ");
+    for &byte in name {
+        match (escaped, byte) {
+            (true, b'\\') => line.extend_from_slice(b"\\\\"),
+            (true, b'\n') => line.extend_from_slice(b"\\n"),
+            (true, b'\r') => line.extend_from_slice(b"\\r"),
+            _ => line.push(byte),
+        }
+    }
+    line.push(b'\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_lines_read_back_by_sha256sum() {
+        let line = |path: &str| String::from_utf8(checksum_line("ab12", Path::new(path)));
+        assert_eq!(line("src/a b.rs").unwrap(), "ab12  src/a b.rs\n");
+        assert_eq!(line("odd\nname").unwrap(), "\\ab12  odd\\nname\n");
+        assert_eq!(line("back\\slash").unwrap(), "\\ab12  back\\\\slash\n");
+
+        // Bytes that aren't UTF-8 are written as they are
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let latin1 = Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+            assert_eq!(checksum_line("ab12", latin1), b"ab12  caf\xe9\n");
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Per-file confirmation before an action runs (`--interactive`).
+//!
+//! This sits between the crawl and the action as a filter: results come in on one
+//! channel, the user is asked about each on the terminal, and accepted ones go out
+//! on another. Prompts use the terminal directly, so stdout can still be piped.
+
+use crate::crawl::{self, Batch, Results};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Files larger than this aren't previewed.
+const PREVIEW_MAX_BYTES: u64 = 64 * 1024;
+const PREVIEW_LINES: usize = 20;
+
+/// The terminal the prompts are written to and answers are read from.
+pub struct Tty {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Tty {
+    /// Open the controlling terminal, independent of stdin/stdout redirection.
+    pub fn open() -> Result<Self> {
+        #[cfg(unix)]
+        let (input, output) = (File::open("/dev/tty"), File::create("/dev/tty"));
+        #[cfg(windows)]
+        let (input, output) = (File::open("CONIN$"), File::create("CONOUT$"));
+
+        let context = "--interactive needs a terminal to ask on";
+        Ok(Self {
+            input: Box::new(BufReader::new(input.context(context)?)),
+            output: Box::new(output.context(context)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+    Preview,
+    Help,
+}
+
+impl Answer {
+    /// Parse a typed answer; an empty line means no, like `rm -i`.
+    fn parse(line: &str) -> Self {
+        match line.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => Answer::Yes,
+            "" | "n" | "no" => Answer::No,
+            "a" | "all" => Answer::All,
+            "q" | "quit" => Answer::Quit,
+            "p" | "preview" => Answer::Preview,
+            _ => Answer::Help,
+        }
+    }
+}
+
+/// Put a confirmation prompt in front of `rx`; only accepted results come out of
+/// the returned receiver. Errors are passed through untouched; one with the
+/// prompt itself is printed, and ends the prompting.
+pub fn confirm_each(rx: Results, tty: Tty) -> Results {
+    let (tx, accepted) = crawl::channel(1);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = prompt_loop(rx, &tx, tty) {
+            eprintln!("Error: {e:#}");
+        }
+    });
+    accepted
+}
+
+fn prompt_loop(mut rx: Results, tx: &mpsc::Sender<Batch>, mut tty: Tty) -> Result<()> {
+    let mut yes_to_all = false;
+
+    while let Some(item) = rx.blocking_recv() {
+        let entry = match item {
+            Ok(entry) => entry,
+            Err(e) => {
+                tx.blocking_send(vec![Err(e)])?;
+                continue;
+            }
+        };
+
+        let accept = yes_to_all
+            || loop {
+                write!(tty.output, "{}? [y/n/a/q/p] ", entry.path().display())?;
+                tty.output.flush()?;
+
+                let mut line = String::new();
+                if tty.input.read_line(&mut line)? == 0 {
+                    // The terminal went away: treat it like quitting
+                    return Ok(());
+                }
+                match Answer::parse(&line) {
+                    Answer::Yes => break true,
+                    Answer::No => break false,
+                    Answer::All => {
+                        yes_to_all = true;
+                        break true;
+                    }
+                    Answer::Quit => return Ok(()),
+                    Answer::Preview => {
+                        // A file that can't be read is still worth an answer
+                        if let Err(e) = preview(entry.path(), &mut tty.output) {
+                            writeln!(tty.output, "(can't preview: {e})")?;
+                        }
+                    }
+                    Answer::Help => writeln!(
+                        tty.output,
+                        "y: yes, n: no, a: yes to this and all remaining, q: quit, p: preview the file"
+                    )?,
+                }
+            };
+
+        if accept && tx.blocking_send(vec![Ok(entry)]).is_err() {
+            // The action stopped listening
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Show the first lines of a small text file, or say why it can't be shown.
+fn preview(path: &Path, out: &mut dyn Write) -> Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    if len > PREVIEW_MAX_BYTES {
+        writeln!(out, "({len} bytes, too large to preview)")?;
+        return Ok(());
+    }
+
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => {
+            writeln!(out, "({len} bytes, binary)")?;
+            return Ok(());
+        }
+    };
+
+    writeln!(out, "----- {} -----", path.display())?;
+    for line in text.lines().take(PREVIEW_LINES) {
+        writeln!(out, "{line}")?;
+    }
+    let total = text.lines().count();
+    if total > PREVIEW_LINES {
+        writeln!(out, "... ({} more lines)", total - PREVIEW_LINES)?;
+    }
+    writeln!(out, "-----")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// A `Write` into shared memory, so tests can read back what was shown.
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn answer(answers: &str, paths: &[PathBuf]) -> (Vec<PathBuf>, String) {
+        let transcript = Arc::new(Mutex::new(Vec::new()));
+        let tty = Tty {
+            input: Box::new(std::io::Cursor::new(answers.to_string())),
+            output: Box::new(SharedBuf(transcript.clone())),
+        };
+        let (tx, rx) = crate::crawl::channel(paths.len());
+        for p in paths {
+            tx.send(vec![Ok(p.clone().into())]).await.unwrap();
+        }
+        drop(tx);
+
+        let mut accepted = confirm_each(rx, tty);
+        let mut out = Vec::new();
+        while let Some(Ok(entry)) = accepted.recv().await {
+            out.push(entry.into_path());
+        }
+        let shown = String::from_utf8(transcript.lock().unwrap().clone()).unwrap();
+        (out, shown)
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[tokio::test]
+    async fn test_yes_no_all() {
+        let (accepted, _) = answer("y\nn\na\n", &paths(&["1", "2", "3", "4", "5"])).await;
+        assert_eq!(accepted, paths(&["1", "3", "4", "5"]));
+    }
+
+    #[tokio::test]
+    async fn test_quit_and_unknown_answers() {
+        // "huh" prints help and re-asks; empty means no; q stops everything
+        let (accepted, shown) = answer("huh\ny\n\nq\n", &paths(&["1", "2", "3", "4"])).await;
+        assert_eq!(accepted, paths(&["1"]));
+        assert!(shown.contains("a: yes to this and all remaining"));
+    }
+
+    #[tokio::test]
+    async fn test_preview_small_text_file() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("notes.txt");
+        std::fs::write(&file, "first line\nsecond line\n")?;
+
+        let (accepted, shown) = answer("p\ny\n", std::slice::from_ref(&file)).await;
+        assert_eq!(accepted, vec![file]);
+        assert!(shown.contains("first line\nsecond line\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preview_of_an_unreadable_file_asks_again() {
+        let (accepted, shown) = answer("p\ny\n", &paths(&["/nonexistent/gone.txt"])).await;
+        assert_eq!(accepted, paths(&["/nonexistent/gone.txt"]));
+        assert!(shown.contains("(can't preview: "));
+        assert_eq!(shown.matches("gone.txt? [y/n/a/q/p]").count(), 2);
+    }
+}
@@ -0,0 +1,472 @@
+//! Actions that consume search results instead of printing them.
+
+pub(crate) mod archive;
+pub(crate) mod clipboard;
+pub(crate) mod delete;
+mod edit;
+mod exec;
+mod hash;
+mod interactive;
+mod perms;
+pub(crate) mod plan;
+mod rename;
+mod replace;
+pub mod touch;
+mod transfer;
+mod trash;
+
+use crate::crawl::Results;
+use crate::SearchConfig;
+use anyhow::{bail, Result};
+use clap::Args;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use transfer::Collision;
+
+pub use replace::ReplaceArgs;
+
+/// What to do with each result, if anything other than printing it.
+/// At most one action (flags in the "action" group) can be chosen. In a
+/// config document each is named as its flag is, `exec` taking a list.
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ActionConfig {
+    /// Run a command for each result ('{}' marks where the path goes; appended at the end if absent;
+    /// also {/} basename, {//} parent, {.} without extension, {/.} basename without extension;
+    /// terminate with ';')
+    #[arg(
+        short = 'x',
+        long,
+        num_args = 1..,
+        allow_hyphen_values = true,
+        value_terminator = ";",
+        value_name = "CMD",
+        group = "action"
+    )]
+    pub exec: Option<Vec<String>>,
+
+    /// Run a command once with as many results as fit the OS argument limit
+    /// ('{}' marks where the paths go; appended at the end if absent; terminate with ';')
+    #[arg(
+        short = 'X',
+        long,
+        num_args = 1..,
+        allow_hyphen_values = true,
+        value_terminator = ";",
+        value_name = "CMD",
+        group = "action"
+    )]
+    pub exec_batch: Option<Vec<String>>,
+
+    /// Number of --exec commands (or --hash and --contains/--not-contains workers) to run at once [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Delete matching files, after listing them and asking for confirmation
+    #[arg(long, group = "action")]
+    pub delete: bool,
+
+    /// With --delete, also remove directories the deletion left empty, and matching directories that are empty
+    #[arg(long, requires = "delete")]
+    pub delete_dirs: bool,
+
+    /// Move matching files to the system trash / recycle bin
+    #[arg(long, group = "action")]
+    pub trash: bool,
+
+    /// Copy matching files into DEST, recreating their paths relative to the search root
+    #[arg(long, value_name = "DEST", group = "action")]
+    pub copy_to: Option<PathBuf>,
+
+    /// Move matching files into DEST, recreating their paths relative to the search root
+    #[arg(long, value_name = "DEST", group = "action")]
+    pub move_to: Option<PathBuf>,
+
+    /// What --copy-to/--move-to do when a file already exists at the destination
+    #[arg(long, value_enum, default_value_t = Collision::Skip)]
+    pub on_collision: Collision,
+
+    /// Write matching files into an archive as they are found, keeping their paths relative to the
+    /// search root (format from the extension: .tar, .tar.gz, .tar.zst or .zip)
+    #[arg(long, value_name = "FILE", group = "action")]
+    pub archive: Option<PathBuf>,
+
+    /// Change the permissions of matching files (octal like 644, or symbolic like u+x,go-w)
+    #[arg(long, value_name = "MODE", conflicts_with = "action")]
+    pub chmod: Option<String>,
+
+    /// Change the owner of matching files (user, user:group or :group)
+    #[arg(long, value_name = "OWNER", conflicts_with = "action")]
+    pub chown: Option<String>,
+
+    /// Rename matching files with a sed-style substitution on the file name, e.g. 's/\.jpeg$/.jpg/'
+    #[arg(long, value_name = "EXPR", group = "action")]
+    pub rename: Option<String>,
+
+    /// Where --rename records what it did, for --undo-rename [default: ./fs-rename-<time>.journal]
+    #[arg(long, value_name = "FILE", requires = "rename")]
+    pub journal: Option<PathBuf>,
+
+    /// Revert the renames recorded in a --rename journal, instead of searching
+    #[arg(long, value_name = "JOURNAL", group = "action")]
+    pub undo_rename: Option<PathBuf>,
+
+    /// Set the modification time of matching files: now, relative like --touch=now-2h,
+    /// or RFC 3339 like --touch=2024-01-31T12:00:00Z
+    #[arg(
+        long,
+        value_name = "TIME",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "now",
+        group = "action"
+    )]
+    pub touch: Option<String>,
+
+    /// With --touch, also set the access time
+    #[arg(long, requires = "touch")]
+    pub atime: bool,
+
+    /// Open the matching files in $VISUAL/$EDITOR once the search is done
+    #[arg(long, group = "action")]
+    pub edit: bool,
+
+    /// Copy the list of matching paths to the system clipboard, one per line
+    #[arg(long, group = "action")]
+    pub clipboard: bool,
+
+    /// With --clipboard, separate paths with NUL instead of newline
+    #[arg(short = '0', long, requires = "clipboard")]
+    pub null: bool,
+
+    /// Print a checksum for each matching file, in the same format as sha256sum
+    #[arg(long, value_name = "ALGORITHM", group = "action")]
+    pub hash: Option<crate::hashing::Algorithm>,
+
+    /// Record matching files in the SQLite database FILE as a new scan: their
+    /// paths, types, sizes and times, and with --hash their checksums
+    #[cfg(feature = "sqlite")]
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "exec", "exec_batch", "delete", "trash", "copy_to", "move_to", "archive", "chmod",
+            "chown", "rename", "undo_rename", "touch", "edit", "clipboard", "dry_run",
+        ],
+        group = "output"
+    )]
+    pub output_sqlite: Option<PathBuf>,
+
+    /// Write matching files to stdout as a Parquet or Arrow file, a row each:
+    /// their paths, types, sizes and times, and with --hash their checksums
+    #[cfg(feature = "parquet")]
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        conflicts_with_all = [
+            "exec", "exec_batch", "delete", "trash", "copy_to", "move_to", "archive", "chmod",
+            "chown", "rename", "undo_rename", "touch", "edit", "clipboard", "dry_run",
+        ],
+        group = "output"
+    )]
+    pub format: Option<crate::export::columnar::Format>,
+
+    /// Print the operations an action would perform without touching anything
+    /// (for --exec/--exec-batch, use --dry-run-exec)
+    #[arg(short = 'n', long, conflicts_with_all = ["exec", "exec_batch"])]
+    pub dry_run: bool,
+
+    /// Print the commands --exec/--exec-batch would run instead of running them
+    #[arg(long)]
+    pub dry_run_exec: bool,
+
+    /// Ask y/n/a/q before applying the action to each match (p previews the file)
+    #[arg(short = 'i', long, conflicts_with = "undo_rename")]
+    pub interactive: bool,
+
+    /// Don't ask for confirmation before destructive actions
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+
+    /// Allow destructive actions on a search nothing narrows, i.e. on every file
+    #[arg(long)]
+    pub allow_all: bool,
+}
+
+impl ActionConfig {
+    /// True when no action was requested and results should just be printed.
+    pub fn is_empty(&self) -> bool {
+        self.exec.is_none()
+            && self.exec_batch.is_none()
+            && !self.delete
+            && !self.trash
+            && self.copy_to.is_none()
+            && self.move_to.is_none()
+            && self.archive.is_none()
+            && self.chmod.is_none()
+            && self.chown.is_none()
+            && self.rename.is_none()
+            && self.touch.is_none()
+            && !self.edit
+            && !self.clipboard
+            && self.hash.is_none()
+            && !self.exports()
+    }
+
+    /// Whether the results are written out as a table.
+    fn exports(&self) -> bool {
+        let exports = false;
+        #[cfg(feature = "sqlite")]
+        let exports = exports || self.output_sqlite.is_some();
+        #[cfg(feature = "parquet")]
+        let exports = exports || self.format.is_some();
+        exports
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+}
+
+/// Drive the selected action from the result channel until the crawl finishes.
+/// `by_content` says whether a content filter narrowed the results down.
+pub async fn run(
+    rx: Results,
+    config: &ActionConfig,
+    search: &SearchConfig,
+    by_content: bool,
+) -> Result<ExitCode> {
+    if (config.delete || config.trash) && !config.allow_all && !by_content && !narrows(search) {
+        bail!("refusing to remove files with nothing to narrow the search down; pass --allow-all if you really mean every file");
+    }
+
+    let rx = if config.interactive {
+        interactive::confirm_each(rx, interactive::Tty::open()?)
+    } else {
+        rx
+    };
+
+    if let Some(cmd) = &config.exec {
+        let template = exec::CommandTemplate::new(cmd)?;
+        return exec::run_each(rx, &template, config.jobs(), config.dry_run_exec).await;
+    }
+
+    if let Some(cmd) = &config.exec_batch {
+        let template = exec::CommandTemplate::for_batch(cmd)?;
+        return exec::run_batch(rx, &template, config.dry_run_exec).await;
+    }
+
+    if config.delete {
+        let opts = delete::DeleteOptions {
+            dirs: config.delete_dirs,
+            // Each file was already confirmed on its own
+            yes: config.yes || config.interactive,
+            dry_run: config.dry_run,
+        };
+        return delete::run(rx, &search.root_path, opts).await;
+    }
+
+    if config.trash {
+        return trash::run(rx, config.dry_run).await;
+    }
+
+    if let Some(dest) = &config.copy_to {
+        let mode = transfer::Mode::Copy;
+        return transfer::run(
+            rx,
+            &search.root_path,
+            dest,
+            mode,
+            config.on_collision,
+            config.dry_run,
+        )
+        .await;
+    }
+
+    if let Some(dest) = &config.move_to {
+        let mode = transfer::Mode::Move;
+        return transfer::run(
+            rx,
+            &search.root_path,
+            dest,
+            mode,
+            config.on_collision,
+            config.dry_run,
+        )
+        .await;
+    }
+
+    if let Some(out) = &config.archive {
+        return archive::run(rx, &search.root_path, out, config.dry_run).await;
+    }
+
+    if config.chmod.is_some() || config.chown.is_some() {
+        let mode = config
+            .chmod
+            .as_deref()
+            .map(perms::ModeSpec::parse)
+            .transpose()?;
+        let owner = config
+            .chown
+            .as_deref()
+            .map(perms::Owner::parse)
+            .transpose()?;
+        return perms::run(rx, mode, owner, config.dry_run).await;
+    }
+
+    if let Some(expr) = &config.rename {
+        let spec = rename::RenameSpec::parse(expr)?;
+        return rename::run(rx, &spec, config.dry_run, config.journal.clone()).await;
+    }
+
+    if let Some(spec) = &config.touch {
+        let when = touch::parse_timestamp(spec, std::time::SystemTime::now())?;
+        return touch::run(rx, when, config.atime, config.dry_run).await;
+    }
+
+    if config.edit {
+        let editor =
+            edit::editor_command(std::env::var("VISUAL").ok(), std::env::var("EDITOR").ok())?;
+        return edit::run(rx, &editor, config.dry_run).await;
+    }
+
+    if config.clipboard {
+        return clipboard::run(rx, config.null, config.dry_run).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = &config.output_sqlite {
+        let root = &search.root_path;
+        return crate::export::sqlite::run(rx, db, root, config.hash, config.jobs()).await;
+    }
+
+    #[cfg(feature = "parquet")]
+    if let Some(format) = config.format {
+        return crate::export::columnar::run(rx, format, config.hash, config.jobs()).await;
+    }
+
+    if let Some(algorithm) = config.hash {
+        return hash::run(rx, algorithm, config.jobs(), config.dry_run).await;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Revert a rename journal; this runs in place of a search.
+pub fn undo_rename(journal: &Path) -> Result<ExitCode> {
+    rename::undo(journal)
+}
+
+/// Find-and-replace across the matched files; this runs its own search.
+pub async fn replace(args: &ReplaceArgs) -> Result<ExitCode> {
+    replace::run(args).await
+}
+
+/// When this process is the helper that keeps clipboard contents alive after
+/// `--clipboard` exits, serve them and return its exit code.
+pub fn hold_clipboard_if_requested() -> Option<Result<ExitCode>> {
+    clipboard::hold_if_requested()
+}
+
+/// Ask a yes/no question on the terminal; anything but "y"/"yes" means no.
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Format a byte count with a binary unit, e.g. "3.4 MiB".
+pub(crate) fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Whether the search leaves any file out: a pattern of only wildcards
+/// (and dots, as in `*.*`) doesn't, so it takes extensions to.
+fn narrows(search: &SearchConfig) -> bool {
+    let everything = search.pattern.chars().all(|c| matches!(c, '*' | '.'));
+    !everything || search.extensions.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[tokio::test]
+    async fn test_delete_refuses_default_pattern() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("precious.txt");
+        std::fs::write(&file, "data")?;
+
+        let search = SearchConfig {
+            root_path: tmp.path().to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let config = ActionConfig {
+            delete: true,
+            yes: true,
+            ..Default::default()
+        };
+
+        for pattern in ["*", "**", "*.*"] {
+            let search = SearchConfig {
+                pattern: pattern.into(),
+                ..search.clone()
+            };
+            let rx = crate::search_files(&search).await;
+            assert!(run(rx, &config, &search, false).await.is_err());
+            assert!(
+                file.exists(),
+                "nothing should be deleted without --allow-all"
+            );
+        }
+
+        // An extension narrows it down enough
+        let search = SearchConfig {
+            extensions: Some(vec!["txt".into()]),
+            ..search
+        };
+        let rx = crate::search_files(&search).await;
+        assert_eq!(run(rx, &config, &search, false).await?, ExitCode::SUCCESS);
+        assert!(!file.exists());
+        Ok(())
+    }
+}
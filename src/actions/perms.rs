@@ -0,0 +1,374 @@
+//! Changing permissions and ownership of search results (`--chmod`, `--chown`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Context, Result};
+use std::process::ExitCode;
+
+/// A chmod-style mode: octal ("755") or symbolic clauses ("u+x,go-w", "a=rX").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeSpec {
+    Absolute(u32),
+    Symbolic(Vec<Clause>),
+}
+
+/// One symbolic clause, e.g. "go-w": who it applies to and the operations on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clause {
+    who: u32,
+    /// Left out, so everyone but the bits the umask clears, as chmod(1) does
+    by_umask: bool,
+    ops: Vec<(char, String)>,
+}
+
+const USER: u32 = 0o4700;
+const GROUP: u32 = 0o2070;
+const OTHER: u32 = 0o1007;
+
+impl ModeSpec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        if !spec.is_empty() && spec.len() <= 4 && spec.chars().all(|c| c.is_digit(8)) {
+            return Ok(ModeSpec::Absolute(u32::from_str_radix(spec, 8)?));
+        }
+
+        let mut clauses = Vec::new();
+        for clause in spec.split(',') {
+            let split = clause.find(['+', '-', '=']).with_context(|| {
+                format!("invalid mode '{spec}': expected octal or e.g. 'u+x,go-w'")
+            })?;
+            let (who_str, mut rest) = clause.split_at(split);
+
+            let mut who = 0;
+            for c in who_str.chars() {
+                who |= match c {
+                    'u' => USER,
+                    'g' => GROUP,
+                    'o' => OTHER,
+                    'a' => USER | GROUP | OTHER,
+                    _ => bail!("invalid mode '{spec}': unknown class '{c}'"),
+                };
+            }
+            let by_umask = who == 0;
+            if by_umask {
+                who = USER | GROUP | OTHER;
+            }
+
+            let mut ops = Vec::new();
+            while let Some(op) = rest.chars().next().filter(|c| "+-=".contains(*c)) {
+                rest = &rest[1..];
+                let end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+                let (perms, tail) = rest.split_at(end);
+                if let Some(bad) = perms.chars().find(|c| !"rwxXst".contains(*c)) {
+                    bail!("invalid mode '{spec}': unknown permission '{bad}'");
+                }
+                ops.push((op, perms.to_string()));
+                rest = tail;
+            }
+            clauses.push(Clause { who, by_umask, ops });
+        }
+        Ok(ModeSpec::Symbolic(clauses))
+    }
+
+    /// The mode a file currently at `old` ends up with, where `umask` is the
+    /// process's, for clauses that don't say who they're for.
+    pub fn apply(&self, old: u32, is_dir: bool, umask: u32) -> u32 {
+        let clauses = match self {
+            ModeSpec::Absolute(mode) => return *mode,
+            ModeSpec::Symbolic(clauses) => clauses,
+        };
+
+        let mut mode = old & 0o7777;
+        for clause in clauses {
+            for (op, perms) in &clause.ops {
+                let mut bits = 0;
+                for p in perms.chars() {
+                    bits |= match p {
+                        'r' => 0o444,
+                        'w' => 0o222,
+                        'x' => 0o111,
+                        // Execute only if it's a directory or already executable by someone
+                        'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                        's' => 0o6000,
+                        't' => 0o1000,
+                        _ => 0,
+                    };
+                }
+                bits &= clause.who;
+                if clause.by_umask {
+                    bits &= !umask;
+                }
+                match op {
+                    '+' => mode |= bits,
+                    '-' => mode &= !bits,
+                    _ => mode = (mode & !clause.who) | bits,
+                }
+            }
+        }
+        mode
+    }
+}
+
+/// A chown-style owner: "user", "user:group", ":group" or "user:", by name or numeric id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Owner {
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Owner {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (user, group) = spec.split_once(':').unwrap_or((spec, ""));
+        let owner = Owner {
+            uid: (!user.is_empty()).then(|| lookup_user(user)).transpose()?,
+            gid: (!group.is_empty())
+                .then(|| lookup_group(group))
+                .transpose()?,
+        };
+        if owner == Owner::default() {
+            bail!("invalid owner '{spec}': expected user, user:group or :group");
+        }
+        Ok(owner)
+    }
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<u32> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let cname = std::ffi::CString::new(name)?;
+    // SAFETY: getpwnam takes a NUL-terminated string; the returned record is read
+    // immediately, before any other passwd lookup could overwrite it.
+    let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+    if pw.is_null() {
+        bail!("no such user: {name}");
+    }
+    Ok(unsafe { (*pw).pw_uid })
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let cname = std::ffi::CString::new(name)?;
+    // SAFETY: as in lookup_user.
+    let gr = unsafe { libc::getgrnam(cname.as_ptr()) };
+    if gr.is_null() {
+        bail!("no such group: {name}");
+    }
+    Ok(unsafe { (*gr).gr_gid })
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_name: &str) -> Result<u32> {
+    bail!("--chown is only supported on Unix")
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_name: &str) -> Result<u32> {
+    bail!("--chown is only supported on Unix")
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    changed: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+/// Applies the mode and/or owner change to each result as it arrives.
+pub async fn run(
+    mut rx: Results,
+    mode: Option<ModeSpec>,
+    owner: Option<Owner>,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    if cfg!(not(unix)) {
+        bail!("--chmod and --chown are only supported on Unix");
+    }
+
+    let umask = umask();
+    let mut summary = Summary::default();
+    let mut plan = Plan::default();
+    while let Some(item) = rx.recv().await {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+
+        let (mode, owner) = (mode.clone(), owner);
+        let target = path.clone();
+        let result =
+            tokio::task::spawn_blocking(move || change_one(&target, mode, umask, owner, dry_run))
+                .await?;
+        match result {
+            Ok(changes) if changes.is_empty() => summary.unchanged += 1,
+            Ok(changes) if dry_run => {
+                for (op, detail) in changes {
+                    plan.add(op, format!("{} ({detail})", path.display()));
+                }
+            }
+            Ok(changes) => {
+                let details: Vec<_> = changes.into_iter().map(|(_, d)| d).collect();
+                println!("Changed: {} ({})", path.display(), details.join(", "));
+                summary.changed += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                summary.failed += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!(
+        "{} changed, {} unchanged, {} failed",
+        summary.changed, summary.unchanged, summary.failed
+    );
+    Ok(if summary.failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Change one file; returns each change made as ("chmod" | "chown", description),
+/// empty if the file already matched.
+#[cfg(unix)]
+fn change_one(
+    path: &std::path::Path,
+    mode: Option<ModeSpec>,
+    umask: u32,
+    owner: Option<Owner>,
+    dry_run: bool,
+) -> std::io::Result<Vec<(&'static str, String)>> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = std::fs::metadata(path)?;
+    let mut changes = Vec::new();
+
+    if let Some(spec) = mode {
+        let old = metadata.mode() & 0o7777;
+        let new = spec.apply(old, metadata.is_dir(), umask);
+        if new != old {
+            if !dry_run {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(new))?;
+            }
+            changes.push(("chmod", format!("mode {old:04o} -> {new:04o}")));
+        }
+    }
+
+    if let Some(owner) = owner {
+        let uid = owner.uid.filter(|&u| u != metadata.uid());
+        let gid = owner.gid.filter(|&g| g != metadata.gid());
+        if uid.is_some() || gid.is_some() {
+            if !dry_run {
+                std::os::unix::fs::chown(path, uid, gid)?;
+            }
+            changes.push((
+                "chown",
+                format!(
+                    "owner {}:{} -> {}:{}",
+                    metadata.uid(),
+                    metadata.gid(),
+                    uid.unwrap_or(metadata.uid()),
+                    gid.unwrap_or(metadata.gid())
+                ),
+            ));
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(not(unix))]
+fn change_one(
+    _path: &std::path::Path,
+    _mode: Option<ModeSpec>,
+    _umask: u32,
+    _owner: Option<Owner>,
+    _dry_run: bool,
+) -> std::io::Result<Vec<(&'static str, String)>> {
+    Ok(Vec::new())
+}
+
+/// The process's umask. Reading it means setting it, so it's put straight back.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // mode_t is a u16 on macOS
+fn umask() -> u32 {
+    // SAFETY: umask only swaps one number for another, and can't fail
+    let mask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(mask) };
+    u32::from(mask)
+}
+
+#[cfg(not(unix))]
+fn umask() -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_mode_spec_octal_and_symbolic() {
+        let m = |s: &str| ModeSpec::parse(s).unwrap();
+
+        assert_eq!(m("755").apply(0o600, false, 0o022), 0o755);
+        assert_eq!(m("0644").apply(0o777, false, 0o022), 0o644);
+        assert_eq!(m("u+x").apply(0o644, false, 0o022), 0o744);
+        assert_eq!(m("go-w").apply(0o666, false, 0o022), 0o644);
+        assert_eq!(m("a=r").apply(0o755, false, 0o022), 0o444);
+        assert_eq!(m("+x").apply(0o644, false, 0o022), 0o755);
+        assert_eq!(m("+x").apply(0o644, false, 0o077), 0o744);
+        // Without who, the umask's bits are left alone
+        assert_eq!(m("+w").apply(0o644, false, 0o022), 0o644);
+        assert_eq!(m("+w").apply(0o644, false, 0o002), 0o664);
+        assert_eq!(m("=r").apply(0o666, false, 0o044), 0o400);
+        assert_eq!(m("a+w").apply(0o644, false, 0o022), 0o666);
+        assert_eq!(m("u=rw,go=r").apply(0o777, false, 0o022), 0o644);
+        assert_eq!(m("u+rw-x").apply(0o500, false, 0o022), 0o600);
+
+        // 'X' only grants execute to directories or already-executable files
+        assert_eq!(m("a+X").apply(0o644, false, 0o022), 0o644);
+        assert_eq!(m("a+X").apply(0o744, false, 0o022), 0o755);
+        assert_eq!(m("a+X").apply(0o644, true, 0o022), 0o755);
+
+        assert!(ModeSpec::parse("u+z").is_err());
+        assert!(ModeSpec::parse("q+x").is_err());
+        assert!(ModeSpec::parse("rwx").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_chmod_dry_run_and_apply() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("script.sh");
+        std::fs::write(&file, "#!/bin/sh")?;
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644))?;
+        let mode_of = |p: &PathBuf| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+
+        for dry_run in [true, false] {
+            let (tx, rx) = crate::crawl::channel(1);
+            tx.send(vec![Ok(file.clone().into())]).await?;
+            drop(tx);
+            let spec = ModeSpec::parse("u+x")?;
+            assert_eq!(run(rx, Some(spec), None, dry_run).await?, ExitCode::SUCCESS);
+            let expected = if dry_run { 0o644 } else { 0o744 };
+            assert_eq!(mode_of(&file), expected);
+        }
+        Ok(())
+    }
+}
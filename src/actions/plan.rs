@@ -0,0 +1,65 @@
+//! The "planned operations" report every action prints under `--dry-run`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Collects the operations an action would have performed, printing each one
+/// as it is planned and a per-operation tally at the end.
+#[derive(Debug, Default)]
+pub struct Plan {
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Plan {
+    /// Report one operation, e.g. `add("delete", path.display())`.
+    pub fn add(&mut self, op: &'static str, subject: impl Display) {
+        println!("[dry-run] {op} {subject}");
+        *self.counts.entry(op).or_default() += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Print the closing summary line.
+    pub fn finish(self) {
+        println!("{}", self.summary());
+    }
+
+    fn summary(&self) -> String {
+        let tally: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(op, n)| format!("{n} {op}"))
+            .collect();
+        if tally.is_empty() {
+            "Dry run: nothing to do.".into()
+        } else {
+            format!(
+                "Dry run: {} operation(s) planned ({}); nothing was changed.",
+                self.len(),
+                tally.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_tallies_by_operation() {
+        let mut plan = Plan::default();
+        assert_eq!(plan.summary(), "Dry run: nothing to do.");
+
+        plan.add("delete", "a.txt");
+        plan.add("rename", "b -> c");
+        plan.add("delete", "d.txt");
+        assert_eq!(plan.len(), 3);
+        assert_eq!(
+            plan.summary(),
+            "Dry run: 3 operation(s) planned (2 delete, 1 rename); nothing was changed."
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Regex-based bulk renaming of search results (`--rename`), with an undo journal
+//! (`--undo-rename`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// A sed-style substitution applied to file names: `s/regex/replacement/[gi]`.
+#[derive(Debug, Clone)]
+pub struct RenameSpec {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl RenameSpec {
+    /// Parse `s<d>regex<d>replacement<d>[flags]` for any delimiter `<d>`. Captures can
+    /// be referenced as `$1`, `${name}` or sed's `\1`; flags are `g` (all matches) and `i`.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let mut chars = expr.chars();
+        let delim = match (chars.next(), chars.next()) {
+            (Some('s'), Some(d)) if !d.is_alphanumeric() && d != '\\' => d,
+            _ => bail!("invalid rename expression '{expr}': expected s/regex/replacement/"),
+        };
+
+        // Split on unescaped delimiters; an escaped delimiter stands for itself
+        let mut parts = vec![String::new()];
+        let mut rest = chars.peekable();
+        while let Some(c) = rest.next() {
+            if c == '\\' && rest.peek() == Some(&delim) {
+                parts.last_mut().unwrap().push(delim);
+                rest.next();
+            } else if c == delim {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts).map_err(|_| {
+            anyhow::anyhow!("invalid rename expression '{expr}': expected s/regex/replacement/")
+        })?;
+
+        let mut global = false;
+        let mut builder = RegexBuilder::new(&pattern);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => bail!("invalid rename flag '{flag}' in '{expr}'"),
+            }
+        }
+
+        let back_ref = Regex::new(r"\\(\d+)").unwrap();
+        let replacement = back_ref.replace_all(&replacement, "$${${1}}").into_owned();
+        if replacement.contains(['/', '\\']) {
+            bail!("a rename replacement can't contain path separators");
+        }
+
+        Ok(Self {
+            regex: builder.build()?,
+            replacement,
+            global,
+        })
+    }
+
+    /// The new file name, or `None` if the regex doesn't match (or changes nothing).
+    fn apply(&self, name: &str) -> Option<String> {
+        if !self.regex.is_match(name) {
+            return None;
+        }
+        let renamed = if self.global {
+            self.regex.replace_all(name, self.replacement.as_str())
+        } else {
+            self.regex.replace(name, self.replacement.as_str())
+        };
+        (renamed != name && !renamed.is_empty()).then(|| renamed.into_owned())
+    }
+}
+
+/// Collects every result, plans all renames up front so collisions can be caught
+/// before anything moves, then applies them, journaling each one as it succeeds.
+pub async fn run(
+    mut rx: Results,
+    spec: &RenameSpec,
+    dry_run: bool,
+    journal: Option<PathBuf>,
+) -> Result<ExitCode> {
+    let mut sources = Vec::new();
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => sources.push(entry.into_path()),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    let plan = plan(&sources, spec);
+    let unchanged = sources.len() - plan.renames.len() - plan.collisions.len();
+    for (from, to) in &plan.collisions {
+        eprintln!(
+            "Collision: {} -> {} (target exists or is claimed twice)",
+            from.display(),
+            to.display()
+        );
+    }
+
+    if dry_run {
+        let mut report = Plan::default();
+        for (from, to) in &plan.renames {
+            report.add("rename", format!("{} -> {}", from.display(), to.display()));
+        }
+        report.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let journal = journal.unwrap_or_else(default_journal);
+    let collisions = plan.collisions.len();
+    let renames = plan.renames;
+    let (renamed, failed) =
+        tokio::task::spawn_blocking(move || apply(&renames, &journal)).await??;
+
+    println!(
+        "{renamed} renamed, {unchanged} unchanged, {collisions} collision(s), {failed} failed"
+    );
+    Ok(if failed > 0 || collisions > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[derive(Debug, Default)]
+struct RenamePlan {
+    renames: Vec<(PathBuf, PathBuf)>,
+    collisions: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Work out every rename. A rename collides when its target already exists, when two
+/// files would get the same name, or when the target is itself about to be renamed.
+/// Names that aren't valid UTF-8 are left alone.
+fn plan(sources: &[PathBuf], spec: &RenameSpec) -> RenamePlan {
+    let candidates: Vec<(PathBuf, PathBuf)> = sources
+        .iter()
+        .filter_map(|from| {
+            let name = from.file_name()?.to_str()?;
+            let to = from.with_file_name(spec.apply(name)?);
+            Some((from.clone(), to))
+        })
+        .collect();
+
+    let mut claims: HashMap<&Path, usize> = HashMap::new();
+    for (_, to) in &candidates {
+        *claims.entry(to.as_path()).or_default() += 1;
+    }
+    let moving: HashSet<&Path> = candidates.iter().map(|(from, _)| from.as_path()).collect();
+
+    let mut plan = RenamePlan::default();
+    for (from, to) in &candidates {
+        let taken = claims[to.as_path()] > 1
+            || moving.contains(to.as_path())
+            || (to.exists() && !same_file(from, to));
+        if taken {
+            plan.collisions.push((from.clone(), to.clone()));
+        } else {
+            plan.renames.push((from.clone(), to.clone()));
+        }
+    }
+    plan
+}
+
+/// True when both paths name the same file, e.g. a case-only rename on a
+/// case-insensitive filesystem.
+fn same_file(a: &Path, b: &Path) -> bool {
+    matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Rename everything in order, appending `from<TAB>to` to the journal after each success.
+/// Returns how many were renamed and how many failed.
+fn apply(renames: &[(PathBuf, PathBuf)], journal: &Path) -> Result<(usize, usize)> {
+    if renames.is_empty() {
+        return Ok((0, 0));
+    }
+
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal)
+        .with_context(|| format!("can't open rename journal {}", journal.display()))?;
+
+    let (mut renamed, mut failed) = (0, 0);
+    for (from, to) in renames {
+        // Nothing is renamed that the journal couldn't undo
+        let line = match journal_line(from, to) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", from.display());
+                failed += 1;
+                continue;
+            }
+        };
+        match fs::rename(from, to) {
+            Ok(()) => {
+                println!("Renamed: {} -> {}", from.display(), to.display());
+                writeln!(log, "{line}")?;
+                renamed += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", from.display());
+                failed += 1;
+            }
+        }
+    }
+    log.flush()?;
+
+    if renamed > 0 {
+        println!(
+            "Undo with: fs --undo-rename {}",
+            journal
+                .canonicalize()
+                .unwrap_or(journal.to_path_buf())
+                .display()
+        );
+    }
+    Ok((renamed, failed))
+}
+
+/// Revert every rename recorded in `journal`, newest first.
+pub fn undo(journal: &Path) -> Result<ExitCode> {
+    let file = fs::File::open(journal)
+        .with_context(|| format!("can't open rename journal {}", journal.display()))?;
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let (from, to) = line
+            .split_once('\t')
+            .with_context(|| format!("malformed journal line: {line}"))?;
+        entries.push((unescape(from)?, unescape(to)?));
+    }
+
+    let (mut restored, mut failed) = (0, 0);
+    for (original, renamed) in entries.iter().rev() {
+        if original.exists() {
+            eprintln!(
+                "Error: can't restore {}: {} already exists",
+                renamed.display(),
+                original.display()
+            );
+            failed += 1;
+            continue;
+        }
+        match fs::rename(renamed, original) {
+            Ok(()) => {
+                println!("Restored: {} -> {}", renamed.display(), original.display());
+                restored += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", renamed.display());
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{restored} restored, {failed} failed");
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// `fs-rename-<unix seconds>.journal` in the current directory.
+fn default_journal() -> PathBuf {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    PathBuf::from(format!("fs-rename-{secs}.journal"))
+}
+
+/// The journal's line for renaming `from` to `to`, with absolute paths so it
+/// can be replayed from any directory.
+fn journal_line(from: &Path, to: &Path) -> Result<String> {
+    let (from, to) = (std::path::absolute(from)?, std::path::absolute(to)?);
+    Ok(format!("{}\t{}", escape(&from)?, escape(&to)?))
+}
+
+/// Journal lines are tab-separated, so tabs, newlines and backslashes get
+/// escaped, and so does each byte of a name that isn't UTF-8 (as `\xff`), for
+/// every path to come back exactly as it was.
+fn escape(path: &Path) -> Result<String> {
+    let bytes = path_bytes(path)?;
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+    Ok(out)
+}
+
+fn unescape(s: &str) -> Result<PathBuf> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push(b'\t'),
+            Some('n') => out.push(b'\n'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .with_context(|| format!("malformed journal escape \\x{hex}"))?;
+                out.push(byte);
+            }
+            Some(other) => out.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => out.push(b'\\'),
+        }
+    }
+    path_from_bytes(out)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Result<&[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes())
+}
+
+/// Elsewhere a path is only journaled if it's Unicode.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Result<&[u8]> {
+    path.to_str()
+        .map(str::as_bytes)
+        .context("can't journal a path that isn't valid Unicode")
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    Ok(String::from_utf8(bytes)
+        .context("malformed journal: a path isn't valid Unicode")?
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let spec = RenameSpec::parse(r"s/IMG_(\d+)/photo-$1/").unwrap();
+        assert_eq!(
+            spec.apply("IMG_0042.jpg").as_deref(),
+            Some("photo-0042.jpg")
+        );
+        assert_eq!(spec.apply("notes.txt"), None);
+
+        // sed-style back-references, another delimiter, and flags
+        let spec = RenameSpec::parse(r"s|(\w+)\.JPEG|\1.jpg|i").unwrap();
+        assert_eq!(spec.apply("cat.jpeg").as_deref(), Some("cat.jpg"));
+
+        let spec = RenameSpec::parse("s/ /_/g").unwrap();
+        assert_eq!(spec.apply("a b c.txt").as_deref(), Some("a_b_c.txt"));
+
+        assert!(RenameSpec::parse("s/a/b").is_err());
+        assert!(RenameSpec::parse("s/a/b/q").is_err());
+        assert!(RenameSpec::parse("s/a/sub\\/dir/").is_err());
+    }
+
+    #[test]
+    fn test_escape_round_trip() -> Result<()> {
+        let tricky = Path::new("dir\\with\ttab\nand newline");
+        assert_eq!(unescape(&escape(tricky)?)?, tricky);
+
+        // A name that isn't UTF-8 comes back byte for byte
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let latin1 = Path::new(std::ffi::OsStr::from_bytes(b"/photos/caf\xe9 \\x41.jpg"));
+            let escaped = escape(latin1)?;
+            assert_eq!(escaped, "/photos/caf\\xe9 \\\\x41.jpg");
+            assert_eq!(unescape(&escaped)?, latin1);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rename_collision_and_undo() -> Result<()> {
+        let tmp = tempdir()?;
+        let a = tmp.path().join("a.jpeg");
+        let b = tmp.path().join("b.jpeg");
+        let b_taken = tmp.path().join("b.jpg");
+        for f in [&a, &b, &b_taken] {
+            fs::write(f, f.to_string_lossy().as_bytes())?;
+        }
+
+        let spec = RenameSpec::parse(r"s/\.jpeg$/.jpg/")?;
+        let journal = tmp.path().join("undo.journal");
+
+        // A dry run leaves everything in place
+        let (tx, rx) = crate::crawl::channel(4);
+        tx.send(vec![Ok(a.clone().into())]).await?;
+        tx.send(vec![Ok(b.clone().into())]).await?;
+        drop(tx);
+        run(rx, &spec, true, Some(journal.clone())).await?;
+        assert!(a.exists() && !journal.exists());
+
+        let (tx, rx) = crate::crawl::channel(4);
+        tx.send(vec![Ok(a.clone().into())]).await?;
+        tx.send(vec![Ok(b.clone().into())]).await?;
+        drop(tx);
+        let code = run(rx, &spec, false, Some(journal.clone())).await?;
+
+        // b.jpeg collides with the existing b.jpg, so it stays; a.jpeg is renamed
+        assert_eq!(code, ExitCode::FAILURE);
+        assert!(!a.exists() && tmp.path().join("a.jpg").exists());
+        assert!(b.exists());
+
+        assert_eq!(undo(&journal)?, ExitCode::SUCCESS);
+        assert!(a.exists() && !tmp.path().join("a.jpg").exists());
+        Ok(())
+    }
+}
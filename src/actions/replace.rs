@@ -0,0 +1,395 @@
+//! In-place find-and-replace across matched files (`fs replace`).
+
+use super::confirm;
+use super::plan::Plan;
+use crate::grep::binary;
+use crate::{search_files, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs::{File, Metadata};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Args, Debug)]
+pub struct ReplaceArgs {
+    /// Regular expression to replace in file contents
+    pub regex: String,
+
+    /// Replacement text; $1 or ${name} insert capture groups
+    pub replacement: String,
+
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Match case-insensitively
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+
+    /// Only show the diffs, don't change any file
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Apply without showing the diffs and asking first
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+
+    /// Keep each original next to the rewritten file, with this suffix added (e.g. .bak)
+    #[arg(long, value_name = "SUFFIX")]
+    pub backup: Option<String>,
+}
+
+/// A file to change, with the hash of its contents when it was searched.
+/// The contents are read again to preview and apply the change, rather than
+/// every file's being held until then.
+#[derive(Debug)]
+struct Change {
+    path: PathBuf,
+    hash: blake3::Hash,
+    matches: usize,
+}
+
+impl Change {
+    /// The file's contents, as long as they're still those searched.
+    fn read(&self) -> Result<Vec<u8>> {
+        let contents = std::fs::read(&self.path)?;
+        if blake3::hash(&contents) != self.hash {
+            bail!("file changed since it was searched, skipping");
+        }
+        Ok(contents)
+    }
+
+    /// A unified diff of the change, for previewing.
+    fn diff(&self, regex: &Regex, replacement: &str) -> Result<String> {
+        let old = self.read()?;
+        let new = regex.replace_all(&old, replacement.as_bytes());
+        let (old, new) = (String::from_utf8_lossy(&old), String::from_utf8_lossy(&new));
+        let name = self.path.display().to_string();
+        Ok(similar::TextDiff::from_lines(old.as_ref(), new.as_ref())
+            .unified_diff()
+            .context_radius(2)
+            .header(&name, &name)
+            .to_string())
+    }
+
+    /// Print the diff, or why there isn't one.
+    fn preview(&self, regex: &Regex, replacement: &str) {
+        match self.diff(regex, replacement) {
+            Ok(diff) => print!("{diff}"),
+            Err(e) => eprintln!("Error: {}: {e}", self.path.display()),
+        }
+    }
+}
+
+/// Searches, works out every change up front, previews them, and only then
+/// rewrites the files.
+pub async fn run(args: &ReplaceArgs) -> Result<ExitCode> {
+    if args.search.search_archives {
+        bail!("fs replace can't rewrite files inside archives; drop --search-archives");
+    }
+    let regex = RegexBuilder::new(&args.regex)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .with_context(|| format!("invalid regex '{}'", args.regex))?;
+    if let Some(suffix) = &args.backup {
+        if suffix.is_empty() || suffix.contains(['/', '\\']) {
+            bail!("invalid --backup suffix '{suffix}'");
+        }
+    }
+
+    let mut rx = search_files(&args.search).await;
+    let mut paths = Vec::new();
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => paths.push(entry.into_path()),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    let planned = regex.clone();
+    let changes = tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .filter_map(|path| match plan_change(&path, &planned) {
+                Ok(change) => change,
+                Err(e) => {
+                    eprintln!("Error: {}: {e}", path.display());
+                    None
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    if args.dry_run {
+        let mut plan = Plan::default();
+        for change in &changes {
+            change.preview(&regex, &args.replacement);
+            plan.add(
+                "replace",
+                format!("{} ({} match(es))", change.path.display(), change.matches),
+            );
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if changes.is_empty() {
+        println!("No matches, nothing to replace.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !args.yes {
+        for change in &changes {
+            change.preview(&regex, &args.replacement);
+        }
+        let total: usize = changes.iter().map(|c| c.matches).sum();
+        println!("{total} match(es) in {} file(s)", changes.len());
+
+        if !std::io::stdin().is_terminal() {
+            bail!("refusing to rewrite files without confirmation; pass --yes when not running interactively");
+        }
+        if !confirm("Apply these changes?")? {
+            println!("Aborted, nothing was changed.");
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    let (replacement, backup) = (args.replacement.clone(), args.backup.clone());
+    let failed = tokio::task::spawn_blocking(move || {
+        let mut failed = 0usize;
+        for change in &changes {
+            match apply(change, &regex, &replacement, backup.as_deref()) {
+                Ok(()) => println!(
+                    "Replaced {} match(es) in {}",
+                    change.matches,
+                    change.path.display()
+                ),
+                Err(e) => {
+                    eprintln!("Error: {}: {e:#}", change.path.display());
+                    failed += 1;
+                }
+            }
+        }
+        failed
+    })
+    .await?;
+
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The change to one file, or `None` if it is binary, a symlink or has no
+/// match. Rewriting a symlink would replace it with a regular file; its
+/// target is changed when it's a result itself.
+fn plan_change(path: &Path, regex: &Regex) -> Result<Option<Change>> {
+    if binary::has_binary_extension(path) || path.symlink_metadata()?.is_symlink() {
+        return Ok(None);
+    }
+    let old = std::fs::read(path)?;
+    if binary::looks_binary(&old) {
+        return Ok(None);
+    }
+
+    let matches = regex.find_iter(&old).count();
+    if matches == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Change {
+        path: path.to_path_buf(),
+        hash: blake3::hash(&old),
+        matches,
+    }))
+}
+
+/// Rewrite one file: write a temporary file next to it with the same owner,
+/// permissions and extended attributes, then rename it over the original, so
+/// the file is never left half-written. A file with other hard links, or one
+/// whose owner or attributes can't be given to another, is rewritten in place
+/// instead, since renaming over it would lose them.
+fn apply(change: &Change, regex: &Regex, replacement: &str, backup: Option<&str>) -> Result<()> {
+    let path = &change.path;
+    let old = change.read()?;
+    let new = regex.replace_all(&old, replacement.as_bytes());
+
+    let metadata = path.symlink_metadata()?;
+    let file_name = path
+        .file_name()
+        .filter(|_| metadata.is_file())
+        .context("not a file")?;
+    if let Some(suffix) = backup {
+        let mut backup_name = file_name.to_os_string();
+        backup_name.push(suffix);
+        std::fs::copy(path, path.with_file_name(backup_name)).context("writing backup")?;
+    }
+
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".fs-replace.{}", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    if links(&metadata) > 1 {
+        return overwrite(path, &new);
+    }
+    // Whether the temporary file took the original's place
+    let write = || -> Result<bool> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(&new)?;
+        if keep_owner_and_attributes(&file, path, &metadata).is_err() {
+            return Ok(false);
+        }
+        file.set_permissions(metadata.permissions())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(true)
+    };
+    match write() {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let _ = std::fs::remove_file(&tmp);
+            overwrite(path, &new)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Write `contents` over the file at `path` as it is.
+fn overwrite(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn links(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &Metadata) -> u64 {
+    1
+}
+
+/// Give `file` the owner and extended attributes of the one at `path`.
+#[cfg(unix)]
+fn keep_owner_and_attributes(file: &File, path: &Path, metadata: &Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    use xattr::FileExt;
+
+    std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()))?;
+    if xattr::SUPPORTED_PLATFORM {
+        for name in xattr::list(path)? {
+            if let Some(value) = xattr::get(path, &name)? {
+                file.set_xattr(&name, &value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn keep_owner_and_attributes(_file: &File, _path: &Path, _metadata: &Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_change_and_diff() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("Cargo.toml");
+        std::fs::write(
+            &file,
+            "[package]\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+        )?;
+        let blob = tmp.path().join("blob");
+        std::fs::write(&blob, b"\0edition = \"2018\"")?;
+
+        let regex = Regex::new(r#"edition = "(\d+)""#)?;
+        let replacement = r#"edition = "2021" # was $1"#;
+        let change = plan_change(&file, &regex)?.unwrap();
+        assert_eq!(change.matches, 1);
+        let diff = change.diff(&regex, replacement)?;
+        assert!(diff.contains("-edition = \"2018\"\n+edition = \"2021\" # was 2018\n"));
+        apply(&change, &regex, replacement, None)?;
+        assert_eq!(
+            std::fs::read_to_string(&file)?,
+            "[package]\nversion = \"0.1.0\"\nedition = \"2021\" # was 2018\n"
+        );
+
+        assert!(plan_change(&blob, &regex)?.is_none());
+        assert!(plan_change(&file, &Regex::new("nope")?)?.is_none());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_keeps_permissions_and_backup() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir()?;
+        let script = tmp.path().join("run.sh");
+        std::fs::write(&script, "echo old\n")?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750))?;
+
+        let regex = Regex::new("old")?;
+        let change = plan_change(&script, &regex)?.unwrap();
+        apply(&change, &regex, "new", Some(".bak"))?;
+
+        assert_eq!(std::fs::read_to_string(&script)?, "echo new\n");
+        assert_eq!(
+            std::fs::metadata(&script)?.permissions().mode() & 0o777,
+            0o750
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("run.sh.bak"))?,
+            "echo old\n"
+        );
+        // Nothing left behind but the file and its backup
+        assert_eq!(std::fs::read_dir(tmp.path())?.count(), 2);
+
+        // A file edited after planning is left alone
+        std::fs::write(&script, "echo edited\n")?;
+        assert!(apply(&change, &regex, "new", None).is_err());
+        assert_eq!(std::fs::read_to_string(&script)?, "echo edited\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_keeps_symlinks_and_hard_links() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("notes.txt");
+        let symlink = tmp.path().join("link.txt");
+        let hard_link = tmp.path().join("hard.txt");
+        std::fs::write(&file, "old\n")?;
+        std::os::unix::fs::symlink("notes.txt", &symlink)?;
+        std::fs::hard_link(&file, &hard_link)?;
+
+        let regex = Regex::new("old")?;
+        assert!(
+            plan_change(&symlink, &regex)?.is_none(),
+            "a symlink is left for its target to be changed"
+        );
+        let change = plan_change(&file, &regex)?.unwrap();
+        apply(&change, &regex, "new", None)?;
+
+        assert!(symlink.symlink_metadata()?.is_symlink());
+        assert_eq!(std::fs::read_to_string(&symlink)?, "new\n");
+        assert_eq!(std::fs::read_to_string(&hard_link)?, "new\n");
+        Ok(())
+    }
+}
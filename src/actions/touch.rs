@@ -0,0 +1,177 @@
+//! Setting modification (and access) times on search results (`--touch`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::{bail, Context, Result};
+use filetime::FileTime;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+/// Parse "now", a relative time like "now-2h" or "now+1d12h", or an RFC 3339
+/// timestamp like "2024-01-31T12:00:00Z". Relative units are s, m, h, d and w.
+pub fn parse_timestamp(spec: &str, now: SystemTime) -> Result<SystemTime> {
+    let Some(offset) = spec.strip_prefix("now") else {
+        let parsed =
+            time::OffsetDateTime::parse(spec, &time::format_description::well_known::Rfc3339)
+                .with_context(|| {
+                    format!("invalid timestamp '{spec}': expected RFC 3339 or e.g. 'now-2h'")
+                })?;
+        return Ok(parsed.into());
+    };
+
+    if offset.is_empty() {
+        return Ok(now);
+    }
+    let parse =
+        |amount| parse_duration(amount).with_context(|| format!("invalid timestamp '{spec}'"));
+    if let Some(amount) = offset.strip_prefix('-') {
+        now.checked_sub(parse(amount)?)
+    } else if let Some(amount) = offset.strip_prefix('+') {
+        now.checked_add(parse(amount)?)
+    } else {
+        bail!("invalid timestamp '{spec}': expected 'now-<duration>' or 'now+<duration>'");
+    }
+    .with_context(|| format!("timestamp '{spec}' is out of range"))
+}
+
+/// Parse a duration made of number/unit pairs, e.g. "90s", "2h", "1d12h".
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    if rest.is_empty() {
+        bail!("missing duration");
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            bail!("expected a number in '{s}'");
+        }
+        let n: u64 = rest[..digits].parse()?;
+        rest = &rest[digits..];
+
+        let unit = rest
+            .chars()
+            .next()
+            .with_context(|| format!("missing unit in '{s}'"))?;
+        let secs = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => bail!("unknown unit '{unit}' in '{s}' (use s, m, h, d or w)"),
+        };
+        rest = &rest[1..];
+        total += Duration::from_secs(n.saturating_mul(secs));
+    }
+    Ok(total)
+}
+
+/// Sets the mtime (and, if `atime` is set, the access time) of each result.
+pub async fn run(
+    mut rx: Results,
+    when: SystemTime,
+    atime: bool,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    let stamp = FileTime::from_system_time(when);
+    let (mut touched, mut failed) = (0usize, 0usize);
+    let mut plan = Plan::default();
+
+    while let Some(item) = rx.recv().await {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+
+        if dry_run {
+            plan.add("touch", path.display());
+            continue;
+        }
+
+        let target = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            if atime {
+                filetime::set_file_times(&target, stamp, stamp)
+            } else {
+                filetime::set_file_mtime(&target, stamp)
+            }
+        })
+        .await?;
+
+        match result {
+            Ok(()) => touched += 1,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("{touched} touched, {failed} failed");
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() -> Result<()> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(parse_timestamp("now", now)?, now);
+        assert_eq!(
+            parse_timestamp("now-2h", now)?,
+            now - Duration::from_secs(7200)
+        );
+        assert_eq!(
+            parse_timestamp("now+1d12h", now)?,
+            now + Duration::from_secs(36 * 3600)
+        );
+        assert_eq!(
+            parse_timestamp("1970-01-02T00:00:00Z", now)?,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(86400)
+        );
+        assert_eq!(
+            parse_timestamp("1970-01-01T01:00:00+01:00", now)?,
+            SystemTime::UNIX_EPOCH
+        );
+
+        assert!(parse_timestamp("now-2y", now).is_err());
+        assert!(parse_timestamp("now-h", now).is_err());
+        assert!(parse_timestamp("yesterday", now).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_touch_sets_mtime() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("cache.bin");
+        std::fs::write(&file, "x")?;
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        let (tx, rx) = crate::crawl::channel(1);
+        tx.send(vec![Ok(file.clone().into())]).await?;
+        drop(tx);
+        assert_eq!(run(rx, when, false, false).await?, ExitCode::SUCCESS);
+
+        assert_eq!(std::fs::metadata(&file)?.modified()?, when);
+        Ok(())
+    }
+}
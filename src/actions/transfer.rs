@@ -0,0 +1,329 @@
+//! Copying or moving search results into another directory (`--copy-to`, `--move-to`),
+//! keeping each file's path relative to the search root, like `rsync -R`.
+
+use super::human_size;
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Copy,
+    Move,
+}
+
+/// What to do when a file already exists at the destination.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collision {
+    /// Leave the existing file alone and skip this one
+    #[default]
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Pick a free name by adding a counter, e.g. "notes.1.txt"
+    Rename,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    done: usize,
+    bytes: u64,
+    skipped: usize,
+    failed: usize,
+}
+
+pub async fn run(
+    mut rx: Results,
+    root: &Path,
+    dest: &Path,
+    mode: Mode,
+    collision: Collision,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    // Results inside the destination are our own output when it lives under the root
+    let dest_abs = std::path::absolute(dest)?;
+    let mut summary = Summary::default();
+    let mut plan = Plan::default();
+
+    while let Some(item) = rx.recv().await {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+
+        if std::path::absolute(&path).is_ok_and(|p| p.starts_with(&dest_abs)) {
+            continue;
+        }
+
+        // Joined to `dest`, anything but a path below the root could land
+        // outside it
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => {
+                eprintln!(
+                    "Error: {}: not under the search root {}, so it has no place under {}",
+                    path.display(),
+                    root.display(),
+                    dest.display()
+                );
+                summary.failed += 1;
+                continue;
+            }
+        };
+        let target = dest.join(relative);
+
+        if dry_run {
+            match resolve_target(&target, collision).await {
+                Ok(Some(to)) => {
+                    plan.add(mode.op(), format!("{} -> {}", path.display(), to.display()))
+                }
+                Ok(None) => plan.add(
+                    "skip",
+                    format!("{} ({} exists)", path.display(), target.display()),
+                ),
+                Err(e) => eprintln!("Error: {}: {e}", path.display()),
+            }
+            continue;
+        }
+
+        match transfer_one(&path, &target, mode, collision).await {
+            Ok(Some(bytes)) => {
+                summary.done += 1;
+                summary.bytes += bytes;
+            }
+            Ok(None) => summary.skipped += 1,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                summary.failed += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let verb = match mode {
+        Mode::Copy => "Copied",
+        Mode::Move => "Moved",
+    };
+    println!(
+        "{verb} {} file(s) ({}), {} skipped, {} failed",
+        summary.done,
+        human_size(summary.bytes),
+        summary.skipped,
+        summary.failed
+    );
+
+    Ok(if summary.failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+impl Mode {
+    /// Name of the operation in the --dry-run report.
+    fn op(self) -> &'static str {
+        match self {
+            Mode::Copy => "copy",
+            Mode::Move => "move",
+        }
+    }
+}
+
+/// Where a file headed for `target` actually ends up, or `None` if it's skipped.
+async fn resolve_target(target: &Path, collision: Collision) -> std::io::Result<Option<PathBuf>> {
+    Ok(match (fs::try_exists(target).await?, collision) {
+        (false, _) | (true, Collision::Overwrite) => Some(target.to_path_buf()),
+        (true, Collision::Skip) => None,
+        (true, Collision::Rename) => Some(free_name(target).await?),
+    })
+}
+
+/// Copy or move one file; returns the bytes transferred, or `None` if skipped.
+async fn transfer_one(
+    src: &Path,
+    target: &Path,
+    mode: Mode,
+    collision: Collision,
+) -> std::io::Result<Option<u64>> {
+    let Some(target) = resolve_target(target, collision).await? else {
+        return Ok(None);
+    };
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    match mode {
+        Mode::Copy => fs::copy(src, &target).await.map(Some),
+        Mode::Move => {
+            let len = fs::metadata(src).await?.len();
+            match fs::rename(src, &target).await {
+                Ok(()) => {}
+                // rename fails across filesystems; fall back to copy + remove
+                Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                    fs::copy(src, &target).await?;
+                    fs::remove_file(src).await?;
+                }
+                Err(e) => return Err(e),
+            }
+            Ok(Some(len))
+        }
+    }
+}
+
+/// First of "name.1.ext", "name.2.ext", ... that doesn't exist yet.
+async fn free_name(target: &Path) -> std::io::Result<PathBuf> {
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    for n in 1.. {
+        let candidate = target.with_file_name(format!("{stem}.{n}{ext}"));
+        if !fs::try_exists(&candidate).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("ran out of candidate names")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs as stdfs;
+    use tempfile::tempdir;
+
+    async fn send_all(paths: &[PathBuf]) -> Results {
+        let (tx, rx) = crate::crawl::channel(paths.len().max(1));
+        for p in paths {
+            tx.send(vec![Ok(p.clone().into())]).await.unwrap();
+        }
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_copy_preserves_structure_and_renames() -> Result<()> {
+        let src = tempdir()?;
+        let dest = tempdir()?;
+        let nested = src.path().join("a").join("b");
+        stdfs::create_dir_all(&nested)?;
+        let file = nested.join("notes.txt");
+        stdfs::write(&file, "new")?;
+
+        // An existing file at the target forces a rename
+        let existing = dest.path().join("a").join("b").join("notes.txt");
+        stdfs::create_dir_all(existing.parent().unwrap())?;
+        stdfs::write(&existing, "old")?;
+
+        let rx = send_all(std::slice::from_ref(&file)).await;
+        let code = run(
+            rx,
+            src.path(),
+            dest.path(),
+            Mode::Copy,
+            Collision::Rename,
+            false,
+        )
+        .await?;
+
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert!(file.exists(), "copy keeps the source");
+        assert_eq!(stdfs::read_to_string(&existing)?, "old");
+        let renamed = existing.with_file_name("notes.1.txt");
+        assert_eq!(stdfs::read_to_string(renamed)?, "new");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move_skips_and_overwrites() -> Result<()> {
+        let src = tempdir()?;
+        let dest = tempdir()?;
+        let file = src.path().join("data.bin");
+        stdfs::write(&file, "fresh")?;
+        let existing = dest.path().join("data.bin");
+        stdfs::write(&existing, "stale")?;
+
+        let rx = send_all(std::slice::from_ref(&file)).await;
+        run(
+            rx,
+            src.path(),
+            dest.path(),
+            Mode::Move,
+            Collision::Skip,
+            false,
+        )
+        .await?;
+        assert!(file.exists(), "skipped files stay where they are");
+        assert_eq!(stdfs::read_to_string(&existing)?, "stale");
+
+        let rx = send_all(std::slice::from_ref(&file)).await;
+        run(
+            rx,
+            src.path(),
+            dest.path(),
+            Mode::Move,
+            Collision::Overwrite,
+            false,
+        )
+        .await?;
+        assert!(!file.exists());
+        assert_eq!(stdfs::read_to_string(&existing)?, "fresh");
+
+        // A dry run plans the move but leaves the file where it is
+        stdfs::write(&file, "again")?;
+        let rx = send_all(std::slice::from_ref(&file)).await;
+        run(
+            rx,
+            src.path(),
+            dest.path(),
+            Mode::Move,
+            Collision::Rename,
+            true,
+        )
+        .await?;
+        assert!(file.exists());
+        assert!(!dest.path().join("data.1.bin").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_results_outside_the_root_are_refused() -> Result<()> {
+        let src = tempdir()?;
+        let elsewhere = tempdir()?;
+        let dest = tempdir()?;
+        let stray = elsewhere.path().join("stray.txt");
+        stdfs::write(&stray, "x")?;
+
+        let rx = send_all(std::slice::from_ref(&stray)).await;
+        let code = run(
+            rx,
+            src.path(),
+            dest.path(),
+            Mode::Move,
+            Collision::Overwrite,
+            false,
+        )
+        .await?;
+        assert_eq!(code, ExitCode::FAILURE);
+        assert!(stray.exists(), "left where it is");
+        assert_eq!(stdfs::read_dir(dest.path())?.count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Moving search results to the platform trash / recycle bin (`--trash`).
+
+use super::plan::Plan;
+use crate::crawl::Results;
+use anyhow::Result;
+use std::process::ExitCode;
+
+/// Trashes each result as it arrives, then prints a summary. Unlike `--delete`
+/// this is recoverable, so there is no confirmation prompt.
+pub async fn run(mut rx: Results, dry_run: bool) -> Result<ExitCode> {
+    let mut plan = Plan::default();
+    let mut trashed = 0usize;
+    let mut failed = 0usize;
+
+    while let Some(item) = rx.recv().await {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+
+        if dry_run {
+            plan.add("trash", path.display());
+            continue;
+        }
+
+        // The trash crate is synchronous (and on some platforms talks to the desktop shell)
+        let target = path.clone();
+        match tokio::task::spawn_blocking(move || trash::delete(&target)).await? {
+            Ok(()) => {
+                println!("Trashed: {}", path.display());
+                trashed += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("Moved {trashed} file(s) to the trash, {failed} failed");
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,303 @@
+//! Timing the crawl itself (`fs bench`): every combination of the chosen
+//! backends and thread counts walks the same tree a few times, with the page
+//! cache warm and, where it can be dropped, cold, and the medians go in one
+//! table. The whole tree is walked, hidden and gitignored files included, and
+//! nothing is printed per file, so the numbers are the traversal alone and
+//! can be compared from one release to the next.
+
+use crate::crawl::{Backend, CrawlOptions, Threads};
+use crate::dup::OutputFormat;
+use crate::{search_files, SearchConfig};
+use anyhow::{bail, Result};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// The tree to walk
+    #[arg(default_value = ".")]
+    pub root_path: PathBuf,
+
+    /// Backends to compare (comma-separated) [default: all of them]
+    #[arg(long, value_name = "BACKEND", value_delimiter = ',')]
+    pub backends: Vec<Backend>,
+
+    /// Values of --threads to compare (comma-separated)
+    #[arg(
+        long,
+        value_name = "N",
+        value_delimiter = ',',
+        default_value = "1,4,auto"
+    )]
+    pub threads: Vec<Threads>,
+
+    /// Timed walks per combination; the table shows their median and best
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub runs: usize,
+
+    /// Also time walks with the page cache dropped before each one (Linux,
+    /// as root; this empties the cache for the whole system)
+    #[arg(long)]
+    pub cold: bool,
+
+    /// How to print the comparison
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Cache {
+    Warm,
+    Cold,
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cache::Warm => "warm",
+            Cache::Cold => "cold",
+        })
+    }
+}
+
+/// One combination's timings.
+#[derive(Debug)]
+struct Row {
+    backend: Backend,
+    threads: Threads,
+    cache: Cache,
+    /// Sorted, fastest first
+    times: Vec<Duration>,
+    files: usize,
+    errors: usize,
+}
+
+impl Row {
+    fn median(&self) -> Duration {
+        self.times[self.times.len() / 2]
+    }
+
+    fn best(&self) -> Duration {
+        self.times[0]
+    }
+
+    fn files_per_second(&self) -> f64 {
+        self.files as f64 / self.median().as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+pub async fn run(args: &BenchArgs) -> Result<ExitCode> {
+    if args.runs == 0 {
+        bail!("--runs must be at least 1");
+    }
+    if !args.root_path.is_dir() {
+        bail!("{} is not a directory", args.root_path.display());
+    }
+    let backends = if args.backends.is_empty() {
+        Backend::value_variants().to_vec()
+    } else {
+        args.backends.clone()
+    };
+
+    let mut caches = vec![Cache::Warm];
+    if args.cold {
+        match drop_caches() {
+            Ok(()) => caches.push(Cache::Cold),
+            Err(e) => eprintln!("Warning: skipping cold runs, can't drop the page cache: {e}"),
+        }
+    }
+
+    // Fill the cache before the first warm run
+    walk(args, Backend::default(), Threads::Fixed(1)).await;
+    let mut rows = Vec::new();
+    for &cache in &caches {
+        for &backend in &backends {
+            for &threads in &args.threads {
+                rows.push(measure(args, backend, threads, cache).await?);
+            }
+        }
+    }
+
+    if rows.iter().any(|row| row.files != rows[0].files) {
+        eprintln!("Warning: not every combination found the same number of files");
+    }
+    match args.format {
+        OutputFormat::Text => print_table(&rows),
+        OutputFormat::Json => print_json(&rows)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn measure(
+    args: &BenchArgs,
+    backend: Backend,
+    threads: Threads,
+    cache: Cache,
+) -> Result<Row> {
+    let mut times = Vec::with_capacity(args.runs);
+    let (mut files, mut errors) = (0, 0);
+    for _ in 0..args.runs {
+        if cache == Cache::Cold {
+            drop_caches()?;
+        }
+        let took;
+        (took, files, errors) = walk(args, backend, threads).await;
+        times.push(took);
+    }
+    times.sort();
+    Ok(Row {
+        backend,
+        threads,
+        cache,
+        times,
+        files,
+        errors,
+    })
+}
+
+/// Walk the whole tree once, counting what's found. Returns the time it
+/// took, the files and the errors.
+async fn walk(args: &BenchArgs, backend: Backend, threads: Threads) -> (Duration, usize, usize) {
+    let config = SearchConfig {
+        root_path: args.root_path.clone(),
+        pattern: "*".into(),
+        max_depth: None,
+        extensions: None,
+        show_hidden: true,
+        include_gitignored: true,
+        ignore_files: Vec::new(),
+        search_archives: false,
+        crawl: CrawlOptions {
+            backend,
+            threads,
+            ..Default::default()
+        },
+    };
+    let started = Instant::now();
+    let mut rx = search_files(&config).await;
+    let (mut files, mut errors) = (0, 0);
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(_) => files += 1,
+            Err(_) => errors += 1,
+        }
+    }
+    (started.elapsed(), files, errors)
+}
+
+/// Write back dirty pages, then have the kernel forget every clean one.
+#[cfg(target_os = "linux")]
+fn drop_caches() -> io::Result<()> {
+    // SAFETY: sync takes no arguments and can't fail
+    unsafe { libc::sync() };
+    std::fs::write("/proc/sys/vm/drop_caches", "3")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_caches() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only supported on Linux",
+    ))
+}
+
+fn print_table(rows: &[Row]) {
+    println!(
+        "{:<10} {:>7} {:<5} {:>9} {:>9} {:>11} {:>8}",
+        "backend", "threads", "cache", "median", "best", "files/s", "speedup"
+    );
+    for row in rows {
+        // Against the first combination with the same cache
+        let first = rows.iter().find(|r| r.cache == row.cache).unwrap_or(row);
+        let speedup = first.median().as_secs_f64() / row.median().as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:<10} {:>7} {:<5} {:>8.3}s {:>8.3}s {:>11.0} {:>7.2}x",
+            row.backend.to_string(),
+            row.threads.to_string(),
+            row.cache.to_string(),
+            row.median().as_secs_f64(),
+            row.best().as_secs_f64(),
+            row.files_per_second(),
+            speedup
+        );
+    }
+    if let Some(row) = rows.first() {
+        println!();
+        println!(
+            "{} files, {} errors, {} run(s) each",
+            row.files,
+            row.errors,
+            row.times.len()
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRow {
+    backend: String,
+    threads: String,
+    cache: Cache,
+    median_secs: f64,
+    best_secs: f64,
+    files: usize,
+    errors: usize,
+}
+
+fn print_json(rows: &[Row]) -> Result<()> {
+    let rows: Vec<JsonRow> = rows
+        .iter()
+        .map(|row| JsonRow {
+            backend: row.backend.to_string(),
+            threads: row.threads.to_string(),
+            cache: row.cache,
+            median_secs: row.median().as_secs_f64(),
+            best_secs: row.best().as_secs_f64(),
+            files: row.files,
+            errors: row.errors,
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&rows)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_every_combination_is_timed() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::create_dir_all(tmp.path().join("a/.hidden"))?;
+        for file in ["one.txt", "a/two.rs", "a/.hidden/three"] {
+            std::fs::write(tmp.path().join(file), "x")?;
+        }
+        std::fs::write(tmp.path().join(".gitignore"), "*.rs\n")?;
+
+        let args = BenchArgs {
+            root_path: tmp.path().to_path_buf(),
+            backends: vec![Backend::Tokio, Backend::Blocking],
+            threads: vec![Threads::Fixed(1), Threads::Auto],
+            runs: 3,
+            cold: false,
+            format: OutputFormat::Text,
+        };
+        let mut rows = Vec::new();
+        for backend in [Backend::Tokio, Backend::Blocking] {
+            for &threads in &args.threads {
+                rows.push(measure(&args, backend, threads, Cache::Warm).await.unwrap());
+            }
+        }
+        // Hidden and gitignored files count too
+        for row in &rows {
+            assert_eq!((row.files, row.errors, row.times.len()), (4, 0, 3));
+            assert!(row.best() <= row.median());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Colored paths in `fs find`'s output. `--color` decides whether, and
+//! `FS_COLORS` (or else `LS_COLORS`) how, in `ls`'s format: `key=SGR` pairs
+//! separated by colons, such as `di=1;34:*.rs=33:fi=0`. A path's directories
+//! are colored as `di`, and its name as its extension's `*.ext`, or else `fi`.
+
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+/// When to color the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum When {
+    /// When printing to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// The SGR sequence for each kind of path, e.g. `1;34`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    dir: Option<String>,
+    file: Option<String>,
+    /// By extension, in lowercase
+    extensions: HashMap<String, String>,
+}
+
+impl Colors {
+    /// The colors for what's printed to stdout, unless `when` says not to
+    /// color it.
+    pub fn for_stdout(when: When) -> Option<Colors> {
+        let color = match when {
+            When::Always => true,
+            When::Never => false,
+            When::Auto => {
+                io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        };
+        let spec = env::var("FS_COLORS").or_else(|_| env::var("LS_COLORS"));
+        color.then(|| Colors::parse(spec.as_deref().unwrap_or("")))
+    }
+
+    /// The colors `spec` sets, with directories bold blue unless it says
+    /// otherwise. Entries it doesn't understand are left out, as `ls` does.
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors {
+            dir: Some("01;34".into()),
+            file: None,
+            extensions: HashMap::new(),
+        };
+        for (key, sgr) in spec.split(':').filter_map(|entry| entry.split_once('=')) {
+            let sgr = (!sgr.is_empty() && sgr.bytes().all(|b| b.is_ascii_digit() || b == b';'))
+                .then(|| sgr.to_owned());
+            match key {
+                "di" => colors.dir = sgr,
+                "fi" => colors.file = sgr,
+                _ => {
+                    if let (Some(extension), Some(sgr)) = (key.strip_prefix("*."), sgr) {
+                        colors.extensions.insert(extension.to_lowercase(), sgr);
+                    }
+                }
+            }
+        }
+        colors
+    }
+
+    /// `path`, colored.
+    pub fn paint(&self, path: &Path) -> String {
+        let full = path.display().to_string();
+        let name = path
+            .file_name()
+            .map_or(0, |name| name.to_string_lossy().len());
+        let (dirs, name) = full.split_at(full.len().saturating_sub(name));
+        let extension = path.extension().and_then(|extension| {
+            self.extensions
+                .get(&extension.to_string_lossy().to_lowercase())
+        });
+        format!(
+            "{}{}",
+            paint(self.dir.as_deref(), dirs),
+            paint(extension.or(self.file.as_ref()).map(String::as_str), name)
+        )
+    }
+}
+
+/// `text` in the color `sgr`, if there is one.
+fn paint(sgr: Option<&str>, text: &str) -> String {
+    match sgr {
+        Some(sgr) if !text.is_empty() => format!("\x1b[{sgr}m{text}\x1b[0m"),
+        _ => text.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_are_colored_as_the_spec_says() {
+        let colors = Colors::parse("fi=0:*.RS=33:*.md=:bad:ex=01;32:*.txt=x");
+        assert_eq!(
+            colors.paint(Path::new("src/main.rs")),
+            "\x1b[01;34msrc/\x1b[0m\x1b[33mmain.rs\x1b[0m"
+        );
+        // Empty and malformed colors are left out
+        assert_eq!(
+            colors.paint(Path::new("notes.md")),
+            "\x1b[0mnotes.md\x1b[0m"
+        );
+        assert_eq!(
+            colors.paint(Path::new("a/b.txt")),
+            "\x1b[01;34ma/\x1b[0m\x1b[0mb.txt\x1b[0m"
+        );
+        let plain = Colors::parse("di=");
+        assert_eq!(plain.paint(Path::new("a/b")), "a/b");
+    }
+}
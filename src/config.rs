@@ -0,0 +1,515 @@
+//! The command line's defaults, from its config file and `FS_*` environment
+//! variables, so that a container image or a CI runner can set them without a
+//! wrapper script. They come in layers, each overriding the ones before:
+//!
+//! 1. the config file: `$FS_CONFIG_PATH`, or else `fs/config.toml` in the
+//!    user's config directory, if it's there. Settings are named as their
+//!    flags are (`show-hidden = true`, `extensions = ["rs", "toml"]`); those
+//!    at the top apply to every command, and those in a table named for a
+//!    subcommand (`[grep]`) to that one only;
+//! 2. `FS_DEFAULT_OPTS`, flags as they'd be typed (`--show-hidden -e rs`),
+//!    for every command;
+//! 3. `FS_IGNORE_FILES`, paths separated as in `PATH`, each an `--ignore-file`,
+//!    and `FS_THREADS`, for `--threads`;
+//! 4. a profile, a named set of settings in the config file's `[profiles]`
+//!    table, for a search run often: `fs @bigmedia ~/Videos` (or
+//!    `fs du @bigmedia`) runs with `[profiles.bigmedia]`'s settings;
+//! 5. the flags on the command line itself.
+//!
+//! Every layer is turned into flags and put in front of the command line's
+//! own, so that clap checks them as it does those. A flag set in a later layer
+//! replaces what earlier ones set it to, lists included. A setting for a flag
+//! the command being run doesn't have is left out, so that `json = true` is
+//! for `fs find` without breaking `fs grep`; one for a flag no command has is
+//! an error.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Arg, Command};
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// A flag and its values (none for a switch), from one layer or another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Setting {
+    /// Its long name, without the dashes
+    flag: String,
+    values: Vec<String>,
+    /// Where it was set, for errors
+    from: String,
+}
+
+/// The command line `args`, program name first, with the defaults the config
+/// file, environment and profile set for the command being run in front of
+/// its own flags, and the `@name` of the profile taken out. `cmd` is the whole
+/// command line's definition.
+pub fn with_defaults(cmd: &Command, mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let sub = args.get(1).and_then(|arg| arg.to_str());
+    // `fs help grep` is about the flags, not a use of them
+    if sub == Some("help") {
+        return Ok(args);
+    }
+    let sub = sub.and_then(|name| cmd.find_subcommand(name));
+    let target = sub.unwrap_or(cmd);
+    // Without a subcommand, `fs` is `fs find`
+    let table = sub.map_or("find", Command::get_name);
+
+    let at = if sub.is_some() { 2 } else { 1 }.min(args.len());
+    // `fs @name`, with the profile's name where the root or the first flag
+    // would otherwise be
+    let profile = args
+        .get(at)
+        .and_then(|arg| arg.to_str()?.strip_prefix('@'))
+        .map(str::to_owned);
+    if profile.is_some() {
+        args.remove(at);
+    }
+
+    let mut settings = Vec::new();
+    let mut profiles = Vec::new();
+    if let Some(path) = config_path() {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("can't read the config file {}", path.display()))?;
+        let from = path.display().to_string();
+        settings.extend(from_toml(cmd, &text, table, &from)?);
+        if let Some(name) = &profile {
+            profiles = from_profile(&text, name, &from)?;
+        }
+    } else if let Some(name) = &profile {
+        bail!("can't run @{name}: there's no config file to find the profile in");
+    }
+    if let Some(opts) = var("FS_DEFAULT_OPTS") {
+        let words = shell_words::split(&opts).context("can't split FS_DEFAULT_OPTS into flags")?;
+        settings.extend(from_flags(cmd, target, words, "FS_DEFAULT_OPTS")?);
+    }
+    if let Some(paths) = env::var_os("FS_IGNORE_FILES") {
+        for path in env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()) {
+            settings.push(Setting {
+                flag: "ignore-file".into(),
+                values: vec![path.to_string_lossy().into_owned()],
+                from: "FS_IGNORE_FILES".into(),
+            });
+        }
+    }
+    if let Some(threads) = var("FS_THREADS") {
+        settings.push(Setting {
+            flag: "threads".into(),
+            values: vec![threads],
+            from: "FS_THREADS".into(),
+        });
+    }
+
+    settings.extend(profiles);
+    settings.extend(given(target, &args[at..]));
+    let flags = to_flags(cmd, target, last_layer(settings))?;
+    args.splice(at..at, flags.into_iter().map(OsString::from));
+    Ok(args)
+}
+
+/// The flags among `args`, from the command line itself, as settings without
+/// values: they're only there to replace the defaults for the same flags.
+fn given(target: &Command, args: &[OsString]) -> Vec<Setting> {
+    let words = args
+        .iter()
+        .filter_map(|arg| arg.to_str())
+        .take_while(|&word| word != "--");
+    let flag = |word: &str| {
+        if let Some(long) = word.strip_prefix("--") {
+            Some(long.split('=').next().unwrap_or(long).to_owned())
+        } else {
+            let letter = word.strip_prefix('-')?.chars().next()?;
+            let arg = target
+                .get_arguments()
+                .find(|arg| arg.get_short() == Some(letter))?;
+            arg.get_long().map(str::to_owned)
+        }
+    };
+    let given = words.filter_map(flag).map(|flag| Setting {
+        flag,
+        values: Vec::new(),
+        from: COMMAND_LINE.into(),
+    });
+    given.collect()
+}
+
+/// Where the flags [`given`] found came from.
+const COMMAND_LINE: &str = "the command line";
+
+/// Of the `settings` for each flag, those from the last layer that set it,
+/// without the command line's own.
+fn last_layer(settings: Vec<Setting>) -> Vec<Setting> {
+    let mut last = std::collections::HashMap::new();
+    for setting in &settings {
+        last.insert(setting.flag.clone(), setting.from.clone());
+    }
+    settings
+        .into_iter()
+        .filter(|setting| last[&setting.flag] == setting.from && setting.from != COMMAND_LINE)
+        .collect()
+}
+
+/// An environment variable, if it's set to something.
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// The config file to read, if any: `$FS_CONFIG_PATH` (which, set but empty,
+/// means none), or else the user's, if they have one.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("FS_CONFIG_PATH") {
+        return (!path.is_empty()).then(|| path.into());
+    }
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    Some(dir?.join("fs").join("config.toml")).filter(|path| path.is_file())
+}
+
+/// The settings in a config file, `text`: those at the top, then those in
+/// the `table` for the subcommand being run. Profiles are left for
+/// [`from_profile`].
+fn from_toml(cmd: &Command, text: &str, table: &str, from: &str) -> Result<Vec<Setting>> {
+    let document: toml::Table = text
+        .parse()
+        .with_context(|| format!("can't parse {from}"))?;
+    let mut settings = Vec::new();
+    let mut own = None;
+    for (key, value) in document {
+        match value {
+            toml::Value::Table(_) if key == "profiles" => {}
+            toml::Value::Table(values) if cmd.find_subcommand(&key).is_some() => {
+                if key == table {
+                    own = Some(values);
+                }
+            }
+            value => settings.extend(setting(key, value, from)?),
+        }
+    }
+    for (key, value) in own.into_iter().flatten() {
+        settings.extend(setting(key, value, &format!("[{table}] in {from}"))?);
+    }
+    Ok(settings)
+}
+
+/// The setting `key = value`, unless it's a switch turned off.
+fn setting(key: String, value: toml::Value, from: &str) -> Result<Option<Setting>> {
+    let text = |value: toml::Value| match value {
+        toml::Value::String(text) => Ok(text),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Datetime(_) => {
+            Ok(value.to_string())
+        }
+        _ => Err(anyhow!(
+            "{from}: `{key}` should be a string, a number, or a list of them"
+        )),
+    };
+    let values = match value {
+        toml::Value::Boolean(false) => return Ok(None),
+        toml::Value::Boolean(true) => Vec::new(),
+        toml::Value::Array(values) => values.into_iter().map(text).collect::<Result<_>>()?,
+        value => vec![text(value)?],
+    };
+    Ok(Some(Setting {
+        flag: key,
+        values,
+        from: from.into(),
+    }))
+}
+
+/// The settings of the profile `name` in a config file, `text`.
+fn from_profile(text: &str, name: &str, from: &str) -> Result<Vec<Setting>> {
+    let mut document: toml::Table = text
+        .parse()
+        .with_context(|| format!("can't parse {from}"))?;
+    let mut profiles = match document.remove("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        _ => toml::Table::new(),
+    };
+    let values = match profiles.remove(name) {
+        Some(toml::Value::Table(values)) => values,
+        Some(_) => bail!("{from}: profiles.{name} should be a table of settings"),
+        None => {
+            let names: Vec<_> = profiles.keys().map(|name| format!("@{name}")).collect();
+            if names.is_empty() {
+                bail!("{from} has no profiles, so there's no @{name}");
+            }
+            bail!("{from} has no profile @{name}, only {}", names.join(", "));
+        }
+    };
+    let from = format!("@{name} in {from}");
+    let mut settings = Vec::new();
+    for (key, value) in values {
+        settings.extend(setting(key, value, &from)?);
+    }
+    Ok(settings)
+}
+
+/// The settings in `words`, flags as they'd be typed. A short flag means what
+/// it does to `target`, the command being run, or else to the first that has it.
+fn from_flags(
+    cmd: &Command,
+    target: &Command,
+    words: Vec<String>,
+    from: &str,
+) -> Result<Vec<Setting>> {
+    let mut settings = Vec::new();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let (arg, attached) = if let Some(long) = word.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (long, None),
+            };
+            (
+                find_arg(cmd, target, |arg| arg.get_long() == Some(name)),
+                value,
+            )
+        } else if let Some(short) = word.strip_prefix('-').filter(|short| !short.is_empty()) {
+            let mut chars = short.chars();
+            let letter = chars.next();
+            let rest = chars.as_str().trim_start_matches('=');
+            let value = (!rest.is_empty()).then(|| rest.to_owned());
+            (
+                find_arg(cmd, target, |arg| arg.get_short() == letter),
+                value,
+            )
+        } else {
+            bail!("{from}: `{word}` isn't a flag, and only flags can be given there");
+        };
+        let arg = arg.ok_or_else(|| anyhow!("{from}: no command has a flag `{word}`"))?;
+        let flag = arg
+            .get_long()
+            .ok_or_else(|| anyhow!("{from}: `{word}` can only be given on the command line"))?;
+        let values = if arg.get_action().takes_values() {
+            let value = attached.or_else(|| words.next());
+            vec![value.ok_or_else(|| anyhow!("{from}: `{word}` needs a value"))?]
+        } else if attached.is_some() {
+            bail!("{from}: `{word}` doesn't take a value");
+        } else {
+            Vec::new()
+        };
+        settings.push(Setting {
+            flag: flag.into(),
+            values,
+            from: from.into(),
+        });
+    }
+    Ok(settings)
+}
+
+/// The flag `target` or, failing that, any of `cmd`'s subcommands has that
+/// `matches`.
+fn find_arg<'a>(
+    cmd: &'a Command,
+    target: &'a Command,
+    matches: impl Fn(&Arg) -> bool,
+) -> Option<&'a Arg> {
+    std::iter::once(target)
+        .chain(std::iter::once(cmd))
+        .chain(cmd.get_subcommands())
+        .find_map(|command| command.get_arguments().find(|arg| matches(arg)))
+}
+
+/// `settings` as flags for `target`, leaving out those it doesn't have.
+fn to_flags(cmd: &Command, target: &Command, settings: Vec<Setting>) -> Result<Vec<String>> {
+    let mut flags = Vec::new();
+    for setting in settings {
+        let Setting { flag, values, from } = setting;
+        let Some(arg) = target
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(&flag))
+        else {
+            if find_arg(cmd, target, |arg| arg.get_long() == Some(&flag)).is_none() {
+                bail!("{from}: no command has a flag --{flag}");
+            }
+            continue;
+        };
+        match (arg.get_action().takes_values(), values.is_empty()) {
+            (true, true) => bail!("{from}: --{flag} needs a value"),
+            (false, false) => bail!("{from}: --{flag} is a switch, so it's only ever `true`"),
+            (false, true) => flags.push(format!("--{flag}")),
+            (true, false) => match arg.get_value_delimiter() {
+                Some(delimiter) => {
+                    flags.push(format!("--{flag}={}", values.join(&delimiter.to_string())))
+                }
+                None => flags.extend(values.iter().map(|value| format!("--{flag}={value}"))),
+            },
+        }
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ArgAction;
+
+    fn cmd() -> Command {
+        let search = [
+            Arg::new("hidden")
+                .long("show-hidden")
+                .action(ArgAction::SetTrue),
+            Arg::new("extensions")
+                .short('e')
+                .long("extensions")
+                .value_delimiter(','),
+            Arg::new("ignore")
+                .long("ignore-file")
+                .action(ArgAction::Append),
+        ];
+        Command::new("fs")
+            .args(search.clone())
+            .arg(Arg::new("json").long("json").action(ArgAction::SetTrue))
+            .subcommand(Command::new("find").args(search.clone()))
+            .subcommand(
+                Command::new("grep")
+                    .args(search)
+                    .arg(Arg::new("context").short('C').long("context")),
+            )
+    }
+
+    #[test]
+    fn test_config_file_settings_become_flags() -> Result<()> {
+        let cmd = cmd();
+        let text = r#"
+            show-hidden = true
+            extensions = ["rs", "toml"]
+            json = true
+            [grep]
+            context = 2
+            [find]
+            ignore-file = ["a", "b"]
+        "#;
+        let flags = |table| -> Result<_> {
+            let target = cmd.find_subcommand(table).unwrap();
+            to_flags(&cmd, target, from_toml(&cmd, text, table, "config.toml")?)
+        };
+        // In the order of their names: --json is only for find, and each table
+        // only for its subcommand
+        assert_eq!(
+            flags("grep")?,
+            ["--extensions=rs,toml", "--show-hidden", "--context=2"]
+        );
+        assert_eq!(
+            to_flags(&cmd, &cmd, from_toml(&cmd, text, "find", "config.toml")?)?,
+            [
+                "--extensions=rs,toml",
+                "--json",
+                "--show-hidden",
+                "--ignore-file=a",
+                "--ignore-file=b"
+            ]
+        );
+
+        let error = flags_error(&cmd, "show-hiden = true");
+        assert_eq!(error, "config.toml: no command has a flag --show-hiden");
+        let error = flags_error(&cmd, "extensions = true");
+        assert_eq!(error, "config.toml: --extensions needs a value");
+        Ok(())
+    }
+
+    fn flags_error(cmd: &Command, text: &str) -> String {
+        from_toml(cmd, text, "find", "config.toml")
+            .and_then(|settings| to_flags(cmd, cmd, settings))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_later_layers_replace_earlier_ones() -> Result<()> {
+        let cmd = cmd();
+        let grep = cmd.find_subcommand("grep").unwrap();
+        let set = |flag: &str, value: &str, from: &str| Setting {
+            flag: flag.into(),
+            values: vec![value.into()],
+            from: from.into(),
+        };
+        let mut settings = vec![
+            set("extensions", "rs", "config.toml"),
+            set("ignore-file", "a", "config.toml"),
+            set("ignore-file", "b", "FS_IGNORE_FILES"),
+            set("ignore-file", "c", "FS_IGNORE_FILES"),
+            set("context", "2", "config.toml"),
+        ];
+        let args = ["-C", "5", "src"].map(OsString::from);
+        settings.extend(given(grep, &args));
+        assert_eq!(
+            to_flags(&cmd, grep, last_layer(settings))?,
+            ["--extensions=rs", "--ignore-file=b", "--ignore-file=c"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_opts_are_read_as_flags() -> Result<()> {
+        let cmd = cmd();
+        let grep = cmd.find_subcommand("grep").unwrap();
+        let words = shell_words::split("--show-hidden -e rs -C3 --json --ignore-file 'my rules'")?;
+        let settings = from_flags(&cmd, grep, words, "FS_DEFAULT_OPTS")?;
+        assert_eq!(
+            to_flags(&cmd, grep, settings)?,
+            [
+                "--show-hidden",
+                "--extensions=rs",
+                "--context=3",
+                "--ignore-file=my rules"
+            ]
+        );
+
+        for (opts, error) in [
+            (
+                "src",
+                "FS_DEFAULT_OPTS: `src` isn't a flag, and only flags can be given there",
+            ),
+            ("--nope", "FS_DEFAULT_OPTS: no command has a flag `--nope`"),
+            ("-e", "FS_DEFAULT_OPTS: `-e` needs a value"),
+            (
+                "--json=yes",
+                "FS_DEFAULT_OPTS: `--json=yes` doesn't take a value",
+            ),
+        ] {
+            let words = shell_words::split(opts)?;
+            let result = from_flags(&cmd, &cmd, words, "FS_DEFAULT_OPTS");
+            assert_eq!(result.unwrap_err().to_string(), error);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_go_under_the_command_line() -> Result<()> {
+        let cmd = cmd();
+        let text = r#"
+            show-hidden = true
+            [profiles]
+            rust = { extensions = ["rs"], show-hidden = false }
+            [profiles.notes]
+            extensions = ["md", "txt"]
+            ignore-file = ["a"]
+        "#;
+        // The profiles aren't settings for every search
+        let settings = from_toml(&cmd, text, "find", "config.toml")?;
+        assert_eq!(to_flags(&cmd, &cmd, settings)?, ["--show-hidden"]);
+
+        let mut settings = from_profile(text, "notes", "config.toml")?;
+        assert_eq!(settings[0].from, "@notes in config.toml");
+        let args = ["-e", "org", "notes"].map(OsString::from);
+        settings.extend(given(&cmd, &args));
+        assert_eq!(
+            to_flags(&cmd, &cmd, last_layer(settings))?,
+            ["--ignore-file=a"]
+        );
+        let settings = from_profile(text, "rust", "config.toml")?;
+        assert_eq!(to_flags(&cmd, &cmd, settings)?, ["--extensions=rs"]);
+
+        let error = from_profile(text, "nope", "config.toml").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "config.toml has no profile @nope, only @notes, @rust"
+        );
+        Ok(())
+    }
+}
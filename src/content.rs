@@ -0,0 +1,213 @@
+//! Filtering name-search results by what is inside the files (`--contains`,
+//! `--not-contains`).
+//!
+//! The crawl's results pass through a pool of blocking workers that read each
+//! file, so only files whose names already passed the cheaper filters are read.
+
+use crate::crawl::{self, Batch, FileEntry, Results};
+use crate::grep::binary;
+use crate::Error;
+use anyhow::Result;
+use clap::Args;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ContentFilter {
+    /// Only keep files with a line matching this regex (binary files are left out)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    #[serde(deserialize_with = "regex")]
+    pub contains: Option<Regex>,
+
+    /// Only keep files with no line matching this regex, e.g. a missing license
+    /// header (binary files are left out)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    #[serde(deserialize_with = "regex")]
+    pub not_contains: Option<Regex>,
+}
+
+fn parse_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).build()
+}
+
+/// A regex in a config document, compiled as the flag's would be.
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    let pattern = Option::<String>::deserialize(deserializer)?;
+    let regex = pattern.as_deref().map(parse_regex).transpose();
+    regex.map_err(serde::de::Error::custom)
+}
+
+impl ContentFilter {
+    pub fn is_empty(&self) -> bool {
+        self.contains.is_none() && self.not_contains.is_none()
+    }
+
+    /// Whether the file at `path` passes, reading only as far as it takes to tell.
+    fn keeps(&self, path: &Path) -> io::Result<bool> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        if binary::has_binary_extension(path) {
+            return Ok(false);
+        }
+
+        let mut reader = BufReader::with_capacity(64 * 1024, File::open(path)?);
+        if binary::looks_binary(reader.fill_buf()?) {
+            return Ok(false);
+        }
+
+        // Once the file is known to contain what it must, only a line it must
+        // not contain can change the answer
+        let mut found = self.contains.is_none();
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            if self.not_contains.as_ref().is_some_and(|r| r.is_match(text)) {
+                return Ok(false);
+            }
+            found = found || self.contains.as_ref().is_some_and(|r| r.is_match(text));
+            if found && self.not_contains.is_none() {
+                return Ok(true);
+            }
+            line.clear();
+        }
+        Ok(found)
+    }
+}
+
+/// Pass on the results whose contents pass `filter`, checking up to `jobs` files
+/// at once. Results come out in the order their checks finish.
+pub fn filter(mut rx: Results, filter: ContentFilter, jobs: usize) -> Results {
+    let (tx, out) = crawl::channel(100);
+    let filter = Arc::new(filter);
+    let jobs = jobs.max(1);
+
+    tokio::spawn(async move {
+        let mut running = JoinSet::new();
+        while let Some(item) = rx.recv().await {
+            let entry = match item {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if tx.send(vec![Err(e)]).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            while running.len() >= jobs {
+                if let Some(done) = running.join_next().await {
+                    if !forward(&tx, done).await {
+                        return;
+                    }
+                }
+            }
+            let filter = Arc::clone(&filter);
+            running.spawn_blocking(move || {
+                let keep = filter.keeps(entry.path());
+                (entry, keep)
+            });
+        }
+
+        while let Some(done) = running.join_next().await {
+            if !forward(&tx, done).await {
+                return;
+            }
+        }
+    });
+
+    out
+}
+
+/// Send on one finished check's entry if it passed, or its error. Returns
+/// false once nobody is listening any more, or the check was cancelled.
+async fn forward(
+    tx: &mpsc::Sender<Batch>,
+    done: Result<(FileEntry, io::Result<bool>), tokio::task::JoinError>,
+) -> bool {
+    let item = match done {
+        Ok((entry, Ok(false))) => {
+            tracing::trace!(path = %entry.path().display(), "skipped: its contents don't pass");
+            return true;
+        }
+        Ok((entry, Ok(true))) => Ok(entry),
+        Ok((entry, Err(e))) => Err(Error::io(entry.into_path(), e)),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => return false,
+    };
+    tx.send(vec![item]).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_filter_keeps_files_with_matching_lines() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let new = tmp.path().join("new.toml");
+        std::fs::write(&new, "[package]\r\nedition = \"2021\"\r\n")?;
+        let old = tmp.path().join("old.toml");
+        std::fs::write(&old, "[package]\nedition = \"2018\"\n")?;
+        let blob = tmp.path().join("blob.toml");
+        std::fs::write(&blob, b"\0edition = \"2021\"\n")?;
+        let missing = tmp.path().join("missing.toml");
+
+        let (tx, rx) = crate::crawl::channel(4);
+        for path in [&new, &old, &blob, &missing] {
+            tx.send(vec![Ok(path.clone().into())]).await?;
+        }
+        drop(tx);
+
+        let content = ContentFilter {
+            contains: Some(parse_regex(r#"^edition = "2021"$"#)?),
+            not_contains: None,
+        };
+        let mut out = filter(rx, content, 2);
+        let (mut kept, mut errors) = (Vec::new(), 0);
+        while let Some(item) = out.recv().await {
+            match item {
+                Ok(entry) => kept.push(entry.into_path()),
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(kept, vec![new]);
+        assert_eq!(errors, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_contains_alone_and_with_contains() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let licensed = tmp.path().join("licensed.rs");
+        std::fs::write(&licensed, "// SPDX-License-Identifier: MIT\nfn main() {}\n")?;
+        let bare = tmp.path().join("bare.rs");
+        std::fs::write(&bare, "fn main() {}\n")?;
+        let blob = tmp.path().join("blob.rs");
+        std::fs::write(&blob, b"\0fn main() {}\n")?;
+
+        let missing = ContentFilter {
+            contains: None,
+            not_contains: Some(parse_regex("SPDX-License-Identifier")?),
+        };
+        assert!(!missing.keeps(&licensed)?);
+        assert!(missing.keeps(&bare)?);
+        assert!(!missing.keeps(&blob)?);
+
+        let both = ContentFilter {
+            contains: Some(parse_regex(r"fn main")?),
+            ..missing
+        };
+        assert!(!both.keeps(&licensed)?);
+        assert!(both.keeps(&bare)?);
+        Ok(())
+    }
+}
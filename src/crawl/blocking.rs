@@ -0,0 +1,160 @@
+//! The `blocking` backend: a whole directory read with `std::fs` in a single
+//! trip to tokio's blocking pool, rather than one trip per entry and per stat
+//! as `tokio::fs` makes. It reads through [`super::FileSystem`], so it's also
+//! how any other file system is read (see `vfs`).
+
+use super::{classify_with, EntryKind, FileEntry, Filters, Found};
+use crate::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use {
+    super::filter,
+    super::output::Output,
+    super::results::{Batch, BATCH},
+    super::stat::Fields,
+    anyhow::Result,
+};
+
+/// Everything a directory read turned up, in the order it was read.
+#[derive(Default)]
+pub(super) struct Listing {
+    /// Matching files, and entries whose type couldn't be read
+    pub found: Vec<Result<FileEntry, Error>>,
+    pub subdirs: Vec<Arc<Path>>,
+    /// How many entries were listed, whatever became of them
+    pub examined: usize,
+    /// Why the directory couldn't be opened, to report like any other entry
+    pub unopened: Option<Error>,
+    /// Why reading stopped partway through
+    pub failed: Option<Error>,
+}
+
+impl Listing {
+    /// Add `name`, at `path` in `dir` (at `depth` below the root), as
+    /// [`super::classify`] found it.
+    pub fn add(
+        &mut self,
+        found: Found,
+        dir: &Arc<Path>,
+        depth: usize,
+        name: OsString,
+        path: PathBuf,
+    ) {
+        let entry = |kind| Ok(FileEntry::in_dir(Arc::clone(dir), depth + 1, name).with_kind(kind));
+        match found {
+            Found::Skip => {}
+            Found::Subdir => self.subdirs.push(path.into()),
+            Found::Entry(kind) => self.found.push(entry(kind)),
+            Found::Both => {
+                self.subdirs.push(path.into());
+                self.found.push(entry(EntryKind::Dir));
+            }
+        }
+    }
+}
+
+/// Reads one directory at `depth` below the root, start to finish.
+pub(super) type List = fn(&Arc<Path>, usize, &Filters) -> Listing;
+
+/// [`super::Reader::read_dir`] on a blocking thread, with `std::fs` or
+/// whatever other `list` does the reading, reading `fields` of the files found
+/// and applying the custom filters on the same trip.
+#[cfg(feature = "tokio")]
+pub(super) async fn read_dir(
+    dir: &Arc<Path>,
+    depth: usize,
+    filters: &Arc<Filters>,
+    fields: Fields,
+    out: &Output,
+    subdirs: &mut Vec<Arc<Path>>,
+    list: List,
+) -> Result<()> {
+    let listing = {
+        let dir = Arc::clone(dir);
+        let filters = Arc::clone(filters);
+        // The directory's span, on the thread that reads it
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let mut listing = list(&dir, depth, &filters);
+            filters.files.read_ahead(&dir, &mut listing.found, fields);
+            filter::apply(
+                &filters,
+                &dir,
+                depth,
+                &mut listing.found,
+                &mut listing.subdirs,
+            );
+            listing
+        })
+        .await?
+    };
+
+    if let Some(progress) = out.progress() {
+        progress.examined(listing.examined);
+    }
+    if let Some(e) = listing.unopened {
+        let _ = out.send(Err(e)).await;
+        return Ok(());
+    }
+    let mut found = listing.found.into_iter();
+    loop {
+        let batch: Batch = found.by_ref().take(BATCH).collect();
+        if batch.is_empty() {
+            break;
+        }
+        out.send_batch(batch).await?;
+    }
+    subdirs.extend(listing.subdirs);
+    listing.failed.map_or(Ok(()), |e| Err(e.into()))
+}
+
+pub(super) fn list(dir: &Arc<Path>, depth: usize, filters: &Filters) -> Listing {
+    let fs = filters.files.fs();
+    let mut listing = Listing::default();
+    let gitignore = filters.gitignore_in(dir, depth);
+    let broken = gitignore.as_deref().and_then(|rules| rules.error_in(dir));
+    listing.found.extend(broken.map(Err));
+    let entries = match fs.read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            listing.unopened = Some(Error::io(&**dir, e));
+            return listing;
+        }
+    };
+
+    for entry in entries {
+        listing.examined += 1;
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                listing.failed = Some(Error::io(&**dir, e));
+                break;
+            }
+        };
+        let path = dir.join(&entry.name);
+        let kind = match entry.kind {
+            Some(kind) => kind,
+            None => match fs.symlink_metadata(&path) {
+                Ok(meta) => meta.kind,
+                Err(e) => {
+                    listing.found.push(Err(Error::io(path, e)));
+                    continue;
+                }
+            },
+        };
+
+        let found = classify_with(
+            &path,
+            kind,
+            |path| fs.is_hidden(path),
+            depth,
+            gitignore.as_deref(),
+            filters,
+        );
+        listing.add(found, dir, depth, entry.name, path);
+    }
+    listing
+}
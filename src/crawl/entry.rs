@@ -0,0 +1,335 @@
+//! What a search finds: a path, and its metadata if anyone asks for it.
+//!
+//! Files in one directory share its path through an `Arc` rather than each
+//! holding a copy, and keep only their own name; the whole path is put
+//! together the first time someone asks for it. On a scan that holds on to
+//! millions of entries, that's most of the memory.
+//!
+//! The crawl may also have read some of an entry's metadata ahead (see
+//! `stat`); [`FileEntry::is_file`], [`FileEntry::size`] and
+//! [`FileEntry::modified`] use that when they can, and so does serializing
+//! an entry, which writes out all of it.
+
+use super::stat::Stat;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io;
+use std::ops::BitOr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+
+/// A file the search found, or whatever else it was asked to emit (see
+/// [`EntryKinds`]). The crawl tells files from directories without
+/// statting them, so the metadata is only read the first time
+/// [`FileEntry::metadata`] is called, then kept.
+#[derive(Debug)]
+pub struct FileEntry {
+    /// The directory it's in, shared with its siblings, unless it was made
+    /// from a whole path
+    dir: Option<Arc<Path>>,
+    name: Box<OsStr>,
+    /// Directories below the search root, 1 for a file directly in it; 0 if
+    /// it was made from a whole path
+    depth: usize,
+    /// `dir` joined with `name`, on first use
+    path: OnceLock<PathBuf>,
+    /// Boxed, since most entries are never statted and it's most of the size
+    metadata: OnceLock<Box<Metadata>>,
+    /// What the crawl read ahead, if anything
+    stat: Option<Box<Stat>>,
+    /// What it is, if the directory listing said
+    kind: Option<EntryKind>,
+}
+
+/// What an entry is, not following a symlink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// A socket, a device, a FIFO
+    Other,
+}
+
+impl From<std::fs::FileType> for EntryKind {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// Which kinds of entries a search yields, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryKinds(u8);
+
+impl EntryKinds {
+    pub const FILES: Self = EntryKinds(1);
+    pub const DIRS: Self = EntryKinds(1 << 1);
+    pub const SYMLINKS: Self = EntryKinds(1 << 2);
+    pub const OTHER: Self = EntryKinds(1 << 3);
+    pub const ALL: Self = EntryKinds(0b1111);
+
+    pub fn contains(self, kind: EntryKind) -> bool {
+        let bit = match kind {
+            EntryKind::File => EntryKinds::FILES,
+            EntryKind::Dir => EntryKinds::DIRS,
+            EntryKind::Symlink => EntryKinds::SYMLINKS,
+            EntryKind::Other => EntryKinds::OTHER,
+        };
+        self.0 & bit.0 != 0
+    }
+}
+
+/// Everything but directories, as the command line lists.
+impl Default for EntryKinds {
+    fn default() -> Self {
+        EntryKinds::FILES | EntryKinds::SYMLINKS | EntryKinds::OTHER
+    }
+}
+
+impl BitOr for EntryKinds {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        EntryKinds(self.0 | other.0)
+    }
+}
+
+impl FileEntry {
+    pub fn new(path: PathBuf) -> Self {
+        FileEntry {
+            dir: None,
+            name: Box::default(),
+            depth: 0,
+            path: OnceLock::from(path),
+            metadata: OnceLock::new(),
+            stat: None,
+            kind: None,
+        }
+    }
+
+    /// The entry `name` in `dir`, `depth` below the search root.
+    pub(crate) fn in_dir(dir: Arc<Path>, depth: usize, name: OsString) -> Self {
+        FileEntry {
+            dir: Some(dir),
+            name: name.into_boxed_os_str(),
+            depth,
+            path: OnceLock::new(),
+            metadata: OnceLock::new(),
+            stat: None,
+            kind: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path
+            .get_or_init(|| join(self.dir.as_deref(), &self.name))
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        let FileEntry {
+            dir, name, path, ..
+        } = self;
+        path.into_inner()
+            .unwrap_or_else(|| join(dir.as_deref(), &name))
+    }
+
+    /// The entry's own metadata, not following a symlink, read on first use.
+    /// A failed read isn't kept, so asking again tries again.
+    pub fn metadata(&self) -> io::Result<&Metadata> {
+        if let Some(metadata) = self.metadata.get() {
+            return Ok(metadata);
+        }
+        let metadata = std::fs::symlink_metadata(self.path())?;
+        Ok(self.metadata.get_or_init(|| Box::new(metadata)))
+    }
+
+    /// What it is, from the directory listing where the crawl found it, or
+    /// else its metadata.
+    pub fn kind(&self) -> io::Result<EntryKind> {
+        if let Some(kind) = self.kind {
+            return Ok(kind);
+        }
+        if self.stat.as_ref().and_then(|stat| stat.is_file) == Some(true) {
+            return Ok(EntryKind::File);
+        }
+        Ok(self.metadata()?.file_type().into())
+    }
+
+    /// How many directories below the search root it is: 1 for a file
+    /// directly in the root.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Whether it's a regular file, not following a symlink.
+    pub fn is_file(&self) -> io::Result<bool> {
+        match self.stat.as_ref().and_then(|stat| stat.is_file) {
+            Some(is_file) => Ok(is_file),
+            None => Ok(self.metadata()?.is_file()),
+        }
+    }
+
+    /// Its size in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        match self.stat.as_ref().and_then(|stat| stat.size) {
+            Some(size) => Ok(size),
+            None => Ok(self.metadata()?.len()),
+        }
+    }
+
+    /// When it was last modified.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        match self.stat.as_ref().and_then(|stat| stat.modified) {
+            Some(modified) => Ok(modified),
+            None => self.metadata()?.modified(),
+        }
+    }
+
+    /// "file", "dir", "symlink" or "other", reading no more than it must.
+    pub(crate) fn type_name(&self) -> io::Result<&'static str> {
+        Ok(match self.kind()? {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Other => "other",
+        })
+    }
+
+    /// It, known from its listing to be a `kind`.
+    pub(super) fn with_kind(mut self, kind: EntryKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Its name in its directory; empty if it was made from a whole path.
+    pub(super) fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Its size, if it was read ahead; never reads it now.
+    #[cfg(feature = "tokio")]
+    pub(super) fn size_read_ahead(&self) -> Option<u64> {
+        self.stat.as_ref().and_then(|stat| stat.size)
+    }
+
+    pub(super) fn read_ahead(&mut self, stat: Stat) {
+        self.stat = Some(Box::new(stat));
+    }
+}
+
+/// An entry made from a whole path has that path set from the start, so it's
+/// never joined.
+fn join(dir: Option<&Path>, name: &OsStr) -> PathBuf {
+    dir.map_or_else(PathBuf::new, |dir| dir.join(name))
+}
+
+/// Its path, type, depth, size and modification time (in nanoseconds since
+/// the Unix epoch, as manifests have it), each null if it couldn't be read.
+impl Serialize for FileEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("FileEntry", 5)?;
+        entry.serialize_field("path", &self.path().to_string_lossy())?;
+        entry.serialize_field("file_type", &self.type_name().ok())?;
+        entry.serialize_field("depth", &self.depth)?;
+        entry.serialize_field("size", &self.size().ok())?;
+        let mtime_ns = self.modified().ok().map(crate::unix_ns);
+        entry.serialize_field("mtime_ns", &mtime_ns)?;
+        entry.end()
+    }
+}
+
+impl From<PathBuf> for FileEntry {
+    fn from(path: PathBuf) -> Self {
+        FileEntry::new(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_is_read_on_demand() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("later.txt");
+        let entry = FileEntry::new(path.clone());
+        // Nothing is read up front, and a failure isn't remembered
+        assert!(entry.metadata().is_err());
+        std::fs::write(&path, "hello")?;
+        assert_eq!(entry.metadata()?.len(), 5);
+        // Once read, it's kept
+        std::fs::write(&path, "hello, world")?;
+        assert_eq!(entry.metadata()?.len(), 5);
+        assert_eq!(entry.into_path(), path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_siblings_share_their_directory() {
+        let dir: Arc<Path> = Path::new("/data/projects/fs/src").into();
+        let entries: Vec<FileEntry> = ["main.rs", "lib.rs"]
+            .into_iter()
+            .map(|name| FileEntry::in_dir(Arc::clone(&dir), 4, name.into()))
+            .collect();
+        // One copy of the directory's path, however many entries are in it
+        assert_eq!(Arc::strong_count(&dir), 3);
+        assert_eq!(
+            entries[0].path(),
+            Path::new("/data/projects/fs/src/main.rs")
+        );
+        assert_eq!(
+            entries[1].metadata().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(entries[0].depth(), 4);
+        let paths: Vec<PathBuf> = entries.into_iter().map(FileEntry::into_path).collect();
+        assert_eq!(paths[1], Path::new("/data/projects/fs/src/lib.rs"));
+    }
+
+    #[test]
+    fn test_kind_from_the_listing_or_metadata() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir: Arc<Path> = tmp.path().into();
+        std::fs::create_dir(dir.join("sub"))?;
+        let sub = FileEntry::in_dir(Arc::clone(&dir), 1, "sub".into());
+        assert_eq!(sub.kind()?, EntryKind::Dir);
+        // What the listing said is taken at its word, with no stat
+        let listed = FileEntry::in_dir(dir, 1, "gone".into()).with_kind(EntryKind::Symlink);
+        assert_eq!(listed.kind()?, EntryKind::Symlink);
+
+        let kinds = EntryKinds::FILES | EntryKinds::DIRS;
+        assert!(kinds.contains(EntryKind::Dir) && !kinds.contains(EntryKind::Symlink));
+        assert!(!EntryKinds::default().contains(EntryKind::Dir));
+        assert!(EntryKinds::ALL.contains(EntryKind::Other));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serializes_what_it_can_read() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir: Arc<Path> = tmp.path().into();
+        std::fs::write(dir.join("a.txt"), "hello")?;
+        let entry = FileEntry::in_dir(Arc::clone(&dir), 1, "a.txt".into());
+        let json = serde_json::to_value(&entry)?;
+        assert_eq!(json["path"], dir.join("a.txt").to_string_lossy().as_ref());
+        assert_eq!(json["file_type"], "file");
+        assert_eq!(json["depth"], 1);
+        assert_eq!(json["size"], 5);
+        assert!(json["mtime_ns"].as_i64().unwrap() > 0);
+
+        let gone = FileEntry::in_dir(dir, 1, "gone.txt".into());
+        let json = serde_json::to_value(&gone)?;
+        assert!(json["file_type"].is_null() && json["size"].is_null());
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! Filters of the embedding application's own, for what globs and
+//! .gitignore can't say: a lookup in its database, a business rule.
+
+use super::rank;
+use super::results::Batch;
+use super::{FileEntry, Filters};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A predicate the walk applies on top of its own filters. It's called on the
+/// crawl's blocking threads, so it may block; with `--threads`, on several at
+/// once.
+pub trait Filter: Send + Sync {
+    /// Whether `entry`, a file (or whatever else the search emits) every
+    /// other filter let through, is a result.
+    fn matches(&self, entry: &FileEntry) -> bool;
+
+    /// Whether to read `dir`, a directory the walk would otherwise enter.
+    /// Turning one down skips everything under it.
+    fn should_descend(&self, dir: &FileEntry) -> bool {
+        let _ = dir;
+        true
+    }
+}
+
+/// A closure filters files and enters every directory.
+impl<F: Fn(&FileEntry) -> bool + Send + Sync> Filter for F {
+    fn matches(&self, entry: &FileEntry) -> bool {
+        self(entry)
+    }
+}
+
+impl Filters {
+    pub(super) fn keeps(&self, entry: &FileEntry) -> bool {
+        self.custom.iter().all(|filter| filter.matches(entry))
+    }
+
+    pub(super) fn enters(&self, dir: &FileEntry) -> bool {
+        self.custom.iter().all(|filter| filter.should_descend(dir))
+    }
+}
+
+/// Drop what the custom filters turn down from what was found in `dir`, at
+/// `depth` below the root, and put the rest in the custom rank's order.
+pub(super) fn apply(
+    filters: &Filters,
+    dir: &Arc<Path>,
+    depth: usize,
+    found: &mut Batch,
+    subdirs: &mut Vec<Arc<Path>>,
+) {
+    if let Some(rank) = &filters.rank {
+        rank::sort(&**rank, found);
+    }
+    if filters.custom.is_empty() {
+        return;
+    }
+    let _span = tracing::trace_span!("custom_filters").entered();
+    found.retain(|item| {
+        let Ok(entry) = item else {
+            return true;
+        };
+        let keeps = filters.keeps(entry);
+        if !keeps {
+            tracing::trace!(path = %entry.path().display(), "{}", super::Why::Custom);
+        }
+        keeps
+    });
+    subdirs.retain(|sub| {
+        let name = sub.file_name().unwrap_or_default().to_os_string();
+        let enters = filters.enters(&FileEntry::in_dir(Arc::clone(dir), depth + 1, name));
+        if !enters {
+            tracing::trace!(path = %sub.display(), "{}", super::Why::Custom);
+        }
+        enters
+    });
+}
@@ -0,0 +1,335 @@
+//! .gitignore files at every level of the tree, each applying to its own
+//! directory and everything below it, as git reads them.
+//!
+//! The rules in force in a directory are a chain: its own compiled .gitignore,
+//! if it has one, then its parent's rules. A directory without one just shares
+//! its parent's chain through the `Arc`, so however deep the tree, each file is
+//! parsed once. For the real file system, the chains are cached by directory
+//! for the life of the process, so searching the same tree again only parses
+//! the .gitignore files that changed since, by size and modification time.
+//! Another file system's are only kept for one search (see `vfs`).
+//!
+//! A .gitignore that doesn't parse is left out entirely, as before, but it's
+//! now reported (as [`Error::IgnoreParse`]) with the directory it's in.
+//!
+//! Ignore files named with `--ignore-file` come under all of those: they only
+//! decide what no .gitignore in the tree mentions.
+
+use super::vfs::{FileSystem, Files};
+use crate::Error;
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::Match;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Size and modification time of a .gitignore, to tell a cached parse is stale.
+type Stamp = (u64, Option<SystemTime>);
+
+/// The rules in force in every directory read so far.
+#[derive(Debug, Default)]
+pub(super) struct Cache(Mutex<HashMap<PathBuf, Arc<Rules>>>);
+
+/// The .gitignore rules in force in one directory.
+#[derive(Debug)]
+pub(super) struct Rules {
+    /// The .gitignore that adds to the parent's rules here, if any
+    own: Option<Gitignore>,
+    /// Where the .gitignore here is and why it couldn't be parsed, if it
+    /// couldn't
+    broken: Option<(PathBuf, String)>,
+    stamp: Option<Stamp>,
+    parent: Option<Arc<Rules>>,
+}
+
+impl Rules {
+    /// Whether an entry of this directory is ignored. The nearest .gitignore
+    /// that mentions it decides, so a deeper `!pattern` lets back in what a
+    /// shallower file ignored.
+    #[cfg(test)]
+    pub fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        self.decide(path, is_dir) == Some(true)
+    }
+
+    /// Whether an entry of this directory is ignored, if any .gitignore in
+    /// force mentions it at all.
+    pub fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        self.rule(path, is_dir).map(|glob| !glob.is_whitelist())
+    }
+
+    /// The rule that decides whether an entry of this directory is ignored,
+    /// if there is one.
+    pub fn rule(&self, path: &Path, is_dir: bool) -> Option<&Glob> {
+        let mut rules = Some(self);
+        while let Some(level) = rules {
+            if let Some(glob) = level.own.as_ref().and_then(|own| rule(own, path, is_dir)) {
+                return Some(glob);
+            }
+            rules = level.parent.as_deref();
+        }
+        None
+    }
+
+    /// Why the .gitignore directly in `dir` couldn't be parsed, if these are
+    /// `dir`'s rules and it couldn't.
+    pub fn error_in(&self, dir: &Path) -> Option<Error> {
+        let (file, message) = self.broken.as_ref()?;
+        (file.parent() == Some(dir)).then(|| Error::IgnoreParse {
+            path: file.clone(),
+            message: message.clone(),
+        })
+    }
+}
+
+/// The rule in `gitignore` that mentions `path`, if one does.
+fn rule<'a>(gitignore: &'a Gitignore, path: &Path, is_dir: bool) -> Option<&'a Glob> {
+    match gitignore.matched(path, is_dir) {
+        Match::Ignore(glob) | Match::Whitelist(glob) => Some(glob),
+        Match::None => None,
+    }
+}
+
+/// A rule that decided whether a path is ignored, for `fs explain`.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Rule {
+    /// The ignore file it's in
+    pub file: Option<PathBuf>,
+    /// As written there
+    pub pattern: String,
+    /// Whether it's a `!pattern`, letting back in what another ignored
+    pub whitelist: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl From<&Glob> for Rule {
+    fn from(glob: &Glob) -> Self {
+        Rule {
+            file: glob.from().map(Path::to_path_buf),
+            pattern: glob.original().to_string(),
+            whitelist: glob.is_whitelist(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Rule {
+    /// The line of its file it's on, counting from 1, if the file can still
+    /// be read. Of two lines the same, the last decides, as git has it.
+    pub fn line(&self) -> Option<usize> {
+        let text = std::fs::read_to_string(self.file.as_ref()?).ok()?;
+        let is_it = |line: &str| line == self.pattern || line.trim_end() == self.pattern;
+        let lines = text.lines().enumerate();
+        lines
+            .filter(|(_, line)| is_it(line))
+            .last()
+            .map(|(i, _)| i + 1)
+    }
+}
+
+/// The ignore files a search was given besides the tree's .gitignore files,
+/// in .gitignore's format, with patterns relative to the search root. Of two
+/// that mention a path, the one given last decides.
+#[derive(Debug, Default)]
+pub struct IgnoreFiles {
+    files: Vec<Gitignore>,
+    /// The first that couldn't be read or parsed, and why
+    broken: Option<(PathBuf, String)>,
+}
+
+impl IgnoreFiles {
+    /// Read `paths` from `files`, for a search under `root`.
+    pub(crate) fn read(files: &Files, root: &Path, paths: &[PathBuf]) -> Self {
+        let mut ignore = IgnoreFiles::default();
+        for path in paths {
+            match parse(files.fs(), root, path) {
+                Ok(gitignore) => ignore.files.push(gitignore),
+                Err(e) => {
+                    ignore.broken.get_or_insert_with(|| (path.clone(), e));
+                }
+            }
+        }
+        ignore
+    }
+
+    /// Whether they ignore `path`.
+    pub(super) fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        self.rule(path, is_dir)
+            .is_some_and(|glob| !glob.is_whitelist())
+    }
+
+    /// The rule among them that decides whether `path` is ignored, if any.
+    pub(super) fn rule(&self, path: &Path, is_dir: bool) -> Option<&Glob> {
+        let mut decisions = self.files.iter().rev();
+        decisions.find_map(|gitignore| rule(gitignore, path, is_dir))
+    }
+
+    /// Why one of them couldn't be used, if one couldn't.
+    pub(super) fn error(&self) -> Option<Error> {
+        let (path, message) = self.broken.clone()?;
+        Some(Error::IgnoreParse { path, message })
+    }
+}
+
+/// The rules in force in `dir`, at `depth` below the search root. Above the
+/// root, nothing applies; below it, the parent must already have been looked
+/// up, which reading the tree from the top guarantees.
+pub(super) fn rules_for(files: &Files, dir: &Path, depth: usize) -> Arc<Rules> {
+    let cache = &files.rules().0;
+    let parent = match (depth, dir.parent()) {
+        (0, _) | (_, None) => None,
+        (_, Some(parent)) => cache.lock().unwrap().get(parent).cloned(),
+    };
+    let file = dir.join(".gitignore");
+    let stamp = files
+        .fs()
+        .metadata(&file)
+        .ok()
+        .map(|meta| (meta.len, meta.modified));
+
+    if let Some(cached) = cache.lock().unwrap().get(dir) {
+        if is_current(cached, stamp, parent.as_ref()) {
+            return Arc::clone(cached);
+        }
+    }
+
+    let rules = match (stamp, parent) {
+        // Nothing new here: the parent's rules are ours
+        (None, Some(parent)) => parent,
+        (stamp, parent) => {
+            let parsed = stamp.map(|_| {
+                let _span = tracing::debug_span!("gitignore", file = %file.display()).entered();
+                let parsed = parse(files.fs(), dir, &file);
+                match &parsed {
+                    Ok(gitignore) => tracing::debug!(rules = gitignore.len(), "parsed"),
+                    Err(e) => tracing::warn!("can't be parsed: {e}"),
+                }
+                parsed
+            });
+            let (own, broken) = match parsed {
+                Some(Ok(gitignore)) => (Some(gitignore), None),
+                Some(Err(e)) => (None, Some((file, e))),
+                None => (None, None),
+            };
+            Arc::new(Rules {
+                own,
+                broken,
+                stamp,
+                parent,
+            })
+        }
+    };
+    cache
+        .lock()
+        .unwrap()
+        .insert(dir.to_path_buf(), Arc::clone(&rules));
+    rules
+}
+
+/// Whether `cached` are still the rules for a directory whose .gitignore now
+/// has `stamp` and whose parent's rules are now `parent`.
+fn is_current(cached: &Arc<Rules>, stamp: Option<Stamp>, parent: Option<&Arc<Rules>>) -> bool {
+    match (stamp, parent) {
+        (None, Some(parent)) => Arc::ptr_eq(cached, parent),
+        (stamp, parent) => {
+            cached.stamp == stamp
+                && match (&cached.parent, parent) {
+                    (Some(cached), Some(parent)) => Arc::ptr_eq(cached, parent),
+                    (None, None) => true,
+                    _ => false,
+                }
+        }
+    }
+}
+
+/// The rules in `file`, or what's wrong with them, line by line as git
+/// would read them.
+fn parse(fs: &dyn FileSystem, dir: &Path, file: &Path) -> Result<Gitignore, String> {
+    let reader = BufReader::new(fs.open(file).map_err(|e| e.to_string())?);
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut errors = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                errors.push(format!("line {}: {e}", i + 1));
+                break;
+            }
+        };
+        if let Err(e) = builder.add_line(Some(file.to_path_buf()), &line) {
+            errors.push(format!("line {}: {e}", i + 1));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::vfs::MemFs;
+    use super::*;
+
+    #[test]
+    fn test_nested_rules_are_shared_and_cached() {
+        let tree = Arc::new(
+            MemFs::new()
+                .with_file("/r/.gitignore", "*.log\n")
+                .with_file("/r/app/.gitignore", "*.tmp\n!keep.log\n"),
+        );
+        tree.create_dir_all("/r/app/src/deep");
+        let files = Files::new(tree.clone());
+        let root = Path::new("/r");
+
+        let top = rules_for(&files, root, 0);
+        let app = rules_for(&files, &root.join("app"), 1);
+        let src = rules_for(&files, &root.join("app/src"), 2);
+        let deep = rules_for(&files, &root.join("app/src/deep"), 3);
+        assert!(top.ignores(&root.join("a.log"), false));
+        assert!(!top.ignores(&root.join("a.tmp"), false));
+        // Below app/, both files apply, and app's can let back in
+        let inside = root.join("app/src/deep");
+        assert!(deep.ignores(&inside.join("a.log"), false));
+        assert!(deep.ignores(&inside.join("a.tmp"), false));
+        assert!(!deep.ignores(&inside.join("keep.log"), false));
+
+        // Directories without a .gitignore share their parent's rules, and a
+        // second search gets the same ones back
+        assert!(Arc::ptr_eq(&app, &src) && Arc::ptr_eq(&src, &deep));
+        assert!(Arc::ptr_eq(&rules_for(&files, root, 0), &top));
+        assert!(Arc::ptr_eq(&rules_for(&files, &root.join("app"), 1), &app));
+
+        // Rooted lower down, nothing above the root applies
+        let alone = rules_for(&files, &root.join("app"), 0);
+        assert!(!alone.ignores(&root.join("app/a.log"), false));
+
+        // A changed .gitignore is read again, and so is everything below it
+        tree.write("/r/.gitignore", "*.txt\n*.csv\n");
+        let top = rules_for(&files, root, 0);
+        let app = rules_for(&files, &root.join("app"), 1);
+        assert!(top.ignores(&root.join("a.txt"), false));
+        assert!(!app.ignores(&root.join("app/a.log"), false));
+    }
+
+    #[test]
+    fn test_unparsable_rules_are_reported_where_they_are() {
+        let tree = MemFs::new().with_file("/r/.gitignore", "*.log\n[unclosed\n");
+        tree.create_dir_all("/r/sub");
+        let files = Files::new(Arc::new(tree));
+        let root = Path::new("/r");
+
+        let top = rules_for(&files, root, 0);
+        let sub = rules_for(&files, &root.join("sub"), 1);
+        // None of the file applies, and only its own directory reports it
+        assert!(!top.ignores(&root.join("a.log"), false));
+        assert!(matches!(
+            top.error_in(root),
+            Some(Error::IgnoreParse { path, message })
+                if path == root.join(".gitignore") && message.starts_with("line 2: ")
+        ));
+        assert!(sub.error_in(&root.join("sub")).is_none());
+    }
+}
@@ -0,0 +1,312 @@
+//! `--mft`: listing a search from the NTFS master file table, the way
+//! Everything does, instead of reading directories one by one.
+//!
+//! FSCTL_ENUM_USN_DATA hands over every file record on the volume (its
+//! reference number, its parent's, its name and attributes) in big batches.
+//! Paths are rebuilt by following the parents up to the search root, and the
+//! usual filters then decide what's shown and which directories count, so
+//! the results match a normal walk, in table order. Only the listing comes
+//! from the table; anything downstream that reads files or their metadata
+//! does so live. A file with several hard links is listed under one name.
+
+use super::output::Output;
+use super::results::BATCH;
+use super::{classify_with, EntryKind, FileEntry, Filters, Found, Rules};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::windows::ffi::OsStringExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::sync::Arc;
+use windows_sys::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_HANDLE_EOF, ERROR_INVALID_FUNCTION, HANDLE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_ATTRIBUTE_DIRECTORY,
+    FILE_ATTRIBUTE_HIDDEN, FILE_FLAG_BACKUP_SEMANTICS, FILE_SHARE_READ, FILE_SHARE_WRITE,
+};
+use windows_sys::Win32::System::Ioctl::{FSCTL_ENUM_USN_DATA, MFT_ENUM_DATA_V0, USN_RECORD_V2};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+
+/// Bytes of records asked for per call.
+const BATCH: usize = 1 << 20;
+
+/// Deeper than any real tree; a longer parent chain is a loop.
+const MAX_CHAIN: usize = 4096;
+
+/// The root's volume has no table to read: not NTFS, or not a local drive.
+/// The search can still walk it.
+#[derive(Debug)]
+pub(super) struct Unsupported(String);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "--mft can't be used here: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// One file record: its parent directory, name and attributes.
+struct Record {
+    parent: u64,
+    name: OsString,
+    attributes: u32,
+}
+
+impl Record {
+    fn is_dir(&self) -> bool {
+        self.attributes & FILE_ATTRIBUTE_DIRECTORY != 0
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.attributes & FILE_ATTRIBUTE_HIDDEN != 0 || self.name.to_string_lossy().starts_with('.')
+    }
+}
+
+/// Send every matching file under `root` to `out`, straight from the table.
+pub(super) fn scan(root: &Path, filters: &Filters, out: &Output) -> Result<()> {
+    let volume = volume_of(root)?;
+    let root_id = file_id(root).with_context(|| format!("reading {}", root.display()))?;
+    let records = read_table(&volume)?;
+
+    let mut dirs = Dirs {
+        records: &records,
+        filters,
+        found: HashMap::from([(root_id, Some(Place::new(root.to_path_buf(), 0, filters)))]),
+    };
+    let mut batch = Vec::with_capacity(BATCH);
+    for record in records.values().filter(|r| !r.is_dir()) {
+        let Some(dir) = dirs.resolve(record.parent) else {
+            continue;
+        };
+        let path = dir.path.join(&record.name);
+        let hidden = record.is_hidden();
+        let gitignore = dir.gitignore.as_deref();
+        // Reparse points aren't told apart here, so everything's a file
+        let kind = EntryKind::File;
+        if let Found::Entry(_) =
+            classify_with(&path, kind, |_| hidden, dir.depth, gitignore, filters)
+        {
+            let name = record.name.clone();
+            let entry = FileEntry::in_dir(Arc::clone(&dir.path), dir.depth + 1, name);
+            if !filters.keeps(&entry) {
+                continue;
+            }
+            batch.push(Ok(entry));
+            if batch.len() == BATCH && out.blocking_send_batch(std::mem::take(&mut batch)).is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+    let _ = out.blocking_send_batch(batch);
+    Ok(())
+}
+
+/// `\\.\C:` for a root on drive C.
+fn volume_of(root: &Path) -> Result<String> {
+    let canonical = root
+        .canonicalize()
+        .with_context(|| format!("reading {}", root.display()))?;
+    match canonical.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(letter) | Prefix::Disk(letter) => {
+                Ok(format!(r"\\.\{}:", letter as char))
+            }
+            _ => Err(Unsupported(format!("{} isn't on a local drive", root.display())).into()),
+        },
+        _ => Err(Unsupported(format!("{} has no drive letter", root.display())).into()),
+    }
+}
+
+/// The file reference number of `path`, which records name their parents by.
+fn file_id(path: &Path) -> io::Result<u64> {
+    // Backup semantics let a directory be opened like a file
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+    // SAFETY: BY_HANDLE_FILE_INFORMATION is plain old data, which the call fills in
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    // SAFETY: the handle is open for as long as `file` lives
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as HANDLE, &mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(u64::from(info.nFileIndexHigh) << 32 | u64::from(info.nFileIndexLow))
+}
+
+/// Every file record on `volume`, by reference number.
+fn read_table(volume: &str) -> Result<HashMap<u64, Record>> {
+    let handle = match OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .open(volume)
+    {
+        Ok(handle) => handle,
+        Err(e) if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) => {
+            bail!("--mft reads {volume} directly, which takes an administrator prompt")
+        }
+        Err(e) => return Err(e).with_context(|| format!("opening {volume}")),
+    };
+
+    let mut records = HashMap::new();
+    let mut query = MFT_ENUM_DATA_V0 {
+        StartFileReferenceNumber: 0,
+        LowUsn: 0,
+        HighUsn: i64::MAX,
+    };
+    // u64s, for the alignment of the records in it
+    let mut buf = vec![0u64; BATCH / 8];
+    loop {
+        let mut returned = 0u32;
+        // SAFETY: query and buf are valid for the sizes passed, and the call
+        // is synchronous
+        let ok = unsafe {
+            DeviceIoControl(
+                handle.as_raw_handle() as HANDLE,
+                FSCTL_ENUM_USN_DATA,
+                std::ptr::addr_of!(query).cast(),
+                std::mem::size_of::<MFT_ENUM_DATA_V0>() as u32,
+                buf.as_mut_ptr().cast(),
+                BATCH as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_HANDLE_EOF) => return Ok(records),
+                Some(ERROR_INVALID_FUNCTION) => {
+                    return Err(Unsupported(format!("{volume} isn't an NTFS volume")).into())
+                }
+                _ => return Err(e).with_context(|| format!("reading the file table of {volume}")),
+            }
+        }
+
+        // SAFETY: buf holds `returned` initialized bytes
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), returned as usize) };
+        // The reply starts with where the next call should pick up
+        query.StartFileReferenceNumber = u64::from_le_bytes(bytes[..8].try_into()?);
+        parse_records(&bytes[8..], &mut records);
+    }
+}
+
+/// Add the USN_RECORD_V2s in `bytes` to `records`.
+fn parse_records(mut bytes: &[u8], records: &mut HashMap<u64, Record>) {
+    let header = std::mem::size_of::<USN_RECORD_V2>();
+    while bytes.len() >= header {
+        // SAFETY: at least a header's worth of bytes is left, and it's read
+        // unaligned
+        let record: USN_RECORD_V2 = unsafe { std::ptr::read_unaligned(bytes.as_ptr().cast()) };
+        let length = record.RecordLength as usize;
+        if length < header || length > bytes.len() {
+            break;
+        }
+        let start = usize::from(record.FileNameOffset);
+        let end = start + usize::from(record.FileNameLength);
+        if record.MajorVersion == 2 && end <= length {
+            let name: Vec<u16> = bytes[start..end]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            records.insert(
+                record.FileReferenceNumber,
+                Record {
+                    parent: record.ParentFileReferenceNumber,
+                    name: OsString::from_wide(&name),
+                    attributes: record.FileAttributes,
+                },
+            );
+        }
+        bytes = &bytes[length..];
+    }
+}
+
+/// A directory the search enters.
+struct Place {
+    /// Shared with the files found in it
+    path: Arc<Path>,
+    /// Below the root
+    depth: usize,
+    gitignore: Option<Arc<Rules>>,
+}
+
+impl Place {
+    fn new(path: PathBuf, depth: usize, filters: &Filters) -> Self {
+        let gitignore = filters.gitignore_in(&path, depth);
+        Place {
+            path: path.into(),
+            depth,
+            gitignore,
+        }
+    }
+}
+
+/// Where directories are, worked out once each.
+struct Dirs<'a> {
+    records: &'a HashMap<u64, Record>,
+    filters: &'a Filters,
+    /// None for a directory outside the root or one the search doesn't enter
+    found: HashMap<u64, Option<Place>>,
+}
+
+impl Dirs<'_> {
+    fn resolve(&mut self, id: u64) -> Option<&Place> {
+        // Climb to the nearest directory already worked out...
+        let mut chain = Vec::new();
+        let mut at = id;
+        while !self.found.contains_key(&at) {
+            match self.records.get(&at) {
+                Some(record) if record.parent != at && chain.len() < MAX_CHAIN => {
+                    chain.push(at);
+                    at = record.parent;
+                }
+                // The top of the volume, without passing the root
+                _ => {
+                    self.found.insert(at, None);
+                }
+            }
+        }
+        // ...then come back down, deciding on each directory in turn
+        for dir in chain.into_iter().rev() {
+            let record = &self.records[&dir];
+            let place = match &self.found[&record.parent] {
+                Some(parent) => {
+                    let path = parent.path.join(&record.name);
+                    let hidden = record.is_hidden();
+                    let gitignore = parent.gitignore.as_deref();
+                    // Directories are entered, but only ever files reported
+                    match classify_with(
+                        &path,
+                        EntryKind::Dir,
+                        |_| hidden,
+                        parent.depth,
+                        gitignore,
+                        self.filters,
+                    ) {
+                        Found::Subdir | Found::Both => {
+                            let depth = parent.depth + 1;
+                            let name = record.name.clone();
+                            let entry = FileEntry::in_dir(Arc::clone(&parent.path), depth, name);
+                            self.filters
+                                .enters(&entry)
+                                .then(|| Place::new(path, depth, self.filters))
+                        }
+                        _ => None,
+                    }
+                }
+                None => None,
+            };
+            self.found.insert(dir, place);
+        }
+        self.found[&id].as_ref()
+    }
+}
@@ -0,0 +1,582 @@
+//! Walking the tree for a search: breadth-first on one task by default (or
+//! depth-first with `--order dfs`, so each directory's results come together
+//! with everything below it), or with `--threads N`, N tasks reading
+//! directories at once, or with `--threads auto`, as many as the storage keeps
+//! up with (see `stats`). Each worker keeps the subdirectories it finds in its
+//! own queue and steals from the others when that runs dry, so one deep branch
+//! doesn't leave the rest idle.
+//!
+//! Breadth-first keeps every directory of the next level waiting, which on a
+//! filer with millions of them is a lot of memory. `--max-frontier` caps that:
+//! past the cap, each walker goes depth-first through what it just found, so
+//! the frontier only grows by the subdirectories along one branch.
+//!
+//! How a directory is read is up to the `--backend`: through `tokio::fs`, one
+//! blocking-pool hop per entry, or all at once on a blocking thread, with
+//! `std::fs` or (on Linux, with the `io-uring` feature) io_uring.
+
+mod blocking;
+mod entry;
+mod filter;
+mod gitignore;
+#[cfg(all(windows, feature = "tokio"))]
+mod mft;
+#[cfg(feature = "tokio")]
+mod output;
+mod parallel;
+#[cfg(feature = "tokio")]
+mod priority;
+mod progress;
+mod rank;
+mod results;
+mod stat;
+#[cfg(feature = "tokio")]
+mod stats;
+mod throttle;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod vfs;
+mod visit;
+#[cfg(feature = "tokio")]
+mod walk;
+#[cfg(windows)]
+mod win32;
+
+use crate::file_matches;
+use crate::Error;
+use clap::{Args, ValueEnum};
+#[cfg(feature = "tokio")]
+pub(crate) use gitignore::Rule;
+use gitignore::Rules;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+pub use entry::{EntryKind, EntryKinds, FileEntry};
+pub use filter::Filter;
+pub use gitignore::IgnoreFiles;
+pub(crate) use parallel::visit_parallel;
+pub use progress::{ProgressEvent, Totals};
+pub use rank::{ByKey, Rank};
+pub use results::{channel, Batch, Results};
+pub use stat::Fields;
+pub use throttle::Rate;
+pub use vfs::{DirEntry, FileSystem, Files, MemFs, Metadata, ReadDir, StdFs};
+pub(crate) use visit::visit;
+pub use visit::Descend;
+#[cfg(all(feature = "blocking", not(feature = "tokio")))]
+pub(crate) use visit::Entries;
+#[cfg(feature = "tokio")]
+pub use walk::crawl;
+
+/// In a config document (see [`crate::Search::from_config`]), each option is
+/// named as its flag is, without the dashes in front, and what isn't given is
+/// as the flag's default.
+#[derive(Args, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CrawlOptions {
+    /// Number of directories to read at once, or "auto" to adjust it to how
+    /// fast the storage answers. With more than one, results come in whatever
+    /// order the directories are read, not breadth-first
+    #[arg(long, value_name = "N", default_value_t = Threads::Fixed(1))]
+    pub threads: Threads,
+
+    /// How directories are read
+    #[arg(long, value_name = "BACKEND", default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    /// Keep at most about N directories waiting to be read, going depth-first
+    /// past that instead of breadth-first, to bound memory on huge trees
+    #[arg(long, value_name = "N")]
+    pub max_frontier: Option<usize>,
+
+    /// Visit the tree level by level, or each directory's whole subtree before
+    /// its next sibling
+    #[arg(long, value_name = "ORDER", default_value = "bfs")]
+    pub order: Order,
+
+    /// Windows, as administrator: list files from the NTFS master file table
+    /// instead of reading directories, for near-instant scans of a whole
+    /// volume. Results come in table order
+    #[arg(long)]
+    pub mft: bool,
+
+    /// When the search is done, print how many directories were read, how
+    /// fast, and what --threads auto settled on, to stderr
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Number of batches of results (a directory's worth, or at most 256, each)
+    /// that can wait for the consumer before --backpressure kicks in
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    #[serde(deserialize_with = "at_least_one")]
+    pub channel_capacity: usize,
+
+    /// What the crawl does when that many are waiting
+    #[arg(long, value_name = "POLICY", default_value = "block")]
+    pub backpressure: Backpressure,
+
+    /// Read at most this many directories a second ("200/s"), or this many
+    /// bytes of directory listings ("10MB/s"), however many --threads share it
+    #[arg(long, value_name = "RATE")]
+    pub throttle: Option<Rate>,
+
+    /// Run at idle CPU and I/O priority, so the search only gets what the
+    /// rest of the machine isn't using
+    #[arg(long)]
+    pub idle_priority: bool,
+
+    /// Metadata of each file to read ahead, for consumers that will ask for it
+    #[arg(skip)]
+    #[serde(skip)]
+    pub stat: Fields,
+
+    /// Stops the crawl from outside, e.g. once --first has its match
+    #[arg(skip)]
+    #[serde(skip)]
+    pub cancel: CancellationToken,
+
+    /// Where to report progress, for a library caller's progress bar
+    #[arg(skip)]
+    #[serde(skip)]
+    pub progress: Option<broadcast::Sender<ProgressEvent>>,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        CrawlOptions {
+            threads: Threads::Fixed(1),
+            backend: Backend::default(),
+            max_frontier: None,
+            order: Order::Bfs,
+            mft: false,
+            stats: false,
+            channel_capacity: 100,
+            backpressure: Backpressure::Block,
+            throttle: None,
+            idle_priority: false,
+            stat: Fields::default(),
+            cancel: CancellationToken::new(),
+            progress: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    Fixed(usize),
+    /// Tuned as the crawl goes, by how read latency changes with load
+    Auto,
+}
+
+impl FromStr for Threads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Threads::Auto);
+        }
+        s.parse()
+            .map(Threads::Fixed)
+            .map_err(|_| format!("expected a number or 'auto', got '{s}'"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Threads {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        from_text(deserializer)
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threads::Fixed(n) => write!(f, "{n}"),
+            Threads::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Order {
+    /// Breadth-first: everything at one depth before anything deeper
+    Bfs,
+    /// Depth-first: a directory's files, then each subdirectory in full. With
+    /// --threads, each worker goes depth-first but their results interleave
+    Dfs,
+}
+
+/// What the crawl does when the consumer falls behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backpressure {
+    /// Pause the crawl until the consumer makes room
+    Block,
+    /// Keep crawling, holding results back in memory until there's room
+    Coalesce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// tokio::fs, which hands every entry and stat to the blocking pool
+    /// separately
+    Tokio,
+    /// std::fs on a blocking thread, one hop per directory
+    Blocking,
+    /// Like blocking, but opening and statting through io_uring, a batch of
+    /// entries per system call
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    IoUring,
+    /// FindFirstFileExW, which lists each entry's attributes with its name
+    #[cfg(windows)]
+    Win32,
+}
+
+impl Default for Backend {
+    #[cfg(windows)]
+    fn default() -> Self {
+        Backend::Win32
+    }
+
+    #[cfg(not(windows))]
+    fn default() -> Self {
+        Backend::Blocking
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no variant is skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+/// What decides which entries are results, which directories are entered,
+/// and in what order.
+pub struct Filters {
+    pub max_depth: usize,
+    pub pattern: String,
+    pub extensions: Option<Vec<String>>,
+    pub show_hidden: bool,
+    pub include_gitignored: bool,
+    /// Ignore files besides the tree's .gitignore files, which apply even
+    /// with `include_gitignored`
+    pub ignore_files: IgnoreFiles,
+    pub search_archives: bool,
+    /// Which kinds of entries are results; directories are entered either way
+    pub emit: EntryKinds,
+    /// The embedding application's own, applied after all of the above
+    pub custom: Vec<Arc<dyn Filter>>,
+    /// The embedding application's order, if it has one
+    pub rank: Option<Arc<dyn Rank>>,
+    /// What the tree is read from
+    pub files: Files,
+}
+
+impl Filters {
+    /// The .gitignore rules in force in `dir`, at `depth` below the root,
+    /// unless gitignored files are included anyway.
+    fn gitignore_in(&self, dir: &Path, depth: usize) -> Option<Arc<Rules>> {
+        (!self.include_gitignored).then(|| gitignore::rules_for(&self.files, dir, depth))
+    }
+
+    /// Whether `path`, of this kind, is a result the walk from `root` would
+    /// have given, for a path some other tool listed instead: it must pass
+    /// the filters as an entry, and each directory from `root` down to it as
+    /// one the walk enters. A path outside `root` only has its own name to
+    /// go by.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn admits(&self, root: &Path, path: &Path, kind: EntryKind) -> bool {
+        match self.walk_to(root, path, kind) {
+            Some(steps) => steps.last().is_some_and(|step| step.result),
+            None => matches!(
+                classify(path, kind, 0, None, self),
+                Found::Entry(_) | Found::Both
+            ),
+        }
+    }
+
+    /// What the walk from `root` decides about `path`, of this kind, and
+    /// about each directory on the way to it, as far as it gets; None if
+    /// `path` isn't under `root`. A relative path is taken to be under the
+    /// default root, `.`, as it's written.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn walk_to(&self, root: &Path, path: &Path, kind: EntryKind) -> Option<Vec<Step>> {
+        let joined;
+        let path = match root == Path::new(".") && path.is_relative() {
+            true => {
+                joined = root.join(path);
+                joined.as_path()
+            }
+            false => path,
+        };
+        let below = path.strip_prefix(root).ok()?;
+        let mut steps = Vec::new();
+        let mut dir = root.to_path_buf();
+        let mut depth = 0;
+        let mut names = below.iter().peekable();
+        while let Some(name) = names.next() {
+            let rules = self.gitignore_in(&dir, depth);
+            let child = dir.join(name);
+            let last = names.peek().is_none();
+            let kind = if last { kind } else { EntryKind::Dir };
+            let rules = rules.as_deref();
+            let (found, mut why) = judge(&child, kind, crate::is_hidden, depth, rules, self);
+            let is_dir = kind == EntryKind::Dir;
+            let rule = rules.and_then(|rules| rules.rule(&child, is_dir));
+            let rule = rule.or_else(|| self.ignore_files.rule(&child, is_dir));
+            // The custom filters have the last word
+            let entry = FileEntry::new(child.clone());
+            let mut result = matches!(found, Found::Entry(_) | Found::Both);
+            let mut entered = matches!(found, Found::Subdir | Found::Both);
+            let turned_down = match last {
+                true => result && !self.keeps(&entry),
+                false => entered && !self.enters(&entry),
+            };
+            if turned_down {
+                (result, entered, why) = (false, false, Why::Custom);
+            }
+            steps.push(Step {
+                path: child.clone(),
+                why,
+                result,
+                entered,
+                rule: rule.map(Rule::from),
+            });
+            if !entered {
+                break;
+            }
+            dir = child;
+            depth += 1;
+        }
+        Some(steps)
+    }
+
+    /// Whether these could match anything at all.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if let Some(e) = self.ignore_files.error() {
+            return Err(e);
+        }
+        let separator = |c| std::path::is_separator(c) || c == '/';
+        let reason = if self.pattern.contains(separator) {
+            "it's matched against file names, which can't contain a path separator"
+        } else if self.pattern.contains('\0') {
+            "file names can't contain a NUL"
+        } else {
+            return Ok(());
+        };
+        Err(Error::PatternInvalid {
+            pattern: self.pattern.clone(),
+            reason,
+        })
+    }
+}
+
+/// A setting the command line reads with `FromStr`, from a config document,
+/// where it may be a number instead of a string: `threads = 8`.
+fn from_text<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Whole(u64),
+        Number(f64),
+        Text(String),
+    }
+    let text = match Setting::deserialize(deserializer)? {
+        Setting::Whole(n) => n.to_string(),
+        Setting::Number(n) => n.to_string(),
+        Setting::Text(text) => text,
+    };
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+/// A count in a config document that, as its flag, can't be 0: a channel
+/// needs room for at least one batch.
+fn at_least_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom("must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+/// What an entry of a directory at `depth` is to the search.
+enum Found {
+    Skip,
+    /// A directory to read
+    Subdir,
+    /// A result of this kind
+    Entry(EntryKind),
+    /// A directory to read that's a result too
+    Both,
+}
+
+/// Sort an entry by its path and kind, which every backend gets from the
+/// directory listing itself where the file system records it (d_type),
+/// without a stat per entry. `gitignore` holds the rules in force in the
+/// entry's directory.
+#[cfg(any(feature = "tokio", all(target_os = "linux", feature = "io-uring")))]
+fn classify(
+    path: &Path,
+    kind: EntryKind,
+    depth: usize,
+    gitignore: Option<&Rules>,
+    filters: &Filters,
+) -> Found {
+    classify_with(path, kind, crate::is_hidden, depth, gitignore, filters)
+}
+
+/// [`classify`] for a backend that can tell hidden files apart itself.
+fn classify_with(
+    path: &Path,
+    kind: EntryKind,
+    is_hidden: impl FnOnce(&Path) -> bool,
+    depth: usize,
+    gitignore: Option<&Rules>,
+    filters: &Filters,
+) -> Found {
+    let (found, why) = judge(path, kind, is_hidden, depth, gitignore, filters);
+    tracing::trace!(path = %path.display(), "{why}");
+    found
+}
+
+/// [`classify_with`], and why it decided as it did.
+fn judge(
+    path: &Path,
+    kind: EntryKind,
+    is_hidden: impl FnOnce(&Path) -> bool,
+    depth: usize,
+    gitignore: Option<&Rules>,
+    filters: &Filters,
+) -> (Found, Why) {
+    let is_dir = kind == EntryKind::Dir;
+    // Unless the user wants gitignored files too, skip if matched; what no
+    // .gitignore mentions is up to the --ignore-file files
+    let decided = gitignore.and_then(|rules| rules.decide(path, is_dir));
+    if decided.unwrap_or_else(|| filters.ignore_files.ignores(path, is_dir)) {
+        let why = if decided.is_some() {
+            Why::Gitignored
+        } else {
+            Why::IgnoreFile
+        };
+        return (Found::Skip, why);
+    }
+
+    // hidden check
+    if !filters.show_hidden && is_hidden(path) {
+        return (Found::Skip, Why::Hidden);
+    }
+
+    let extensions = filters.extensions.as_deref();
+    // Queue subdirectories, and report them too if asked to
+    if is_dir {
+        let enter = depth < filters.max_depth;
+        let emit = filters.emit.contains(kind) && file_matches(path, &filters.pattern, extensions);
+        let why = if enter { Why::Entered } else { Why::MaxDepth };
+        let found = match (enter, emit) {
+            (true, true) => Found::Both,
+            (true, false) => Found::Subdir,
+            (false, true) => Found::Entry(kind),
+            (false, false) => Found::Skip,
+        };
+        return (found, why);
+    }
+    if !filters.emit.contains(kind) {
+        return (Found::Skip, Why::Kind);
+    }
+
+    // Otherwise, check pattern / extension; archives are let through for
+    // --search-archives to look inside
+    if file_matches(path, &filters.pattern, extensions) {
+        (Found::Entry(kind), Why::Matched)
+    } else if filters.search_archives && is_archive(path) {
+        (Found::Entry(kind), Why::Archive)
+    } else {
+        (Found::Skip, Why::Name)
+    }
+}
+
+/// Why the walk decided what it did about an entry, for the log and
+/// `fs explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Why {
+    /// A .gitignore in the tree ignores it
+    Gitignored,
+    /// One of the --ignore-file files does
+    IgnoreFile,
+    Hidden,
+    /// A directory at --max-depth, which isn't read
+    MaxDepth,
+    /// Not a kind of entry the search reports
+    Kind,
+    /// Its name doesn't match the pattern or the extensions
+    Name,
+    /// One of the library caller's filters turned it down
+    Custom,
+    /// A directory the walk reads
+    Entered,
+    Matched,
+    /// An archive, let through for --search-archives to look inside
+    Archive,
+}
+
+impl fmt::Display for Why {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Why::Gitignored => "skipped: ignored by a .gitignore",
+            Why::IgnoreFile => "skipped: ignored by an --ignore-file",
+            Why::Hidden => "skipped: hidden",
+            Why::MaxDepth => "not entered: at --max-depth",
+            Why::Kind => "skipped: not a kind of entry asked for",
+            Why::Name => "skipped: the name doesn't match",
+            Why::Custom => "skipped: turned down by a custom filter",
+            Why::Entered => "entered",
+            Why::Matched => "matched",
+            Why::Archive => "matched: an archive to look inside",
+        })
+    }
+}
+
+/// What the walk decides about one entry on the way to a path, as
+/// [`Filters::walk_to`] has it.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Step {
+    pub path: std::path::PathBuf,
+    pub why: Why,
+    /// Whether it's a result
+    pub result: bool,
+    /// Whether it's a directory the walk reads
+    pub entered: bool,
+    /// The ignore rule that mentions it, ignoring it or letting it back in
+    pub rule: Option<Rule>,
+}
+
+/// Whether `path` is an archive the command line can look inside.
+#[cfg(feature = "tokio")]
+fn is_archive(path: &Path) -> bool {
+    crate::actions::archive::Format::detect(path).is_some()
+}
+
+/// Without the command line, nothing looks inside archives.
+#[cfg(not(feature = "tokio"))]
+fn is_archive(_path: &Path) -> bool {
+    false
+}
+
+/// A directory to read, and its depth below the root.
+type Job = (Arc<Path>, usize);
@@ -0,0 +1,205 @@
+//! Where the crawl's results go, and what it does when the consumer falls
+//! behind (`--backpressure`): wait for room in the channel, or keep crawling
+//! and coalesce what doesn't fit into one batch that's handed over as room
+//! frees up, trading memory for never stalling the walk.
+//!
+//! It's also where the crawl finds out it should stop: once nobody is
+//! listening, or once it's been cancelled from outside, every send fails. And
+//! it's where progress is counted, for anyone subscribed (see `progress`).
+
+use super::progress::Progress;
+use super::results::Batch;
+use super::{Backpressure, FileEntry};
+use crate::Error;
+use std::fmt;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_util::sync::CancellationToken;
+
+/// Nobody is listening for results any more, or the crawl was cancelled.
+#[derive(Debug)]
+pub(super) struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+pub(super) struct Output {
+    tx: mpsc::Sender<Batch>,
+    policy: Backpressure,
+    /// Results waiting for room, in the order they were found
+    held: Mutex<Batch>,
+    cancel: CancellationToken,
+    progress: Option<Progress>,
+}
+
+impl Output {
+    pub fn new(
+        tx: mpsc::Sender<Batch>,
+        policy: Backpressure,
+        cancel: CancellationToken,
+        progress: Option<Progress>,
+    ) -> Self {
+        Output {
+            tx,
+            policy,
+            held: Mutex::new(Vec::new()),
+            cancel,
+            progress,
+        }
+    }
+
+    /// Where to report progress, if anyone asked for it.
+    pub fn progress(&self) -> Option<&Progress> {
+        self.progress.as_ref()
+    }
+
+    /// Send one result, or fail once nobody's listening.
+    pub async fn send(&self, item: Result<FileEntry, Error>) -> Result<(), Closed> {
+        self.send_batch(vec![item]).await
+    }
+
+    /// Send results together, or fail once nobody's listening.
+    pub async fn send_batch(&self, batch: Batch) -> Result<(), Closed> {
+        if self.is_closed() {
+            return Err(Closed);
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if let Some(progress) = &self.progress {
+            progress.found(&batch);
+        }
+        match self.policy {
+            Backpressure::Block => self.wait_to_send(batch).await,
+            Backpressure::Coalesce => self.hold(batch),
+        }
+    }
+
+    /// Send `batch` once there's room, unless the crawl is cancelled first.
+    async fn wait_to_send(&self, batch: Batch) -> Result<(), Closed> {
+        tokio::select! {
+            sent = self.tx.send(batch) => sent.map_err(|_| Closed),
+            () = self.cancel.cancelled() => Err(Closed),
+        }
+    }
+
+    /// [`Output::send_batch`] from a blocking thread.
+    #[cfg(windows)]
+    pub fn blocking_send_batch(&self, batch: Batch) -> Result<(), Closed> {
+        if self.is_closed() {
+            return Err(Closed);
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if let Some(progress) = &self.progress {
+            progress.found(&batch);
+        }
+        match self.policy {
+            Backpressure::Block => self.tx.blocking_send(batch).map_err(|_| Closed),
+            Backpressure::Coalesce => self.hold(batch),
+        }
+    }
+
+    /// Add `batch` to what's held back, then hand it all over if there's room.
+    fn hold(&self, batch: Batch) -> Result<(), Closed> {
+        let mut held = self.held.lock().unwrap();
+        held.extend(batch);
+        self.hand_over(&mut held)
+    }
+
+    /// Hand over what was held back if there's room now, so it doesn't wait
+    /// for the next result.
+    pub fn drain(&self) {
+        let _ = self.hand_over(&mut self.held.lock().unwrap());
+    }
+
+    fn hand_over(&self, held: &mut Batch) -> Result<(), Closed> {
+        if held.is_empty() {
+            return Ok(());
+        }
+        match self.tx.try_send(std::mem::take(held)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(batch)) => {
+                *held = batch;
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(Closed),
+        }
+    }
+
+    /// At the end of the crawl, wait for room for everything held back.
+    pub async fn flush(&self) -> Result<(), Closed> {
+        let held = std::mem::take(&mut *self.held.lock().unwrap());
+        if held.is_empty() {
+            return Ok(());
+        }
+        self.wait_to_send(held).await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed() || self.cancel.is_cancelled()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Wait until [`Output::is_closed`].
+    pub async fn closed(&self) {
+        tokio::select! {
+            () = self.tx.closed() => {}
+            () = self.cancel.cancelled() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::results;
+    use super::*;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_coalesce_keeps_order_without_waiting() {
+        let (tx, mut rx) = results::channel(2);
+        let output = Output::new(tx, Backpressure::Coalesce, CancellationToken::new(), None);
+        // Five results into room for two batches: none of these wait, and
+        // the last three go out together
+        for i in 0..5 {
+            output
+                .send(Ok(FileEntry::new(i.to_string().into())))
+                .await
+                .unwrap();
+        }
+        assert_eq!(output.held.lock().unwrap().len(), 3);
+
+        let collect = tokio::spawn(async move {
+            let mut batches = Vec::new();
+            while let Some(batch) = rx.recv_batch().await {
+                let paths: Vec<PathBuf> =
+                    batch.into_iter().map(|r| r.unwrap().into_path()).collect();
+                batches.push(paths);
+            }
+            batches
+        });
+        output.flush().await.unwrap();
+        drop(output);
+        let batches = collect.await.unwrap();
+        let path = |i: usize| PathBuf::from(i.to_string());
+        assert_eq!(
+            batches,
+            [
+                vec![path(0)],
+                vec![path(1)],
+                vec![path(2), path(3), path(4)]
+            ]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! The visitor on many threads: each worker reads directories and hands what
+//! it finds to a callback of its own, so per-file work (hashing, parsing)
+//! scales with the workers instead of queueing behind one consumer.
+
+use super::blocking::List;
+use super::visit::{hand_over, lister, read, Descend};
+use super::{CrawlOptions, FileEntry, Filters, Job, Order, Threads};
+use crate::Error;
+use crossbeam_deque::{Injector, Stealer, Worker};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// [`super::visit`], on `options.threads` threads, each with a callback of
+/// its own from `new_visitor`. Each directory's entries go to the callback
+/// of the worker that read it; a callback that breaks stops every worker.
+/// Returns what the first to break broke with, if one did. A callback that
+/// panics stops them too, and the panic goes on once they all have.
+pub(crate) fn visit_parallel<B, F>(
+    root: PathBuf,
+    filters: &Filters,
+    options: &CrawlOptions,
+    mut new_visitor: impl FnMut() -> F,
+) -> Option<B>
+where
+    B: Send,
+    F: FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend> + Send,
+{
+    if let Err(e) = filters.check() {
+        return new_visitor()(Err(e)).break_value();
+    }
+    let threads = match options.threads {
+        Threads::Fixed(threads) => threads.max(1),
+        Threads::Auto => thread::available_parallelism().map_or(4, usize::from),
+    };
+    let new_worker = match options.order {
+        Order::Bfs => Worker::new_fifo,
+        Order::Dfs => Worker::new_lifo,
+    };
+    let workers: Vec<Worker<Job>> = (0..threads).map(|_| new_worker()).collect();
+    let pool = Pool {
+        list: lister(filters, options.backend),
+        filters,
+        options,
+        injector: Injector::new(),
+        stealers: workers.iter().map(Worker::stealer).collect(),
+        pending: AtomicUsize::new(1),
+        stopped: AtomicBool::new(false),
+        broke: Mutex::new(None),
+        idle: Mutex::new(()),
+        wake: Condvar::new(),
+    };
+    pool.injector.push((Arc::from(root), 0));
+
+    let mut workers = workers.into_iter();
+    let first = workers.next().expect("at least one worker");
+    thread::scope(|scope| {
+        for local in workers {
+            let (pool, visitor) = (&pool, new_visitor());
+            // Where there are no threads to be had (WASI, say), the one on
+            // this thread walks the whole tree by itself
+            let _ = thread::Builder::new().spawn_scoped(scope, move || pool.work(local, visitor));
+        }
+        pool.work(first, new_visitor());
+    });
+    pool.broke.into_inner().unwrap_or_else(|e| e.into_inner())
+}
+
+struct Pool<'a, B> {
+    list: List,
+    filters: &'a Filters,
+    options: &'a CrawlOptions,
+    /// The root, before any worker has a queue of its own to steal from
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    /// Directories queued or being read; none left means the walk is done
+    pending: AtomicUsize,
+    /// A callback broke, or the search was cancelled
+    stopped: AtomicBool,
+    /// What the first callback to break broke with
+    broke: Mutex<Option<B>>,
+    /// Idle workers wait on `wake` holding this, so a wakeup sent between
+    /// their last look for work and their wait isn't missed
+    idle: Mutex<()>,
+    wake: Condvar,
+}
+
+impl<B> Pool<'_, B> {
+    /// Read directories and hand them to `f` until there are none left
+    /// anywhere, or the walk has stopped.
+    fn work<F>(&self, local: Worker<Job>, mut f: F)
+    where
+        F: FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend>,
+    {
+        let mut subdirs = Vec::new();
+        while let Some((dir, depth)) = self.next_job(&local) {
+            let reading = Reading(self);
+            let read = read(self.list, &dir, depth, self.filters, self.options);
+            let flow = hand_over(&dir, depth, read, self.filters, &mut f, &mut subdirs);
+            let found = subdirs.len();
+            self.pending.fetch_add(found, Ordering::SeqCst);
+            if self.options.order == Order::Dfs {
+                // Reversed, so the first subdirectory is popped first
+                subdirs.reverse();
+            }
+            for sub in subdirs.drain(..) {
+                local.push((sub, depth + 1));
+            }
+            drop(reading);
+
+            if let ControlFlow::Break(b) = flow {
+                self.broke.lock().unwrap().get_or_insert(b);
+                self.stop();
+                return;
+            }
+            if found > 0 {
+                self.wake();
+            }
+        }
+    }
+
+    /// The next directory to read, waiting for one while other workers may
+    /// still find some; none once the walk is done or stopped.
+    fn next_job(&self, local: &Worker<Job>) -> Option<Job> {
+        loop {
+            if self.options.cancel.is_cancelled() {
+                self.stop();
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(job) = self.find_job(local) {
+                return Some(job);
+            }
+            let idle = self.idle.lock().unwrap();
+            if self.stopped.load(Ordering::SeqCst) || self.pending.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            // Look again under the lock, which every wakeup takes first
+            if let Some(job) = self.find_job(local) {
+                return Some(job);
+            }
+            drop(self.wake.wait(idle).unwrap());
+        }
+    }
+
+    /// From our own queue, else the shared one, else stolen from another
+    /// worker.
+    fn find_job(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(|steal| steal.success())
+        })
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        let _idle = self.idle.lock().unwrap();
+        self.wake.notify_all();
+    }
+}
+
+/// A directory being read, counted done when this is dropped, even by a
+/// callback panicking: then the walk stops, rather than the other workers
+/// waiting forever for that directory's subdirectories.
+struct Reading<'p, 'a, B>(&'p Pool<'a, B>);
+
+impl<B> Drop for Reading<'_, '_, B> {
+    fn drop(&mut self) {
+        let last = self.0.pending.fetch_sub(1, Ordering::SeqCst) == 1;
+        if thread::panicking() {
+            self.0.stop();
+        } else if last {
+            self.0.wake();
+        }
+    }
+}
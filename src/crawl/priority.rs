@@ -0,0 +1,67 @@
+//! `--idle-priority`: only take the CPU and disk time the rest of the machine
+//! isn't using.
+
+use std::io;
+
+/// Lower the whole process to idle CPU and I/O priority.
+#[cfg(target_os = "linux")]
+pub(super) fn lower() -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3 << 13;
+
+    // Both are per thread on Linux, so every thread there is now gets them;
+    // threads started later take them from the one that starts them
+    for task in std::fs::read_dir("/proc/self/task")? {
+        let Ok(tid) = task?.file_name().to_string_lossy().parse::<libc::c_int>() else {
+            continue;
+        };
+        // SAFETY: plain syscalls on our own threads' ids
+        unsafe {
+            if libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                tid,
+                IOPRIO_CLASS_IDLE,
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, 19) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub(super) fn lower() -> io::Result<()> {
+    // Background: lowest CPU priority, and throttled disk and network I/O
+    // SAFETY: a plain syscall on our own process
+    if unsafe { libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(super) fn lower() -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN,
+    };
+
+    // Background mode lowers CPU, I/O and memory priority together
+    // SAFETY: GetCurrentProcess returns a pseudo-handle that needn't be closed
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub(super) fn lower() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not on this platform",
+    ))
+}
@@ -0,0 +1,114 @@
+//! A side channel for progress bars: what the crawl is doing and has seen so
+//! far, apart from the results themselves. Events go out on a tokio broadcast
+//! channel, so any number of subscribers can listen, and one that falls behind
+//! misses the oldest events (its next `recv` says how many) instead of holding
+//! up the crawl.
+
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use {
+    super::results::Batch,
+    std::sync::atomic::{AtomicU64, Ordering},
+    tokio::sync::broadcast,
+};
+
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// A directory is about to be read, at `depth` below the root
+    DirEntered { path: Arc<Path>, depth: usize },
+    /// Something couldn't be read. The error itself comes with the results
+    Error { message: Arc<str> },
+    /// A directory was read; what the crawl has seen so far
+    Totals(Totals),
+}
+
+/// Running totals, as of a [`ProgressEvent::Totals`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    /// Directories read
+    pub dirs: u64,
+    /// Entries listed in them, whether or not they were results
+    pub entries: u64,
+    /// Results found
+    pub matched: u64,
+    pub errors: u64,
+    /// Total size of the results, where it's read with the listing (Linux)
+    pub bytes: u64,
+}
+
+#[cfg(feature = "tokio")]
+pub(super) struct Progress {
+    tx: broadcast::Sender<ProgressEvent>,
+    dirs: AtomicU64,
+    entries: AtomicU64,
+    matched: AtomicU64,
+    errors: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[cfg(feature = "tokio")]
+impl Progress {
+    pub fn new(tx: broadcast::Sender<ProgressEvent>) -> Self {
+        Progress {
+            tx,
+            dirs: AtomicU64::new(0),
+            entries: AtomicU64::new(0),
+            matched: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn entered(&self, dir: &Arc<Path>, depth: usize) {
+        self.send(ProgressEvent::DirEntered {
+            path: Arc::clone(dir),
+            depth,
+        });
+    }
+
+    /// Count `entries` listed in a directory.
+    pub fn examined(&self, entries: usize) {
+        self.entries.fetch_add(entries as u64, Ordering::Relaxed);
+    }
+
+    /// Count the results in `batch`, and report its errors.
+    pub fn found(&self, batch: &Batch) {
+        for item in batch {
+            match item {
+                Ok(entry) => {
+                    self.matched.fetch_add(1, Ordering::Relaxed);
+                    let size = entry.size_read_ahead().unwrap_or(0);
+                    self.bytes.fetch_add(size, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                    self.send(ProgressEvent::Error {
+                        message: e.to_string().into(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Count a directory read, and report the totals.
+    pub fn read(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        self.send(ProgressEvent::Totals(self.totals()));
+    }
+
+    fn totals(&self) -> Totals {
+        Totals {
+            dirs: self.dirs.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+            matched: self.matched.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn send(&self, event: ProgressEvent) {
+        // Nobody subscribed is fine
+        let _ = self.tx.send(event);
+    }
+}
@@ -0,0 +1,162 @@
+//! An order of the embedding application's own, for both what the walk reads
+//! next and the order of each directory's results: "most recently modified
+//! directories first", say, to turn up what's likely wanted early on a scan
+//! that takes a while.
+
+use super::results::Batch;
+use super::{EntryKind, FileEntry, Job, Order};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Which of two entries the walk takes first. Of two directories waiting to
+/// be read, the first is read first; of two files found in one directory,
+/// the first is sent first. It's called wherever the crawl happens to be,
+/// async tasks included, so it should be quick.
+pub trait Rank: Send + Sync {
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering;
+}
+
+/// A closure is a comparator, as for `sort_by`.
+impl<F: Fn(&FileEntry, &FileEntry) -> Ordering + Send + Sync> Rank for F {
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// A priority function: the entry with the highest key goes first.
+///
+/// ```
+/// // Most recently modified first, and anything that can't be read last
+/// let newest = fs::ByKey(|entry: &fs::FileEntry| entry.modified().ok());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ByKey<F>(pub F);
+
+impl<K: Ord, F: Fn(&FileEntry) -> K + Send + Sync> Rank for ByKey<F> {
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        (self.0)(b).cmp(&(self.0)(a))
+    }
+}
+
+/// Put `found` in `rank`'s order, errors first, as they were.
+pub(super) fn sort(rank: &dyn Rank, found: &mut Batch) {
+    found.sort_by(|a, b| match (a, b) {
+        (Ok(a), Ok(b)) => rank.compare(a, b),
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => Ordering::Equal,
+    });
+}
+
+/// Directories waiting to be read by a walk on one thread.
+pub(super) enum Frontier {
+    /// Breadth-first takes the oldest next; depth-first, or breadth-first
+    /// with more than `max` queued, the newest
+    Queue {
+        queue: VecDeque<Job>,
+        order: Order,
+        max: usize,
+    },
+    /// The first by `rank` on top; the order and cap don't apply
+    Ranked {
+        heap: BinaryHeap<Queued>,
+        rank: Arc<dyn Rank>,
+    },
+}
+
+impl Frontier {
+    pub fn new(root: Arc<Path>, order: Order, max: usize, rank: Option<&Arc<dyn Rank>>) -> Self {
+        let mut frontier = match rank {
+            Some(rank) => Frontier::Ranked {
+                heap: BinaryHeap::new(),
+                rank: Arc::clone(rank),
+            },
+            None => Frontier::Queue {
+                queue: VecDeque::new(),
+                order,
+                max,
+            },
+        };
+        frontier.push((root, 0));
+        frontier
+    }
+
+    pub fn pop(&mut self) -> Option<Job> {
+        match self {
+            Frontier::Queue { queue, order, max } => {
+                if *order == Order::Dfs || queue.len() > *max {
+                    queue.pop_back()
+                } else {
+                    queue.pop_front()
+                }
+            }
+            Frontier::Ranked { heap, .. } => heap.pop().map(|queued| queued.job),
+        }
+    }
+
+    /// Queue `subdirs`, `depth` below the root, leaving it empty.
+    pub fn extend(&mut self, subdirs: &mut Vec<Arc<Path>>, depth: usize) {
+        if let Frontier::Queue {
+            order: Order::Dfs, ..
+        } = self
+        {
+            // Reversed, so the first subdirectory is popped first
+            subdirs.reverse();
+        }
+        for sub in subdirs.drain(..) {
+            self.push((sub, depth));
+        }
+    }
+
+    fn push(&mut self, job: Job) {
+        match self {
+            Frontier::Queue { queue, .. } => queue.push_back(job),
+            Frontier::Ranked { heap, rank } => heap.push(Queued {
+                entry: entry_for(&job.0, job.1),
+                job,
+                rank: Arc::clone(rank),
+            }),
+        }
+    }
+}
+
+/// A directory in a ranked frontier, with the entry `rank` compares it as,
+/// which keeps whatever metadata it reads for the next comparison.
+pub(super) struct Queued {
+    job: Job,
+    entry: FileEntry,
+    rank: Arc<dyn Rank>,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The heap pops the greatest, which is the one the rank puts first
+        self.rank.compare(&other.entry, &self.entry)
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+/// The directory `dir`, `depth` below the root, as an entry to rank.
+fn entry_for(dir: &Path, depth: usize) -> FileEntry {
+    match (dir.parent(), dir.file_name()) {
+        (Some(parent), Some(name)) => FileEntry::in_dir(parent.into(), depth, OsString::from(name)),
+        _ => FileEntry::new(dir.to_path_buf()),
+    }
+    .with_kind(EntryKind::Dir)
+}
@@ -0,0 +1,125 @@
+//! The receiving end of a search. Results travel in batches, a directory's
+//! worth at a time, so the channel's cost is paid per batch rather than per
+//! path; consumers take them one at a time or a whole batch at once, or as a
+//! [`Stream`] of single results.
+
+use super::FileEntry;
+use crate::Error;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::sync::mpsc;
+
+/// Results sent together.
+pub type Batch = Vec<Result<FileEntry, Error>>;
+
+/// Most results the crawl puts in one batch.
+#[cfg(feature = "tokio")]
+pub(crate) const BATCH: usize = 256;
+
+/// A channel with room for `capacity` batches.
+pub fn channel(capacity: usize) -> (mpsc::Sender<Batch>, Results) {
+    let (tx, rx) = mpsc::channel(capacity);
+    (tx, Results::new(rx))
+}
+
+pub struct Results {
+    rx: mpsc::Receiver<Batch>,
+    /// What's left of the batch being handed out
+    batch: std::vec::IntoIter<Result<FileEntry, Error>>,
+}
+
+impl Results {
+    pub fn new(rx: mpsc::Receiver<Batch>) -> Self {
+        Results {
+            rx,
+            batch: Vec::new().into_iter(),
+        }
+    }
+
+    /// The next result, or None once the search is over.
+    pub async fn recv(&mut self) -> Option<Result<FileEntry, Error>> {
+        loop {
+            if let Some(item) = self.batch.next() {
+                return Some(item);
+            }
+            self.batch = self.rx.recv().await?.into_iter();
+        }
+    }
+
+    /// [`Results::recv`] from a blocking thread.
+    pub fn blocking_recv(&mut self) -> Option<Result<FileEntry, Error>> {
+        loop {
+            if let Some(item) = self.batch.next() {
+                return Some(item);
+            }
+            self.batch = self.rx.blocking_recv()?.into_iter();
+        }
+    }
+
+    /// The rest of the batch being handed out, or else the next one.
+    pub async fn recv_batch(&mut self) -> Option<Batch> {
+        let rest: Batch = self.batch.by_ref().collect();
+        if !rest.is_empty() {
+            return Some(rest);
+        }
+        self.rx.recv().await
+    }
+
+    /// Whether nothing is ready to be received without waiting.
+    pub fn is_empty(&self) -> bool {
+        self.batch.len() == 0 && self.rx.is_empty()
+    }
+}
+
+impl Stream for Results {
+    type Item = Result<FileEntry, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.batch.next() {
+                return Poll::Ready(Some(item));
+            }
+            match ready!(self.rx.poll_recv(cx)) {
+                Some(batch) => self.batch = batch.into_iter(),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.batch.len(), None)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_one_at_a_time_or_the_rest_of_a_batch() {
+        let (tx, mut rx) = channel(2);
+        let entry = |name: &str| Ok(FileEntry::new(name.into()));
+        tx.send(vec![entry("a"), entry("b"), entry("c")])
+            .await
+            .unwrap();
+        tx.send(vec![entry("d")]).await.unwrap();
+        drop(tx);
+
+        let path = |item: Option<Result<FileEntry, Error>>| item.unwrap().unwrap().into_path();
+        assert_eq!(path(rx.recv().await), PathBuf::from("a"));
+        assert!(!rx.is_empty());
+        // What's left of the first batch comes before the next one
+        let rest: Vec<PathBuf> = rx
+            .recv_batch()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.unwrap().into_path())
+            .collect();
+        assert_eq!(rest, [PathBuf::from("b"), PathBuf::from("c")]);
+        assert_eq!(path(rx.recv().await), PathBuf::from("d"));
+        assert!(rx.recv().await.is_none() && rx.is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+//! Reading ahead just the metadata a consumer needs. `dup` wants each file's
+//! type, size and modification time and `--delete` its size; asking the crawl
+//! for those [`Fields`] lets it read them while the directory is still at
+//! hand, instead of a full stat per file later on.
+//!
+//! On Linux, that's one `statx` per file relative to the open directory, so
+//! its path isn't looked up again each time, asking only for the fields
+//! wanted. It also says not to revalidate with the server, which on NFS and
+//! CIFS mounts is most of what a stat costs; local file systems ignore it.
+//! Elsewhere nothing is read ahead, and the entry stats itself on demand.
+
+use super::FileEntry;
+use crate::Error;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Which parts of each file's metadata will be asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fields {
+    /// [`FileEntry::is_file`]
+    pub kind: bool,
+    /// [`FileEntry::size`]
+    pub size: bool,
+    /// [`FileEntry::modified`]
+    pub modified: bool,
+}
+
+impl Fields {
+    pub fn is_empty(&self) -> bool {
+        *self == Fields::default()
+    }
+}
+
+/// What was read ahead for an entry; None for what wasn't.
+#[derive(Debug, Default)]
+pub(super) struct Stat {
+    pub is_file: Option<bool>,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+/// Read `fields` of the files found in `dir`.
+#[cfg(target_os = "linux")]
+pub(super) fn read_ahead(dir: &Path, found: &mut [Result<FileEntry, Error>], fields: Fields) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
+
+    if fields.is_empty() || found.is_empty() {
+        return;
+    }
+    // Without the directory open, each entry stats itself when asked
+    let Ok(handle) = std::fs::File::open(dir) else {
+        return;
+    };
+    let mask = [
+        (fields.kind, libc::STATX_TYPE),
+        (fields.size, libc::STATX_SIZE),
+        (fields.modified, libc::STATX_MTIME),
+    ]
+    .into_iter()
+    .filter(|&(wanted, _)| wanted)
+    .fold(0, |mask, (_, bit)| mask | bit);
+    let flags = libc::AT_SYMLINK_NOFOLLOW | libc::AT_STATX_DONT_SYNC;
+
+    for entry in found.iter_mut().flatten() {
+        let Ok(name) = CString::new(entry.name().as_bytes()) else {
+            continue;
+        };
+        // SAFETY: statx is plain old data, which the call fills in
+        let mut buf: libc::statx = unsafe { std::mem::zeroed() };
+        // SAFETY: the directory stays open, and name and buf outlive the call
+        if unsafe { libc::statx(handle.as_raw_fd(), name.as_ptr(), flags, mask, &mut buf) } != 0 {
+            continue;
+        }
+        // The file system may not have everything asked for
+        let got = |bit| buf.stx_mask & bit != 0;
+        let mtime = buf.stx_mtime;
+        entry.read_ahead(Stat {
+            is_file: got(libc::STATX_TYPE)
+                .then(|| u32::from(buf.stx_mode) & libc::S_IFMT == libc::S_IFREG),
+            size: got(libc::STATX_SIZE).then_some(buf.stx_size),
+            modified: got(libc::STATX_MTIME).then(|| {
+                let secs = Duration::from_secs(mtime.tv_sec.unsigned_abs());
+                let at = if mtime.tv_sec < 0 {
+                    SystemTime::UNIX_EPOCH - secs
+                } else {
+                    SystemTime::UNIX_EPOCH + secs
+                };
+                at + Duration::from_nanos(mtime.tv_nsec.into())
+            }),
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn read_ahead(_dir: &Path, _found: &mut [Result<FileEntry, Error>], _fields: Fields) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_read_ahead_matches_a_stat() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::write(tmp.path().join("a.txt"), "hello")?;
+        let dir: Arc<Path> = tmp.path().into();
+        let mut found = vec![Ok(FileEntry::in_dir(Arc::clone(&dir), 1, "a.txt".into()))];
+        let fields = Fields {
+            size: true,
+            modified: true,
+            ..Fields::default()
+        };
+        read_ahead(&dir, &mut found, fields);
+
+        let entry = found.pop().unwrap().unwrap();
+        // Changed since, so what's reported is what was read ahead
+        let before = std::fs::metadata(entry.path())?;
+        std::fs::write(entry.path(), "hello, world")?;
+        if cfg!(target_os = "linux") {
+            assert_eq!(entry.size()?, 5);
+            assert_eq!(entry.modified()?, before.modified()?);
+        }
+        // Nothing was read ahead for the type, so that's read now
+        assert!(entry.is_file()?);
+        assert_eq!(entry.metadata()?.len(), 12);
+        Ok(())
+    }
+}
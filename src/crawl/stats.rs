@@ -0,0 +1,167 @@
+//! What `--stats` reports about a crawl, and how `--threads auto` uses the
+//! same numbers to pick how many directories to read at once.
+//!
+//! The tuning is a latency gradient, as in TCP Vegas: the fastest reads seen
+//! so far are the storage unloaded, and reads getting slower than that mean
+//! it's queueing them, so fewer are let through. While they aren't, a few more
+//! are let through each time. An NVMe drive keeps up with a hundred readers,
+//! a spinning disk or an NFS mount with a handful, and a search crossing from
+//! one to the other settles again, since the baseline slowly forgets.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often `--threads auto` looks at the numbers.
+pub(super) const INTERVAL: Duration = Duration::from_millis(50);
+
+/// The most directories `--threads auto` reads at once.
+pub(super) const MAX_THREADS: usize = 128;
+
+/// How much the unloaded latency is let rise per interval, so a slower disk
+/// further down the tree becomes the new baseline instead of a sign of load.
+const DRIFT: f64 = 1.02;
+
+/// Counted by every read, whatever the backend.
+#[derive(Default)]
+pub(super) struct Stats {
+    dirs: AtomicU64,
+    /// Total time spent reading them, in nanoseconds
+    busy: AtomicU64,
+    tuning: Mutex<Option<Tuner>>,
+}
+
+impl Stats {
+    pub fn record(&self, took: Duration) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        self.busy
+            .fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Directories read so far, and the time they took.
+    pub fn totals(&self) -> (u64, Duration) {
+        let busy = Duration::from_nanos(self.busy.load(Ordering::Relaxed));
+        (self.dirs.load(Ordering::Relaxed), busy)
+    }
+
+    pub fn tuned(&self, tuner: &Tuner) {
+        *self.tuning.lock().unwrap() = Some(tuner.clone());
+    }
+
+    /// The `--stats` summary, on stderr.
+    pub fn print(&self, elapsed: Duration) {
+        let (dirs, busy) = self.totals();
+        let seconds = elapsed.as_secs_f64();
+        let rate = if seconds > 0.0 {
+            dirs as f64 / seconds
+        } else {
+            0.0
+        };
+        let latency = if dirs > 0 {
+            busy.as_secs_f64() * 1000.0 / dirs as f64
+        } else {
+            0.0
+        };
+        eprintln!(
+            "{dirs} directories read in {seconds:.2}s ({rate:.0} a second), {latency:.2}ms each on average"
+        );
+        if let Some(tuner) = &*self.tuning.lock().unwrap() {
+            eprintln!(
+                "--threads auto: started at {}, ranged from {} to {}, ended at {} after {} change(s)",
+                tuner.start, tuner.lowest, tuner.highest, tuner.limit, tuner.changes
+            );
+        }
+    }
+}
+
+/// `--threads auto`'s state: how many readers it lets through now, and what
+/// it's seen.
+#[derive(Debug, Clone)]
+pub(super) struct Tuner {
+    pub limit: usize,
+    max: usize,
+    /// Mean read time with the storage unloaded, as far as we know, in seconds
+    baseline: Option<f64>,
+    start: usize,
+    lowest: usize,
+    highest: usize,
+    changes: usize,
+}
+
+impl Tuner {
+    pub fn new(start: usize, max: usize) -> Self {
+        let start = start.clamp(1, max);
+        Tuner {
+            limit: start,
+            max,
+            baseline: None,
+            start,
+            lowest: start,
+            highest: start,
+            changes: 0,
+        }
+    }
+
+    /// Take in the `dirs` read over the last interval and the `busy` time
+    /// they took, and return the new limit.
+    pub fn update(&mut self, dirs: u64, busy: Duration) -> usize {
+        if dirs == 0 {
+            return self.limit;
+        }
+        let latency = busy.as_secs_f64() / dirs as f64;
+        let baseline = self
+            .baseline
+            .map_or(latency, |baseline| (baseline * DRIFT).min(latency));
+        self.baseline = Some(baseline);
+
+        // Shrink by how much slower reads are than unloaded (at most by half,
+        // so one bad interval can't stall the crawl), and grow by a little
+        // headroom so there's always room to find out if more would help
+        let limit = self.limit as f64;
+        let gradient = if latency > 0.0 {
+            (baseline / latency).clamp(0.5, 1.0)
+        } else {
+            1.0
+        };
+        let limit = ((limit * gradient + limit.sqrt()).round() as usize).clamp(1, self.max);
+
+        if limit != self.limit {
+            self.changes += 1;
+            self.lowest = self.lowest.min(limit);
+            self.highest = self.highest.max(limit);
+            self.limit = limit;
+        }
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuner_follows_latency() {
+        let ms = Duration::from_millis;
+        let mut tuner = Tuner::new(4, MAX_THREADS);
+        // Reads as fast with more readers as with few: keep adding them
+        let mut last = tuner.limit;
+        for _ in 0..30 {
+            let limit = tuner.update(100, ms(100));
+            assert!(limit >= last);
+            last = limit;
+        }
+        assert_eq!(tuner.limit, MAX_THREADS);
+
+        // Then each read takes four times as long: back off, down to where
+        // the headroom balances the slowdown
+        for _ in 0..30 {
+            tuner.update(100, ms(400));
+        }
+        assert!((2..=8).contains(&tuner.limit), "{}", tuner.limit);
+        assert_eq!((tuner.start, tuner.highest), (4, MAX_THREADS));
+
+        // An interval without reads changes nothing
+        let limit = tuner.limit;
+        assert_eq!(tuner.update(0, Duration::ZERO), limit);
+    }
+}
@@ -0,0 +1,163 @@
+//! `--throttle`: a token bucket in front of every directory read, so a
+//! scheduled scan takes no more of the disk than it's given.
+//!
+//! The rate is either directories read per second, or bytes per second, where
+//! a directory costs its size as the file system reports it, which is roughly
+//! how much reading its listing takes. Workers that find the bucket empty go
+//! into debt and sleep it off, so however many there are, together they keep
+//! to the rate.
+
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "tokio")]
+use std::{path::Path, sync::Mutex, time::Duration, time::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    /// Directories read per second
+    Ops(f64),
+    /// Bytes of directory listings read per second
+    Bytes(f64),
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    /// "200" or "200/s" for directories, or a size like "10MB/s" for bytes.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let amount = s.strip_suffix("/s").unwrap_or(s);
+        let rate = if amount.chars().any(|c| c.is_ascii_alphabetic()) {
+            Rate::Bytes(crate::parse_size(amount)? as f64)
+        } else {
+            let ops = amount
+                .parse()
+                .map_err(|_| format!("expected e.g. '200/s' or '10MB/s', got '{s}'"))?;
+            Rate::Ops(ops)
+        };
+        match rate {
+            Rate::Ops(n) | Rate::Bytes(n) if n > 0.0 && n.is_finite() => Ok(rate),
+            _ => Err(format!("the rate must be more than zero, got '{s}'")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        super::from_text(deserializer)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rate::Ops(n) => write!(f, "{n}/s"),
+            Rate::Bytes(n) => write!(f, "{n}B/s"),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub(super) struct Throttle {
+    rate: Rate,
+    bucket: Mutex<Bucket>,
+}
+
+#[cfg(feature = "tokio")]
+struct Bucket {
+    /// Negative while workers are waiting for what they've taken
+    tokens: f64,
+    filled: Instant,
+}
+
+#[cfg(feature = "tokio")]
+impl Throttle {
+    pub fn new(rate: Rate) -> Self {
+        let throttle = Throttle {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                filled: Instant::now(),
+            }),
+        };
+        throttle.bucket.lock().unwrap().tokens = throttle.burst();
+        throttle
+    }
+
+    fn per_second(&self) -> f64 {
+        match self.rate {
+            Rate::Ops(n) | Rate::Bytes(n) => n,
+        }
+    }
+
+    /// The most saved up while idle: a tenth of a second's worth, so a pause
+    /// doesn't turn into a burst.
+    fn burst(&self) -> f64 {
+        (self.per_second() / 10.0).max(1.0)
+    }
+
+    /// Wait until `dir` may be read.
+    pub async fn acquire(&self, dir: &Path) {
+        let cost = match self.rate {
+            Rate::Ops(_) => 1.0,
+            Rate::Bytes(_) => tokio::fs::metadata(dir)
+                .await
+                .map_or(0.0, |meta| meta.len() as f64),
+        };
+        let wait = self.take(cost);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take `cost` from the bucket, returning how long until it's paid for.
+    fn take(&self, cost: f64) -> Duration {
+        let rate = self.per_second();
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let earned = now.duration_since(bucket.filled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + earned).min(self.burst()) - cost;
+        bucket.filled = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!("200".parse(), Ok(Rate::Ops(200.0)));
+        assert_eq!("0.5/s".parse(), Ok(Rate::Ops(0.5)));
+        assert_eq!("10MB/s".parse(), Ok(Rate::Bytes(10.0 * 1024.0 * 1024.0)));
+        assert_eq!("512K".parse(), Ok(Rate::Bytes(512.0 * 1024.0)));
+        assert!("0".parse::<Rate>().is_err());
+        assert!("fast".parse::<Rate>().is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_workers_keep_to_the_rate_together() {
+        let throttle = std::sync::Arc::new(Throttle::new(Rate::Ops(200.0)));
+        let started = Instant::now();
+        let mut workers = tokio::task::JoinSet::new();
+        for _ in 0..4 {
+            let throttle = std::sync::Arc::clone(&throttle);
+            workers.spawn(async move {
+                for _ in 0..15 {
+                    throttle.acquire(Path::new(".")).await;
+                }
+            });
+        }
+        while workers.join_next().await.is_some() {}
+        // 60 reads, 20 of them saved up: the other 40 take 0.2s at 200/s
+        let took = started.elapsed();
+        assert!(took >= Duration::from_millis(180), "{took:?}");
+        assert!(took < Duration::from_secs(2), "{took:?}");
+    }
+}
@@ -0,0 +1,398 @@
+//! What the walk reads the tree through: the real file system by default, or
+//! anything else implementing [`FileSystem`], such as [`MemFs`], an in-memory
+//! tree for tests, or an archive or a remote store.
+//!
+//! Only the `blocking` backend goes through the trait; the others are ways of
+//! reading a real disk faster, so with another file system the walk reads it
+//! as `blocking` would, whatever `--backend` says. Entries it finds carry the
+//! metadata the walk read for them, since [`FileEntry::metadata`] can only
+//! ask the real file system.
+
+use super::gitignore;
+use super::stat::{self, Fields, Stat};
+use super::{EntryKind, FileEntry};
+use crate::Error;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A tree the walk can read.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// The entries of `dir`, besides `.` and `..`, in any order. An error
+    /// partway through ends the listing there.
+    fn read_dir(&self, dir: &Path) -> io::Result<ReadDir<'_>>;
+
+    /// What's at `path`, following a symlink.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// What's at `path` itself, even if it's a symlink.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// The contents of the file at `path`.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Whether `path` is hidden; by default, whether its name starts with a dot.
+    fn is_hidden(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'))
+    }
+}
+
+pub type ReadDir<'a> = Box<dyn Iterator<Item = io::Result<DirEntry>> + 'a>;
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: OsString,
+    /// What it is, if the listing says; otherwise the walk asks
+    /// [`FileSystem::symlink_metadata`]
+    pub kind: Option<EntryKind>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: EntryKind,
+    /// Size in bytes
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl From<std::fs::Metadata> for Metadata {
+    fn from(meta: std::fs::Metadata) -> Self {
+        Metadata {
+            kind: meta.file_type().into(),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
+}
+
+/// The real file system, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn read_dir(&self, dir: &Path) -> io::Result<ReadDir<'_>> {
+        let entries = std::fs::read_dir(dir)?.map(|entry| {
+            let entry = entry?;
+            // From the listing itself where the file system records it
+            let kind = entry.file_type().ok().map(EntryKind::from);
+            Ok(DirEntry {
+                name: entry.file_name(),
+                kind,
+            })
+        });
+        Ok(Box::new(entries))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        std::fs::metadata(path).map(Into::into)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        std::fs::symlink_metadata(path).map(Into::into)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn is_hidden(&self, path: &Path) -> bool {
+        crate::is_hidden(path)
+    }
+}
+
+/// A file system held in memory, for tests. Parent directories are made as
+/// needed, and each write gets a later modification time than the last.
+///
+/// ```
+/// use fs::{MemFs, SearchBuilder};
+///
+/// let tree = MemFs::new()
+///     .with_file("/src/main.rs", "fn main() {}")
+///     .with_file("/src/.gitignore", "*.log\n")
+///     .with_file("/src/debug.log", "");
+/// let search = SearchBuilder::new("/src").file_system(tree).build();
+/// let found: Vec<_> = search.iter().unwrap().map(|item| item.unwrap().into_path()).collect();
+/// assert_eq!(found, [std::path::PathBuf::from("/src/main.rs")]);
+/// ```
+#[derive(Debug, Default)]
+pub struct MemFs {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    writes: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File {
+        contents: Arc<[u8]>,
+        modified: SystemTime,
+    },
+    Dir,
+    Symlink(PathBuf),
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        MemFs::default()
+    }
+
+    /// [`MemFs::write`], for building a tree in one expression.
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        self.write(path, contents);
+        self
+    }
+
+    /// Put a file at `path`, replacing whatever was there.
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        // A tick later than the last write, so a rewrite is seen as a change
+        let tick = self.writes.fetch_add(1, Ordering::Relaxed) + 1;
+        let node = Node::File {
+            contents: contents.as_ref().into(),
+            modified: SystemTime::UNIX_EPOCH + Duration::from_nanos(tick),
+        };
+        insert(&mut self.nodes.lock().unwrap(), path.as_ref(), node);
+    }
+
+    /// Make a directory at `path`, and its parents.
+    pub fn create_dir_all(&self, path: impl AsRef<Path>) {
+        insert(&mut self.nodes.lock().unwrap(), path.as_ref(), Node::Dir);
+    }
+
+    /// Put a symlink at `path`, pointing at `target`.
+    pub fn symlink(&self, target: impl Into<PathBuf>, path: impl AsRef<Path>) {
+        let node = Node::Symlink(target.into());
+        insert(&mut self.nodes.lock().unwrap(), path.as_ref(), node);
+    }
+
+    /// The node at `path`, following symlinks on the way there, and the last
+    /// one too if `follow`, through up to 40 of them as the kernel allows.
+    fn resolve(&self, path: &Path, follow: bool) -> io::Result<(PathBuf, Node)> {
+        let nodes = self.nodes.lock().unwrap();
+        let mut resolved = PathBuf::new();
+        let mut node = Node::Dir;
+        let mut pending: Vec<PathBuf> = rev_components(path);
+        let mut links = 0;
+        while let Some(part) = pending.pop() {
+            match part.components().next() {
+                Some(Component::ParentDir) => {
+                    resolved.pop();
+                }
+                Some(Component::CurDir) | None => {}
+                _ => resolved.push(&part),
+            }
+            node = match nodes.get(&resolved) {
+                Some(node) => node.clone(),
+                None if resolved.parent().is_none() => Node::Dir,
+                None => return Err(io::ErrorKind::NotFound.into()),
+            };
+            if let Node::Symlink(target) = &node {
+                if pending.is_empty() && !follow {
+                    break;
+                }
+                links += 1;
+                if links > 40 {
+                    return Err(io::Error::other("too many levels of symbolic links"));
+                }
+                resolved.pop();
+                pending.extend(rev_components(target));
+            }
+        }
+        Ok((resolved, node))
+    }
+}
+
+/// `path`, a component at a time, last first.
+fn rev_components(path: &Path) -> Vec<PathBuf> {
+    let parts = path
+        .components()
+        .map(|part| PathBuf::from(part.as_os_str()));
+    parts.rev().collect()
+}
+
+fn insert(nodes: &mut BTreeMap<PathBuf, Node>, path: &Path, node: Node) {
+    for parent in path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() {
+            break;
+        }
+        nodes.entry(parent.to_path_buf()).or_insert(Node::Dir);
+    }
+    nodes.insert(path.to_path_buf(), node);
+}
+
+fn metadata_of(node: &Node) -> Metadata {
+    let (kind, len, modified) = match node {
+        Node::File { contents, modified } => {
+            (EntryKind::File, contents.len() as u64, Some(*modified))
+        }
+        Node::Dir => (EntryKind::Dir, 0, None),
+        Node::Symlink(target) => (EntryKind::Symlink, target.as_os_str().len() as u64, None),
+    };
+    Metadata {
+        kind,
+        len,
+        modified,
+    }
+}
+
+impl FileSystem for MemFs {
+    fn read_dir(&self, dir: &Path) -> io::Result<ReadDir<'_>> {
+        let (dir, node) = self.resolve(dir, true)?;
+        if !matches!(node, Node::Dir) {
+            return Err(io::Error::from(io::ErrorKind::NotADirectory));
+        }
+        let nodes = self.nodes.lock().unwrap();
+        let entries: Vec<io::Result<DirEntry>> = nodes
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(&dir))
+            .map(|(path, node)| {
+                Ok(DirEntry {
+                    name: path.file_name().unwrap_or_default().to_os_string(),
+                    kind: Some(metadata_of(node).kind),
+                })
+            })
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(metadata_of(&self.resolve(path, true)?.1))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(metadata_of(&self.resolve(path, false)?.1))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.resolve(path, true)?.1 {
+            Node::File { contents, .. } => Ok(Box::new(io::Cursor::new(contents))),
+            _ => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+        }
+    }
+}
+
+/// The rules parsed from the real file system's .gitignore files, kept for the
+/// life of the process.
+static REAL_RULES: LazyLock<Arc<gitignore::Cache>> = LazyLock::new(Default::default);
+
+/// The file system a search reads, with the .gitignore rules parsed from it:
+/// for the real one, shared by every search; for any other, only kept for one
+/// search, since the same paths may mean something else next time.
+#[derive(Debug)]
+pub struct Files {
+    fs: Arc<dyn FileSystem>,
+    rules: Arc<gitignore::Cache>,
+    real: bool,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Files {
+            fs: Arc::new(StdFs),
+            rules: Arc::clone(&REAL_RULES),
+            real: true,
+        }
+    }
+}
+
+impl Files {
+    /// `fs`, for one search.
+    pub(crate) fn new(fs: Arc<dyn FileSystem>) -> Self {
+        Files {
+            fs,
+            rules: Arc::default(),
+            real: false,
+        }
+    }
+
+    pub(super) fn fs(&self) -> &dyn FileSystem {
+        &*self.fs
+    }
+
+    /// Whether it's the real file system, which every backend can read.
+    pub(super) fn is_real(&self) -> bool {
+        self.real
+    }
+
+    pub(super) fn rules(&self) -> &gitignore::Cache {
+        &self.rules
+    }
+
+    /// Read ahead `fields` of the files found in `dir`: with a `statx` each
+    /// on the real file system, or everything, from the file system itself,
+    /// on any other.
+    pub(super) fn read_ahead(
+        &self,
+        dir: &Path,
+        found: &mut [Result<FileEntry, Error>],
+        fields: Fields,
+    ) {
+        if self.real {
+            return stat::read_ahead(dir, found, fields);
+        }
+        for entry in found.iter_mut().flatten() {
+            if let Ok(meta) = self.fs.symlink_metadata(entry.path()) {
+                entry.read_ahead(Stat {
+                    is_file: Some(meta.kind == EntryKind::File),
+                    size: Some(meta.len),
+                    modified: meta.modified,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_fs_lists_and_follows() -> io::Result<()> {
+        let tree = MemFs::new().with_file("/a/b/c.txt", "hello");
+        tree.symlink("b", "/a/link");
+
+        let mut names: Vec<_> = tree
+            .read_dir(Path::new("/a"))?
+            .map(|entry| entry.map(|e| (e.name, e.kind.unwrap())))
+            .collect::<io::Result<_>>()?;
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            names,
+            [
+                ("b".into(), EntryKind::Dir),
+                ("link".into(), EntryKind::Symlink)
+            ]
+        );
+        assert_eq!(tree.read_dir(Path::new("/a/link"))?.count(), 1);
+
+        let mut contents = String::new();
+        tree.open(Path::new("/a/link/c.txt"))?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "hello");
+        let before = tree.metadata(Path::new("/a/b/c.txt"))?;
+        assert_eq!(before.len, 5);
+
+        // A rewrite is newer, whatever its size
+        tree.write("/a/b/c.txt", "world");
+        let after = tree.metadata(Path::new("/a/b/c.txt"))?;
+        assert!(after.modified > before.modified);
+        assert_eq!(
+            tree.read_dir(Path::new("/a/b/c.txt"))
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::NotADirectory)
+        );
+        assert_eq!(
+            tree.metadata(Path::new("/nope")).err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Walking the tree on the caller's thread, handing each entry to a callback
+//! that decides whether to go on, skip a directory's contents, or stop. No
+//! channel, no tasks: the walk goes as fast as the callback returns.
+
+use super::blocking::{self, List};
+use super::rank::Frontier;
+use super::results::Batch;
+use super::{filter, Backend, CrawlOptions, EntryKind, FileEntry, Filters};
+use crate::Error;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Whether the walk reads a directory it's come to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Descend {
+    Yes,
+    /// Leave out everything under it
+    Skip,
+}
+
+/// Hand `f` every matching file under `root`, every directory the filters
+/// would enter before entering it, and every error, in `options.order` or the
+/// custom rank's.
+/// Returns what `f` broke with, if it did.
+pub(crate) fn visit<B>(
+    root: PathBuf,
+    filters: &Filters,
+    options: &CrawlOptions,
+    mut f: impl FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend>,
+) -> Option<B> {
+    if let Err(e) = filters.check() {
+        return f(Err(e)).break_value();
+    }
+    let list = lister(filters, options.backend);
+    let rank = filters.rank.as_ref();
+    let mut frontier = Frontier::new(Arc::from(root), options.order, usize::MAX, rank);
+    let mut subdirs = Vec::new();
+
+    while !options.cancel.is_cancelled() {
+        let Some((dir, depth)) = frontier.pop() else {
+            break;
+        };
+        let read = read(list, &dir, depth, filters, options);
+        if let ControlFlow::Break(b) = hand_over(&dir, depth, read, filters, &mut f, &mut subdirs) {
+            return Some(b);
+        }
+        frontier.extend(&mut subdirs, depth + 1);
+    }
+    None
+}
+
+/// Hand `f` what was read in `dir`, `depth` below the root, then each
+/// subdirectory the filters would enter, keeping in `subdirs` those `f`
+/// lets the walk into.
+pub(super) fn hand_over<B>(
+    dir: &Arc<Path>,
+    depth: usize,
+    (mut found, entered): (Batch, Vec<Arc<Path>>),
+    filters: &Filters,
+    f: &mut impl FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend>,
+    subdirs: &mut Vec<Arc<Path>>,
+) -> ControlFlow<B> {
+    if filters.emit.contains(EntryKind::Dir) {
+        // Those it enters are handed over below, before they're read
+        let read: HashSet<&Path> = entered.iter().map(|sub| &**sub).collect();
+        found.retain(|item| !matches!(item, Ok(entry) if read.contains(entry.path())));
+    }
+    for item in found {
+        f(item)?;
+    }
+    let mut entered: Vec<_> = entered
+        .into_iter()
+        .map(|sub| {
+            let name = sub.file_name().unwrap_or_default().to_os_string();
+            let entry = FileEntry::in_dir(Arc::clone(dir), depth + 1, name);
+            (entry.with_kind(EntryKind::Dir), sub)
+        })
+        .collect();
+    if let Some(rank) = &filters.rank {
+        entered.sort_by(|(a, _), (b, _)| rank.compare(a, b));
+    }
+    for (entry, sub) in entered {
+        if let Descend::Yes = f(Ok(entry))? {
+            subdirs.push(sub);
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// The walk as an iterator: each time what was found in the last directory
+/// runs out, the next one is read on the caller's thread. Everything the
+/// filters would enter is entered; stop early by dropping it.
+#[cfg(any(test, all(feature = "blocking", not(feature = "tokio"))))]
+pub(crate) struct Entries {
+    filters: Filters,
+    options: CrawlOptions,
+    list: List,
+    /// None once the filters turned out not to be usable
+    frontier: Option<Frontier>,
+    found: std::vec::IntoIter<Result<FileEntry, Error>>,
+}
+
+#[cfg(any(test, all(feature = "blocking", not(feature = "tokio"))))]
+impl Entries {
+    pub fn new(root: PathBuf, filters: Filters, options: CrawlOptions) -> Self {
+        let (frontier, found) = match filters.check() {
+            Ok(()) => {
+                let rank = filters.rank.as_ref();
+                let root = Arc::from(root);
+                (
+                    Some(Frontier::new(root, options.order, usize::MAX, rank)),
+                    Vec::new(),
+                )
+            }
+            Err(e) => (None, vec![Err(e)]),
+        };
+        Entries {
+            list: lister(&filters, options.backend),
+            filters,
+            options,
+            frontier,
+            found: found.into_iter(),
+        }
+    }
+}
+
+#[cfg(any(test, all(feature = "blocking", not(feature = "tokio"))))]
+impl Iterator for Entries {
+    type Item = Result<FileEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.found.next() {
+                return Some(item);
+            }
+            if self.options.cancel.is_cancelled() {
+                return None;
+            }
+            let (dir, depth) = self.frontier.as_mut()?.pop()?;
+            let (found, mut subdirs) = read(self.list, &dir, depth, &self.filters, &self.options);
+            if let Some(frontier) = &mut self.frontier {
+                frontier.extend(&mut subdirs, depth + 1);
+            }
+            self.found = found.into_iter();
+        }
+    }
+}
+
+/// Read `dir`, `depth` below the root: what was found there, its errors
+/// first, and the subdirectories the filters would enter.
+pub(super) fn read(
+    list: List,
+    dir: &Arc<Path>,
+    depth: usize,
+    filters: &Filters,
+    options: &CrawlOptions,
+) -> (Batch, Vec<Arc<Path>>) {
+    let _span = tracing::debug_span!("read_dir", dir = %dir.display(), depth).entered();
+    let mut listing = list(dir, depth, filters);
+    filters
+        .files
+        .read_ahead(dir, &mut listing.found, options.stat);
+    filter::apply(
+        filters,
+        dir,
+        depth,
+        &mut listing.found,
+        &mut listing.subdirs,
+    );
+    let mut found: Batch = [listing.unopened, listing.failed]
+        .into_iter()
+        .flatten()
+        .map(Err)
+        .collect();
+    found.append(&mut listing.found);
+    (found, listing.subdirs)
+}
+
+/// How the walk reads a whole directory on the caller's thread. The other
+/// backends only read real disks.
+pub(super) fn lister(filters: &Filters, backend: Backend) -> List {
+    if !filters.files.is_real() {
+        return blocking::list;
+    }
+    match backend {
+        Backend::Tokio | Backend::Blocking => blocking::list,
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        Backend::IoUring => super::uring::list,
+        #[cfg(windows)]
+        Backend::Win32 => super::win32::list,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::{EntryKinds, Files, IgnoreFiles, MemFs, Order};
+
+    fn filters(pattern: &str, tree: MemFs) -> Filters {
+        Filters {
+            max_depth: usize::MAX,
+            pattern: pattern.into(),
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: IgnoreFiles::default(),
+            search_archives: false,
+            emit: EntryKinds::default(),
+            custom: Vec::new(),
+            rank: None,
+            files: Files::new(Arc::new(tree)),
+        }
+    }
+
+    #[test]
+    fn test_entries_read_one_directory_at_a_time() {
+        let tree = MemFs::new()
+            .with_file("/t/a.txt", "")
+            .with_file("/t/sub/b.txt", "")
+            .with_file("/t/sub/deeper/c.txt", "");
+        let options = CrawlOptions {
+            order: Order::Bfs,
+            ..CrawlOptions::default()
+        };
+        let cancel = options.cancel.clone();
+        let mut entries = Entries::new("/t".into(), filters("*", tree), options);
+
+        let first = entries.next().unwrap().unwrap();
+        assert_eq!(first.path(), Path::new("/t/a.txt"));
+        // Only the root has been read so far
+        assert!(entries.found.as_slice().is_empty());
+        let second = entries.next().unwrap().unwrap();
+        assert_eq!(second.path(), Path::new("/t/sub/b.txt"));
+
+        // Nothing more is read once cancelled
+        cancel.cancel();
+        assert!(entries.next().is_none());
+
+        // A pattern that can't match says so, and that's all
+        let tree = MemFs::new().with_file("/t/a.txt", "");
+        let invalid = Entries::new("/t".into(), filters("a/b", tree), CrawlOptions::default());
+        let items: Vec<_> = invalid.collect();
+        assert!(matches!(items[..], [Err(Error::PatternInvalid { .. })]));
+    }
+}
@@ -0,0 +1,783 @@
+//! The crawl on tokio's runtime, sending what it finds down a channel: on
+//! one task, or on many sharing the work (see the module above).
+
+use super::output::Output;
+use super::progress::Progress;
+use super::rank::Frontier;
+use super::results::{Batch, BATCH};
+use super::stat::{self, Fields};
+use super::stats::{self, Stats, Tuner};
+use super::throttle::Throttle;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use super::uring;
+use super::{blocking, filter, priority};
+use super::{classify, Backend, CrawlOptions, EntryKind, FileEntry, Filters, Found, Job};
+#[cfg(windows)]
+use super::{mft, win32};
+use super::{Order, Threads};
+use crate::Error;
+use anyhow::Result;
+use crossbeam_deque::{Injector, Stealer, Worker};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::fs;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinSet;
+use tracing::Instrument;
+
+/// What the crawl reads directories with and filters their entries by.
+struct Reader {
+    filters: Arc<Filters>,
+    backend: Backend,
+    stats: Arc<Stats>,
+    throttle: Option<Throttle>,
+    stat: Fields,
+}
+
+/// Send every matching file under `root` to `tx`, in batches, until they've
+/// all been sent or `options.cancel` is cancelled, which isn't an error.
+#[tracing::instrument(name = "search", skip_all, fields(root = %root.display()))]
+pub async fn crawl(
+    root: PathBuf,
+    filters: Filters,
+    options: &CrawlOptions,
+    tx: &mpsc::Sender<Batch>,
+) -> Result<()> {
+    filters.check()?;
+    if options.idle_priority {
+        if let Err(e) = priority::lower() {
+            eprintln!("Warning: couldn't lower the search's priority: {e}");
+        }
+    }
+    let stats = Arc::new(Stats::default());
+    let progress = options.progress.clone().map(Progress::new);
+    let reader = Reader {
+        filters: Arc::new(filters),
+        backend: options.backend,
+        stats: Arc::clone(&stats),
+        throttle: options.throttle.map(Throttle::new),
+        stat: Fields {
+            // For the progress's byte count
+            size: options.stat.size || progress.is_some(),
+            ..options.stat
+        },
+    };
+    let out = Arc::new(Output::new(
+        tx.clone(),
+        options.backpressure,
+        options.cancel.clone(),
+        progress,
+    ));
+    tracing::info!(threads = %options.threads, backend = %options.backend, "started");
+    let started = Instant::now();
+    let mut result = if options.mft && reader.filters.files.is_real() {
+        crawl_mft(root, reader, options, &out).await
+    } else {
+        walk(root, reader, options, &out).await
+    };
+    if result.is_ok() {
+        result = out.flush().await.map_err(Into::into);
+    }
+    if options.stats {
+        stats.print(started.elapsed());
+    }
+    let (dirs, _) = stats.totals();
+    let elapsed = started.elapsed();
+    let cancelled = out.is_cancelled();
+    tracing::info!(dirs, ?elapsed, cancelled, "finished");
+    if out.is_cancelled() {
+        return Ok(());
+    }
+    result
+}
+
+/// List the files under `root` from the master file table of its volume, or
+/// walk the tree after all if the volume doesn't have one.
+#[cfg(windows)]
+async fn crawl_mft(
+    root: PathBuf,
+    reader: Reader,
+    options: &CrawlOptions,
+    out: &Arc<Output>,
+) -> Result<()> {
+    let scanned = {
+        let (root, filters, out) = (root.clone(), Arc::clone(&reader.filters), Arc::clone(out));
+        tokio::task::spawn_blocking(move || mft::scan(&root, &filters, &out)).await?
+    };
+    match scanned {
+        Err(e) if e.is::<mft::Unsupported>() => {
+            eprintln!("Warning: {e}; reading directories instead");
+            walk(root, reader, options, out).await
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(windows))]
+async fn crawl_mft(
+    _root: PathBuf,
+    _reader: Reader,
+    _options: &CrawlOptions,
+    _out: &Arc<Output>,
+) -> Result<()> {
+    anyhow::bail!("--mft reads the NTFS master file table, which only Windows can do")
+}
+
+/// Read the tree directory by directory.
+async fn walk(
+    root: PathBuf,
+    reader: Reader,
+    options: &CrawlOptions,
+    out: &Arc<Output>,
+) -> Result<()> {
+    let max_frontier = options.max_frontier.unwrap_or(usize::MAX);
+    match options.threads {
+        // Only one frontier can be kept in order
+        _ if reader.filters.rank.is_some() => {
+            crawl_serial(root, &reader, options.order, max_frontier, out).await
+        }
+        Threads::Fixed(threads) if threads <= 1 => {
+            crawl_serial(root, &reader, options.order, max_frontier, out).await
+        }
+        threads => {
+            crawl_parallel(
+                root,
+                reader,
+                threads,
+                options.order,
+                max_frontier,
+                Arc::clone(out),
+            )
+            .await
+        }
+    }
+}
+
+/// Walks the tree without recursion, respecting .gitignore, hidden, patterns,
+/// etc., taking directories from the frontier in `order`, or the custom rank.
+async fn crawl_serial(
+    root: PathBuf,
+    reader: &Reader,
+    order: Order,
+    max_frontier: usize,
+    out: &Output,
+) -> Result<()> {
+    let rank = reader.filters.rank.as_ref();
+    let mut frontier = Frontier::new(Arc::from(root), order, max_frontier, rank);
+    let mut subdirs = Vec::new();
+
+    // Nothing may be sent for a while, so check between directories too
+    while !out.is_closed() {
+        let Some((dir, depth)) = frontier.pop() else {
+            break;
+        };
+        reader.read_dir(&dir, depth, out, &mut subdirs).await?;
+        frontier.extend(&mut subdirs, depth + 1);
+    }
+
+    Ok(())
+}
+
+impl Reader {
+    /// Send the matching files directly in `dir` (at `depth` below the root) to
+    /// `out`, and add the subdirectories to search next to `subdirs`. The files
+    /// found share `dir`. A directory that can't be opened is reported on
+    /// `out`; failing partway through it, or the receiver going away, is an
+    /// error.
+    #[tracing::instrument(skip_all, level = "debug", fields(dir = %dir.display(), depth = depth))]
+    async fn read_dir(
+        &self,
+        dir: &Arc<Path>,
+        depth: usize,
+        out: &Output,
+        subdirs: &mut Vec<Arc<Path>>,
+    ) -> Result<()> {
+        if let Some(throttle) = &self.throttle {
+            tokio::select! {
+                () = throttle.acquire(dir) => {}
+                () = out.closed() => return Ok(()),
+            }
+        }
+        if let Some(progress) = out.progress() {
+            progress.entered(dir, depth);
+        }
+        // The other backends only read real disks
+        let backend = if self.filters.files.is_real() {
+            self.backend
+        } else {
+            Backend::Blocking
+        };
+        let started = Instant::now();
+        let result = match backend {
+            Backend::Tokio => {
+                read_dir_async(dir, depth, &self.filters, self.stat, out, subdirs).await
+            }
+            Backend::Blocking => {
+                self.read_dir_with(blocking::list, dir, depth, out, subdirs)
+                    .await
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Backend::IoUring => {
+                self.read_dir_with(uring::list, dir, depth, out, subdirs)
+                    .await
+            }
+            #[cfg(windows)]
+            Backend::Win32 => {
+                self.read_dir_with(win32::list, dir, depth, out, subdirs)
+                    .await
+            }
+        };
+        self.stats.record(started.elapsed());
+        if let Some(progress) = out.progress() {
+            progress.read();
+        }
+        out.drain();
+        result
+    }
+
+    async fn read_dir_with(
+        &self,
+        list: blocking::List,
+        dir: &Arc<Path>,
+        depth: usize,
+        out: &Output,
+        subdirs: &mut Vec<Arc<Path>>,
+    ) -> Result<()> {
+        blocking::read_dir(dir, depth, &self.filters, self.stat, out, subdirs, list).await
+    }
+}
+
+/// [`Reader::read_dir`] through `tokio::fs`.
+async fn read_dir_async(
+    dir: &Arc<Path>,
+    depth: usize,
+    filters: &Arc<Filters>,
+    fields: Fields,
+    out: &Output,
+    subdirs: &mut Vec<Arc<Path>>,
+) -> Result<()> {
+    let gitignore = if filters.include_gitignored {
+        None
+    } else {
+        let (dir, filters) = (Arc::clone(dir), Arc::clone(filters));
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || span.in_scope(|| filters.gitignore_in(&dir, depth)))
+            .await?
+    };
+    let mut entries = match fs::read_dir(&**dir).await {
+        Ok(e) => e,
+        Err(e) => {
+            // e.g., permission denied or path doesn't exist
+            let _ = out.send(Err(Error::io(&**dir, e))).await;
+            return Ok(());
+        }
+    };
+
+    let (mut found, mut dirs) = (Vec::new(), Vec::new());
+    let broken = gitignore.as_deref().and_then(|rules| rules.error_in(dir));
+    found.extend(broken.map(Err));
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| Error::io(&**dir, e))?
+    {
+        if let Some(progress) = out.progress() {
+            progress.examined(1);
+        }
+        // A ranked directory's results go out together, in order
+        if found.len() >= BATCH && filters.rank.is_none() {
+            let batch = std::mem::take(&mut found);
+            let (batch, _) = finish(dir, depth, filters, fields, batch, Vec::new()).await?;
+            out.send_batch(batch).await?;
+        }
+        let name = entry.file_name();
+        let path = dir.join(&name);
+        let kind = match entry.file_type().await {
+            Ok(t) => EntryKind::from(t),
+            Err(e) => {
+                found.push(Err(Error::io(path, e)));
+                continue;
+            }
+        };
+
+        let found_as = classify(&path, kind, depth, gitignore.as_deref(), filters);
+        if let Found::Entry(_) | Found::Both = found_as {
+            let entry = FileEntry::in_dir(Arc::clone(dir), depth + 1, name);
+            found.push(Ok(entry.with_kind(kind)));
+        }
+        if let Found::Subdir | Found::Both = found_as {
+            dirs.push(path.into());
+        }
+    }
+
+    let (found, dirs) = finish(dir, depth, filters, fields, found, dirs).await?;
+    subdirs.extend(dirs);
+    out.send_batch(found).await?;
+    Ok(())
+}
+
+/// [`stat::read_ahead`] and the custom filters on a blocking thread, if
+/// there's anything for them to do.
+async fn finish(
+    dir: &Arc<Path>,
+    depth: usize,
+    filters: &Arc<Filters>,
+    fields: Fields,
+    mut found: Batch,
+    mut subdirs: Vec<Arc<Path>>,
+) -> Result<(Batch, Vec<Arc<Path>>)> {
+    if fields.is_empty() && filters.custom.is_empty() && filters.rank.is_none() {
+        return Ok((found, subdirs));
+    }
+    let (dir, filters) = (Arc::clone(dir), Arc::clone(filters));
+    let span = tracing::Span::current();
+    Ok(tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        stat::read_ahead(&dir, &mut found, fields);
+        filter::apply(&filters, &dir, depth, &mut found, &mut subdirs);
+        (found, subdirs)
+    })
+    .await?)
+}
+
+/// What the workers of a parallel crawl share.
+struct Pool {
+    reader: Reader,
+    out: Arc<Output>,
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    /// Directories queued or being read; the crawl is over when it hits zero
+    pending: AtomicUsize,
+    /// Past this many pending, workers keep what they find to themselves and
+    /// go depth-first
+    max_frontier: usize,
+    /// Workers past this many sit out; only `--threads auto` changes it
+    active: AtomicUsize,
+    /// Wakes idle workers when there is new work, or none left at all
+    notify: Notify,
+}
+
+async fn crawl_parallel(
+    root: PathBuf,
+    reader: Reader,
+    threads: Threads,
+    order: Order,
+    max_frontier: usize,
+    out: Arc<Output>,
+) -> Result<()> {
+    // Auto starts with a worker per core and can let in up to the most
+    let (threads, active) = match threads {
+        Threads::Fixed(threads) => (threads, threads),
+        Threads::Auto => {
+            let cores = std::thread::available_parallelism().map_or(4, usize::from);
+            (stats::MAX_THREADS, cores.min(stats::MAX_THREADS))
+        }
+    };
+    let new_worker = match order {
+        Order::Bfs => Worker::new_fifo,
+        Order::Dfs => Worker::new_lifo,
+    };
+    let workers: Vec<Worker<Job>> = (0..threads).map(|_| new_worker()).collect();
+    let pool = Arc::new(Pool {
+        reader,
+        out,
+        injector: Injector::new(),
+        stealers: workers.iter().map(Worker::stealer).collect(),
+        pending: AtomicUsize::new(1),
+        max_frontier,
+        active: AtomicUsize::new(active),
+        notify: Notify::new(),
+    });
+    pool.injector.push((Arc::from(root), 0));
+
+    let mut tasks = JoinSet::new();
+    for (index, local) in workers.into_iter().enumerate() {
+        let pool = Arc::clone(&pool);
+        tasks.spawn(async move { pool.work(index, local).await }.in_current_span());
+    }
+    let tuning = (active < threads).then(|| {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move { pool.tune().await })
+    });
+    // Wake idle workers to leave once nobody wants more results
+    let closing = {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move {
+            pool.out.closed().await;
+            pool.notify.notify_waiters();
+        })
+    };
+    let result = async {
+        while let Some(done) = tasks.join_next().await {
+            done?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Some(tuning) = tuning {
+        tuning.abort();
+    }
+    closing.abort();
+    result
+}
+
+impl Pool {
+    /// Read directories until there are none left anywhere, or nobody is
+    /// listening for results any more.
+    async fn work(&self, index: usize, local: Worker<Job>) {
+        let mut subdirs = Vec::new();
+        // Directories found while over the frontier cap, newest first
+        let mut own = Vec::new();
+        loop {
+            if index >= self.active.load(Ordering::SeqCst) {
+                // Sat out: leave what only we could see to the others (our
+                // queue they can steal) and wait to be let back in
+                if !own.is_empty() {
+                    own.drain(..).for_each(|job| self.injector.push(job));
+                    self.notify.notify_waiters();
+                }
+                let notified = self.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.finished() {
+                    return;
+                }
+                if index >= self.active.load(Ordering::SeqCst) {
+                    notified.await;
+                    continue;
+                }
+            }
+
+            let (dir, depth) = match own.pop().or_else(|| self.find_job(&local)) {
+                Some(job) => job,
+                None => {
+                    // Register for a wakeup before the last look, so work
+                    // queued in between isn't missed
+                    let notified = self.notify.notified();
+                    tokio::pin!(notified);
+                    notified.as_mut().enable();
+                    match self.find_job(&local) {
+                        Some(job) => job,
+                        None if self.finished() => return,
+                        None => {
+                            notified.await;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let result = self
+                .reader
+                .read_dir(&dir, depth, &self.out, &mut subdirs)
+                .await;
+            if !subdirs.is_empty() {
+                let found = subdirs.len();
+                let pending = self.pending.fetch_add(found, Ordering::SeqCst) + found;
+                let jobs = subdirs.drain(..).map(|sub| (sub, depth + 1));
+                if pending > self.max_frontier {
+                    own.extend(jobs);
+                } else {
+                    jobs.for_each(|job| local.push(job));
+                    self.notify.notify_waiters();
+                }
+            }
+            if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.notify.notify_waiters();
+            }
+
+            if let Err(e) = result {
+                // Unlike a single walker, the others carry on past a directory
+                // that failed partway
+                let e = Error::from_crawl(e, &dir);
+                if self.out.is_closed() || self.out.send(Err(e)).await.is_err() {
+                    self.notify.notify_waiters();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// `--threads auto`: every so often, let in as many workers as the
+    /// latency of the last reads says the storage can take.
+    async fn tune(&self) {
+        let stats = &self.reader.stats;
+        let mut tuner = Tuner::new(self.active.load(Ordering::SeqCst), self.stealers.len());
+        stats.tuned(&tuner);
+        let mut last = stats.totals();
+        loop {
+            tokio::time::sleep(stats::INTERVAL).await;
+            let now = stats.totals();
+            let before = tuner.limit;
+            let limit = tuner.update(now.0 - last.0, now.1 - last.1);
+            last = now;
+            if limit != before {
+                self.active.store(limit, Ordering::SeqCst);
+                stats.tuned(&tuner);
+                self.notify.notify_waiters();
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) == 0 || self.out.is_closed()
+    }
+
+    /// The next directory: from our own queue, else a batch from the shared
+    /// one, else one stolen from another worker.
+    fn find_job(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(|steal| steal.success())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::{channel, Backpressure, EntryKinds, Files, IgnoreFiles};
+
+    async fn results(root: &Path, threads: usize, max_depth: usize) -> (Vec<PathBuf>, usize) {
+        let options = CrawlOptions {
+            threads: Threads::Fixed(threads),
+            ..Default::default()
+        };
+        results_with(root, options, max_depth).await
+    }
+
+    async fn results_with(
+        root: &Path,
+        options: CrawlOptions,
+        max_depth: usize,
+    ) -> (Vec<PathBuf>, usize) {
+        let (tx, mut rx) = channel(100);
+        let filters = Filters {
+            max_depth,
+            pattern: "*".into(),
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: IgnoreFiles::default(),
+            search_archives: false,
+            emit: EntryKinds::default(),
+            custom: Vec::new(),
+            rank: None,
+            files: Files::default(),
+        };
+        let root = root.to_path_buf();
+        tokio::spawn(async move { crawl(root, filters, &options, &tx).await });
+
+        let (mut found, mut errors) = (Vec::new(), 0);
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => found.push(entry.into_path()),
+                Err(_) => errors += 1,
+            }
+        }
+        found.sort();
+        (found, errors)
+    }
+
+    #[tokio::test]
+    async fn test_depth_first_keeps_subtrees_together() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for dir in ["a/x/deep", "a/y", "b/z", "c"] {
+            let dir = tmp.path().join(dir);
+            std::fs::create_dir_all(&dir)?;
+            for i in 0..3 {
+                std::fs::write(dir.join(format!("{i}.txt")), "x")?;
+            }
+        }
+
+        for order in [Order::Bfs, Order::Dfs] {
+            let (tx, mut rx) = channel(100);
+            let options = CrawlOptions {
+                order,
+                ..Default::default()
+            };
+            let root = tmp.path().to_path_buf();
+            let filters = Filters {
+                max_depth: usize::MAX,
+                pattern: "*".into(),
+                extensions: None,
+                show_hidden: false,
+                include_gitignored: false,
+                ignore_files: IgnoreFiles::default(),
+                search_archives: false,
+                emit: EntryKinds::default(),
+                custom: Vec::new(),
+                rank: None,
+                files: Files::default(),
+            };
+            tokio::spawn(async move { crawl(root, filters, &options, &tx).await });
+            let mut found = Vec::new();
+            while let Some(item) = rx.recv().await {
+                found.push(item.unwrap().into_path());
+            }
+            assert_eq!(found.len(), 12);
+
+            let depth = |p: &PathBuf| p.components().count();
+            if order == Order::Bfs {
+                assert!(found.windows(2).all(|w| depth(&w[0]) <= depth(&w[1])));
+                continue;
+            }
+            // Everything under a directory comes out in one run
+            for dir in ["a", "a/x", "a/y", "b", "c"] {
+                let dir = tmp.path().join(dir);
+                let under: Vec<usize> = (0..found.len())
+                    .filter(|&i| found[i].starts_with(&dir))
+                    .collect();
+                assert_eq!(under.last().unwrap() - under[0] + 1, under.len(), "{dir:?}");
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_crawl_finds_the_same_files() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        // A wide, uneven tree: one deep chain and many shallow siblings
+        let mut deep = tmp.path().to_path_buf();
+        for level in 0..12 {
+            deep.push(format!("d{level}"));
+            std::fs::create_dir(&deep)?;
+            std::fs::write(deep.join("f.txt"), "x")?;
+        }
+        for i in 0..40 {
+            let dir = tmp.path().join(format!("wide{i}")).join("inner");
+            std::fs::create_dir_all(&dir)?;
+            for j in 0..5 {
+                std::fs::write(dir.join(format!("{j}.rs")), "x")?;
+            }
+        }
+        std::fs::create_dir(tmp.path().join(".hidden"))?;
+        std::fs::write(tmp.path().join(".hidden/skip.txt"), "x")?;
+
+        let (expected, errors) = results(tmp.path(), 1, usize::MAX).await;
+        assert_eq!((expected.len(), errors), (12 + 40 * 5, 0));
+        for threads in [2, 4, 16] {
+            for _ in 0..5 {
+                assert_eq!(
+                    results(tmp.path(), threads, usize::MAX).await,
+                    (expected.clone(), 0)
+                );
+            }
+        }
+        assert_eq!(
+            results(tmp.path(), 4, 2).await,
+            results(tmp.path(), 1, 2).await
+        );
+        let backends = [
+            Backend::Tokio,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Backend::IoUring,
+        ];
+        for (threads, backend) in [1, 4].into_iter().flat_map(|t| backends.map(|b| (t, b))) {
+            let options = CrawlOptions {
+                threads: Threads::Fixed(threads),
+                backend,
+                ..Default::default()
+            };
+            assert_eq!(
+                results_with(tmp.path(), options, usize::MAX).await,
+                (expected.clone(), 0)
+            );
+        }
+        // Holding results back changes when they arrive, not what's found
+        for threads in [1, 4] {
+            let options = CrawlOptions {
+                threads: Threads::Fixed(threads),
+                backpressure: Backpressure::Coalesce,
+                ..Default::default()
+            };
+            assert_eq!(
+                results_with(tmp.path(), options, usize::MAX).await,
+                (expected.clone(), 0)
+            );
+        }
+        // Capping the frontier changes the order, not what's found
+        for threads in [1, 4] {
+            let options = CrawlOptions {
+                threads: Threads::Fixed(threads),
+                max_frontier: Some(2),
+                ..Default::default()
+            };
+            assert_eq!(
+                results_with(tmp.path(), options, usize::MAX).await,
+                (expected.clone(), 0)
+            );
+        }
+
+        // Auto lets in only some of its workers to begin with
+        for _ in 0..5 {
+            let options = CrawlOptions {
+                threads: Threads::Auto,
+                ..Default::default()
+            };
+            assert_eq!(
+                results_with(tmp.path(), options, usize::MAX).await,
+                (expected.clone(), 0)
+            );
+        }
+
+        // An unreadable root is still reported once, and the workers all stop
+        let missing = tmp.path().join("missing");
+        assert_eq!(results(&missing, 4, usize::MAX).await, (vec![], 1));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_cancel_stops_the_crawl() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for i in 0..50 {
+            let dir = tmp.path().join(format!("d{i}"));
+            std::fs::create_dir(&dir)?;
+            std::fs::write(dir.join("f.txt"), "x")?;
+        }
+
+        for threads in [1, 4] {
+            let options = CrawlOptions {
+                threads: Threads::Fixed(threads),
+                ..Default::default()
+            };
+            let cancel = options.cancel.clone();
+            let (tx, mut rx) = channel(1);
+            let filters = Filters {
+                max_depth: usize::MAX,
+                pattern: "*".into(),
+                extensions: None,
+                show_hidden: false,
+                include_gitignored: false,
+                ignore_files: IgnoreFiles::default(),
+                search_archives: false,
+                emit: EntryKinds::default(),
+                custom: Vec::new(),
+                rank: None,
+                files: Files::default(),
+            };
+            let root = tmp.path().to_path_buf();
+            let handle = tokio::spawn(async move { crawl(root, filters, &options, &tx).await });
+
+            // Stop after the first result, with the crawl waiting for room:
+            // it returns, without an error, and without reading on
+            assert!(rx.recv().await.unwrap().is_ok());
+            cancel.cancel();
+            let done = tokio::time::timeout(std::time::Duration::from_secs(5), handle).await;
+            assert!(done.expect("the crawl should stop").unwrap().is_ok());
+            let mut rest = 0;
+            while rx.recv().await.is_some() {
+                rest += 1;
+            }
+            assert!(rest < 49, "{rest} more results after cancelling");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! The `win32` backend, the default on Windows. std lists a directory with
+//! FindFirstFileExW too, but the hidden check costs a metadata call per entry;
+//! here the attributes that come with each name answer it, and
+//! FIND_FIRST_EX_LARGE_FETCH asks for the listing in bigger batches.
+
+use super::blocking::Listing;
+use super::{classify_with, EntryKind, Filters, Rules};
+use crate::Error;
+use std::ffi::OsString;
+use std::io;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::sync::Arc;
+use windows_sys::Win32::Foundation::{ERROR_NO_MORE_FILES, HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::Storage::FileSystem::{
+    FindClose, FindExInfoBasic, FindExSearchNameMatch, FindFirstFileExW, FindNextFileW,
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_REPARSE_POINT,
+    FIND_FIRST_EX_LARGE_FETCH, WIN32_FIND_DATAW,
+};
+
+/// Reparse tags with this bit are links to somewhere else (symlinks,
+/// junctions), which std's `file_type()` doesn't count as directories either.
+const NAME_SURROGATE: u32 = 0x2000_0000;
+
+/// An open FindFirstFileExW search, closed on drop.
+struct Search(HANDLE);
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from a successful FindFirstFileExW
+        unsafe { FindClose(self.0) };
+    }
+}
+
+/// [`super::blocking::list`] with FindFirstFileExW.
+pub(super) fn list(dir: &Arc<Path>, depth: usize, filters: &Filters) -> Listing {
+    let mut listing = Listing::default();
+    let gitignore = filters.gitignore_in(dir, depth);
+    let broken = gitignore.as_deref().and_then(|rules| rules.error_in(dir));
+    listing.found.extend(broken.map(Err));
+    let pattern: Vec<u16> = dir
+        .join("*")
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    // SAFETY: WIN32_FIND_DATAW is plain old data, which the calls fill in
+    let mut data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+    // SAFETY: pattern is NUL-terminated and data is the size FindExInfoBasic
+    // writes
+    let handle = unsafe {
+        FindFirstFileExW(
+            pattern.as_ptr(),
+            FindExInfoBasic,
+            std::ptr::addr_of_mut!(data).cast(),
+            FindExSearchNameMatch,
+            std::ptr::null(),
+            FIND_FIRST_EX_LARGE_FETCH,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        // Even an empty directory lists . and .., so it's missing or unreadable
+        listing.unopened = Some(Error::io(&**dir, io::Error::last_os_error()));
+        return listing;
+    }
+    let search = Search(handle);
+
+    loop {
+        add(
+            &mut listing,
+            dir,
+            &data,
+            depth,
+            gitignore.as_deref(),
+            filters,
+        );
+        // SAFETY: the search is open and data is a WIN32_FIND_DATAW
+        if unsafe { FindNextFileW(search.0, &mut data) } == 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_NO_MORE_FILES as i32) {
+                listing.failed = Some(Error::io(&**dir, e));
+            }
+            return listing;
+        }
+    }
+}
+
+fn add(
+    listing: &mut Listing,
+    dir: &Arc<Path>,
+    data: &WIN32_FIND_DATAW,
+    depth: usize,
+    gitignore: Option<&Rules>,
+    filters: &Filters,
+) {
+    let len = data
+        .cFileName
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(data.cFileName.len());
+    let name = OsString::from_wide(&data.cFileName[..len]);
+    if name == "." || name == ".." {
+        return;
+    }
+    listing.examined += 1;
+
+    let attributes = data.dwFileAttributes;
+    // dwReserved0 holds the reparse tag of a reparse point
+    let is_link =
+        attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 && data.dwReserved0 & NAME_SURROGATE != 0;
+    let kind = if is_link {
+        EntryKind::Symlink
+    } else if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+        EntryKind::Dir
+    } else {
+        EntryKind::File
+    };
+    let hidden = attributes & FILE_ATTRIBUTE_HIDDEN != 0 || name.to_string_lossy().starts_with('.');
+
+    let path = dir.join(&name);
+    let found = classify_with(&path, kind, |_| hidden, depth, gitignore, filters);
+    listing.add(found, dir, depth, name, path);
+}
@@ -0,0 +1,168 @@
+//! How much space a tree's files take (`fs du`): the sizes of the files the
+//! search matches, totalled for each directory down to `--depth` below the
+//! root and for the whole tree, biggest first. A directory's total includes
+//! everything below it, as `du -d` has it, but only the files the search's
+//! filters let through (`fs du -e log` is the space the logs take). Sizes are
+//! file lengths, as `du --apparent-size` has them.
+
+use crate::actions::human_size;
+use crate::dup::OutputFormat;
+use crate::{search_files, SearchConfig};
+use anyhow::Result;
+use clap::Args;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Args, Debug)]
+pub struct DuArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Total up each directory down to this many below the root (0 only
+    /// totals the whole tree)
+    #[arg(short = 'd', long, value_name = "N", default_value_t = 1)]
+    pub depth: usize,
+
+    /// How to print the totals
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+}
+
+/// The size and number of the files under a directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+struct Total {
+    size: u64,
+    files: u64,
+}
+
+impl Total {
+    fn add(&mut self, size: u64) {
+        self.size += size;
+        self.files += 1;
+    }
+}
+
+/// The totals so far, for the root and each directory down to `depth`.
+struct Usage {
+    root: PathBuf,
+    depth: usize,
+    dirs: HashMap<PathBuf, Total>,
+    total: Total,
+}
+
+impl Usage {
+    fn new(root: &Path, depth: usize) -> Self {
+        Usage {
+            root: root.to_path_buf(),
+            depth,
+            dirs: HashMap::new(),
+            total: Total::default(),
+        }
+    }
+
+    /// Count the file at `path` towards the root and each directory it's in,
+    /// down to `depth`.
+    fn add(&mut self, path: &Path, size: u64) {
+        self.total.add(size);
+        let Some(dirs) = path.strip_prefix(&self.root).ok().and_then(Path::parent) else {
+            return;
+        };
+        let mut dir = self.root.clone();
+        for part in dirs.components().take(self.depth) {
+            dir.push(part);
+            self.dirs.entry(dir.clone()).or_default().add(size);
+        }
+    }
+
+    /// Each directory's total, biggest first, then the whole tree's.
+    fn rows(self) -> Vec<(PathBuf, Total)> {
+        let mut rows: Vec<_> = self.dirs.into_iter().collect();
+        rows.sort_by(|(a, a_total), (b, b_total)| b_total.size.cmp(&a_total.size).then(a.cmp(b)));
+        rows.push((self.root, self.total));
+        rows
+    }
+}
+
+pub async fn run(args: &DuArgs) -> Result<ExitCode> {
+    let mut config = args.search.clone();
+    config.crawl.stat.size = true;
+    let mut usage = Usage::new(&config.root_path, args.depth);
+
+    let mut rx = search_files(&config).await;
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) => match entry.size() {
+                Ok(size) => usage.add(entry.path(), size),
+                Err(e) => eprintln!("Error: {}: {e}", entry.path().display()),
+            },
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    let rows = usage.rows();
+    match args.format {
+        OutputFormat::Text => {
+            for (dir, total) in &rows {
+                println!(
+                    "{:>10} {:>8} {}",
+                    human_size(total.size),
+                    total.files,
+                    dir.display()
+                );
+            }
+        }
+        OutputFormat::Json => {
+            #[derive(Serialize)]
+            struct Row<'a> {
+                path: &'a Path,
+                #[serde(flatten)]
+                total: Total,
+            }
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(path, total)| Row {
+                    path,
+                    total: *total,
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directories_total_what_is_below_them() {
+        let root = Path::new("/tree");
+        let mut usage = Usage::new(root, 1);
+        for (path, size) in [
+            ("/tree/top.txt", 1),
+            ("/tree/a/one", 10),
+            ("/tree/a/deep/two", 20),
+            ("/tree/b/three", 40),
+        ] {
+            usage.add(Path::new(path), size);
+        }
+        let rows = usage.rows();
+        let total = |size, files| Total { size, files };
+        assert_eq!(
+            rows,
+            [
+                (root.join("b"), total(40, 1)),
+                (root.join("a"), total(30, 2)),
+                (root.to_path_buf(), total(71, 4)),
+            ]
+        );
+
+        // Only the whole tree at depth 0
+        let mut usage = Usage::new(root, 0);
+        usage.add(Path::new("/tree/a/one"), 10);
+        assert_eq!(usage.rows(), [(root.to_path_buf(), total(10, 1))]);
+    }
+}
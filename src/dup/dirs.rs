@@ -0,0 +1,369 @@
+//! Finding directories with identical contents (`fs dup --dirs`), such as a
+//! project checked out twice or a photo folder copied wholesale.
+//!
+//! A directory's identity is a Merkle-style rollup: a hash over its files'
+//! names and hashes and its subdirectories' names and identities. Hashing every
+//! file would be wasteful, so a cheap rollup of names and sizes comes first and
+//! only the files under directories that share it are read. Only the files the
+//! search found count, so e.g. `--pattern '*.jpg'` compares just the photos.
+//! When two directories match, their matching subdirectories aren't listed
+//! again.
+
+use super::{Candidate, OutputFormat};
+use crate::actions::human_size;
+use crate::hashing;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Directories with identical contents, sorted by path.
+#[derive(Debug)]
+pub struct DirSet {
+    pub hash: blake3::Hash,
+    pub dirs: Vec<PathBuf>,
+    /// Total size and number of files in each of them
+    pub size: u64,
+    pub files: usize,
+}
+
+impl DirSet {
+    /// Space that deleting all but one copy would free.
+    fn wasted(&self) -> u64 {
+        self.size * (self.dirs.len() as u64 - 1)
+    }
+}
+
+/// A directory under the root, as far as the search saw it.
+#[derive(Debug, Default)]
+struct Node {
+    /// File names and their index in the candidates
+    files: Vec<(OsString, usize)>,
+    subdirs: Vec<PathBuf>,
+    size: u64,
+    file_count: usize,
+}
+
+/// The sets of identical directories under `root` holding at least `min_size`
+/// bytes, biggest waste first. Also returns how many files couldn't be read.
+pub async fn find_duplicate_dirs(
+    candidates: Vec<Candidate>,
+    root: &Path,
+    min_size: u64,
+    jobs: usize,
+) -> (Vec<DirSet>, usize) {
+    let nodes = tree(&candidates, root);
+    // Deepest first, so every subdirectory is rolled up before its parent
+    let mut order: Vec<&PathBuf> = nodes.keys().collect();
+    order.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    let mut shapes: HashMap<&Path, blake3::Hash> = HashMap::new();
+    for dir in &order {
+        let node = &nodes[dir.as_path()];
+        let shape = rollup(
+            node,
+            |i| candidates[i].size.to_le_bytes(),
+            |sub| *shapes[sub.as_path()].as_bytes(),
+        );
+        shapes.insert(dir.as_path(), shape);
+    }
+
+    // Only directories sharing a shape can share contents
+    let mut by_shape: HashMap<blake3::Hash, Vec<&Path>> = HashMap::new();
+    for (&dir, &shape) in &shapes {
+        let node = &nodes[dir];
+        if node.size >= min_size {
+            by_shape.entry(shape).or_default().push(dir);
+        }
+    }
+    let mut needed = Vec::new();
+    for dirs in by_shape.values().filter(|dirs| dirs.len() > 1) {
+        for &dir in dirs {
+            files_below(&nodes, dir, &mut needed);
+        }
+    }
+    let (hashes, errors) = hash_files(&candidates, needed, jobs).await;
+
+    // Directories with an unreadable file somewhere below have no identity
+    let mut identities: HashMap<&Path, blake3::Hash> = HashMap::new();
+    for dir in &order {
+        let node = &nodes[dir.as_path()];
+        let complete = node.files.iter().all(|(_, i)| hashes.contains_key(i))
+            && node
+                .subdirs
+                .iter()
+                .all(|sub| identities.contains_key(sub.as_path()));
+        if complete {
+            let identity = rollup(
+                node,
+                |i| *hashes[&i].as_bytes(),
+                |sub| *identities[sub.as_path()].as_bytes(),
+            );
+            identities.insert(dir.as_path(), identity);
+        }
+    }
+
+    let mut by_identity: HashMap<blake3::Hash, Vec<&Path>> = HashMap::new();
+    for (&dir, &identity) in &identities {
+        if nodes[dir].size >= min_size {
+            by_identity.entry(identity).or_default().push(dir);
+        }
+    }
+    let duplicated: HashSet<blake3::Hash> = by_identity
+        .iter()
+        .filter(|(_, dirs)| dirs.len() > 1)
+        .map(|(&hash, _)| hash)
+        .collect();
+    let is_duplicated = |dir: &Path| identities.get(dir).is_some_and(|h| duplicated.contains(h));
+
+    let mut sets: Vec<DirSet> = by_identity
+        .into_iter()
+        .filter(|(_, dirs)| dirs.len() > 1)
+        // Inside directories that are already listed as copies
+        .filter(|(_, dirs)| {
+            !dirs
+                .iter()
+                .all(|dir| dir.parent().is_some_and(is_duplicated))
+        })
+        .map(|(hash, dirs)| {
+            let node = &nodes[dirs[0]];
+            let mut dirs: Vec<PathBuf> = dirs.into_iter().map(Path::to_path_buf).collect();
+            dirs.sort();
+            DirSet {
+                hash,
+                dirs,
+                size: node.size,
+                files: node.file_count,
+            }
+        })
+        .collect();
+    sets.sort_by(|a, b| {
+        b.wasted()
+            .cmp(&a.wasted())
+            .then_with(|| a.dirs.cmp(&b.dirs))
+    });
+    (sets, errors)
+}
+
+/// Every directory from `root` down that holds a found file, with its files,
+/// its subdirectories and the totals below it.
+fn tree(candidates: &[Candidate], root: &Path) -> HashMap<PathBuf, Node> {
+    let mut nodes: HashMap<PathBuf, Node> = HashMap::new();
+    for (i, file) in candidates.iter().enumerate() {
+        let (Some(parent), Some(name)) = (file.path.parent(), file.path.file_name()) else {
+            continue;
+        };
+        if !parent.starts_with(root) {
+            continue;
+        }
+        nodes
+            .entry(parent.to_path_buf())
+            .or_default()
+            .files
+            .push((name.to_os_string(), i));
+
+        let mut child = parent;
+        for dir in parent.ancestors() {
+            let node = nodes.entry(dir.to_path_buf()).or_default();
+            node.size += file.size;
+            node.file_count += 1;
+            if dir != child && !node.subdirs.iter().any(|sub| sub == child) {
+                node.subdirs.push(child.to_path_buf());
+            }
+            if dir == root {
+                break;
+            }
+            child = dir;
+        }
+    }
+    nodes
+}
+
+/// Hash a directory's entries in name order: each file's name and `file(i)`,
+/// each subdirectory's name and `subdir(path)`.
+fn rollup<F, D, const N: usize, const M: usize>(node: &Node, file: F, subdir: D) -> blake3::Hash
+where
+    F: Fn(usize) -> [u8; N],
+    D: Fn(&PathBuf) -> [u8; M],
+{
+    let mut entries: BTreeMap<(u8, &OsString), Vec<u8>> = BTreeMap::new();
+    for (name, i) in &node.files {
+        entries.insert((b'f', name), file(*i).to_vec());
+    }
+    let names: Vec<(OsString, &PathBuf)> = node
+        .subdirs
+        .iter()
+        .map(|sub| (sub.file_name().unwrap_or_default().to_os_string(), sub))
+        .collect();
+    for (name, sub) in &names {
+        entries.insert((b'd', name), subdir(sub).to_vec());
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    for ((kind, name), value) in entries {
+        let name = name.as_encoded_bytes();
+        hasher.update(&[kind]);
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(&value);
+    }
+    hasher.finalize()
+}
+
+/// Add the index of every file at or below `dir` to `out`.
+fn files_below(nodes: &HashMap<PathBuf, Node>, dir: &Path, out: &mut Vec<usize>) {
+    let node = &nodes[dir];
+    out.extend(node.files.iter().map(|(_, i)| *i));
+    for sub in &node.subdirs {
+        files_below(nodes, sub, out);
+    }
+}
+
+/// The full hash of each listed file, by index, with up to `jobs` read at once,
+/// and how many couldn't be read.
+async fn hash_files(
+    candidates: &[Candidate],
+    mut indexes: Vec<usize>,
+    jobs: usize,
+) -> (HashMap<usize, blake3::Hash>, usize) {
+    indexes.sort_unstable();
+    indexes.dedup();
+    let (tx, rx) = mpsc::channel(jobs.max(1) * 2);
+    let files: Vec<(usize, PathBuf)> = indexes
+        .into_iter()
+        .map(|i| (i, candidates[i].path.clone()))
+        .collect();
+    tokio::spawn(async move {
+        for file in files {
+            if tx.send(file).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut hashed = hashing::spawn_pool(rx, jobs, |(_, path): &(usize, PathBuf)| {
+        hashing::blake3_file(path)
+    });
+
+    let mut hashes = HashMap::new();
+    let mut errors = 0usize;
+    while let Some(((i, path), hash)) = hashed.recv().await {
+        match hash {
+            Ok(hash) => {
+                hashes.insert(i, hash);
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                errors += 1;
+            }
+        }
+    }
+    (hashes, errors)
+}
+
+#[derive(Serialize)]
+struct JsonReport {
+    sets: Vec<JsonSet>,
+    wasted: u64,
+}
+
+#[derive(Serialize)]
+struct JsonSet {
+    size: u64,
+    files: usize,
+    hash: String,
+    wasted: u64,
+    dirs: Vec<String>,
+}
+
+pub fn print(sets: &[DirSet], format: OutputFormat) -> Result<()> {
+    let wasted: u64 = sets.iter().map(DirSet::wasted).sum();
+    if format == OutputFormat::Json {
+        let report = JsonReport {
+            sets: sets
+                .iter()
+                .map(|set| JsonSet {
+                    size: set.size,
+                    files: set.files,
+                    hash: set.hash.to_hex().to_string(),
+                    wasted: set.wasted(),
+                    dirs: set.dirs.iter().map(|d| d.display().to_string()).collect(),
+                })
+                .collect(),
+            wasted,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    for set in sets {
+        println!(
+            "{} directories of {} file(s), {} ({} wasted):",
+            set.dirs.len(),
+            set.files,
+            human_size(set.size),
+            human_size(set.wasted())
+        );
+        for dir in &set.dirs {
+            println!("  {}", dir.display());
+        }
+        println!();
+    }
+    let redundant: usize = sets.iter().map(|set| set.dirs.len() - 1).sum();
+    println!(
+        "{} duplicate directory set(s), {redundant} redundant director(ies), {} wasted",
+        sets.len(),
+        human_size(wasted)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_identical_subtrees() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        // photos/ and backup/photos-copy/ match, down to the nested raw/ folders;
+        // near/ has the same names and sizes but one different byte
+        for dir in ["photos", "backup/photos-copy", "near"] {
+            std::fs::create_dir_all(root.join(dir).join("raw"))?;
+            std::fs::write(root.join(dir).join("a.jpg"), "aaaa")?;
+            let raw = if dir == "near" { "RAW!" } else { "raw!" };
+            std::fs::write(root.join(dir).join("raw/b.cr2"), raw)?;
+        }
+        std::fs::write(root.join("backup/notes.txt"), "hi")?;
+
+        let mut candidates = Vec::new();
+        for path in [
+            "photos/a.jpg",
+            "photos/raw/b.cr2",
+            "backup/photos-copy/a.jpg",
+            "backup/photos-copy/raw/b.cr2",
+            "near/a.jpg",
+            "near/raw/b.cr2",
+            "backup/notes.txt",
+        ] {
+            let path = root.join(path);
+            candidates.push(Candidate {
+                size: std::fs::metadata(&path)?.len(),
+                modified: None,
+                order: candidates.len(),
+                path,
+            });
+        }
+
+        let (sets, errors) = find_duplicate_dirs(candidates, root, 1, 2).await;
+        assert_eq!(errors, 0);
+        let listed: Vec<_> = sets.iter().map(|set| set.dirs.clone()).collect();
+        assert_eq!(
+            listed,
+            [vec![root.join("backup/photos-copy"), root.join("photos")]]
+        );
+        assert_eq!((sets[0].size, sets[0].files, sets[0].wasted()), (8, 2, 8));
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+//! Finding near-duplicates (`fs dup --similar`) with context-triggered
+//! piecewise hashing, the scheme behind ssdeep.
+//!
+//! A rolling hash over the last 7 bytes picks cut points that depend only on
+//! nearby content, so an edit moves or changes just the pieces around it. Each
+//! piece adds one character to a signature, and two files are as similar as
+//! their signatures are close in edit distance, scored 0 to 100. Signatures
+//! are only compared when they share a run of 7 characters, which an index
+//! finds without comparing every pair.
+
+use super::{Candidate, OutputFormat};
+use crate::hashing;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+const WINDOW: usize = 7;
+const MIN_BLOCK_SIZE: u64 = 3;
+/// The longest signature; the one for the doubled block size gets half
+const SIGNATURE_LENGTH: usize = 64;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// A file's fuzzy hash: signatures at one block size and at twice that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyHash {
+    block_size: u64,
+    signature: Vec<u8>,
+    double: Vec<u8>,
+}
+
+impl fmt::Display for FuzzyHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.block_size,
+            String::from_utf8_lossy(&self.signature),
+            String::from_utf8_lossy(&self.double)
+        )
+    }
+}
+
+/// The sum of the last [`WINDOW`] bytes, weighted and unweighted, plus a
+/// shifting xor of them.
+#[derive(Default)]
+struct Rolling {
+    window: [u8; WINDOW],
+    n: usize,
+    h1: u32,
+    h2: u32,
+    h3: u32,
+}
+
+impl Rolling {
+    fn update(&mut self, byte: u8) -> u32 {
+        let c = byte as u32;
+        self.h2 = self
+            .h2
+            .wrapping_sub(self.h1)
+            .wrapping_add(WINDOW as u32 * c);
+        self.h1 = self
+            .h1
+            .wrapping_add(c)
+            .wrapping_sub(self.window[self.n % WINDOW] as u32);
+        self.window[self.n % WINDOW] = byte;
+        self.n += 1;
+        self.h3 = (self.h3 << 5) ^ c;
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+/// One signature being built: the hash of the current piece and the
+/// characters for the finished ones.
+struct Piece {
+    hash: u32,
+    started: bool,
+    signature: Vec<u8>,
+    limit: usize,
+}
+
+impl Piece {
+    const INIT: u32 = 0x2802_1967;
+
+    fn new(limit: usize) -> Self {
+        Piece {
+            hash: Self::INIT,
+            started: false,
+            signature: Vec::new(),
+            limit,
+        }
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.hash = self.hash.wrapping_mul(0x0100_0193) ^ byte as u32;
+        self.started = true;
+    }
+
+    /// End the piece at a cut point. Once the signature is nearly full the last
+    /// piece runs on to the end of the file instead.
+    fn cut(&mut self) {
+        if self.signature.len() < self.limit - 1 {
+            self.signature.push(BASE64[(self.hash % 64) as usize]);
+            self.hash = Self::INIT;
+            self.started = false;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.started {
+            self.signature.push(BASE64[(self.hash % 64) as usize]);
+        }
+        self.signature
+    }
+}
+
+/// Fuzzy-hash the file at `path`, which is `size` bytes long, in one pass.
+///
+/// The block size is the smallest `3 * 2^k` that would cut the file into at
+/// most 64 pieces, halved while that leaves the signature under half full.
+/// Every block size that could end up chosen is hashed at once, so the file is
+/// read a single time.
+pub fn hash_file(path: &Path, size: u64) -> io::Result<FuzzyHash> {
+    let mut top = 0;
+    while MIN_BLOCK_SIZE << top << 6 < size {
+        top += 1;
+    }
+    // Level k cuts at block size 3 * 2^k; each needs a full-length signature
+    // (for when it's chosen) and a half-length one (for when the level below is)
+    let mut levels: Vec<(u64, Piece, Piece)> = (0..=top + 1)
+        .map(|k| {
+            let block_size = MIN_BLOCK_SIZE << k;
+            (
+                block_size,
+                Piece::new(SIGNATURE_LENGTH),
+                Piece::new(SIGNATURE_LENGTH / 2),
+            )
+        })
+        .collect();
+
+    let mut rolling = Rolling::default();
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buf[..n] {
+            let roll = rolling.update(byte) as u64;
+            for (block_size, full, half) in &mut levels {
+                full.update(byte);
+                half.update(byte);
+                if roll % *block_size == *block_size - 1 {
+                    full.cut();
+                    half.cut();
+                }
+            }
+        }
+    }
+
+    let mut signatures: Vec<(u64, Vec<u8>, Vec<u8>)> = levels
+        .into_iter()
+        .map(|(block_size, full, half)| (block_size, full.finish(), half.finish()))
+        .collect();
+    let mut k = top;
+    while k > 0 && signatures[k].1.len() <= SIGNATURE_LENGTH / 2 {
+        k -= 1;
+    }
+    let double = std::mem::take(&mut signatures[k + 1].2);
+    let (block_size, signature, _) = signatures.swap_remove(k);
+    Ok(FuzzyHash {
+        block_size,
+        signature,
+        double,
+    })
+}
+
+/// How alike two fuzzy hashes are, from 0 (nothing in common) to 100. Only
+/// hashes whose block sizes are equal or a factor of two apart can be compared.
+pub fn similarity(a: &FuzzyHash, b: &FuzzyHash) -> u32 {
+    let (a1, a2) = (collapse(&a.signature), collapse(&a.double));
+    let (b1, b2) = (collapse(&b.signature), collapse(&b.double));
+    if a.block_size == b.block_size {
+        score(&a1, &b1, a.block_size).max(score(&a2, &b2, a.block_size * 2))
+    } else if a.block_size == b.block_size * 2 {
+        score(&a1, &b2, a.block_size)
+    } else if a.block_size * 2 == b.block_size {
+        score(&a2, &b1, b.block_size)
+    } else {
+        0
+    }
+}
+
+/// Shorten runs of one character to three, which carry no more information
+/// and would otherwise dominate the edit distance.
+fn collapse(signature: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(signature.len());
+    for (i, &c) in signature.iter().enumerate() {
+        if i < 3 || signature[i - 3..i].iter().any(|&p| p != c) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Score two signatures made at the same block size.
+fn score(a: &[u8], b: &[u8], block_size: u64) -> u32 {
+    if a.len() < WINDOW || b.len() < WINDOW || !shares_window(a, b) {
+        return 0;
+    }
+    let distance = edit_distance(a, b) as u64;
+    let scaled = distance * SIGNATURE_LENGTH as u64 / (a.len() + b.len()) as u64;
+    let scaled = 100 * scaled / SIGNATURE_LENGTH as u64;
+    if scaled >= 100 {
+        return 0;
+    }
+    let score = 100 - scaled;
+    // Small files make short signatures, which match by chance too easily
+    let cap_below = (99 + WINDOW as u64) / WINDOW as u64 * MIN_BLOCK_SIZE;
+    if block_size >= cap_below {
+        return score as u32;
+    }
+    let cap = block_size / MIN_BLOCK_SIZE * a.len().min(b.len()) as u64;
+    score.min(cap) as u32
+}
+
+fn shares_window(a: &[u8], b: &[u8]) -> bool {
+    let windows: HashSet<&[u8]> = a.windows(WINDOW).collect();
+    b.windows(WINDOW).any(|w| windows.contains(w))
+}
+
+/// Insertions and deletions cost 1, substitutions 2.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + if ca == cb { 0 } else { 2 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Files that are all alike, by chains of matches scoring at least the
+/// threshold, sorted by path.
+#[derive(Debug)]
+pub struct SimilarSet {
+    /// Each file and its best score against another file of the set
+    pub files: Vec<(PathBuf, u32, FuzzyHash)>,
+}
+
+/// Fuzzy-hash every candidate and group those scoring at least `threshold`
+/// against each other, biggest groups first. Also returns how many files
+/// couldn't be read.
+pub async fn find_similar(
+    candidates: Vec<Candidate>,
+    threshold: u32,
+    jobs: usize,
+) -> (Vec<SimilarSet>, usize) {
+    let (tx, rx) = mpsc::channel(jobs.max(1) * 2);
+    tokio::spawn(async move {
+        for candidate in candidates {
+            if tx.send(candidate).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut hashed = hashing::spawn_pool(rx, jobs, |candidate: &Candidate| {
+        hash_file(&candidate.path, candidate.size)
+    });
+
+    let mut files: Vec<(Candidate, FuzzyHash)> = Vec::new();
+    let mut errors = 0usize;
+    while let Some((candidate, hash)) = hashed.recv().await {
+        match hash {
+            Ok(hash) => files.push((candidate, hash)),
+            Err(e) => {
+                eprintln!("Error: {}: {e}", candidate.path.display());
+                errors += 1;
+            }
+        }
+    }
+    files.sort_by_key(|(candidate, _)| candidate.order);
+    let hashes: Vec<&FuzzyHash> = files.iter().map(|(_, hash)| hash).collect();
+    let (groups, best) = cluster(&hashes, threshold);
+
+    let mut sets: Vec<SimilarSet> = groups
+        .into_iter()
+        .map(|members| {
+            let mut files: Vec<_> = members
+                .into_iter()
+                .map(|i| (files[i].0.path.clone(), best[i], files[i].1.clone()))
+                .collect();
+            files.sort_by(|a, b| a.0.cmp(&b.0));
+            SimilarSet { files }
+        })
+        .collect();
+    sets.sort_by(|a, b| {
+        b.files
+            .len()
+            .cmp(&a.files.len())
+            .then_with(|| a.files[0].0.cmp(&b.files[0].0))
+    });
+    (sets, errors)
+}
+
+/// Group the hashes joined by a score of at least `threshold`, as lists of
+/// indexes with more than one member, and each hash's best score.
+fn cluster(hashes: &[&FuzzyHash], threshold: u32) -> (Vec<Vec<usize>>, Vec<u32>) {
+    // Signatures sharing a 7-character run at the same block size, the only
+    // pairs that can score above zero
+    let mut index: HashMap<(u64, &[u8]), Vec<usize>> = HashMap::new();
+    let collapsed: Vec<(Vec<u8>, Vec<u8>)> = hashes
+        .iter()
+        .map(|h| (collapse(&h.signature), collapse(&h.double)))
+        .collect();
+    for (i, hash) in hashes.iter().enumerate() {
+        let (signature, double) = &collapsed[i];
+        let mut seen = HashSet::new();
+        for (block_size, sig) in [(hash.block_size, signature), (hash.block_size * 2, double)] {
+            for window in sig.windows(WINDOW) {
+                if seen.insert((block_size, window)) {
+                    index.entry((block_size, window)).or_default().push(i);
+                }
+            }
+        }
+    }
+    let mut pairs = HashSet::new();
+    for members in index.values() {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                pairs.insert((i.min(j), i.max(j)));
+            }
+        }
+    }
+
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut best = vec![0; hashes.len()];
+    for (i, j) in pairs {
+        let score = similarity(hashes[i], hashes[j]);
+        if score >= threshold {
+            best[i] = best[i].max(score);
+            best[j] = best[j].max(score);
+            let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+            parent[ri.max(rj)] = ri.min(rj);
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..hashes.len() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(i);
+    }
+    let groups = groups.into_values().filter(|g| g.len() > 1).collect();
+    (groups, best)
+}
+
+#[derive(Serialize)]
+struct JsonSet {
+    files: Vec<JsonFile>,
+}
+
+#[derive(Serialize)]
+struct JsonFile {
+    path: String,
+    /// Best score against another file of the set
+    similarity: u32,
+    fuzzy_hash: String,
+}
+
+pub fn print(sets: &[SimilarSet], threshold: u32, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        let sets: Vec<JsonSet> = sets
+            .iter()
+            .map(|set| JsonSet {
+                files: set
+                    .files
+                    .iter()
+                    .map(|(path, score, hash)| JsonFile {
+                        path: path.display().to_string(),
+                        similarity: *score,
+                        fuzzy_hash: hash.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&sets)?);
+        return Ok(());
+    }
+
+    for set in sets {
+        println!("{} similar files:", set.files.len());
+        for (path, score, _) in &set.files {
+            println!("  {score:>3}%  {}", path.display());
+        }
+        println!();
+    }
+    let files: usize = sets.iter().map(|set| set.files.len()).sum();
+    println!(
+        "{} group(s) of {files} file(s) at least {threshold}% similar",
+        sets.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic text that doesn't repeat, so cut points are spread out.
+    fn text(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"abcdefghij klmnopqrstuvwxyz\n"[(state >> 33) as usize % 28]
+            })
+            .collect()
+    }
+
+    fn hash(dir: &Path, name: &str, data: &[u8]) -> io::Result<FuzzyHash> {
+        let path = dir.join(name);
+        std::fs::write(&path, data)?;
+        hash_file(&path, data.len() as u64)
+    }
+
+    #[test]
+    fn test_edits_keep_files_similar() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let original = text(1, 40_000);
+        let mut edited = original.clone();
+        edited.splice(
+            20_000..20_000,
+            b"a new paragraph in the middle".iter().copied(),
+        );
+        edited[5_000..5_010].copy_from_slice(b"0123456789");
+        let other = text(2, 40_000);
+
+        let a = hash(tmp.path(), "a", &original)?;
+        let b = hash(tmp.path(), "b", &edited)?;
+        let c = hash(tmp.path(), "c", &other)?;
+        assert!(a.signature.len() > SIGNATURE_LENGTH / 2, "{a}");
+        assert_eq!(similarity(&a, &a), 100);
+        assert!(similarity(&a, &b) >= 80, "{a} vs {b}");
+        assert_eq!(similarity(&a, &c), 0, "{a} vs {c}");
+
+        let (groups, best) = cluster(&[&a, &b, &c], 80);
+        assert_eq!(groups, [vec![0, 1]]);
+        assert_eq!(best[2], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collapse_and_edit_distance() {
+        assert_eq!(collapse(b"aaaaabbbc"), b"aaabbbc");
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 5);
+        assert_eq!(edit_distance(b"", b"abc"), 3);
+    }
+}
@@ -0,0 +1,348 @@
+//! Making duplicates share one copy's data on disk (`fs dup --dedupe`), either
+//! as copy-on-write clones (reflink: btrfs, XFS, APFS) or as hard links to the
+//! kept file, and giving hard-linked files their own copies back (`--undo`).
+//!
+//! Each redundant copy is replaced by a clone of, or a link to, the kept one:
+//! it is made next to the copy and renamed over it. Nothing is replaced if
+//! either file changed since it was hashed, so the contents at every path stay
+//! exactly what they were. A clone gets the replaced file's permissions, owner
+//! and modification time; a hard link can't have its own, so they are recorded
+//! in a report instead, which `--undo` restores them from.
+
+use super::{Candidate, Dedupe};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What a file had of its own besides its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attributes {
+    /// Nanoseconds since the Unix epoch
+    pub mtime_ns: i64,
+    /// Permission bits, owner and group; only recorded on Unix
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Attributes {
+    fn of(meta: &Metadata) -> Self {
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(meta.mode() & 0o7777),
+                Some(meta.uid()),
+                Some(meta.gid()),
+            )
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        Attributes {
+            mtime_ns: crate::manifest::mtime_ns(meta),
+            mode,
+            uid,
+            gid,
+        }
+    }
+
+    /// Give the file at `path` these permissions, owner and modification time.
+    fn apply(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            if let Some(mode) = self.mode {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+            }
+            let current = fs::metadata(path)?;
+            let uid = self.uid.filter(|&uid| uid != current.uid());
+            let gid = self.gid.filter(|&gid| gid != current.gid());
+            if uid.is_some() || gid.is_some() {
+                std::os::unix::fs::chown(path, uid, gid)?;
+            }
+        }
+        let secs = self.mtime_ns.div_euclid(1_000_000_000);
+        let nanos = self.mtime_ns.rem_euclid(1_000_000_000) as u32;
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, nanos))
+    }
+}
+
+/// One file replaced by a hard link: a line of the `--report` that `--undo` reads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRecord {
+    pub path: PathBuf,
+    pub linked_to: PathBuf,
+    pub size: u64,
+    #[serde(flatten)]
+    pub attributes: Attributes,
+}
+
+/// What re-linking one file did.
+#[derive(Debug)]
+pub enum Relinked {
+    Cloned,
+    HardLinked(LinkRecord),
+    /// It already was a hard link to the kept file
+    AlreadyLinked,
+}
+
+/// Make `target` share `source`'s data by `method`, after checking neither
+/// changed since the search saw them.
+pub fn relink(source: &Candidate, target: &Candidate, method: Dedupe) -> io::Result<Relinked> {
+    let source_meta = unchanged(source)?;
+    let meta = unchanged(target)?;
+    if method == Dedupe::Hardlink && same_file(&source_meta, &meta) {
+        return Ok(Relinked::AlreadyLinked);
+    }
+    if links(&meta) > 1 {
+        // Replacing this name would leave the other links holding the old data
+        return Err(io::Error::other(
+            "has other hard links; re-linking it would free nothing",
+        ));
+    }
+    if method == Dedupe::Hardlink && !same_device(&source_meta, &meta) {
+        return Err(io::Error::other(format!(
+            "is on a different file system than {}, so it can't be linked to it",
+            source.path.display()
+        )));
+    }
+
+    let tmp = temp_path(&target.path);
+    let made = match method {
+        Dedupe::Reflink => {
+            clone_file(&source.path, &tmp).and_then(|()| Attributes::of(&meta).apply(&tmp))
+        }
+        Dedupe::Hardlink => fs::hard_link(&source.path, &tmp),
+    };
+    replace(&tmp, &target.path, made)?;
+
+    Ok(match method {
+        Dedupe::Reflink => Relinked::Cloned,
+        Dedupe::Hardlink => Relinked::HardLinked(LinkRecord {
+            path: target.path.clone(),
+            linked_to: source.path.clone(),
+            size: target.size,
+            attributes: Attributes::of(&meta),
+        }),
+    })
+}
+
+/// Give a hard-linked file its own copy of the data again, with the attributes
+/// it had before it was linked.
+pub fn undo(record: &LinkRecord) -> io::Result<()> {
+    let meta = fs::symlink_metadata(&record.path)?;
+    let source_meta = fs::symlink_metadata(&record.linked_to)?;
+    if !same_file(&source_meta, &meta) {
+        return Err(io::Error::other(format!(
+            "is no longer a hard link to {}",
+            record.linked_to.display()
+        )));
+    }
+
+    let tmp = temp_path(&record.path);
+    let made = fs::copy(&record.linked_to, &tmp).and_then(|_| record.attributes.apply(&tmp));
+    replace(&tmp, &record.path, made)
+}
+
+/// Rename the finished `tmp` over `path`, or clean it up if making it failed.
+fn replace(tmp: &Path, path: &Path, made: io::Result<()>) -> io::Result<()> {
+    let result = made.and_then(|()| fs::rename(tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(tmp);
+    }
+    result
+}
+
+/// The file's current metadata, or an error if its size or modification time
+/// no longer match what was hashed.
+pub fn unchanged(candidate: &Candidate) -> io::Result<Metadata> {
+    let meta = fs::symlink_metadata(&candidate.path)?;
+    if !meta.is_file() || meta.len() != candidate.size || meta.modified().ok() != candidate.modified
+    {
+        return Err(io::Error::other(format!(
+            "{} changed since it was hashed",
+            candidate.path.display()
+        )));
+    }
+    Ok(meta)
+}
+
+#[cfg(unix)]
+fn links(meta: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(meta)
+}
+
+#[cfg(not(unix))]
+fn links(_meta: &Metadata) -> u64 {
+    1
+}
+
+#[cfg(unix)]
+fn same_device(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev()
+}
+
+/// Without device numbers, leave it to the link call to refuse.
+#[cfg(not(unix))]
+fn same_device(_a: &Metadata, _b: &Metadata) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    false
+}
+
+/// A name beside `path` for the replacement, so the final rename stays within
+/// one directory and file system.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.fs-dedupe-{}", std::process::id()))
+}
+
+/// Create `dest` as a copy-on-write clone of `src` with the FICLONE ioctl.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn clone_file(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let src = fs::File::open(src)?;
+    let dest_file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(dest)?;
+    // SAFETY: both descriptors are open for as long as the call runs
+    if unsafe { libc::ioctl(dest_file.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } == 0 {
+        return Ok(());
+    }
+    Err(unsupported(io::Error::last_os_error()))
+}
+
+/// Create `dest` as a copy-on-write clone of `src` with clonefile(2).
+#[cfg(target_vendor = "apple")]
+fn clone_file(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dest = CString::new(dest.as_os_str().as_bytes())?;
+    // SAFETY: both paths are NUL-terminated and outlive the call
+    if unsafe { libc::clonefile(src.as_ptr(), dest.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    Err(unsupported(io::Error::last_os_error()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn clone_file(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks aren't supported on this platform",
+    ))
+}
+
+/// Name the usual reasons a clone is refused instead of the bare errno text.
+#[cfg(unix)]
+fn unsupported(e: io::Error) -> io::Error {
+    match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) | Some(libc::ENOTTY) => io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this file system doesn't support reflinks",
+        ),
+        Some(libc::EXDEV) => io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the copies are on different file systems, which can't share data",
+        ),
+        _ => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &Path) -> Candidate {
+        let meta = fs::metadata(path).unwrap();
+        Candidate {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            order: 0,
+        }
+    }
+
+    #[test]
+    fn test_relink_keeps_contents_and_refuses_changed_files() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let a = tmp.path().join("a.bin");
+        let b = tmp.path().join("b.bin");
+        fs::write(&a, "same bytes")?;
+        fs::write(&b, "same bytes")?;
+        let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&b, old)?;
+        let (source, target) = (candidate(&a), candidate(&b));
+
+        // Whether or not the temp dir's file system can clone, nothing is lost
+        match relink(&source, &target, Dedupe::Reflink) {
+            Ok(_) => {
+                let meta = fs::metadata(&b)?;
+                assert_eq!(filetime::FileTime::from_last_modification_time(&meta), old);
+            }
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported, "{e}"),
+        }
+        assert_eq!(fs::read(&b)?, b"same bytes");
+        assert_eq!(fs::read_dir(tmp.path())?.count(), 2);
+
+        fs::write(&b, "other data")?;
+        let err = relink(&source, &target, Dedupe::Reflink).unwrap_err();
+        assert!(err.to_string().contains("changed since it was hashed"));
+        assert_eq!(fs::read(&b)?, b"other data");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_then_undo() -> io::Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let tmp = tempfile::tempdir()?;
+        let a = tmp.path().join("a.bin");
+        let b = tmp.path().join("b.bin");
+        fs::write(&a, "same bytes")?;
+        fs::write(&b, "same bytes")?;
+        fs::set_permissions(&b, fs::Permissions::from_mode(0o600))?;
+        let old = filetime::FileTime::from_unix_time(1_000_000_000, 500);
+        filetime::set_file_mtime(&b, old)?;
+        let (source, target) = (candidate(&a), candidate(&b));
+
+        let Relinked::HardLinked(record) = relink(&source, &target, Dedupe::Hardlink)? else {
+            panic!("expected a new hard link");
+        };
+        assert_eq!(fs::metadata(&a)?.ino(), fs::metadata(&b)?.ino());
+        assert_eq!(record.attributes.mode, Some(0o600));
+        assert!(matches!(
+            relink(&source, &candidate(&b), Dedupe::Hardlink)?,
+            Relinked::AlreadyLinked
+        ));
+
+        // The record survives the report's JSON round trip
+        let record: LinkRecord = serde_json::from_str(&serde_json::to_string(&record)?)?;
+        undo(&record)?;
+        let meta = fs::metadata(&b)?;
+        assert_ne!(fs::metadata(&a)?.ino(), meta.ino());
+        assert_eq!(fs::read(&b)?, b"same bytes");
+        assert_eq!(meta.mode() & 0o7777, 0o600);
+        assert_eq!(filetime::FileTime::from_last_modification_time(&meta), old);
+
+        let err = undo(&record).unwrap_err();
+        assert!(err.to_string().contains("no longer a hard link"));
+        Ok(())
+    }
+}
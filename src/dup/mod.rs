@@ -0,0 +1,806 @@
+//! Finding duplicate files (`fs dup`).
+//!
+//! Candidates are narrowed in rounds that each cost more than the last: files
+//! are grouped by size, then by a BLAKE3 hash of their first and last 64 KiB
+//! (`--partial-size`), and only files still sharing a group are hashed in full.
+//! Files no bigger than both ends together are settled by the partial round.
+//! Each hashing round runs in the shared hashing pool.
+
+mod dirs;
+mod fuzzy;
+mod link;
+
+use crate::actions::delete::{self, DeleteOptions};
+use crate::actions::plan::Plan;
+use crate::actions::{confirm, human_size};
+use crate::crawl::{self, FileEntry};
+use crate::hashing;
+use crate::{parse_size, search_files, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+#[derive(Args, Debug)]
+pub struct DupArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// How to print the duplicate sets
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Find directories whose whole contents (file names and data, all the way
+    /// down) are identical, instead of single files
+    #[arg(long, conflicts_with = "change")]
+    pub dirs: bool,
+
+    /// Find near-duplicates instead, like slightly edited copies of a document:
+    /// files whose fuzzy hashes are at least PERCENT similar (--similar=90)
+    /// [default: 80]
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "80",
+        value_parser = clap::value_parser!(u32).range(1..=100),
+        conflicts_with_all = ["change", "dirs"]
+    )]
+    pub similar: Option<u32>,
+
+    /// Delete all but one file of each set, after listing them and asking for confirmation
+    #[arg(long, value_name = "KEEP", group = "change")]
+    pub delete_duplicates: Option<Keep>,
+
+    /// Keep every path but make the copies share the first one's data on disk,
+    /// after asking for confirmation
+    #[arg(long, value_name = "METHOD", group = "change")]
+    pub dedupe: Option<Dedupe>,
+
+    /// With --dedupe hardlink, also write what was linked to FILE (JSON Lines),
+    /// for --undo
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+
+    /// Give every file in a --report its own copy again, with the permissions,
+    /// owner and modification time it had before (skips the search)
+    #[arg(long, value_name = "REPORT", group = "change")]
+    pub undo: Option<PathBuf>,
+
+    /// With --delete-duplicates, --dedupe or --undo, go ahead without asking first
+    #[arg(short = 'y', long, requires = "change")]
+    pub yes: bool,
+
+    /// With --delete-duplicates, --dedupe or --undo, only show what would be done
+    #[arg(short = 'n', long, requires = "change")]
+    pub dry_run: bool,
+
+    /// Bytes hashed from each end of a file to group candidates before hashing
+    /// them in full (e.g. 64K, 1M; 0 skips straight to full hashes)
+    #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_size)]
+    pub partial_size: u64,
+
+    /// Ignore files (or with --dirs, directories) smaller than this (e.g. 1M to
+    /// only look at big ones)
+    #[arg(long, value_name = "SIZE", default_value = "1", value_parser = parse_size)]
+    pub min_size: u64,
+
+    /// Number of files to hash at once [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Which file of a duplicate set survives --delete-duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Keep {
+    /// The one the search found first
+    KeepFirst,
+    /// The most recently modified one
+    KeepNewest,
+}
+
+/// How --dedupe makes copies share their data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dedupe {
+    /// Copy-on-write clones (btrfs, XFS, APFS): later writes to one copy don't
+    /// affect the others
+    Reflink,
+    /// Hard links to the first copy, on the same file system: a write through
+    /// any path changes them all
+    Hardlink,
+}
+
+/// A regular file that might have duplicates.
+#[derive(Debug)]
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+    /// Position in the search's results, for "first found"
+    order: usize,
+}
+
+/// Files with identical contents, in the order the search found them.
+#[derive(Debug)]
+struct DuplicateSet {
+    hash: blake3::Hash,
+    files: Vec<Candidate>,
+}
+
+impl DuplicateSet {
+    fn size(&self) -> u64 {
+        self.files[0].size
+    }
+
+    /// Space that deleting all but one copy would free.
+    fn wasted(&self) -> u64 {
+        self.size() * (self.files.len() as u64 - 1)
+    }
+
+    /// The files --delete-duplicates removes: every one but the keeper.
+    fn redundant(&self, keep: Keep) -> impl Iterator<Item = &Candidate> {
+        let keeper = self.keeper(keep);
+        self.files
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| i != keeper)
+            .map(|(_, file)| file)
+    }
+
+    /// Which file --delete-duplicates keeps.
+    fn keeper(&self, keep: Keep) -> usize {
+        match keep {
+            Keep::KeepFirst => 0,
+            // Ties go to the first found
+            Keep::KeepNewest => (0..self.files.len())
+                .rev()
+                .max_by_key(|&i| self.files[i].modified)
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport {
+    sets: Vec<JsonSet>,
+    wasted: u64,
+}
+
+#[derive(Serialize)]
+struct JsonSet {
+    size: u64,
+    hash: String,
+    wasted: u64,
+    files: Vec<String>,
+}
+
+/// Searches, finds the duplicates among the results, reports them, and deletes
+/// the redundant copies if asked to.
+pub async fn run(args: &DupArgs) -> Result<ExitCode> {
+    if args.search.search_archives {
+        bail!("fs dup compares files on disk; drop --search-archives");
+    }
+    if args.delete_duplicates.is_some() && args.format == OutputFormat::Json {
+        bail!("--delete-duplicates prints its own listing; drop --format json");
+    }
+    if args.dedupe.is_some() && args.format == OutputFormat::Json {
+        bail!("--dedupe prints its own listing; drop --format json");
+    }
+    if args.report.is_some() && args.dedupe != Some(Dedupe::Hardlink) {
+        bail!("--report records hard links; use it with --dedupe hardlink");
+    }
+    if let Some(report) = &args.undo {
+        return undo_links(report, args.yes, args.dry_run).await;
+    }
+    let jobs = args.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+
+    let mut search = args.search.clone();
+    search.crawl.stat = crawl::Fields {
+        kind: true,
+        size: true,
+        modified: true,
+    };
+    let mut rx = search_files(&search).await;
+    let mut candidates = Vec::new();
+    let mut errors = 0usize;
+    while let Some(item) = rx.recv().await {
+        let entry = match item {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                errors += 1;
+                continue;
+            }
+        };
+        // Symlinks aren't copies, and every empty file is trivially the same,
+        // though an empty file is still part of what makes a directory
+        let min_size = if args.dirs { 0 } else { args.min_size.max(1) };
+        let size = match entry.is_file() {
+            Ok(true) => entry.size(),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        let size = match size {
+            Ok(size) if size >= min_size => size,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", entry.path().display());
+                errors += 1;
+                continue;
+            }
+        };
+        let modified = entry.modified().ok();
+        candidates.push(Candidate {
+            path: entry.into_path(),
+            size,
+            modified,
+            order: candidates.len(),
+        });
+    }
+
+    if args.dirs {
+        let root = &args.search.root_path;
+        let min_size = args.min_size.max(1);
+        let (sets, hash_errors) = dirs::find_duplicate_dirs(candidates, root, min_size, jobs).await;
+        dirs::print(&sets, args.format)?;
+        return Ok(if errors + hash_errors > 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        });
+    }
+
+    if let Some(threshold) = args.similar {
+        let (sets, hash_errors) = fuzzy::find_similar(candidates, threshold, jobs).await;
+        fuzzy::print(&sets, threshold, args.format)?;
+        return Ok(if errors + hash_errors > 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        });
+    }
+
+    let (sets, hash_errors) = find_duplicates(candidates, args.partial_size, jobs).await;
+    errors += hash_errors;
+
+    match args.format {
+        OutputFormat::Text => print_text(&sets),
+        OutputFormat::Json => print_json(&sets)?,
+    }
+
+    if let Some(keep) = args.delete_duplicates {
+        println!();
+        let opts = DeleteOptions {
+            dirs: false,
+            yes: args.yes,
+            dry_run: args.dry_run,
+        };
+        return delete_redundant(&sets, keep, &args.search.root_path, opts).await;
+    }
+    if let Some(method) = args.dedupe {
+        println!();
+        let report = args.report.as_deref();
+        return relink_sets(sets, method, report, args.yes, args.dry_run).await;
+    }
+
+    Ok(if errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Delete all but the `keep` copy of each set, each only if neither it nor
+/// the kept copy changed since they were hashed.
+async fn delete_redundant(
+    sets: &[DuplicateSet],
+    keep: Keep,
+    root: &Path,
+    opts: DeleteOptions,
+) -> Result<ExitCode> {
+    let mut redundant = Vec::new();
+    let mut kept = HashMap::new();
+    for set in sets {
+        let keeper = &set.files[set.keeper(keep)];
+        for file in set.redundant(keep) {
+            redundant.push(Ok(FileEntry::new(file.path.clone())));
+            kept.insert(file.path.as_path(), (file, keeper));
+        }
+    }
+    let (tx, rx) = crawl::channel(1);
+    tx.send(redundant).await?;
+    drop(tx);
+    let check = |path: &Path| {
+        if let Some((file, keeper)) = kept.get(path) {
+            link::unchanged(keeper)?;
+            link::unchanged(file)?;
+        }
+        Ok(())
+    };
+    delete::run_checked(rx, root, opts, check).await
+}
+
+/// Narrow the candidates down to sets of identical files, biggest waste first.
+/// Also returns how many files couldn't be read.
+async fn find_duplicates(
+    candidates: Vec<Candidate>,
+    partial_size: u64,
+    jobs: usize,
+) -> (Vec<DuplicateSet>, usize) {
+    let mut by_size: HashMap<u64, Vec<Candidate>> = HashMap::new();
+    for candidate in candidates {
+        by_size.entry(candidate.size).or_default().push(candidate);
+    }
+    let groups = by_size.into_values().filter(|g| g.len() > 1).collect();
+
+    let (groups, partial_errors) = regroup(groups, jobs, Round::Partial(partial_size)).await;
+    // A partial hash of a small file already covered all of it
+    let (settled, unsettled): (Vec<_>, Vec<_>) = groups
+        .into_iter()
+        .partition(|(_, files)| files[0].size <= partial_size.saturating_mul(2));
+    let unsettled = unsettled.into_iter().map(|(_, files)| files).collect();
+    let (confirmed, full_errors) = regroup(unsettled, jobs, Round::Full).await;
+
+    let mut sets: Vec<DuplicateSet> = settled
+        .into_iter()
+        .chain(confirmed)
+        .map(|(hash, files)| DuplicateSet { hash, files })
+        .collect();
+    sets.sort_by_key(|set| (std::cmp::Reverse(set.wasted()), set.files[0].order));
+    (sets, partial_errors + full_errors)
+}
+
+/// How much of each file a hashing round reads.
+#[derive(Debug, Clone, Copy)]
+enum Round {
+    /// This many bytes from each end
+    Partial(u64),
+    Full,
+}
+
+impl Round {
+    fn hash(self, candidate: &Candidate) -> io::Result<blake3::Hash> {
+        let Round::Partial(len) = self else {
+            return hashing::blake3_file(&candidate.path);
+        };
+        let mut file = File::open(&candidate.path)?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader((&mut file).take(len))?;
+        if candidate.size > len {
+            // The tail, or just the rest when the two ends overlap, so that a
+            // file no bigger than 2 * len is hashed exactly in full
+            file.seek(SeekFrom::Start(candidate.size.saturating_sub(len).max(len)))?;
+            hasher.update_reader(file.take(len))?;
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+/// Split each group by the hash `round` computes for its files, with up to
+/// `jobs` hashed at once, keeping only the parts with more than one file.
+/// Files that can't be read are reported and dropped; the count of them is
+/// returned alongside.
+async fn regroup(
+    groups: Vec<Vec<Candidate>>,
+    jobs: usize,
+    round: Round,
+) -> (Vec<(blake3::Hash, Vec<Candidate>)>, usize) {
+    let (tx, rx) = mpsc::channel(jobs.max(1) * 2);
+    tokio::spawn(async move {
+        for (group, files) in groups.into_iter().enumerate() {
+            for candidate in files {
+                if tx.send((group, candidate)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    let mut hashed = hashing::spawn_pool(rx, jobs, move |(_, candidate): &(usize, Candidate)| {
+        round.hash(candidate)
+    });
+
+    let mut parts: HashMap<(usize, blake3::Hash), Vec<Candidate>> = HashMap::new();
+    let mut errors = 0usize;
+    while let Some(((group, candidate), hash)) = hashed.recv().await {
+        match hash {
+            Ok(hash) => parts.entry((group, hash)).or_default().push(candidate),
+            Err(e) => {
+                eprintln!("Error: {}: {e}", candidate.path.display());
+                errors += 1;
+            }
+        }
+    }
+
+    let mut groups: Vec<_> = parts
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|((_, hash), mut files)| {
+            files.sort_by_key(|file| file.order);
+            (hash, files)
+        })
+        .collect();
+    groups.sort_by_key(|(_, files)| files[0].order);
+    (groups, errors)
+}
+
+/// Makes every file of each set but the first share the first one's data,
+/// after asking for confirmation, and prints a summary. Hard links are listed
+/// and, with `report`, written there for `--undo`.
+async fn relink_sets(
+    sets: Vec<DuplicateSet>,
+    method: Dedupe,
+    report: Option<&Path>,
+    yes: bool,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    let op = match method {
+        Dedupe::Reflink => "reflink",
+        Dedupe::Hardlink => "hardlink",
+    };
+    if dry_run {
+        let mut plan = Plan::default();
+        for set in &sets {
+            for file in set.redundant(Keep::KeepFirst) {
+                plan.add(
+                    op,
+                    format!("{} => {}", file.path.display(), set.files[0].path.display()),
+                );
+            }
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if sets.is_empty() {
+        println!("Nothing to re-link.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            bail!("refusing to re-link without confirmation; pass --yes when not running interactively");
+        }
+        if !confirm("Re-link the copies to share the first file's data?")? {
+            println!("Aborted, nothing was changed.");
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+    // Opened first so an unwritable report stops us before anything is linked
+    let mut report_file = match report {
+        Some(path) => Some(BufWriter::new(
+            File::create(path).with_context(|| format!("creating {}", path.display()))?,
+        )),
+        None => None,
+    };
+
+    // Each link is recorded as soon as it's made, so that however the run
+    // ends, what it linked can be undone
+    let (recorded, relinked, reclaimed, failed) = tokio::task::spawn_blocking(move || {
+        let (mut recorded, mut relinked, mut reclaimed, mut failed) = (0usize, 0usize, 0u64, 0);
+        for set in &sets {
+            let source = &set.files[0];
+            for file in set.redundant(Keep::KeepFirst) {
+                match link::relink(source, file, method) {
+                    Ok(link::Relinked::AlreadyLinked) => {}
+                    Ok(relinked_file) => {
+                        relinked += 1;
+                        reclaimed += file.size;
+                        if let link::Relinked::HardLinked(record) = relinked_file {
+                            println!(
+                                "linked {} => {}",
+                                record.path.display(),
+                                record.linked_to.display()
+                            );
+                            if let Some(out) = &mut report_file {
+                                // A link that can't be recorded stops the rest
+                                serde_json::to_writer(&mut *out, &record)?;
+                                out.write_all(b"\n")?;
+                                out.flush()?;
+                                recorded += 1;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {}: {e}", file.path.display());
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        anyhow::Ok((recorded, relinked, reclaimed, failed))
+    })
+    .await?
+    .context("can't write the report; what was linked up to now is in it")?;
+
+    if let Some(path) = report {
+        println!(
+            "Wrote {recorded} link(s) to {}; `fs dup --undo {}` splits them again",
+            path.display(),
+            path.display()
+        );
+    }
+    println!(
+        "Re-linked {relinked} file(s), reclaiming up to {}, {failed} failed",
+        human_size(reclaimed)
+    );
+
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Gives every hard link listed in a `--report` its own copy again, after
+/// listing them and asking for confirmation.
+async fn undo_links(report: &Path, yes: bool, dry_run: bool) -> Result<ExitCode> {
+    let file = File::open(report).with_context(|| format!("opening {}", report.display()))?;
+    let mut records: Vec<link::LinkRecord> = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: not a link record", report.display(), i + 1))?;
+        records.push(record);
+    }
+
+    if dry_run {
+        let mut plan = Plan::default();
+        for record in &records {
+            plan.add("unlink", record.path.display());
+        }
+        plan.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if records.is_empty() {
+        println!("Nothing to undo.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for record in &records {
+        println!("{}", record.path.display());
+    }
+    let total: u64 = records.iter().map(|record| record.size).sum();
+    println!(
+        "{} file(s), {} more space once they have their own copies",
+        records.len(),
+        human_size(total)
+    );
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            bail!("refusing to undo links without confirmation; pass --yes when not running interactively");
+        }
+        if !confirm("Give these files their own copies again?")? {
+            println!("Aborted, nothing was changed.");
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    let (restored, failed) = tokio::task::spawn_blocking(move || {
+        let (mut restored, mut failed) = (0usize, 0usize);
+        for record in &records {
+            match link::undo(record) {
+                Ok(()) => restored += 1,
+                Err(e) => {
+                    eprintln!("Error: {}: {e}", record.path.display());
+                    failed += 1;
+                }
+            }
+        }
+        (restored, failed)
+    })
+    .await?;
+    println!("Restored {restored} file(s), {failed} failed");
+
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn print_text(sets: &[DuplicateSet]) {
+    for set in sets {
+        println!(
+            "{} files of {} ({} wasted):",
+            set.files.len(),
+            human_size(set.size()),
+            human_size(set.wasted())
+        );
+        for file in &set.files {
+            println!("  {}", file.path.display());
+        }
+        println!();
+    }
+    let redundant: usize = sets.iter().map(|set| set.files.len() - 1).sum();
+    let wasted: u64 = sets.iter().map(DuplicateSet::wasted).sum();
+    println!(
+        "{} duplicate set(s), {redundant} redundant file(s), {} wasted",
+        sets.len(),
+        human_size(wasted)
+    );
+}
+
+fn print_json(sets: &[DuplicateSet]) -> Result<()> {
+    let report = JsonReport {
+        sets: sets
+            .iter()
+            .map(|set| JsonSet {
+                size: set.size(),
+                hash: set.hash.to_hex().to_string(),
+                wasted: set.wasted(),
+                files: set
+                    .files
+                    .iter()
+                    .map(|file| file.path.display().to_string())
+                    .collect(),
+            })
+            .collect(),
+        wasted: sets.iter().map(DuplicateSet::wasted).sum(),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn candidate(path: &Path, order: usize) -> Candidate {
+        let meta = std::fs::metadata(path).unwrap();
+        Candidate {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            order,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicates_and_keepers() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        // Same size and both ends, different in the middle
+        let partial = 1024;
+        let big = vec![7u8; 3 * partial];
+        let mut big_other = big.clone();
+        big_other[partial + 10] = 8;
+        let files = [
+            ("a.jpg", big.clone()),
+            ("b.txt", b"same".to_vec()),
+            ("c.jpg", big.clone()),
+            ("d.jpg", big_other),
+            ("e.txt", b"diff".to_vec()),
+            ("f.txt", b"same".to_vec()),
+            ("g.jpg", big),
+        ];
+        let mut candidates = Vec::new();
+        for (order, (name, contents)) in files.iter().enumerate() {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, contents)?;
+            candidates.push(candidate(&path, order));
+        }
+        // c.jpg is the newest copy
+        let c = tmp.path().join("c.jpg");
+        File::options()
+            .write(true)
+            .open(&c)?
+            .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+        candidates[2].modified = std::fs::metadata(&c)?.modified().ok();
+
+        let (sets, errors) = find_duplicates(candidates, partial as u64, 3).await;
+        assert_eq!(errors, 0);
+        let names = |files: Vec<&Candidate>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            names(sets[0].files.iter().collect()),
+            ["a.jpg", "c.jpg", "g.jpg"]
+        );
+        assert_eq!(sets[0].wasted(), 2 * 3 * partial as u64);
+        assert_eq!(sets[0].hash, blake3::hash(&vec![7u8; 3 * partial]));
+        assert_eq!(names(sets[1].files.iter().collect()), ["b.txt", "f.txt"]);
+        assert_eq!(sets[1].hash, blake3::hash(b"same"));
+
+        assert_eq!(
+            names(sets[0].redundant(Keep::KeepFirst).collect()),
+            ["c.jpg", "g.jpg"]
+        );
+        assert_eq!(
+            names(sets[0].redundant(Keep::KeepNewest).collect()),
+            ["a.jpg", "g.jpg"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_only_unchanged_duplicates_are_deleted() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut candidates = Vec::new();
+        for (order, name) in ["a", "b", "c", "d", "e", "f"].into_iter().enumerate() {
+            let path = tmp.path().join(name);
+            let contents = if order < 3 { "one" } else { "two" };
+            std::fs::write(&path, contents)?;
+            candidates.push(candidate(&path, order));
+        }
+        let (sets, _) = find_duplicates(candidates, 1024, 2).await;
+        assert_eq!(sets.len(), 2);
+
+        // b was written to since, and so was d, the copy kept of the others
+        std::fs::write(tmp.path().join("b"), "one, and more")?;
+        std::fs::write(tmp.path().join("d"), "two, and more")?;
+        let opts = DeleteOptions {
+            dirs: false,
+            yes: true,
+            dry_run: false,
+        };
+        let code = delete_redundant(&sets, Keep::KeepFirst, tmp.path(), opts).await?;
+        assert_eq!(code, ExitCode::FAILURE);
+        let left: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+            .into_iter()
+            .filter(|name| tmp.path().join(name).exists())
+            .collect();
+        assert_eq!(left, ["a", "b", "d", "e", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_hash_reads_both_ends() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let hash = |contents: &[u8], round: Round| -> io::Result<blake3::Hash> {
+            let path = tmp.path().join("file");
+            std::fs::write(&path, contents)?;
+            round.hash(&candidate(&path, 0))
+        };
+        let partial = Round::Partial(4);
+
+        // Small enough that the two ends are the whole file
+        assert_eq!(hash(b"abcdefg", partial)?, blake3::hash(b"abcdefg"));
+        // Only the ends count
+        assert_eq!(
+            hash(b"abcdXXXXwxyz", partial)?,
+            hash(b"abcdYYYYwxyz", partial)?
+        );
+        assert_ne!(
+            hash(b"abcdXXXXwxyz", partial)?,
+            hash(b"abcdXXXXwxyZ", partial)?
+        );
+        assert_ne!(
+            hash(b"abcdXXXXwxyz", Round::Full)?,
+            hash(b"abcdYYYYwxyz", Round::Full)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("1.5mib"), Ok(3 << 19));
+        assert_eq!(parse_size("2 G"), Ok(2 << 30));
+        assert!(parse_size("12 parsecs").is_err());
+        assert!(parse_size("K").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! What can go wrong in a search, for library callers to match on. The walk
+//! carries on past each of these, handing it over with the results; the
+//! command line just prints them.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Not allowed to read `path`
+    PermissionDenied { path: PathBuf },
+    /// `path` was to be read as a directory, but isn't one
+    NotADirectory { path: PathBuf },
+    /// The .gitignore at `path` couldn't be parsed, so none of its rules apply
+    IgnoreParse { path: PathBuf, message: String },
+    /// The search's pattern can never match a file name
+    PatternInvalid {
+        pattern: String,
+        reason: &'static str,
+    },
+    /// Any other I/O failure on `path`
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl Error {
+    /// `source`, from reading `path`, as the variant that fits it best.
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path },
+            io::ErrorKind::NotADirectory => Error::NotADirectory { path },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// An error from the crawl's own plumbing, which is one of ours unless
+    /// something unexpected (a panicking filter, say) happened at `path`.
+    #[cfg(any(test, feature = "tokio"))]
+    pub(crate) fn from_crawl(e: anyhow::Error, path: &Path) -> Self {
+        e.downcast().unwrap_or_else(|e: anyhow::Error| Error::Io {
+            path: path.to_path_buf(),
+            source: io::Error::other(format!("{e:#}")),
+        })
+    }
+
+    /// The file or directory it's about, if it's about one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::PermissionDenied { path }
+            | Error::NotADirectory { path }
+            | Error::IgnoreParse { path, .. }
+            | Error::Io { path, .. } => Some(path),
+            Error::PatternInvalid { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PermissionDenied { path } => {
+                write!(f, "{}: permission denied", path.display())
+            }
+            Error::NotADirectory { path } => write!(f, "{}: not a directory", path.display()),
+            Error::IgnoreParse { path, message } => write!(f, "{}: {message}", path.display()),
+            Error::PatternInvalid { pattern, reason } => {
+                write!(f, "invalid pattern '{pattern}': {reason}")
+            }
+            Error::Io { path, source } => write!(f, "{}: {source}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_sort_by_kind() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            Error::io("/root", denied),
+            Error::PermissionDenied { path } if path == Path::new("/root")
+        ));
+        let gone = Error::io("/gone", io::Error::from(io::ErrorKind::NotFound));
+        assert!(
+            matches!(&gone, Error::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+        );
+        assert_eq!(gone.path(), Some(Path::new("/gone")));
+
+        // Ours come back out of the crawl's plumbing as they went in
+        let crawled = Error::from_crawl(anyhow::Error::new(gone), Path::new("/"));
+        assert_eq!(crawled.path(), Some(Path::new("/gone")));
+        let other = Error::from_crawl(anyhow::anyhow!("task panicked"), Path::new("/dir"));
+        assert_eq!(other.to_string(), "/dir: task panicked");
+    }
+}
@@ -0,0 +1,170 @@
+//! `fs explain PATH` (or `fs --why PATH`): why the search would or wouldn't
+//! find PATH. It follows the walk from the root down to PATH, saying of each
+//! directory on the way whether the walk goes into it, and of PATH whether
+//! it's a result, and why, with the .gitignore rule (file, line and pattern)
+//! behind each decision one had a say in. What it says is what the walk
+//! itself decides, as `-vvv` logs it.
+
+use crate::crawl::{EntryKind, Step};
+use crate::search::Custom;
+use crate::SearchConfig;
+use anyhow::{Context, Result};
+use clap::Args;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Args, Debug)]
+pub struct ExplainArgs {
+    /// The path to explain, as the search prints it or from here
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    #[command(flatten)]
+    pub search: SearchConfig,
+}
+
+pub async fn run(args: &ExplainArgs) -> Result<ExitCode> {
+    explain(&args.search, &args.path)
+}
+
+/// Print why searching as `config` says would or wouldn't find `path`, and
+/// succeed only if it would.
+pub fn explain(config: &SearchConfig, path: &Path) -> Result<ExitCode> {
+    let metadata = path
+        .symlink_metadata()
+        .with_context(|| format!("can't explain {}", path.display()))?;
+    let filters = crate::crawl_filters(config, &Custom::default());
+    filters.check()?;
+
+    // An absolute path against a relative root, or the other way around
+    let here = std::env::current_dir().context("can't tell the current directory")?;
+    let (root, path) = match (config.root_path.is_absolute(), path.is_absolute()) {
+        (false, true) => {
+            // Without a `.` left in it
+            let root = here.join(&config.root_path).components().collect();
+            (root, path.to_path_buf())
+        }
+        (true, false) => (config.root_path.clone(), here.join(path)),
+        _ => (config.root_path.clone(), path.to_path_buf()),
+    };
+    let kind = EntryKind::from(metadata.file_type());
+    let Some(steps) = filters.walk_to(&root, &path, kind) else {
+        println!(
+            "{}: not found: it isn't under the root, {}",
+            path.display(),
+            root.display()
+        );
+        return Ok(ExitCode::FAILURE);
+    };
+    if steps.is_empty() {
+        println!("{}: the root itself, which isn't a result", path.display());
+        return Ok(ExitCode::FAILURE);
+    }
+    for step in &steps {
+        println!("{}", describe(step));
+        if let Some(rule) = &step.rule {
+            let file = match &rule.file {
+                Some(file) => file.display().to_string(),
+                None => "?".into(),
+            };
+            let line = rule.line().map_or(String::new(), |line| format!(":{line}"));
+            let back_in = if rule.whitelist {
+                ", which lets it back in"
+            } else {
+                ""
+            };
+            println!("    {file}{line}: {}{back_in}", rule.pattern);
+        }
+    }
+
+    let found = steps.len() == path_len(&root, &path) && steps.last().is_some_and(|s| s.result);
+    if found {
+        println!("=> found");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("=> not found");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// How many entries there are between `root` and `path`, `path` included.
+fn path_len(root: &Path, path: &Path) -> usize {
+    let below = match root == Path::new(".") && path.is_relative() {
+        true => path.strip_prefix(".").unwrap_or(path),
+        false => path.strip_prefix(root).unwrap_or(path),
+    };
+    below.iter().count()
+}
+
+/// One step, as `path: decision`, saying too whether a directory on the way
+/// is a result besides.
+fn describe(step: &Step) -> String {
+    let mut line = format!("{}: {}", step.path.display(), step.why);
+    match (step.entered, step.result) {
+        (true, true) => line += ", and a result",
+        (false, true) if step.why != crate::crawl::Why::Matched => line += ", but a result",
+        _ => {}
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::Why;
+
+    #[test]
+    fn test_the_walk_to_a_path_says_what_stopped_it() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("src/gen"))?;
+        std::fs::write(root.join(".gitignore"), "# built\n*.log\ngen/\n")?;
+        std::fs::write(root.join("src/.gitignore"), "!keep.log\n")?;
+        for file in ["src/main.rs", "src/gen/out.rs", "src/keep.log", "src/x.log"] {
+            std::fs::write(root.join(file), "")?;
+        }
+        let config = SearchConfig {
+            root_path: root.to_path_buf(),
+            ..SearchConfig::default()
+        };
+        let filters = crate::crawl_filters(&config, &Custom::default());
+        let walk = |path: &str, kind| filters.walk_to(root, &root.join(path), kind).unwrap();
+
+        let steps = walk("src/main.rs", EntryKind::File);
+        let whys: Vec<_> = steps.iter().map(|step| step.why).collect();
+        assert_eq!(whys, [Why::Entered, Why::Matched]);
+        assert!(steps[1].result && steps[1].rule.is_none());
+
+        // The walk stops at the ignored directory, and says which rule did it
+        let steps = walk("src/gen/out.rs", EntryKind::File);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].why, Why::Gitignored);
+        let rule = steps[1].rule.as_ref().unwrap();
+        assert_eq!(rule.file.as_deref(), Some(&*root.join(".gitignore")));
+        assert_eq!((rule.pattern.as_str(), rule.line()), ("gen/", Some(3)));
+
+        // A deeper rule lets one back in
+        let steps = walk("src/keep.log", EntryKind::File);
+        assert!(steps[1].result && steps[1].rule.as_ref().unwrap().whitelist);
+        assert_eq!(walk("src/x.log", EntryKind::File)[1].why, Why::Gitignored);
+
+        let config = SearchConfig {
+            max_depth: Some(0),
+            extensions: Some(vec!["md".into()]),
+            ..config
+        };
+        let filters = crate::crawl_filters(&config, &Custom::default());
+        let steps = filters.walk_to(root, &root.join("src/main.rs"), EntryKind::File);
+        assert_eq!(steps.unwrap()[0].why, Why::MaxDepth);
+        let steps = filters.walk_to(
+            &root.join("src"),
+            &root.join("src/main.rs"),
+            EntryKind::File,
+        );
+        assert_eq!(steps.unwrap()[0].why, Why::Name);
+        assert!(filters
+            .walk_to(&root.join("src"), root, EntryKind::Dir)
+            .is_none());
+        Ok(())
+    }
+}
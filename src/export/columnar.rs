@@ -0,0 +1,242 @@
+//! `--format parquet` and `--format arrow`: the results as a columnar file on
+//! stdout, for DuckDB, Spark, pandas and the like to load as it is. A batch
+//! of rows is written at a time, so a search of any size takes no more
+//! memory than a batch does. Times are timestamps in nanoseconds, in UTC.
+
+use super::{Row, Rows};
+use crate::crawl::Results;
+use crate::hashing::Algorithm;
+use anyhow::{bail, Result};
+use arrow_array::builder::{StringBuilder, TimestampNanosecondBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use clap::ValueEnum;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use std::io::{self, IsTerminal, Write};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+/// How many rows go into a batch.
+const BATCH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Parquet, compressed with zstd
+    Parquet,
+    /// An Arrow IPC file, also known as Feather
+    Arrow,
+}
+
+/// Write the results on `rx` to stdout in `format`, reading and hashing them
+/// on `jobs` threads.
+pub async fn run(
+    rx: Results,
+    format: Format,
+    hash: Option<Algorithm>,
+    jobs: usize,
+) -> Result<ExitCode> {
+    let stdout = io::stdout();
+    if stdout.is_terminal() {
+        bail!(
+            "--format writes a binary file, not text; redirect it to one, e.g. > results.parquet"
+        );
+    }
+    let mut rows = Rows::new(rx, hash, jobs);
+    write(&mut rows, format, io::BufWriter::new(stdout)).await?;
+    Ok(if rows.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Write `rows` to `output` in `format`; how many there were.
+async fn write<W: Write + Send>(rows: &mut Rows, format: Format, output: W) -> Result<usize> {
+    let schema = schema();
+    let mut writer = match format {
+        Format::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build();
+            Writer::Parquet(ArrowWriter::try_new(
+                output,
+                Arc::clone(&schema),
+                Some(properties),
+            )?)
+        }
+        Format::Arrow => Writer::Arrow(arrow_ipc::writer::FileWriter::try_new(output, &schema)?),
+    };
+    let mut columns = Columns::default();
+    let mut written = 0;
+    while let Some(row) = rows.next().await {
+        columns.push(&row);
+        written += 1;
+        if written % BATCH == 0 {
+            writer.write(&columns.finish(&schema)?)?;
+        }
+    }
+    if written % BATCH != 0 || written == 0 {
+        writer.write(&columns.finish(&schema)?)?;
+    }
+    writer.finish()?;
+    Ok(written)
+}
+
+/// The columns, as [`Row`] has them.
+fn schema() -> SchemaRef {
+    let time = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+    Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("parent", DataType::Utf8, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("ext", DataType::Utf8, true),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("modified", time.clone(), true),
+        Field::new("accessed", time.clone(), true),
+        Field::new("created", time, true),
+        Field::new("hash", DataType::Utf8, true),
+    ]))
+}
+
+enum Writer<W: Write + Send> {
+    Parquet(ArrowWriter<W>),
+    Arrow(arrow_ipc::writer::FileWriter<W>),
+}
+
+impl<W: Write + Send> Writer<W> {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            Writer::Parquet(writer) => writer.write(batch)?,
+            Writer::Arrow(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    /// Write what's left, the footer, and flush.
+    fn finish(self) -> Result<()> {
+        match self {
+            Writer::Parquet(writer) => {
+                writer.into_inner()?.flush()?;
+            }
+            Writer::Arrow(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rows of the batch being put together, a column at a time.
+struct Columns {
+    path: StringBuilder,
+    parent: StringBuilder,
+    name: StringBuilder,
+    ext: StringBuilder,
+    kind: StringBuilder,
+    size: UInt64Builder,
+    modified: TimestampNanosecondBuilder,
+    accessed: TimestampNanosecondBuilder,
+    created: TimestampNanosecondBuilder,
+    hash: StringBuilder,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        let time = || TimestampNanosecondBuilder::new().with_timezone("UTC");
+        Columns {
+            path: StringBuilder::new(),
+            parent: StringBuilder::new(),
+            name: StringBuilder::new(),
+            ext: StringBuilder::new(),
+            kind: StringBuilder::new(),
+            size: UInt64Builder::new(),
+            modified: time(),
+            accessed: time(),
+            created: time(),
+            hash: StringBuilder::new(),
+        }
+    }
+}
+
+impl Columns {
+    fn push(&mut self, row: &Row) {
+        self.path.append_value(&row.path);
+        self.parent.append_value(&row.parent);
+        self.name.append_value(&row.name);
+        self.ext.append_option(row.ext.as_deref());
+        self.kind.append_value(row.kind);
+        self.size.append_value(row.size);
+        self.modified.append_option(row.modified_ns);
+        self.accessed.append_option(row.accessed_ns);
+        self.created.append_option(row.created_ns);
+        self.hash.append_option(row.hash.as_deref());
+    }
+
+    /// The rows so far as a batch, leaving the columns empty.
+    fn finish(&mut self, schema: &SchemaRef) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.path.finish()),
+            Arc::new(self.parent.finish()),
+            Arc::new(self.name.finish()),
+            Arc::new(self.ext.finish()),
+            Arc::new(self.kind.finish()),
+            Arc::new(self.size.finish()),
+            Arc::new(self.modified.finish()),
+            Arc::new(self.accessed.finish()),
+            Arc::new(self.created.finish()),
+            Arc::new(self.hash.finish()),
+        ];
+        Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchConfig;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+
+    #[tokio::test]
+    async fn test_both_formats_read_back_as_written() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("tree");
+        std::fs::create_dir(&root)?;
+        std::fs::write(root.join("a.txt"), "abc")?;
+        let config = SearchConfig {
+            root_path: root.clone(),
+            ..SearchConfig::default()
+        };
+
+        for format in [Format::Parquet, Format::Arrow] {
+            let file = tmp.path().join(format!("{format:?}"));
+            let rx = crate::search_files(&config).await;
+            let mut rows = Rows::new(rx, Some(Algorithm::Md5), 2);
+            assert_eq!(write(&mut rows, format, File::create(&file)?).await?, 1);
+
+            let batches: Vec<RecordBatch> = match format {
+                Format::Parquet => ParquetRecordBatchReaderBuilder::try_new(File::open(&file)?)?
+                    .build()?
+                    .collect::<Result<_, _>>()?,
+                Format::Arrow => arrow_ipc::reader::FileReader::try_new(File::open(&file)?, None)?
+                    .collect::<Result<_, _>>()?,
+            };
+            let batch = &batches[0];
+            assert_eq!(batch.schema(), schema());
+            let text = |column: &str| batch[column].as_string::<i32>().value(0).to_string();
+            assert_eq!(text("path"), root.join("a.txt").to_string_lossy());
+            assert_eq!((text("name"), text("ext")), ("a.txt".into(), "txt".into()));
+            assert_eq!(text("hash"), "900150983cd24fb0d6963f7d28e17f72");
+            assert_eq!(batch["size"].as_primitive::<UInt64Type>().value(0), 3);
+        }
+        Ok(())
+    }
+}
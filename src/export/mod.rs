@@ -0,0 +1,155 @@
+//! Search results written out as a table, for analysis with other tools: a
+//! row for each result with its path taken apart, its type, size and times,
+//! and its hash if `--hash` asked for one. `--output-sqlite` writes them into
+//! a SQLite database, and `--format` as a Parquet or Arrow file.
+
+#[cfg(feature = "parquet")]
+pub mod columnar;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use crate::crawl::{EntryKind, FileEntry, Results};
+use crate::hashing::{self, hash_file, Algorithm};
+use crate::{unix_ns, Error};
+use std::io;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// A result as a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Row {
+    /// As `fs` prints it
+    pub path: String,
+    /// The directory it's in, as `path` starts
+    pub parent: String,
+    pub name: String,
+    /// Its extension, lowercased, if it has one
+    pub ext: Option<String>,
+    /// "file", "dir", "symlink" or "other"
+    pub kind: &'static str,
+    pub size: u64,
+    /// Times in nanoseconds since the Unix epoch, where the platform has them
+    pub modified_ns: Option<i64>,
+    pub accessed_ns: Option<i64>,
+    pub created_ns: Option<i64>,
+    /// Of a file's contents, in lowercase hex
+    pub hash: Option<String>,
+}
+
+impl Row {
+    /// The row for `path`, as it is on disk now, hashed with `hash` if it's a
+    /// file. A hash that fails leaves the row without one, and the error.
+    fn read(path: &Path, hash: Option<Algorithm>) -> io::Result<Read> {
+        let metadata = path.symlink_metadata()?;
+        let kind = match EntryKind::from(metadata.file_type()) {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Other => "other",
+        };
+        let (hash, failed) = match hash.filter(|_| kind == "file") {
+            Some(algorithm) => match hash_file(path, algorithm) {
+                Ok(digest) => (Some(digest), None),
+                Err(e) => (None, Some(e)),
+            },
+            None => (None, None),
+        };
+        let row = Row {
+            path: path.to_string_lossy().into_owned(),
+            parent: path
+                .parent()
+                .map_or_else(String::new, |p| p.to_string_lossy().into_owned()),
+            name: path
+                .file_name()
+                .map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+            ext: path.extension().map(|e| e.to_string_lossy().to_lowercase()),
+            kind,
+            size: metadata.len(),
+            modified_ns: metadata.modified().ok().map(unix_ns),
+            accessed_ns: metadata.accessed().ok().map(unix_ns),
+            created_ns: metadata.created().ok().map(unix_ns),
+            hash,
+        };
+        Ok((row, failed))
+    }
+}
+
+/// A row, and the error hashing its file if that failed.
+type Read = (Row, Option<io::Error>);
+
+/// A result, and unless it's an error, its row or why that couldn't be read.
+type Item = (Result<FileEntry, Error>, Option<io::Result<Read>>);
+
+/// The rows of a search's results, read (and hashed, if asked) on blocking
+/// threads, in the order they're done.
+pub(crate) struct Rows {
+    rx: mpsc::Receiver<Item>,
+    /// Whether a file couldn't be read or hashed
+    pub failed: bool,
+}
+
+impl Rows {
+    /// The rows of the results on `rx`, hashed with `hash` if given, on up to
+    /// `jobs` threads.
+    pub fn new(rx: Results, hash: Option<Algorithm>, jobs: usize) -> Self {
+        let rx = hashing::spawn_pool(rx, jobs, move |item: &Result<FileEntry, Error>| {
+            item.as_ref()
+                .ok()
+                .map(|entry| Row::read(entry.path(), hash))
+        });
+        Rows { rx, failed: false }
+    }
+
+    /// The next row, after reporting what couldn't be read on the way.
+    pub async fn next(&mut self) -> Option<Row> {
+        while let Some((item, read)) = self.rx.recv().await {
+            match (item, read) {
+                (Ok(_), Some(Ok((row, None)))) => return Some(row),
+                // Still a row, only without its hash
+                (Ok(_), Some(Ok((row, Some(e))))) => {
+                    eprintln!("Error: {}: {e}", row.path);
+                    self.failed = true;
+                    return Some(row);
+                }
+                (Ok(entry), Some(Err(e))) => {
+                    eprintln!("Error: {}: {e}", entry.path().display());
+                    self.failed = true;
+                }
+                (Err(e), _) => eprintln!("Error: {e}"),
+                (Ok(_), None) => unreachable!("every result is read"),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_row_takes_the_path_apart() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("Photo.JPG");
+        std::fs::write(&path, "abc")?;
+
+        let (row, failed) = Row::read(&path, Some(Algorithm::Md5))?;
+        assert!(failed.is_none());
+        assert_eq!(row.parent, tmp.path().to_string_lossy());
+        assert_eq!(
+            (row.name.as_str(), row.ext.as_deref()),
+            ("Photo.JPG", Some("jpg"))
+        );
+        assert_eq!((row.kind, row.size), ("file", 3));
+        assert!(row.modified_ns.is_some());
+        assert_eq!(
+            row.hash.as_deref(),
+            Some("900150983cd24fb0d6963f7d28e17f72")
+        );
+
+        // Directories aren't hashed
+        let (row, _) = Row::read(tmp.path(), Some(Algorithm::Md5))?;
+        assert_eq!((row.kind, row.hash), ("dir", None));
+        Ok(())
+    }
+}
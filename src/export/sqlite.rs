@@ -0,0 +1,190 @@
+//! `--output-sqlite FILE`: the results as rows of a SQLite database, for
+//! plain SQL over them. Each search adds a scan to the `scans` table, when it
+//! ran, from which root and with which hash, and a row to `files` for each
+//! result, so one database can hold many scans to compare:
+//!
+//! ```sql
+//! SELECT ext, count(*), sum(size) FROM files WHERE scan_id = 1 GROUP BY ext;
+//! SELECT path FROM files WHERE scan_id = 2
+//!     EXCEPT SELECT path FROM files WHERE scan_id = 1;
+//! ```
+
+use super::{Row, Rows};
+use crate::crawl::Results;
+use crate::hashing::Algorithm;
+use crate::unix_ns;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, Transaction};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    root TEXT NOT NULL,
+    started_ns INTEGER NOT NULL,
+    finished_ns INTEGER,
+    hash_algorithm TEXT
+);
+CREATE TABLE IF NOT EXISTS files (
+    scan_id INTEGER NOT NULL REFERENCES scans (id),
+    path TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    name TEXT NOT NULL,
+    ext TEXT,
+    type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    modified_ns INTEGER,
+    accessed_ns INTEGER,
+    created_ns INTEGER,
+    hash TEXT
+);
+CREATE INDEX IF NOT EXISTS files_path ON files (path, scan_id);
+CREATE INDEX IF NOT EXISTS files_parent ON files (scan_id, parent);
+CREATE INDEX IF NOT EXISTS files_ext ON files (scan_id, ext);
+CREATE INDEX IF NOT EXISTS files_size ON files (scan_id, size);
+CREATE INDEX IF NOT EXISTS files_hash ON files (hash) WHERE hash IS NOT NULL;
+";
+
+/// Write the results on `rx`, found under `root`, into the database `db` as
+/// a new scan, reading and hashing them on `jobs` threads.
+pub async fn run(
+    rx: Results,
+    db: &Path,
+    root: &Path,
+    hash: Option<Algorithm>,
+    jobs: usize,
+) -> Result<ExitCode> {
+    let mut connection =
+        Connection::open(db).with_context(|| format!("can't open {}", db.display()))?;
+    connection
+        .execute_batch(SCHEMA)
+        .with_context(|| format!("{} isn't a database fs can write to", db.display()))?;
+    // All of the scan or none of it
+    let transaction = connection.transaction()?;
+    let scan = start(&transaction, root, hash)?;
+
+    let mut rows = Rows::new(rx, hash, jobs);
+    let mut written = 0;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO files (scan_id, path, parent, name, ext, type, size, modified_ns, \
+             accessed_ns, created_ns, hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        while let Some(row) = rows.next().await {
+            insert_row(&mut insert, scan, &row)?;
+            written += 1;
+        }
+    }
+    transaction.execute(
+        "UPDATE scans SET finished_ns = ? WHERE id = ?",
+        params![unix_ns(SystemTime::now()), scan],
+    )?;
+    transaction.commit()?;
+    eprintln!("Wrote {written} entries to {} as scan {scan}", db.display());
+
+    Ok(if rows.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Add a scan, starting now; its id.
+fn start(transaction: &Transaction, root: &Path, hash: Option<Algorithm>) -> Result<i64> {
+    let hash = hash.map(|algorithm| match algorithm {
+        Algorithm::Sha256 => "sha256",
+        Algorithm::Blake3 => "blake3",
+        Algorithm::Md5 => "md5",
+    });
+    transaction.execute(
+        "INSERT INTO scans (root, started_ns, hash_algorithm) VALUES (?, ?, ?)",
+        params![root.to_string_lossy(), unix_ns(SystemTime::now()), hash],
+    )?;
+    Ok(transaction.last_insert_rowid())
+}
+
+fn insert_row(insert: &mut rusqlite::Statement, scan: i64, row: &Row) -> Result<()> {
+    insert.execute(params![
+        scan,
+        row.path,
+        row.parent,
+        row.name,
+        row.ext,
+        row.kind,
+        // SQLite's integers are signed
+        i64::try_from(row.size).unwrap_or(i64::MAX),
+        row.modified_ns,
+        row.accessed_ns,
+        row.created_ns,
+        row.hash,
+    ])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchConfig;
+
+    #[tokio::test]
+    async fn test_each_search_adds_a_scan() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("tree");
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(root.join("src/lib.rs"), "abc")?;
+        std::fs::write(root.join("README.md"), "# tree\n")?;
+        let db = tmp.path().join("scan.db");
+        let config = SearchConfig {
+            root_path: root.clone(),
+            ..SearchConfig::default()
+        };
+
+        let rx = crate::search_files(&config).await;
+        let code = run(rx, &db, &root, Some(Algorithm::Md5), 2).await?;
+        assert_eq!(code, ExitCode::SUCCESS);
+        std::fs::remove_file(root.join("README.md"))?;
+        let rx = crate::search_files(&config).await;
+        run(rx, &db, &root, None, 2).await?;
+
+        let connection = Connection::open(&db)?;
+        let lib = connection.query_row(
+            "SELECT parent, name, ext, type, size, hash FROM files
+                WHERE scan_id = 1 AND path = ?",
+            [root.join("src/lib.rs").to_string_lossy()],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            },
+        )?;
+        let src = root.join("src").to_string_lossy().into_owned();
+        let hash = "900150983cd24fb0d6963f7d28e17f72".to_string();
+        assert_eq!(
+            lib,
+            (src, "lib.rs".into(), "rs".into(), "file".into(), 3, hash)
+        );
+
+        // What the second scan no longer has
+        let gone: Vec<String> = connection
+            .prepare(
+                "SELECT name FROM files WHERE scan_id = 1
+                    EXCEPT SELECT name FROM files WHERE scan_id = 2",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(gone, ["README.md"]);
+        let hashes: Vec<Option<String>> = connection
+            .prepare("SELECT hash_algorithm FROM scans ORDER BY id")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(hashes, [Some("md5".into()), None]);
+        Ok(())
+    }
+}
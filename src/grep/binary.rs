@@ -0,0 +1,48 @@
+//! Telling binary files apart from text before searching them.
+
+use std::path::Path;
+
+/// How much of the start of a file is checked for NUL bytes.
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+/// Extensions that are binary however their first bytes look.
+const BINARY_EXTENSIONS: &[&str] = &[
+    "7z", "a", "avi", "bin", "bmp", "bz2", "class", "dll", "dmg", "dylib", "eot", "exe", "flac",
+    "gif", "gz", "ico", "img", "iso", "jar", "jpeg", "jpg", "lib", "mkv", "mov", "mp3", "mp4", "o",
+    "obj", "ogg", "otf", "pdf", "png", "pyc", "rar", "rlib", "so", "sqlite", "tar", "tgz", "tif",
+    "tiff", "ttf", "war", "wasm", "wav", "webp", "woff", "woff2", "xz", "zip", "zst",
+];
+
+/// True if the file's extension says it is binary.
+pub fn has_binary_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| BINARY_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// True if `head`, the start of a file, contains a NUL byte, which text files
+/// (in UTF-8 or any single-byte encoding) never do.
+pub fn looks_binary(head: &[u8]) -> bool {
+    head[..head.len().min(SNIFF_LEN)].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_detection() {
+        assert!(has_binary_extension(Path::new("logo.PNG")));
+        assert!(has_binary_extension(Path::new("target/libfs.rlib")));
+        assert!(!has_binary_extension(Path::new("main.rs")));
+        assert!(!has_binary_extension(Path::new("Makefile")));
+
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\0\0\0"));
+        assert!(!looks_binary("plain text, caf\u{e9}\n".as_bytes()));
+
+        // A NUL past the sniffed prefix doesn't count
+        let mut late = vec![b'a'; SNIFF_LEN];
+        late.push(0);
+        assert!(!looks_binary(&late));
+    }
+}
@@ -0,0 +1,93 @@
+//! Rendering matches with surrounding context lines (`-A`/`-B`/`-C`).
+
+use std::collections::VecDeque;
+
+/// Receives every line of one file in order and renders the matching ones, plus
+/// up to `before`/`after` lines around them, the way grep does: `name:N:text`
+/// for matches, `name-N-text` for context, and `--` between separate groups.
+#[derive(Debug)]
+pub struct ContextWriter<'a> {
+    name: &'a str,
+    before: usize,
+    after: usize,
+    /// The last `before` lines that weren't printed, in case a match follows.
+    recent: VecDeque<(usize, Vec<u8>)>,
+    /// How many more lines to print as trailing context of the last match.
+    after_left: usize,
+    last_printed: Option<usize>,
+    pub output: String,
+}
+
+impl<'a> ContextWriter<'a> {
+    pub fn new(name: &'a str, before: usize, after: usize) -> Self {
+        Self {
+            name,
+            before,
+            after,
+            recent: VecDeque::with_capacity(before),
+            after_left: 0,
+            last_printed: None,
+            output: String::new(),
+        }
+    }
+
+    /// Feed the next line (without its line terminator).
+    pub fn line(&mut self, number: usize, text: &[u8], is_match: bool) {
+        if is_match {
+            while let Some((n, t)) = self.recent.pop_front() {
+                self.emit(n, &t, '-');
+            }
+            self.emit(number, text, ':');
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            self.emit(number, text, '-');
+            self.after_left -= 1;
+        } else if self.before > 0 {
+            if self.recent.len() == self.before {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((number, text.to_vec()));
+        }
+    }
+
+    fn emit(&mut self, number: usize, text: &[u8], sep: char) {
+        let with_context = self.before > 0 || self.after > 0;
+        if with_context && self.last_printed.is_some_and(|last| number > last + 1) {
+            self.output.push_str("--\n");
+        }
+        self.output.push_str(&format!(
+            "{}{sep}{number}{sep}{}\n",
+            self.name,
+            String::from_utf8_lossy(text)
+        ));
+        self.last_printed = Some(number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, before: usize, after: usize) -> String {
+        let mut w = ContextWriter::new("f", before, after);
+        for (i, line) in text.lines().enumerate() {
+            w.line(i + 1, line.as_bytes(), line.contains('*'));
+        }
+        w.output
+    }
+
+    #[test]
+    fn test_context_groups_and_separators() {
+        let text = "a\nb\n*c\nd\ne\nf\ng\n*h\n*i\nj";
+        assert_eq!(render(text, 0, 0), "f:3:*c\nf:8:*h\nf:9:*i\n");
+        assert_eq!(
+            render(text, 1, 1),
+            "f-2-b\nf:3:*c\nf-4-d\n--\nf-7-g\nf:8:*h\nf:9:*i\nf-10-j\n"
+        );
+        // Overlapping windows merge into one group
+        assert_eq!(
+            render(text, 2, 3),
+            "f-1-a\nf-2-b\nf:3:*c\nf-4-d\nf-5-e\nf-6-f\nf-7-g\nf:8:*h\nf:9:*i\nf-10-j\n"
+        );
+    }
+}
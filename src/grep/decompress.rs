@@ -0,0 +1,79 @@
+//! Transparent decompression for `--search-compressed`.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A compression format recognised by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Wrap `file` in a streaming decoder, so memory use stays bounded however
+    /// large the file is. Concatenated streams (as produced by appending to a
+    /// rotated log) are read through to the end.
+    pub fn decoder(self, file: File) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(file)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_decoders_round_trip() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let text = b"line one\nline two\n";
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        gz.write_all(text)?;
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(text)?;
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), Default::default());
+        bz.write_all(text)?;
+        let files = [
+            ("app.log.gz", gz.finish()?),
+            ("app.log.zst", zstd::encode_all(&text[..], 0)?),
+            ("app.log.xz", xz.finish()?),
+            ("app.log.BZ2", bz.finish()?),
+        ];
+
+        for (name, bytes) in files {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, bytes)?;
+            let compression = Compression::from_path(&path).expect(name);
+
+            let mut out = Vec::new();
+            compression
+                .decoder(File::open(&path)?)?
+                .read_to_end(&mut out)?;
+            assert_eq!(out, text, "{name}");
+        }
+
+        assert_eq!(Compression::from_path(Path::new("app.log")), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Transcoding UTF-16 and legacy encodings to UTF-8 before searching (`--encoding`).
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::Read;
+
+/// Parse an encoding label as given to `--encoding`, e.g. "utf-16le",
+/// "latin1", "shift_jis" or "windows-1252".
+pub fn parse_label(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding '{label}'"))
+}
+
+/// Work out how the text starting with `head` is encoded, if it needs decoding
+/// at all: a byte order mark decides, and otherwise UTF-16 without one is
+/// recognised by ASCII text leaving every other byte zero. `None` means
+/// the bytes are searched as they are.
+pub fn detect(head: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return Some(encoding);
+    }
+
+    let sample = &head[..head.len().min(super::binary::SNIFF_LEN) & !1];
+    if sample.len() < 4 {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let zeros_at = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    let mostly = |n: usize| n * 10 >= pairs * 4;
+    let rarely = |n: usize| n * 20 <= pairs;
+
+    if mostly(odd) && rarely(even) {
+        Some(UTF_16LE)
+    } else if mostly(even) && rarely(odd) {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Decode a whole buffer to UTF-8; a byte order mark, if any, is removed.
+pub fn decode(encoding: &'static Encoding, data: &[u8]) -> Vec<u8> {
+    let (text, _) = encoding.decode_with_bom_removal(data);
+    text.into_owned().into_bytes()
+}
+
+/// Wrap `reader` so it yields UTF-8, transcoding as it goes.
+pub fn decoding_reader<R: Read>(encoding: &'static Encoding, reader: R) -> impl Read {
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"plain ascii text\n"), None);
+        assert_eq!(detect(b"\xef\xbb\xbfwith a BOM"), Some(encoding_rs::UTF_8));
+        assert_eq!(detect(b"\xff\xfeh\0i\0"), Some(UTF_16LE));
+
+        assert_eq!(detect(&utf16le("Event log entry 1\r\n")), Some(UTF_16LE));
+        let be: Vec<u8> = "Event log"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        assert_eq!(detect(&be), Some(UTF_16BE));
+
+        // A real binary has zeros everywhere, not every other byte
+        assert_eq!(detect(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_decode_and_decoding_reader() -> std::io::Result<()> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(utf16le("caf\u{e9} ok\n"));
+        assert_eq!(decode(UTF_16LE, &bytes), "caf\u{e9} ok\n".as_bytes());
+
+        let mut out = String::new();
+        decoding_reader(UTF_16LE, &bytes[..]).read_to_string(&mut out)?;
+        assert_eq!(out, "caf\u{e9} ok\n");
+
+        let latin1 = parse_label("latin1").unwrap();
+        assert_eq!(decode(latin1, b"caf\xe9"), "caf\u{e9}".as_bytes());
+        assert!(parse_label("klingon").is_err());
+        Ok(())
+    }
+}
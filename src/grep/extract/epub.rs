@@ -0,0 +1,168 @@
+//! EPUB books: zipped XHTML chapters, read in the order the book lists them.
+
+use super::xml::{self, Node};
+use super::Extractor;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+pub struct Epub;
+
+/// Elements that end a line of text when they close.
+const BLOCKS: &[&[u8]] = &[
+    b"p",
+    b"div",
+    b"br",
+    b"li",
+    b"tr",
+    b"h1",
+    b"h2",
+    b"h3",
+    b"h4",
+    b"h5",
+    b"h6",
+    b"blockquote",
+    b"pre",
+    b"dt",
+    b"dd",
+];
+
+impl Extractor for Epub {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["epub"]
+    }
+
+    /// Each chapter's text in reading order, a line per paragraph.
+    fn extract(&self, path: &Path) -> io::Result<String> {
+        let mut package = xml::open(path)?;
+        let container = xml::read_member(&mut package, "META-INF/container.xml")?
+            .ok_or_else(|| io::Error::other("no META-INF/container.xml"))?;
+        let mut opf_path = None;
+        xml::walk(&container, |node| {
+            if let Node::Start(e) = node {
+                if e.local_name().as_ref() == b"rootfile" && opf_path.is_none() {
+                    opf_path = xml::attribute(e, b"full-path");
+                }
+            }
+        })?;
+        let opf_path = opf_path.ok_or_else(|| io::Error::other("no package document"))?;
+        let opf = xml::read_member(&mut package, &opf_path)?
+            .ok_or_else(|| io::Error::other(format!("missing {opf_path}")))?;
+
+        // The manifest names every file by id; the spine lists ids in reading order
+        let mut manifest = HashMap::new();
+        let mut spine = Vec::new();
+        xml::walk(&opf, |node| {
+            if let Node::Start(e) = node {
+                match e.local_name().as_ref() {
+                    b"item" => {
+                        if let (Some(id), Some(href)) =
+                            (xml::attribute(e, b"id"), xml::attribute(e, b"href"))
+                        {
+                            manifest.insert(id, href);
+                        }
+                    }
+                    b"itemref" => spine.extend(xml::attribute(e, b"idref")),
+                    _ => {}
+                }
+            }
+        })?;
+
+        let base = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut text = String::new();
+        for id in spine {
+            let Some(href) = manifest.get(&id) else {
+                continue;
+            };
+            let name = if base.is_empty() {
+                href.clone()
+            } else {
+                format!("{base}/{href}")
+            };
+            if let Some(chapter) = xml::read_member(&mut package, &name)? {
+                chapter_text(&chapter, &mut text)?;
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// Append the visible text of one XHTML chapter to `text`.
+fn chapter_text(data: &[u8], text: &mut String) -> io::Result<()> {
+    // Inside <head>, <script> or <style>, nothing is shown
+    let mut hidden = 0usize;
+    xml::walk(data, |node| match node {
+        Node::Start(e) => {
+            if matches!(e.local_name().as_ref(), b"head" | b"script" | b"style") {
+                hidden += 1;
+            }
+        }
+        Node::End(name) => {
+            if matches!(name, b"head" | b"script" | b"style") {
+                hidden = hidden.saturating_sub(1);
+            } else if BLOCKS.contains(&name) && !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        Node::Text(t) if hidden == 0 => {
+            // Source line breaks inside a paragraph are just spaces, and the
+            // indentation between blocks isn't text at all
+            let t = if text.is_empty() || text.ends_with('\n') {
+                t.trim_start()
+            } else {
+                t
+            };
+            text.extend(
+                t.chars()
+                    .map(|c| if c == '\n' || c == '\r' { ' ' } else { c }),
+            );
+        }
+        Node::Text(_) => {}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[test]
+    fn test_epub_text_in_spine_order() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let book = tmp.path().join("book.epub");
+        let mut zip = zip::ZipWriter::new(File::create(&book)?);
+        let members = [
+            (
+                "META-INF/container.xml",
+                r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/content.opf",
+                r#"<package><manifest>
+                <item id="c1" href="one.xhtml"/><item id="c2" href="text/two.xhtml"/>
+                </manifest><spine><itemref idref="c2"/><itemref idref="c1"/></spine></package>"#,
+            ),
+            (
+                "OEBPS/one.xhtml",
+                "<html><head><title>One</title></head><body><p>It was a dark\nand stormy night.</p></body></html>",
+            ),
+            (
+                "OEBPS/text/two.xhtml",
+                "<html><body><h1>Preface</h1><p>First&#8212;a word.</p><style>p {}</style></body></html>",
+            ),
+        ];
+        for (name, contents) in members {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .map_err(io::Error::other)?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish().map_err(io::Error::other)?;
+
+        assert_eq!(
+            Epub.extract(&book)?,
+            "Preface\nFirst\u{2014}a word.\nIt was a dark and stormy night.\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! Text extractors for rich documents (`fs grep --extract`): the text is pulled
+//! out of PDF, docx, xlsx and EPUB files and searched instead of their bytes.
+//!
+//! Each format is behind a cargo feature (`pdf`, `office`, `epub`, or
+//! `documents` for all of them), so a default build carries none of their
+//! dependencies. A new format is one more `Extractor` in `EXTRACTORS`.
+
+#[cfg(feature = "epub")]
+mod epub;
+#[cfg(feature = "office")]
+mod office;
+#[cfg(feature = "pdf")]
+mod pdf;
+#[cfg(any(feature = "office", feature = "epub"))]
+mod xml;
+
+use std::io;
+use std::path::Path;
+
+/// Pulls searchable text out of one document format.
+pub trait Extractor: Sync {
+    /// The (lowercase) file extensions this extractor reads.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// The document's text, with a line per paragraph (or spreadsheet row) so
+    /// line numbers in the output mean something.
+    fn extract(&self, path: &Path) -> io::Result<String>;
+}
+
+/// The extractors compiled into this build.
+static EXTRACTORS: &[&dyn Extractor] = &[
+    #[cfg(feature = "pdf")]
+    &pdf::Pdf,
+    #[cfg(feature = "office")]
+    &office::Docx,
+    #[cfg(feature = "office")]
+    &office::Xlsx,
+    #[cfg(feature = "epub")]
+    &epub::Epub,
+];
+
+/// The extractor for `path`'s extension, if this build has one.
+pub fn for_path(path: &Path) -> Option<&'static dyn Extractor> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    EXTRACTORS
+        .iter()
+        .copied()
+        .find(|extractor| extractor.extensions().contains(&ext.as_str()))
+}
+
+/// Whether this build has any extractors at all.
+pub fn any_available() -> bool {
+    !EXTRACTORS.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_path_matches_compiled_extractors() {
+        assert!(for_path(Path::new("notes.txt")).is_none());
+        assert_eq!(
+            for_path(Path::new("Report.PDF")).is_some(),
+            cfg!(feature = "pdf")
+        );
+        assert_eq!(
+            for_path(Path::new("budget.xlsx")).is_some(),
+            cfg!(feature = "office")
+        );
+        assert_eq!(
+            for_path(Path::new("novel.epub")).is_some(),
+            cfg!(feature = "epub")
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Word (docx) and Excel (xlsx) documents: zipped Office Open XML.
+
+use super::xml::{self, Node};
+use super::Extractor;
+use std::io;
+use std::path::Path;
+
+pub struct Docx;
+
+impl Extractor for Docx {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx", "docm"]
+    }
+
+    /// The body text, a line per paragraph.
+    fn extract(&self, path: &Path) -> io::Result<String> {
+        let mut package = xml::open(path)?;
+        let document = xml::read_member(&mut package, "word/document.xml")?
+            .ok_or_else(|| io::Error::other("no word/document.xml"))?;
+
+        let mut text = String::new();
+        let mut in_text = false;
+        xml::walk(&document, |node| match node {
+            Node::Start(e) => match e.local_name().as_ref() {
+                b"t" => in_text = true,
+                b"tab" => text.push('\t'),
+                b"br" | b"cr" => text.push('\n'),
+                _ => {}
+            },
+            Node::End(b"t") => in_text = false,
+            Node::End(b"p") => text.push('\n'),
+            Node::Text(t) if in_text => text.push_str(t),
+            _ => {}
+        })?;
+        Ok(text)
+    }
+}
+
+pub struct Xlsx;
+
+impl Extractor for Xlsx {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["xlsx", "xlsm"]
+    }
+
+    /// Every sheet's rows in turn, a line per row with cells separated by tabs.
+    fn extract(&self, path: &Path) -> io::Result<String> {
+        let mut package = xml::open(path)?;
+        let shared = match xml::read_member(&mut package, "xl/sharedStrings.xml")? {
+            Some(data) => shared_strings(&data)?,
+            None => Vec::new(),
+        };
+
+        let mut sheets: Vec<(u32, String)> = package
+            .file_names()
+            .filter_map(|name| {
+                let name = name.ok()?;
+                let number = name
+                    .strip_prefix("xl/worksheets/sheet")?
+                    .strip_suffix(".xml")?
+                    .parse()
+                    .ok()?;
+                Some((number, name.to_string()))
+            })
+            .collect();
+        sheets.sort();
+
+        let mut text = String::new();
+        for (_, name) in sheets {
+            if let Some(sheet) = xml::read_member(&mut package, &name)? {
+                sheet_rows(&sheet, &shared, &mut text)?;
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// The workbook's shared string table; cells refer to strings by index.
+fn shared_strings(data: &[u8]) -> io::Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let (mut current, mut in_text) = (String::new(), false);
+    xml::walk(data, |node| match node {
+        Node::Start(e) if e.local_name().as_ref() == b"t" => in_text = true,
+        Node::End(b"t") => in_text = false,
+        Node::End(b"si") => strings.push(std::mem::take(&mut current)),
+        Node::Text(t) if in_text => current.push_str(t),
+        _ => {}
+    })?;
+    Ok(strings)
+}
+
+/// Append one sheet's rows to `text`.
+fn sheet_rows(data: &[u8], shared: &[String], text: &mut String) -> io::Result<()> {
+    let mut cells: Vec<String> = Vec::new();
+    let mut cell_type: Option<String> = None;
+    let (mut value, mut in_value) = (String::new(), false);
+    xml::walk(data, |node| match node {
+        Node::Start(e) => match e.local_name().as_ref() {
+            b"c" => cell_type = xml::attribute(e, b"t"),
+            b"v" | b"t" => in_value = true,
+            _ => {}
+        },
+        Node::End(b"v" | b"t") => in_value = false,
+        Node::End(b"c") => {
+            let raw = std::mem::take(&mut value);
+            let cell = match cell_type.as_deref() {
+                Some("s") => raw
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| shared.get(i).cloned())
+                    .unwrap_or_default(),
+                _ => raw,
+            };
+            cells.push(cell);
+        }
+        Node::End(b"row") => {
+            if cells.iter().any(|c| !c.is_empty()) {
+                text.push_str(&cells.join("\t"));
+                text.push('\n');
+            }
+            cells.clear();
+        }
+        Node::Text(t) if in_value => value.push_str(t),
+        _ => {}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn write_package(path: &Path, members: &[(&str, &str)]) -> io::Result<()> {
+        let mut zip = zip::ZipWriter::new(File::create(path)?);
+        for (name, contents) in members {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .map_err(io::Error::other)?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_docx_and_xlsx_text() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let docx = tmp.path().join("letter.docx");
+        write_package(
+            &docx,
+            &[(
+                "word/document.xml",
+                r#"<w:document xmlns:w="w"><w:body>
+                <w:p><w:r><w:t>Dear </w:t></w:r><w:r><w:t>Ann &amp; Bo,</w:t></w:r></w:p>
+                <w:p><w:r><w:t>Total:</w:t><w:tab/><w:t>42</w:t></w:r></w:p>
+                </w:body></w:document>"#,
+            )],
+        )?;
+        assert_eq!(Docx.extract(&docx)?, "Dear Ann & Bo,\nTotal:\t42\n");
+
+        let xlsx = tmp.path().join("budget.xlsx");
+        write_package(
+            &xlsx,
+            &[
+                (
+                    "xl/sharedStrings.xml",
+                    r#"<sst><si><t>Rent</t></si><si><r><t>Food</t></r><r><t> &amp; drink</t></r></si></sst>"#,
+                ),
+                (
+                    "xl/worksheets/sheet2.xml",
+                    r#"<worksheet><sheetData><row><c t="inlineStr"><is><t>second sheet</t></is></c></row></sheetData></worksheet>"#,
+                ),
+                (
+                    "xl/worksheets/sheet1.xml",
+                    r#"<worksheet><sheetData>
+                    <row><c t="s"><v>0</v></c><c><v>1200</v></c></row>
+                    <row><c t="s"><v>1</v></c><c><v>350.5</v></c></row>
+                    </sheetData></worksheet>"#,
+                ),
+            ],
+        )?;
+        assert_eq!(
+            Xlsx.extract(&xlsx)?,
+            "Rent\t1200\nFood & drink\t350.5\nsecond sheet\n"
+        );
+        Ok(())
+    }
+}
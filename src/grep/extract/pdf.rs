@@ -0,0 +1,17 @@
+//! PDF text, through `pdf-extract`.
+
+use super::Extractor;
+use std::io;
+use std::path::Path;
+
+pub struct Pdf;
+
+impl Extractor for Pdf {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn extract(&self, path: &Path) -> io::Result<String> {
+        pdf_extract::extract_text(path).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,76 @@
+//! Reading the zipped XML that docx, xlsx and EPUB files are made of.
+
+use quick_xml::escape::resolve_xml_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+pub type Package = ZipArchive<BufReader<File>>;
+
+pub fn open(path: &Path) -> io::Result<Package> {
+    ZipArchive::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)
+}
+
+/// The contents of the member called `name`, or `None` if there isn't one.
+pub fn read_member(package: &mut Package, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut member = match package.by_name(name) {
+        Ok(member) => member,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(io::Error::other(e)),
+    };
+    let mut data = Vec::new();
+    member.read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+/// One step through an XML document.
+pub enum Node<'a> {
+    Start(&'a BytesStart<'a>),
+    /// The local name (without namespace prefix) of an element that just closed.
+    End(&'a [u8]),
+    Text(&'a str),
+}
+
+/// Call `f` for each element and run of text in `xml`, in document order. Empty
+/// elements (`<br/>`) start and end; entity and character references become text.
+pub fn walk(xml: &[u8], mut f: impl FnMut(Node<'_>)) -> io::Result<()> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf).map_err(io::Error::other)? {
+            Event::Start(e) => f(Node::Start(&e)),
+            Event::Empty(e) => {
+                f(Node::Start(&e));
+                f(Node::End(e.local_name().as_ref()));
+            }
+            Event::End(e) => f(Node::End(e.local_name().as_ref())),
+            Event::Text(e) => f(Node::Text(&e.decode().map_err(io::Error::other)?)),
+            Event::CData(e) => f(Node::Text(&e.decode().map_err(io::Error::other)?)),
+            Event::GeneralRef(e) => {
+                if let Some(c) = e.resolve_char_ref().map_err(io::Error::other)? {
+                    f(Node::Text(c.encode_utf8(&mut [0; 4])));
+                } else if let Some(text) =
+                    resolve_xml_entity(&e.decode().map_err(io::Error::other)?)
+                {
+                    f(Node::Text(text));
+                }
+            }
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// The value of the attribute with this local name, if the element has it.
+pub fn attribute(element: &BytesStart<'_>, local_name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == local_name)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
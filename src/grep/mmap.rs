@@ -0,0 +1,62 @@
+//! Memory-mapped reading for content search (`--mmap`).
+
+use clap::ValueEnum;
+use memmap2::Mmap;
+use std::fs::File;
+
+/// Files at least this large are mapped under `--mmap auto`; below it, the
+/// cost of setting up the mapping outweighs saving the copies.
+const AUTO_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MmapChoice {
+    /// Map large files, read small ones
+    #[default]
+    Auto,
+    /// Map every file that can be mapped
+    Always,
+    /// Always use buffered reads
+    Never,
+}
+
+/// Map `file` if `choice` says to, or `None` to read it normally. Mapping can
+/// fail (empty files, special files, some network filesystems), in which case
+/// the caller falls back to buffered reads.
+pub fn map(file: &File, choice: MmapChoice) -> Option<Mmap> {
+    let len = file.metadata().ok()?.len();
+    let wanted = match choice {
+        MmapChoice::Auto => len >= AUTO_THRESHOLD,
+        MmapChoice::Always => len > 0,
+        MmapChoice::Never => false,
+    };
+    if !wanted {
+        return None;
+    }
+    // SAFETY: the mapping is only read. If another process truncates the file
+    // while it is being searched, reads past the new end fault; like grep tools
+    // that map files, we accept that in exchange for not copying the data.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_choices() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let small = tmp.path().join("small.txt");
+        std::fs::write(&small, "hello\n")?;
+        let empty = tmp.path().join("empty.txt");
+        std::fs::write(&empty, "")?;
+
+        let file = File::open(&small)?;
+        assert!(map(&file, MmapChoice::Auto).is_none());
+        assert!(map(&file, MmapChoice::Never).is_none());
+        assert_eq!(&map(&file, MmapChoice::Always).unwrap()[..], b"hello\n");
+
+        // Nothing to map; the caller reads it normally
+        assert!(map(&File::open(&empty)?, MmapChoice::Always).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,913 @@
+//! Content search (`fs grep`): a regex over the contents of every file the
+//! crawl yields, printed as `path:line:text`.
+//!
+//! The crawl feeds a pool of blocking workers, so directory walking and file
+//! reading overlap. Each worker renders a whole file's matches before printing,
+//! so output from different files never interleaves.
+
+pub(crate) mod binary;
+mod context;
+mod decompress;
+mod encoding;
+mod extract;
+mod mmap;
+
+use crate::actions::archive::Format;
+use crate::vpath::{self, VirtualPath};
+use crate::{file_matches, search_files, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use context::ContextWriter;
+use decompress::Compression;
+use mmap::MmapChoice;
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// The context options, which -c, -l and -L leave nothing to apply to.
+const CONTEXT_ARGS: [&str; 3] = ["after_context", "before_context", "context"];
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    /// Regular expression to search file contents for
+    pub regex: String,
+
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Match case-insensitively
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+
+    /// Print N lines of context after each match
+    #[arg(short = 'A', long, value_name = "N")]
+    pub after_context: Option<usize>,
+
+    /// Print N lines of context before each match
+    #[arg(short = 'B', long, value_name = "N")]
+    pub before_context: Option<usize>,
+
+    /// Print N lines of context around each match (-A/-B take precedence)
+    #[arg(short = 'C', long, value_name = "N")]
+    pub context: Option<usize>,
+
+    /// Search binary files as if they were text instead of skipping them
+    #[arg(long, conflicts_with = "list_binary_matches")]
+    pub binary: bool,
+
+    /// Instead of skipping binary files silently, name the ones that match
+    #[arg(long)]
+    pub list_binary_matches: bool,
+
+    /// Decompress .gz, .zst, .xz and .bz2 files on the fly and search their contents
+    #[arg(short = 'z', long)]
+    pub search_compressed: bool,
+
+    /// When to read files through memory maps instead of buffered reads
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    pub mmap: MmapChoice,
+
+    /// Let matches span lines (e.g. 'fn \w+\(\)\s*\{'); each file is searched as a whole
+    #[arg(short = 'U', long)]
+    pub multiline: bool,
+
+    /// Read files in this encoding (e.g. utf-16le, latin1, shift_jis) instead of
+    /// detecting UTF-16 and byte order marks
+    #[arg(short = 'E', long, value_parser = encoding::parse_label)]
+    pub encoding: Option<&'static encoding_rs::Encoding>,
+
+    /// Search the text of PDF, docx, xlsx and EPUB files rather than their bytes
+    /// (each format needs its cargo feature; `documents` enables them all)
+    #[arg(long)]
+    pub extract: bool,
+
+    /// Print only how many lines matched in each file (with --multiline, how many matches)
+    #[arg(short = 'c', long, group = "mode", conflicts_with_all = CONTEXT_ARGS)]
+    pub count: bool,
+
+    /// Print only the names of files with a match
+    #[arg(short = 'l', long, group = "mode", conflicts_with_all = CONTEXT_ARGS)]
+    pub files_with_matches: bool,
+
+    /// Print only the names of files without a match
+    #[arg(short = 'L', long, group = "mode", conflicts_with_all = CONTEXT_ARGS)]
+    pub files_without_match: bool,
+
+    /// Number of files to search at once [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+/// What to print for each file searched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Every matching line, plus any context
+    Lines,
+    /// `-c`: `name:count` for each file with a match
+    Count,
+    /// `-l`: the names of files with a match
+    FilesWithMatches,
+    /// `-L`: the names of files without one
+    FilesWithoutMatch,
+}
+
+impl Output {
+    /// How many matches settle what gets printed; searching a file stops there.
+    fn enough(self) -> usize {
+        match self {
+            Output::Lines | Output::Count => usize::MAX,
+            Output::FilesWithMatches | Output::FilesWithoutMatch => 1,
+        }
+    }
+
+    /// The report for a file named `name` in which `count` lines matched.
+    fn summarize(self, name: &str, count: usize) -> FileReport {
+        let (output, matches) = match self {
+            Output::Lines => unreachable!("matching lines are rendered as they are found"),
+            Output::Count if count > 0 => (format!("{name}:{count}\n"), count),
+            Output::FilesWithMatches if count > 0 => (format!("{name}\n"), count),
+            Output::FilesWithoutMatch if count == 0 => (format!("{name}\n"), 1),
+            _ => (String::new(), 0),
+        };
+        FileReport { output, matches }
+    }
+}
+
+/// What searching one file produced: the rendered output and how many lines
+/// matched (with -L, whether the file was listed, so the exit code says so).
+#[derive(Debug, Default)]
+struct FileReport {
+    output: String,
+    matches: usize,
+}
+
+impl FileReport {
+    /// Add another file's report to this one, with `--` between them if `separate`.
+    fn append(&mut self, other: FileReport, separate: bool) {
+        if separate && !self.output.is_empty() && !other.output.is_empty() {
+            self.output.push_str("--\n");
+        }
+        self.output.push_str(&other.output);
+        self.matches += other.matches;
+    }
+}
+
+/// The search's name filters, reapplied to archive members.
+#[derive(Debug)]
+struct NameFilter {
+    pattern: String,
+    extensions: Option<Vec<String>>,
+}
+
+impl NameFilter {
+    fn matches(&self, path: &Path) -> bool {
+        file_matches(path, &self.pattern, self.extensions.as_deref())
+    }
+}
+
+/// A compiled pattern plus the options that decide how files are read and printed.
+#[derive(Debug)]
+struct Searcher {
+    regex: Regex,
+    /// The same pattern with `^`/`$` matching at line boundaries, for finding
+    /// candidate lines in a whole buffer. `None` if the pattern uses `\A`/`\z`,
+    /// which can't be translated, so buffers are then searched line by line.
+    finder: Option<Regex>,
+    /// Set with --multiline: the pattern applied to whole files at once.
+    spanning: Option<Regex>,
+    /// Set with --encoding; otherwise each file's encoding is detected.
+    encoding: Option<&'static encoding_rs::Encoding>,
+    output: Output,
+    before: usize,
+    after: usize,
+    binary: bool,
+    list_binary_matches: bool,
+    search_compressed: bool,
+    /// Set with --extract
+    extract: bool,
+    mmap: MmapChoice,
+    /// Set with --search-archives
+    archive_names: Option<NameFilter>,
+}
+
+impl Searcher {
+    fn new(args: &GrepArgs) -> Result<Self> {
+        let regex = RegexBuilder::new(&args.regex)
+            .case_insensitive(args.ignore_case)
+            .build()
+            .with_context(|| format!("invalid regex '{}'", args.regex))?;
+        let finder = if args.regex.contains("\\A") || args.regex.contains("\\z") {
+            None
+        } else {
+            let finder = RegexBuilder::new(&args.regex)
+                .case_insensitive(args.ignore_case)
+                .multi_line(true)
+                .crlf(true)
+                .build()?;
+            Some(finder)
+        };
+        let spanning = args
+            .multiline
+            .then(|| {
+                RegexBuilder::new(&args.regex)
+                    .case_insensitive(args.ignore_case)
+                    .multi_line(true)
+                    .crlf(true)
+                    .build()
+            })
+            .transpose()?;
+        let output = if args.count {
+            Output::Count
+        } else if args.files_with_matches {
+            Output::FilesWithMatches
+        } else if args.files_without_match {
+            Output::FilesWithoutMatch
+        } else {
+            Output::Lines
+        };
+        Ok(Self {
+            regex,
+            finder,
+            spanning,
+            encoding: args.encoding,
+            output,
+            before: args.before_context.or(args.context).unwrap_or(0),
+            after: args.after_context.or(args.context).unwrap_or(0),
+            binary: args.binary,
+            list_binary_matches: args.list_binary_matches,
+            search_compressed: args.search_compressed,
+            extract: args.extract,
+            mmap: args.mmap,
+            archive_names: args.search.search_archives.then(|| NameFilter {
+                pattern: args.search.pattern.clone(),
+                extensions: args.search.extensions.clone(),
+            }),
+        })
+    }
+
+    fn search_file(&self, path: &Path) -> io::Result<FileReport> {
+        if let Some(names) = &self.archive_names {
+            if let Some(format) = Format::detect(path) {
+                return self.search_archive(path, format, names);
+            }
+        }
+        self.search_disk_file(path)
+    }
+
+    fn search_disk_file(&self, path: &Path) -> io::Result<FileReport> {
+        if let Some(extractor) = self.extract.then(|| extract::for_path(path)).flatten() {
+            let text = extractor.extract(path)?;
+            return self.search_text_slice(text.as_bytes(), &path.display().to_string(), false);
+        }
+        let file = File::open(path)?;
+        let compression = self
+            .search_compressed
+            .then(|| Compression::from_path(path))
+            .flatten();
+        // A compressed file's extension says nothing about what is inside it
+        let binary_extension = compression.is_none() && binary::has_binary_extension(path);
+        let name = path.display().to_string();
+
+        let source: Box<dyn Read + Send> = match compression {
+            Some(compression) => compression.decoder(file)?,
+            None => match mmap::map(&file, self.mmap) {
+                Some(map) => return self.search_slice(&map, &name, binary_extension),
+                None => Box::new(file),
+            },
+        };
+        self.search_stream(source, &name, binary_extension)
+    }
+
+    /// Search the members of an archive whose names pass the name filters, named
+    /// `archive!member`, plus the archive file itself if its own name passes them.
+    fn search_archive(
+        &self,
+        path: &Path,
+        format: Format,
+        names: &NameFilter,
+    ) -> io::Result<FileReport> {
+        let mut report = if names.matches(path) {
+            self.search_disk_file(path)?
+        } else {
+            FileReport::default()
+        };
+
+        vpath::for_each_member(path, format, |member, contents| {
+            if names.matches(member) {
+                let name = VirtualPath::Member {
+                    archive: path.to_path_buf(),
+                    member: member.to_path_buf(),
+                };
+                let binary_extension = binary::has_binary_extension(member);
+                let found = self.search_stream(contents, &name.to_string(), binary_extension)?;
+                report.append(found, self.has_context());
+            }
+            Ok(())
+        })?;
+        Ok(report)
+    }
+
+    /// Search text from any source, unless it turns out to be binary.
+    fn search_stream(
+        &self,
+        source: impl Read,
+        name: &str,
+        binary_extension: bool,
+    ) -> io::Result<FileReport> {
+        let mut reader = BufReader::with_capacity(64 * 1024, source);
+        match self.encoding_for(reader.fill_buf()?) {
+            Some(encoding) => {
+                let decoded = encoding::decoding_reader(encoding, reader);
+                self.search_text(BufReader::with_capacity(64 * 1024, decoded), name, false)
+            }
+            None => self.search_text(reader, name, binary_extension),
+        }
+    }
+
+    /// The encoding to decode a file from, given its first bytes; `None` to search
+    /// the bytes as they are.
+    fn encoding_for(&self, head: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+        self.encoding.or_else(|| encoding::detect(head))
+    }
+
+    /// Search a stream that is now either UTF-8 or binary.
+    fn search_text(
+        &self,
+        mut reader: impl BufRead,
+        name: &str,
+        binary_extension: bool,
+    ) -> io::Result<FileReport> {
+        if !self.binary && (binary_extension || binary::looks_binary(reader.fill_buf()?)) {
+            return self.search_binary(reader, name);
+        }
+        if self.spanning.is_some() {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            return self.search_text_slice(&data, name, binary_extension);
+        }
+        if self.output != Output::Lines {
+            let count = self.count_reader(reader, self.output.enough())?;
+            return Ok(self.output.summarize(name, count));
+        }
+        self.search_reader(reader, name)
+    }
+
+    /// Binary files are skipped, or with --list-binary-matches named if they match.
+    fn search_binary(&self, mut reader: impl BufRead, name: &str) -> io::Result<FileReport> {
+        if !self.list_binary_matches {
+            return Ok(FileReport::default());
+        }
+
+        // Naming the file only takes one match, unless -c wants them all
+        let limit = match self.output {
+            Output::Lines => 1,
+            output => output.enough(),
+        };
+        let count = if let Some(spanning) = &self.spanning {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            spanning.find_iter(&data).take(limit).count()
+        } else {
+            self.count_reader(reader, limit)?
+        };
+
+        if self.output != Output::Lines {
+            return Ok(self.output.summarize(name, count));
+        }
+        Ok(if count > 0 {
+            FileReport {
+                output: format!("{name}: binary file matches\n"),
+                matches: 1,
+            }
+        } else {
+            FileReport::default()
+        })
+    }
+
+    /// Search a whole file held in memory, decoding it first if needed.
+    fn search_slice(
+        &self,
+        data: &[u8],
+        name: &str,
+        binary_extension: bool,
+    ) -> io::Result<FileReport> {
+        match self.encoding_for(data) {
+            Some(encoding) => {
+                self.search_text_slice(&encoding::decode(encoding, data), name, false)
+            }
+            None => self.search_text_slice(data, name, binary_extension),
+        }
+    }
+
+    /// Search a buffer that is either UTF-8 or binary.
+    fn search_text_slice(
+        &self,
+        data: &[u8],
+        name: &str,
+        binary_extension: bool,
+    ) -> io::Result<FileReport> {
+        if !self.binary && (binary_extension || binary::looks_binary(data)) {
+            return self.search_binary(data, name);
+        }
+        if self.output != Output::Lines {
+            let limit = self.output.enough();
+            let count = match (&self.spanning, &self.finder) {
+                (Some(spanning), _) => spanning.find_iter(data).take(limit).count(),
+                (None, Some(finder)) => self.matching_lines(finder, data).take(limit).count(),
+                (None, None) => self.count_reader(data, limit)?,
+            };
+            return Ok(self.output.summarize(name, count));
+        }
+        if let Some(spanning) = &self.spanning {
+            return Ok(self.search_spanning(spanning, data, name));
+        }
+        let Some(finder) = self.finder.as_ref().filter(|_| !self.has_context()) else {
+            return self.search_reader(data, name);
+        };
+
+        let mut writer = ContextWriter::new(name, 0, 0);
+        let mut matches = 0;
+        for (number, text) in self.matching_lines(finder, data) {
+            matches += 1;
+            writer.line(number, text, true);
+        }
+        Ok(FileReport {
+            output: writer.output,
+            matches,
+        })
+    }
+
+    /// The matching lines of `data` and their numbers. Rather than splitting every
+    /// line, this looks for the next match in the rest of the buffer with `finder`
+    /// and only then works out which line it is on, re-checking that line on its
+    /// own since a match in the buffer may span lines.
+    fn matching_lines<'a>(
+        &'a self,
+        finder: &'a Regex,
+        data: &'a [u8],
+    ) -> impl Iterator<Item = (usize, &'a [u8])> + 'a {
+        let (mut pos, mut number, mut counted_to) = (0, 1, 0);
+        let mut done = false;
+        std::iter::from_fn(move || {
+            while !done {
+                let found = finder.find_at(data, pos)?;
+                let start = data[..found.start()]
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |i| i + 1)
+                    .max(pos);
+                let end = data[start..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(data.len(), |i| start + i);
+
+                number += data[counted_to..start]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                counted_to = start;
+
+                let text = trim_newline(&data[start..end]);
+                if end >= data.len() {
+                    done = true;
+                } else {
+                    pos = end + 1;
+                }
+                if self.regex.is_match(text) {
+                    return Some((number, text));
+                }
+            }
+            None
+        })
+    }
+
+    /// With --multiline: match against the whole buffer, then print every line
+    /// that a match touches (and any context around them), numbered as usual.
+    fn search_spanning(&self, spanning: &Regex, data: &[u8], name: &str) -> FileReport {
+        let newlines = |bytes: &[u8]| bytes.iter().filter(|&&b| b == b'\n').count();
+
+        // First and last line numbers of each match, in order
+        let mut spans = Vec::new();
+        let (mut line, mut counted_to) = (1, 0);
+        for found in spanning.find_iter(data) {
+            line += newlines(&data[counted_to..found.start()]);
+            counted_to = found.start();
+            // A match ending right after a newline doesn't reach into the next line
+            let last_byte = found.end().max(found.start() + 1) - 1;
+            let last = line + newlines(&data[found.start()..last_byte.min(data.len())]);
+            spans.push((line, last));
+        }
+
+        let mut writer = ContextWriter::new(name, self.before, self.after);
+        let mut spans_left = spans.iter().peekable();
+        for (i, text) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+            let number = i + 1;
+            while spans_left.next_if(|(_, last)| *last < number).is_some() {}
+            let is_match = spans_left.peek().is_some_and(|(first, _)| *first <= number);
+            writer.line(number, trim_newline(text), is_match);
+        }
+
+        FileReport {
+            output: writer.output,
+            matches: spans.len(),
+        }
+    }
+
+    /// Scan `reader` line by line, rendering each matching line as `name:line:text`
+    /// (and context lines, if asked for, as `name-line-text`).
+    fn search_reader(&self, mut reader: impl BufRead, name: &str) -> io::Result<FileReport> {
+        let mut writer = ContextWriter::new(name, self.before, self.after);
+        let mut matches = 0;
+        let mut line = Vec::new();
+        let mut number = 0usize;
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(FileReport {
+                    output: writer.output,
+                    matches,
+                });
+            }
+            number += 1;
+
+            let text = trim_newline(&line);
+            let is_match = self.regex.is_match(text);
+            matches += usize::from(is_match);
+            writer.line(number, text, is_match);
+        }
+    }
+
+    /// Count matching lines without rendering them, stopping once there are `limit`.
+    fn count_reader(&self, mut reader: impl BufRead, limit: usize) -> io::Result<usize> {
+        let mut line = Vec::new();
+        let mut count = 0;
+        while count < limit && reader.read_until(b'\n', &mut line)? > 0 {
+            count += usize::from(self.regex.is_match(trim_newline(&line)));
+            line.clear();
+        }
+        Ok(count)
+    }
+
+    fn has_context(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+}
+
+/// Searches the files it's given one at a time, for callers other than `fs
+/// grep` such as `fs mcp`: with or without case, and with lines of context
+/// around each match, rendered as `fs grep` prints them.
+pub(crate) struct FileSearcher(Searcher);
+
+impl FileSearcher {
+    pub(crate) fn new(regex: &str, ignore_case: bool, context: usize) -> Result<Self> {
+        let args = GrepArgs {
+            regex: regex.to_string(),
+            search: SearchConfig::default(),
+            ignore_case,
+            after_context: None,
+            before_context: None,
+            context: Some(context),
+            binary: false,
+            list_binary_matches: false,
+            search_compressed: false,
+            mmap: MmapChoice::Auto,
+            multiline: false,
+            encoding: None,
+            extract: false,
+            count: false,
+            files_with_matches: false,
+            files_without_match: false,
+            jobs: None,
+        };
+        Searcher::new(&args).map(FileSearcher)
+    }
+
+    /// The matches in the file at `path`, and how many lines matched; none in
+    /// a binary file.
+    pub(crate) fn search(&self, path: &Path) -> io::Result<(String, usize)> {
+        let report = self.0.search_file(path)?;
+        Ok((report.output, report.matches))
+    }
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Runs the search and exits like grep: 0 if anything matched, 1 if nothing did,
+/// 2 if nothing matched and there were errors.
+pub async fn run(args: &GrepArgs) -> Result<ExitCode> {
+    if args.extract && !extract::any_available() {
+        bail!("this fs was built without document extractors; rebuild with --features documents");
+    }
+    let searcher = Arc::new(Searcher::new(args)?);
+    let jobs = args.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+
+    let mut rx = search_files(&args.search).await;
+    let mut running = JoinSet::new();
+    let mut tally = Tally {
+        separate_files: searcher.has_context(),
+        ..Tally::default()
+    };
+
+    while let Some(item) = rx.recv().await {
+        let path = match item {
+            Ok(entry) => entry.into_path(),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                tally.errors += 1;
+                continue;
+            }
+        };
+
+        while running.len() >= jobs.max(1) {
+            if let Some(done) = running.join_next().await {
+                tally.report(done?);
+            }
+        }
+        let searcher = Arc::clone(&searcher);
+        running.spawn_blocking(move || {
+            let report = searcher.search_file(&path);
+            (path, report)
+        });
+    }
+
+    while let Some(done) = running.join_next().await {
+        tally.report(done?);
+    }
+
+    Ok(tally.exit_code())
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    matches: usize,
+    errors: usize,
+    /// With context lines, files' groups are separated by `--` like groups within a file.
+    separate_files: bool,
+    printed_any: bool,
+}
+
+impl Tally {
+    /// Print one searched file's output, or its error.
+    fn report(&mut self, (path, result): (PathBuf, io::Result<FileReport>)) {
+        match result {
+            Ok(report) => {
+                self.matches += report.matches;
+                if report.output.is_empty() {
+                    return;
+                }
+                let mut out = io::stdout().lock();
+                if self.separate_files && self.printed_any {
+                    let _ = out.write_all(b"--\n");
+                }
+                let _ = out.write_all(report.output.as_bytes());
+                self.printed_any = true;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                self.errors += 1;
+            }
+        }
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        if self.matches > 0 {
+            ExitCode::SUCCESS
+        } else if self.errors > 0 {
+            ExitCode::from(2)
+        } else {
+            ExitCode::from(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        grep: GrepArgs,
+    }
+
+    fn searcher(args: &[&str]) -> Searcher {
+        let cli = Cli::parse_from(std::iter::once("grep").chain(args.iter().copied()));
+        Searcher::new(&cli.grep).unwrap()
+    }
+
+    #[test]
+    fn test_search_reader_prints_matching_lines() -> Result<()> {
+        let text = "fn main() {\r\n    // TODO: tidy\n}\n// todo later";
+        let report = searcher(&["TODO"]).search_reader(text.as_bytes(), "src/main.rs")?;
+        assert_eq!(report.matches, 1);
+        assert_eq!(report.output, "src/main.rs:2:    // TODO: tidy\n");
+
+        let report = searcher(&["-i", "todo"]).search_reader(text.as_bytes(), "a")?;
+        assert_eq!(report.matches, 2);
+        assert_eq!(report.output, "a:2:    // TODO: tidy\na:4:// todo later\n");
+
+        let report =
+            searcher(&["-C", "1", "-A", "0", "TODO"]).search_reader(text.as_bytes(), "a")?;
+        assert_eq!(report.output, "a-1-fn main() {\na:2:    // TODO: tidy\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_file_tolerates_invalid_utf8() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("latin1.txt");
+        std::fs::write(&file, b"caf\xe9 needle\n")?;
+
+        let report = searcher(&["needle"]).search_file(&file)?;
+        assert_eq!(report.matches, 1);
+        assert!(report.output.ends_with(":1:caf\u{fffd} needle\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_files_skipped_listed_or_searched() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let blob = tmp.path().join("blob");
+        std::fs::write(&blob, b"\0\x01header\nneedle\n")?;
+        let image = tmp.path().join("text.png");
+        std::fs::write(&image, "needle\n")?;
+
+        for file in [&blob, &image] {
+            assert_eq!(searcher(&["needle"]).search_file(file)?.matches, 0);
+
+            let listed = searcher(&["needle", "--list-binary-matches"]).search_file(file)?;
+            assert_eq!(
+                listed.output,
+                format!("{}: binary file matches\n", file.display())
+            );
+        }
+
+        let forced = searcher(&["needle", "--binary"]).search_file(&blob)?;
+        assert_eq!(forced.output, format!("{}:2:needle\n", blob.display()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_archive_members() -> Result<()> {
+        use std::io::Write;
+
+        let tmp = tempfile::tempdir()?;
+        let archive = tmp.path().join("backup.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive)?);
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("etc/app.conf", options)?;
+        zip.write_all(b"port = 80\nhost = example.org\n")?;
+        zip.start_file("etc/notes.txt", options)?;
+        zip.write_all(b"host = old\n")?;
+        zip.finish()?;
+
+        let report =
+            searcher(&["host", "--search-archives", "-e", "conf"]).search_file(&archive)?;
+        assert_eq!(
+            report.output,
+            format!("{}!etc/app.conf:2:host = example.org\n", archive.display())
+        );
+
+        // Without the flag the archive is just a binary file
+        assert_eq!(searcher(&["host"]).search_file(&archive)?.matches, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_slice_matches_line_by_line_search() -> Result<()> {
+        let lf = "alpha\nbeta gamma\n\ngamma\nalpha beta\nend";
+        let crlf = lf.replace('\n', "\r\n");
+        let patterns = [
+            "gamma",
+            "a\\s+b",
+            "^$",
+            "a$",
+            "end",
+            "^(alpha|end)",
+            "\\Aalpha",
+        ];
+        for text in [lf, &crlf] {
+            for pattern in patterns {
+                let s = searcher(&[pattern]);
+                let by_line = s.search_reader(text.as_bytes(), "f")?;
+                let by_slice = s.search_slice(text.as_bytes(), "f", false)?;
+                assert_eq!(by_slice.output, by_line.output, "pattern {pattern:?}");
+                assert_eq!(by_slice.matches, by_line.matches, "pattern {pattern:?}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiline_matches_span_lines() -> Result<()> {
+        let text = "fn main()\n{\n    run();\n}\n\nfn helper() {}\n";
+        let s = searcher(&["-U", r"fn \w+\(\)\s*\{"]);
+        let report = s.search_slice(text.as_bytes(), "f", false)?;
+        assert_eq!(report.matches, 2);
+        assert_eq!(report.output, "f:1:fn main()\nf:2:{\nf:6:fn helper() {}\n");
+
+        // Line-oriented search can't see across the newline
+        assert_eq!(
+            searcher(&[r"\)\s*\{"])
+                .search_slice(text.as_bytes(), "f", false)?
+                .matches,
+            1
+        );
+
+        // Through a reader, and with context lines
+        let s = searcher(&["-U", "-A", "1", r"run\(\);\n\}"]);
+        let report = s.search_stream(text.as_bytes(), "f", false)?;
+        assert_eq!(report.output, "f:3:    run();\nf:4:}\nf-5-\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_utf16_and_forced_encodings() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log = tmp.path().join("setup.log");
+        let utf16: Vec<u8> = "started\r\nerror 0x80070005\r\n"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        std::fs::write(&log, &utf16)?;
+        let latin1 = tmp.path().join("menu.txt");
+        std::fs::write(&latin1, b"caf\xe9 au lait\n")?;
+
+        let expected = format!("{}:2:error 0x80070005\n", log.display());
+        for mmap in ["never", "always"] {
+            let report = searcher(&["--mmap", mmap, "error"]).search_file(&log)?;
+            assert_eq!(report.output, expected, "--mmap {mmap}");
+        }
+
+        let report = searcher(&["--encoding", "latin1", "café"]).search_file(&latin1)?;
+        assert_eq!(report.matches, 1);
+        assert_eq!(searcher(&["café"]).search_file(&latin1)?.matches, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_and_file_name_modes() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let todo = tmp.path().join("todo.rs");
+        std::fs::write(&todo, "// TODO: one\nfn main() {}\n// TODO: two\n")?;
+        let done = tmp.path().join("done.rs");
+        std::fs::write(&done, "fn main() {}\n")?;
+        let (todo_name, done_name) = (todo.display(), done.display());
+
+        for mmap in ["never", "always"] {
+            let count = searcher(&["--mmap", mmap, "-c", "TODO"]);
+            assert_eq!(count.search_file(&todo)?.output, format!("{todo_name}:2\n"));
+            assert_eq!(count.search_file(&done)?.output, "");
+
+            let with = searcher(&["--mmap", mmap, "-l", "TODO"]);
+            let report = with.search_file(&todo)?;
+            assert_eq!(report.output, format!("{todo_name}\n"));
+            // Stopped at the first match
+            assert_eq!(report.matches, 1);
+            assert_eq!(with.search_file(&done)?.output, "");
+
+            let without = searcher(&["--mmap", mmap, "-L", "TODO"]);
+            assert_eq!(without.search_file(&todo)?.matches, 0);
+            let report = without.search_file(&done)?;
+            assert_eq!(report.output, format!("{done_name}\n"));
+            assert_eq!(report.matches, 1);
+        }
+
+        let spanning = searcher(&["-U", "-c", r"TODO[^\n]*\nfn"]);
+        assert_eq!(
+            spanning.search_file(&todo)?.output,
+            format!("{todo_name}:1\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_compressed() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log = tmp.path().join("app.log.1.zst");
+        std::fs::write(&log, zstd::encode_all(&b"ok\nerror: disk full\n"[..], 0)?)?;
+
+        // Without the flag, a .zst file is binary and skipped
+        assert_eq!(searcher(&["error"]).search_file(&log)?.matches, 0);
+
+        let report = searcher(&["-z", "error"]).search_file(&log)?;
+        assert_eq!(
+            report.output,
+            format!("{}:2:error: disk full\n", log.display())
+        );
+        Ok(())
+    }
+}
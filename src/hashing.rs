@@ -0,0 +1,195 @@
+//! File hashing shared by `--hash` and `fs dup`: the digest algorithms, and a
+//! pool of blocking workers that hashes files as they arrive.
+
+use crate::crawl::{FileEntry, Results};
+use crate::Error;
+use clap::ValueEnum;
+use serde::Deserialize;
+use sha2::Digest;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// Files at least this large are hashed by BLAKE3 on several threads at once,
+/// through a memory map; below it, splitting the work costs more than it saves.
+const MULTITHREAD_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    Md5,
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Md5(h) => h.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(h) => hex(&h.finalize()),
+            Hasher::Md5(h) => hex(&h.finalize()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hash a file's contents and return the lowercase hex digest.
+pub fn hash_file(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+    let mut hasher = match algorithm {
+        Algorithm::Blake3 => return Ok(blake3_file(path)?.to_hex().to_string()),
+        Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+        Algorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+    };
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// The BLAKE3 hash of a whole file, using several threads for large ones.
+pub fn blake3_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    if std::fs::metadata(path)?.len() >= MULTITHREAD_THRESHOLD {
+        hasher.update_mmap_rayon(path)?;
+    } else {
+        hasher.update_reader(File::open(path)?)?;
+    }
+    Ok(hasher.finalize())
+}
+
+/// Where [`spawn_pool`] takes its work from: a plain channel, or a search's
+/// results.
+pub trait Source<T>: Send + 'static {
+    fn recv(&mut self) -> impl Future<Output = Option<T>> + Send;
+}
+
+impl<T: Send + 'static> Source<T> for mpsc::Receiver<T> {
+    fn recv(&mut self) -> impl Future<Output = Option<T>> + Send {
+        mpsc::Receiver::recv(self)
+    }
+}
+
+impl Source<Result<FileEntry, Error>> for Results {
+    fn recv(&mut self) -> impl Future<Output = Option<Result<FileEntry, Error>>> + Send {
+        Results::recv(self)
+    }
+}
+
+/// Run `hash` on each item from `rx` on up to `jobs` blocking threads, sending
+/// every item back with its result in the order they finish. Stops early if the
+/// receiver of the results is dropped.
+pub fn spawn_pool<T, R, F>(mut rx: impl Source<T>, jobs: usize, hash: F) -> mpsc::Receiver<(T, R)>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(&T) -> R + Send + Sync + 'static,
+{
+    let jobs = jobs.max(1);
+    let (tx, out) = mpsc::channel(jobs);
+    let hash = Arc::new(hash);
+
+    tokio::spawn(async move {
+        let mut running = JoinSet::new();
+        loop {
+            // Keep the pool full while there is work; otherwise hand on results
+            tokio::select! {
+                item = rx.recv(), if running.len() < jobs => {
+                    let Some(item) = item else { break };
+                    let hash = Arc::clone(&hash);
+                    running.spawn_blocking(move || {
+                        let result = hash(&item);
+                        (item, result)
+                    });
+                }
+                Some(done) = running.join_next(), if !running.is_empty() => {
+                    let Ok(done) = done else { continue };
+                    if tx.send(done).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        while let Some(done) = running.join_next().await {
+            let Ok(done) = done else { continue };
+            if tx.send(done).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("abc.txt");
+        std::fs::write(&file, "abc")?;
+
+        assert_eq!(
+            hash_file(&file, Algorithm::Sha256)?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash_file(&file, Algorithm::Blake3)?,
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            hash_file(&file, Algorithm::Md5)?,
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+
+        // Big enough for the multithreaded path, which must agree with the plain one
+        let big = tmp.path().join("big.bin");
+        let data: Vec<u8> = (0..MULTITHREAD_THRESHOLD + 1).map(|i| i as u8).collect();
+        std::fs::write(&big, &data)?;
+        assert_eq!(blake3_file(&big)?, blake3::hash(&data));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pool_returns_every_item() {
+        let (tx, rx) = mpsc::channel(4);
+        let mut results = spawn_pool(rx, 3, |n: &u64| n * n);
+        tokio::spawn(async move {
+            for n in 0..50 {
+                tx.send(n).await.unwrap();
+            }
+        });
+
+        let mut squares = Vec::new();
+        while let Some((n, square)) = results.recv().await {
+            assert_eq!(square, n * n);
+            squares.push(n);
+        }
+        squares.sort();
+        assert_eq!(squares, (0..50).collect::<Vec<_>>());
+    }
+}
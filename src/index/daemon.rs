@@ -0,0 +1,409 @@
+//! `fs daemon`: the index read once and kept in memory, answering `fs locate
+//! --daemon` over a Unix domain socket (a named pipe on Windows), so that a
+//! lookup costs neither starting up nor reading the index. The daemon reads
+//! the index again whenever the file changes, as `fs index build --watch`
+//! keeps changing it.
+//!
+//! The protocol is JSON, each message preceded by its length as four bytes,
+//! big-endian. A client sends a query, `{"pattern": "readme", "basename":
+//! true}` with the fields of [`QueryArgs`], and gets back `{"found": {"root":
+//! ..., "built_ns": ..., "paths": [...]}}` with the paths below the root, or
+//! `{"error": "..."}`; as many times over as it likes on one connection.
+
+use super::{Index, IndexFile, QueryArgs};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The longest query the daemon accepts; a pattern and a few flags need far
+/// less.
+const MAX_QUERY: u32 = 4 << 20;
+
+/// The longest answer a client accepts, with every path of a large index.
+const MAX_ANSWER: u32 = 1 << 30;
+
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    #[command(flatten)]
+    pub file: IndexFile,
+
+    #[command(flatten)]
+    pub socket: Socket,
+}
+
+/// Where the daemon listens.
+#[derive(Args, Debug)]
+pub struct Socket {
+    /// The daemon's socket, or on Windows its named pipe [default:
+    /// fs-index.sock in $XDG_RUNTIME_DIR or the temporary directory]
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+impl Socket {
+    /// The socket given, or else the default one.
+    fn resolve(&self) -> PathBuf {
+        if let Some(socket) = &self.socket {
+            return socket.clone();
+        }
+        #[cfg(windows)]
+        return PathBuf::from(r"\\.\pipe\fs-index");
+        #[cfg(not(windows))]
+        match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir).join("fs-index.sock"),
+            None => std::env::temp_dir().join(format!("fs-index-{}.sock", user_id())),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn user_id() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn user_id() -> u32 {
+    0
+}
+
+/// The daemon's answer to a query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Answer {
+    Found(Reply),
+    Error(String),
+}
+
+/// What the daemon found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reply {
+    /// The indexed root, absolute
+    pub root: PathBuf,
+    /// When the index was built, in nanoseconds since the Unix epoch
+    pub built_ns: i64,
+    /// The matches, below the root
+    pub paths: Vec<String>,
+}
+
+/// The index in memory, and which version of the file it was read from.
+struct Loaded {
+    file: PathBuf,
+    index: Arc<Index>,
+    stamp: Option<Stamp>,
+}
+
+impl Loaded {
+    fn read(file: PathBuf) -> Result<Self> {
+        let stamp = stamp(&file);
+        let index = Arc::new(Index::read(&file)?);
+        Ok(Loaded { file, index, stamp })
+    }
+
+    /// The index as the file has it now, read again if it's changed.
+    fn current(&mut self) -> Result<Arc<Index>> {
+        let stamp = stamp(&self.file);
+        if stamp != self.stamp {
+            *self = Loaded::read(self.file.clone())?;
+        }
+        Ok(Arc::clone(&self.index))
+    }
+}
+
+/// Which version of the index file there is. It's replaced by a rename when
+/// it's written, so a new one is a new file even if it has the same length
+/// and the same mtime, to the clock's resolution, as the last.
+#[derive(Debug, PartialEq, Eq)]
+struct Stamp {
+    id: u64,
+    modified: SystemTime,
+    len: u64,
+}
+
+fn stamp(file: &Path) -> Option<Stamp> {
+    let metadata = std::fs::metadata(file).ok()?;
+    Some(Stamp {
+        id: file_id(&metadata),
+        modified: metadata.modified().ok()?,
+        len: metadata.len(),
+    })
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+/// Without inode numbers, the mtime and length have to do.
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
+pub async fn run(args: &DaemonArgs) -> Result<ExitCode> {
+    let loaded = Loaded::read(args.file.resolve()?)?;
+    let socket = args.socket.resolve();
+    let listener = bind(&socket).await?;
+    eprintln!(
+        "Serving the index of {} ({} entries) on {}; Ctrl-C to stop",
+        loaded.index.root.display(),
+        loaded.index.entries.len(),
+        socket.display()
+    );
+    let loaded = Arc::new(Mutex::new(loaded));
+    let result = tokio::select! {
+        result = serve(listener, loaded) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    #[cfg(unix)]
+    let _ = std::fs::remove_file(&socket);
+    result.map(|()| ExitCode::SUCCESS)
+}
+
+/// Ask the daemon on `socket` for what `query` finds.
+pub async fn ask(socket: &Socket, query: &QueryArgs) -> Result<Reply> {
+    let mut stream = connect(&socket.resolve()).await?;
+    write_message(&mut stream, &serde_json::to_vec(query)?).await?;
+    let Some(message) = read_message(&mut stream, MAX_ANSWER).await? else {
+        bail!("the daemon hung up without answering");
+    };
+    match serde_json::from_slice(&message).context("the daemon's answer isn't understood")? {
+        Answer::Found(reply) => Ok(reply),
+        Answer::Error(e) => bail!("the daemon says: {e}"),
+    }
+}
+
+/// Answer the queries on one connection until the client hangs up.
+async fn answer<S>(mut stream: S, loaded: Arc<Mutex<Loaded>>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(message) = read_message(&mut stream, MAX_QUERY).await? {
+        let loaded = Arc::clone(&loaded);
+        let answer = tokio::task::spawn_blocking(move || {
+            let query: QueryArgs = serde_json::from_slice(&message)?;
+            let index = loaded.lock().unwrap().current()?;
+            let paths = index
+                .look_up(&query)?
+                .into_iter()
+                .map(String::from)
+                .collect();
+            anyhow::Ok(Reply {
+                root: index.root.clone(),
+                built_ns: index.built_ns,
+                paths,
+            })
+        })
+        .await?;
+        let answer = match answer {
+            Ok(reply) => Answer::Found(reply),
+            Err(e) => Answer::Error(format!("{e:#}")),
+        };
+        write_message(&mut stream, &serde_json::to_vec(&answer)?).await?;
+    }
+    Ok(())
+}
+
+/// A message of up to `max` bytes, or None if the other side hung up before
+/// starting another.
+async fn read_message(stream: &mut (impl AsyncRead + Unpin), max: u32) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > max {
+        bail!("a message says it's {len} bytes long, more than any should be");
+    }
+    let mut message = vec![0; len as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn write_message(stream: &mut (impl AsyncWrite + Unpin), message: &[u8]) -> Result<()> {
+    let len = u32::try_from(message.len())
+        .ok()
+        .filter(|&len| len <= MAX_ANSWER)
+        .context("the message is too long to send")?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn bind(socket: &Path) -> Result<tokio::net::UnixListener> {
+    if socket.symlink_metadata().is_ok() {
+        if tokio::net::UnixStream::connect(socket).await.is_ok() {
+            bail!("a daemon is already listening on {}", socket.display());
+        }
+        // Left behind by one that's gone
+        std::fs::remove_file(socket)
+            .with_context(|| format!("can't remove {}", socket.display()))?;
+    }
+    tokio::net::UnixListener::bind(socket)
+        .with_context(|| format!("can't listen on {}", socket.display()))
+}
+
+#[cfg(unix)]
+async fn serve(listener: tokio::net::UnixListener, loaded: Arc<Mutex<Loaded>>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let loaded = Arc::clone(&loaded);
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, loaded).await {
+                eprintln!("Error: {e:#}");
+            }
+        });
+    }
+}
+
+/// A connection to the daemon on `socket`, if it's one of ours: anyone can
+/// make a socket where the default one goes without $XDG_RUNTIME_DIR.
+#[cfg(unix)]
+async fn connect(socket: &Path) -> Result<tokio::net::UnixStream> {
+    let stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .with_context(|| not_listening(socket))?;
+    let uid = stream.peer_cred()?.uid();
+    if uid != user_id() {
+        bail!(
+            "the daemon on {} is another user's (uid {uid}), so it isn't asked",
+            socket.display()
+        );
+    }
+    Ok(stream)
+}
+
+fn not_listening(socket: &Path) -> String {
+    format!(
+        "no daemon is listening on {}; start `fs daemon`",
+        socket.display()
+    )
+}
+
+#[cfg(windows)]
+type Pipe = tokio::net::windows::named_pipe::NamedPipeServer;
+
+#[cfg(windows)]
+async fn bind(socket: &Path) -> Result<(Pipe, PathBuf)> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let pipe = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(socket)
+        .with_context(|| format!("can't listen on {}", socket.display()))?;
+    Ok((pipe, socket.to_path_buf()))
+}
+
+#[cfg(windows)]
+async fn serve((mut pipe, name): (Pipe, PathBuf), loaded: Arc<Mutex<Loaded>>) -> Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    loop {
+        pipe.connect().await?;
+        // The next client gets an instance of its own
+        let connected = std::mem::replace(&mut pipe, ServerOptions::new().create(&name)?);
+        let loaded = Arc::clone(&loaded);
+        tokio::spawn(async move {
+            if let Err(e) = answer(connected, loaded).await {
+                eprintln!("Error: {e:#}");
+            }
+        });
+    }
+}
+
+#[cfg(windows)]
+async fn connect(socket: &Path) -> Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(socket)
+        .with_context(|| not_listening(socket))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::index::Entry;
+    use crate::EntryKind;
+
+    #[tokio::test]
+    async fn test_the_daemon_answers_from_the_index_as_it_is_now() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("index");
+        let entry = |path: &str| Entry {
+            path: path.into(),
+            kind: EntryKind::File,
+            size: 0,
+            mtime_ns: 0,
+        };
+        let mut index = Index {
+            root: "/src".into(),
+            built_ns: 7,
+            entries: vec![entry("README.md"), entry("lib/readme.txt")],
+        };
+        index.write(&file)?;
+
+        let socket = Socket {
+            socket: Some(tmp.path().join("sock")),
+        };
+        let listener = bind(&socket.resolve()).await?;
+        let loaded = Arc::new(Mutex::new(Loaded::read(file.clone())?));
+        let daemon = tokio::spawn(serve(listener, loaded));
+        assert!(bind(&socket.resolve()).await.is_err());
+
+        let query = QueryArgs {
+            pattern: "readme".into(),
+            ignore_case: true,
+            ..QueryArgs::default()
+        };
+        let reply = ask(&socket, &query).await?;
+        assert_eq!(reply.root, Path::new("/src"));
+        assert_eq!(reply.built_ns, 7);
+        assert_eq!(reply.paths, ["README.md", "lib/readme.txt"]);
+
+        index.entries.remove(0);
+        index.built_ns = 8;
+        index.write(&file)?;
+        let reply = ask(&socket, &query).await?;
+        assert_eq!(
+            (reply.built_ns, reply.paths),
+            (8, vec!["lib/readme.txt".into()])
+        );
+
+        // A bad query gets an error back, not a dropped connection
+        let mut stream = connect(&socket.resolve()).await?;
+        write_message(&mut stream, br#"{"limit": "lots"}"#).await?;
+        let answer = read_message(&mut stream, MAX_ANSWER).await?.unwrap();
+        assert!(matches!(serde_json::from_slice(&answer)?, Answer::Error(_)));
+
+        // Nor is a query longer than any should be read
+        let mut stream = connect(&socket.resolve()).await?;
+        stream.write_all(&(MAX_QUERY + 1).to_be_bytes()).await?;
+        assert!(read_message(&mut stream, MAX_ANSWER).await?.is_none());
+        daemon.abort();
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_replaced_index_has_a_new_stamp() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let (file, next) = (tmp.path().join("index"), tmp.path().join("index.new"));
+        std::fs::write(&file, "one")?;
+        let before = stamp(&file).context("no stamp")?;
+
+        // The same length and mtime, but another file
+        std::fs::write(&next, "two")?;
+        let mtime = filetime::FileTime::from_system_time(before.modified);
+        filetime::set_file_mtime(&next, mtime)?;
+        std::fs::rename(&next, &file)?;
+        let after = stamp(&file).context("no stamp")?;
+        assert_eq!((after.modified, after.len), (before.modified, before.len));
+        assert_ne!(after, before);
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! The index file. After a header come the entries, sorted by path, each path
+//! written as how many bytes it shares with the one before and the rest of
+//! it, which on a real tree, where neighbours share most of their
+//! directories, shrinks the paths to a fraction of their length:
+//!
+//! ```text
+//! "fs-index" version:u8 built:i64 root:str count:varint
+//! then count times: shared:varint rest:str kind:u8 size:varint mtime:zigzag
+//! ```
+//!
+//! Numbers are LEB128 varints (`zigzag` for the signed modification time,
+//! `built` is plain little-endian) and a `str` is a varint length and that
+//! many bytes of UTF-8.
+
+use super::{Entry, Index};
+use crate::EntryKind;
+use std::io;
+use std::path::PathBuf;
+
+const MAGIC: &[u8] = b"fs-index";
+const VERSION: u8 = 1;
+
+/// `index` as the bytes of an index file. Its entries must be sorted by path.
+pub(super) fn encode(index: &Index) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64 + index.entries.len() * 24);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&index.built_ns.to_le_bytes());
+    put_str(&mut out, &index.root.to_string_lossy());
+    put_varint(&mut out, index.entries.len() as u64);
+    let mut last = "";
+    for entry in &index.entries {
+        let mut shared = common_prefix(last, &entry.path);
+        while !entry.path.is_char_boundary(shared) {
+            shared -= 1;
+        }
+        put_varint(&mut out, shared as u64);
+        put_str(&mut out, &entry.path[shared..]);
+        out.push(kind_byte(entry.kind));
+        put_varint(&mut out, entry.size);
+        put_varint(&mut out, zigzag(entry.mtime_ns));
+        last = &entry.path;
+    }
+    out
+}
+
+/// The index in the bytes of an index file.
+pub(super) fn decode(bytes: &[u8]) -> io::Result<Index> {
+    let mut input = Input(bytes);
+    if input.take(MAGIC.len())? != MAGIC {
+        return Err(damaged("it isn't an index file"));
+    }
+    let version = input.take(1)?[0];
+    if version != VERSION {
+        return Err(damaged(&format!(
+            "it's version {version} of the format, not {VERSION}; build it again"
+        )));
+    }
+    let built_ns = i64::from_le_bytes(input.take(8)?.try_into().unwrap());
+    let root = PathBuf::from(input.str()?);
+    let count = input.varint()?;
+    // Not trusting the count for more than the file could hold
+    let mut entries = Vec::with_capacity((count as usize).min(bytes.len() / 4));
+    let mut last = String::new();
+    for _ in 0..count {
+        let shared = input.varint()? as usize;
+        if shared > last.len() || !last.is_char_boundary(shared) {
+            return Err(damaged("a path shares more than the one before it has"));
+        }
+        let mut path = String::with_capacity(shared + 16);
+        path.push_str(&last[..shared]);
+        path.push_str(input.str()?);
+        let kind = match input.take(1)?[0] {
+            0 => EntryKind::File,
+            1 => EntryKind::Dir,
+            2 => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let size = input.varint()?;
+        let mtime_ns = unzigzag(input.varint()?);
+        last.clone_from(&path);
+        entries.push(Entry {
+            path,
+            kind,
+            size,
+            mtime_ns,
+        });
+    }
+    if !input.0.is_empty() {
+        return Err(damaged("there's more after the last entry"));
+    }
+    Ok(Index {
+        root,
+        built_ns,
+        entries,
+    })
+}
+
+fn kind_byte(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::File => 0,
+        EntryKind::Dir => 1,
+        EntryKind::Symlink => 2,
+        EntryKind::Other => 3,
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn damaged(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("damaged index: {why}"))
+}
+
+/// What's left of an index file to read.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(damaged("it ends partway through"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err(damaged("a number is too long"))
+    }
+
+    fn str(&mut self) -> io::Result<&'a str> {
+        let len = self.varint()?;
+        let len = usize::try_from(len).map_err(|_| damaged("a path is too long"))?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| damaged("a path isn't UTF-8"))
+    }
+}
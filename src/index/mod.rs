@@ -0,0 +1,474 @@
+//! `fs index build` and `fs locate`: a tree walked once and written down, so
+//! that looking for a name in it afterwards, `locate`-style, is a scan of a
+//! file in memory rather than of the disk. The index records each entry's
+//! path below the root, kind, size and modification time; `fs locate` warns
+//! when it's older than `--max-age`, since what it answers is only as fresh
+//! as the last build, unless `fs index build --watch` keeps it up to date.
+//! `fs daemon` holds it in memory, read once, for `fs locate --daemon` to ask.
+
+pub mod daemon;
+mod format;
+mod update;
+
+use crate::actions::human_size;
+use crate::actions::touch::parse_duration;
+use crate::crawl::{EntryKinds, Fields};
+use crate::manifest::relative;
+use crate::search::Custom;
+use crate::{start_search, unix_ns, EntryKind, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    #[command(subcommand)]
+    pub command: IndexCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IndexCommand {
+    /// Walk a tree, with the search's filters, and write its index over the last one
+    Build(Box<BuildArgs>),
+    /// Print which tree the index is of, how old it is and how much it holds
+    Info(IndexFile),
+}
+
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    #[command(flatten)]
+    pub file: IndexFile,
+
+    /// Don't stop once it's written: keep the index up to date from the file
+    /// events, rewriting it a second after changes
+    #[arg(long)]
+    pub watch: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct LocateArgs {
+    #[command(flatten)]
+    pub query: QueryArgs,
+
+    /// Warn when the index is older than this (e.g. 12h, 2d)
+    #[arg(long, value_name = "DURATION", default_value = "1d", value_parser = parse_duration)]
+    pub max_age: Duration,
+
+    #[command(flatten)]
+    pub file: IndexFile,
+
+    /// Ask the `fs daemon` listening on the socket, which has the index in
+    /// memory already, instead of reading it
+    #[arg(long, conflicts_with = "path")]
+    pub daemon: bool,
+
+    #[command(flatten)]
+    pub socket: daemon::Socket,
+}
+
+/// What `fs locate` looks for, as given on its command line or sent to the
+/// daemon.
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryArgs {
+    /// What to look for in the paths below the indexed root; as with -p,
+    /// '*' is ignored and the rest is matched anywhere in them
+    pub pattern: String,
+
+    /// Match the pattern against names only, not the directories above them
+    #[arg(short, long)]
+    pub basename: bool,
+
+    /// Match regardless of case
+    #[arg(short, long)]
+    pub ignore_case: bool,
+
+    /// Only files with these extensions (comma-separated)
+    #[arg(short, long, value_delimiter = ',')]
+    pub extensions: Option<Vec<String>>,
+
+    /// Print at most N paths
+    #[arg(short = 'n', long, value_name = "N")]
+    pub limit: Option<usize>,
+}
+
+/// Where the index is kept.
+#[derive(Args, Debug)]
+pub struct IndexFile {
+    /// The index file [default: $FS_INDEX_PATH, or fs/index in the user's cache directory]
+    #[arg(long = "index", value_name = "FILE")]
+    pub path: Option<PathBuf>,
+}
+
+impl IndexFile {
+    /// The file given, or else the default one.
+    fn resolve(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.path {
+            return Ok(path.clone());
+        }
+        if let Some(path) = env::var_os("FS_INDEX_PATH").filter(|path| !path.is_empty()) {
+            return Ok(path.into());
+        }
+        let dir = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        };
+        match dir {
+            Some(dir) => Ok(dir.join("fs").join("index")),
+            None => bail!("there's no cache directory to keep the index in; give --index"),
+        }
+    }
+}
+
+/// A tree as it was when indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// The tree's root, absolute
+    pub root: PathBuf,
+    /// When it was indexed, or since last known to be current, in
+    /// nanoseconds since the Unix epoch
+    pub built_ns: i64,
+    /// Sorted by path
+    pub entries: Vec<Entry>,
+}
+
+/// One entry of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Relative to the root, with `/` separators
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch
+    pub mtime_ns: i64,
+}
+
+impl Index {
+    /// Read the index at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| {
+            format!(
+                "can't read the index {}; `fs index build` writes it",
+                path.display()
+            )
+        })?;
+        format::decode(&bytes).with_context(|| format!("can't read {}", path.display()))
+    }
+
+    /// Write it to `path`, replacing what's there only once it's all written.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("can't create {}", dir.display()))?;
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, format::encode(self))
+            .and_then(|()| std::fs::rename(&partial, path))
+            .with_context(|| format!("can't write {}", path.display()))
+    }
+
+    /// How long ago it was built.
+    pub fn age(&self) -> Duration {
+        age(self.built_ns)
+    }
+
+    /// The entries `query` matches, in order.
+    pub fn find<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.entries.iter().filter(|entry| query.matches(entry))
+    }
+
+    /// Where `entry` is on disk.
+    pub fn path_of(&self, entry: &Entry) -> PathBuf {
+        self.root.join(&entry.path)
+    }
+
+    /// The paths below the root of the entries `args` asks for, in order.
+    pub fn look_up(&self, args: &QueryArgs) -> Result<Vec<&str>> {
+        let query = Query::new(args)?;
+        let limit = args.limit.unwrap_or(usize::MAX);
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .take(limit)
+            .map(|entry| entry.path.as_str())
+            .collect())
+    }
+}
+
+/// How long ago `built_ns` was.
+fn age(built_ns: i64) -> Duration {
+    let now = unix_ns(SystemTime::now());
+    Duration::from_nanos(now.saturating_sub(built_ns).max(0) as u64)
+}
+
+/// What `fs locate` looks for.
+#[derive(Debug, Clone)]
+pub struct Query {
+    pattern: Regex,
+    basename: bool,
+    extensions: Option<Vec<String>>,
+}
+
+impl Query {
+    pub fn new(args: &QueryArgs) -> Result<Self> {
+        let pattern = RegexBuilder::new(&regex::escape(&args.pattern.replace('*', "")))
+            .case_insensitive(args.ignore_case)
+            .build()?;
+        Ok(Query {
+            pattern,
+            basename: args.basename,
+            extensions: args.extensions.clone(),
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let name = entry.path.rsplit('/').next().unwrap_or_default();
+        if let Some(extensions) = &self.extensions {
+            let ext = name.rsplit_once('.').map(|(_, ext)| ext);
+            let wanted = |ext: &str| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext));
+            if entry.kind == EntryKind::Dir || !ext.is_some_and(wanted) {
+                return false;
+            }
+        }
+        let haystack = if self.basename { name } else { &entry.path };
+        self.pattern.is_match(haystack)
+    }
+}
+
+pub async fn run(args: &IndexArgs) -> Result<ExitCode> {
+    match &args.command {
+        IndexCommand::Build(args) => build_command(args).await,
+        IndexCommand::Info(file) => info(file),
+    }
+}
+
+async fn build_command(args: &BuildArgs) -> Result<ExitCode> {
+    let path = args.file.resolve()?;
+    if args.watch {
+        update::build_and_keep_up(&args.search, &path).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let (index, errors) = build(&args.search).await?;
+    index.write(&path)?;
+    let size = std::fs::metadata(&path).map_or(0, |meta| meta.len());
+    eprintln!(
+        "Indexed {} entries under {} into {} ({})",
+        index.entries.len(),
+        index.root.display(),
+        path.display(),
+        human_size(size)
+    );
+    Ok(if errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Walk the tree `search` describes and index everything it finds,
+/// directories too; also how many entries couldn't be read.
+pub async fn build(search: &SearchConfig) -> Result<(Index, usize)> {
+    if search.search_archives {
+        bail!("the index records entries on disk; drop --search-archives");
+    }
+    let mut config = search.clone();
+    config.root_path = std::fs::canonicalize(&search.root_path)
+        .with_context(|| format!("can't index {}", search.root_path.display()))?;
+    config.crawl.stat = Fields {
+        kind: true,
+        size: true,
+        modified: true,
+    };
+    let custom = Custom {
+        emit: EntryKinds::ALL,
+        ..Custom::default()
+    };
+    let built_ns = unix_ns(SystemTime::now());
+
+    let mut rx = start_search(&config, &custom);
+    let mut entries = Vec::new();
+    let mut errors = 0usize;
+    while let Some(item) = rx.recv().await {
+        let read = item.map_err(io::Error::other).and_then(|found| {
+            Ok(Entry {
+                kind: found.kind()?,
+                size: found.size()?,
+                mtime_ns: found.modified().map_or(0, unix_ns),
+                path: relative(found.path(), &config.root_path),
+            })
+        });
+        match read {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                errors += 1;
+            }
+        }
+    }
+    entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    let index = Index {
+        root: config.root_path,
+        built_ns,
+        entries,
+    };
+    Ok((index, errors))
+}
+
+pub async fn locate(args: &LocateArgs) -> Result<ExitCode> {
+    let found = if args.daemon {
+        let reply = daemon::ask(&args.socket, &args.query).await?;
+        warn_if_old(&reply.root, reply.built_ns, args.max_age);
+        print(&reply.root, reply.paths.iter().map(String::as_str))?
+    } else {
+        let index = Index::read(&args.file.resolve()?)?;
+        warn_if_old(&index.root, index.built_ns, args.max_age);
+        print(&index.root, index.look_up(&args.query)?.into_iter())?
+    };
+    Ok(if found {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn warn_if_old(root: &Path, built_ns: i64, max_age: Duration) {
+    let age = age(built_ns);
+    if age > max_age {
+        eprintln!(
+            "Warning: the index of {} is {} old; `fs index build` brings it up to date",
+            root.display(),
+            human_age(age)
+        );
+    }
+}
+
+/// Print `paths` under `root`, until stdout's reader goes away; whether
+/// there were any.
+fn print<'a>(root: &Path, paths: impl Iterator<Item = &'a str>) -> Result<bool> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut found = false;
+    for path in paths {
+        found = true;
+        let written = writeln!(out, "{}", root.join(path).display());
+        if matches!(&written, Err(e) if e.kind() == io::ErrorKind::BrokenPipe) {
+            return Ok(true);
+        }
+        written?;
+    }
+    match out.flush() {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(found),
+    }
+}
+
+fn info(file: &IndexFile) -> Result<ExitCode> {
+    let path = file.resolve()?;
+    let index = Index::read(&path)?;
+    let count = |kind| index.entries.iter().filter(|e| e.kind == kind).count();
+    let size: u64 = index.entries.iter().map(|e| e.size).sum();
+    println!("Index: {}", path.display());
+    println!("Root:  {}", index.root.display());
+    println!("Built: {} ago", human_age(index.age()));
+    println!(
+        "Holds: {} files ({}), {} directories, {} other",
+        count(EntryKind::File),
+        human_size(size),
+        count(EntryKind::Dir),
+        count(EntryKind::Symlink) + count(EntryKind::Other)
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `age` in its largest whole unit: "3 days", "1 hour", "40 seconds".
+fn human_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{n} {unit}{}", if n == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_an_index_is_written_read_back_and_searched() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("tree");
+        std::fs::create_dir_all(root.join("src/héllo"))?;
+        std::fs::write(root.join("src/main.rs"), "fn main() {}\n")?;
+        std::fs::write(root.join("src/héllo/Lib.RS"), "")?;
+        std::fs::write(root.join("README.md"), "# tree\n")?;
+        std::fs::write(root.join(".hidden.rs"), "")?;
+
+        let search = SearchConfig {
+            root_path: root.clone(),
+            ..SearchConfig::default()
+        };
+        let (index, errors) = build(&search).await?;
+        assert_eq!(errors, 0);
+        let paths: Vec<_> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "README.md",
+                "src",
+                "src/héllo",
+                "src/héllo/Lib.RS",
+                "src/main.rs"
+            ]
+        );
+        assert_eq!(index.entries[0].size, 7);
+        assert_eq!(index.entries[1].kind, EntryKind::Dir);
+
+        let file = tmp.path().join("cache/index");
+        index.write(&file)?;
+        let read = Index::read(&file)?;
+        assert_eq!(read, index);
+        assert!(read.age() < Duration::from_secs(60));
+
+        let find = |pattern: &str, basename, ignore_case, extensions: Option<&str>| {
+            let args = QueryArgs {
+                pattern: pattern.into(),
+                basename,
+                ignore_case,
+                extensions: extensions.map(|e| vec![e.into()]),
+                limit: None,
+            };
+            read.look_up(&args).unwrap()
+        };
+        assert_eq!(find("src", false, false, None).len(), 4);
+        assert_eq!(find("src", true, false, None), ["src"]);
+        assert_eq!(find("*.rs", false, false, None), ["src/main.rs"]);
+        assert_eq!(find("lib", false, true, Some("rs")), ["src/héllo/Lib.RS"]);
+        assert_eq!(
+            read.path_of(&read.entries[4]),
+            root.canonicalize()?.join("src/main.rs")
+        );
+
+        // A file cut short, or not an index at all, is refused
+        let bytes = std::fs::read(&file)?;
+        assert!(format::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(format::decode(b"# tree\n").is_err());
+        Ok(())
+    }
+}
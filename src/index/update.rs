@@ -0,0 +1,168 @@
+//! `fs index build --watch`: the index kept up to date from the file events
+//! after it's built, rather than rebuilt. Each change is made to the index in
+//! memory as it's reported, and the file rewritten once the changes pause, or
+//! at most once a second while they don't; and every few minutes besides, so
+//! that its age says how long ago it was known to be current, and `fs locate`
+//! only warns once whatever keeps it up to date has stopped.
+
+use super::{build, Entry, Index};
+use crate::crawl::EntryKinds;
+use crate::manifest::relative;
+use crate::search::Custom;
+use crate::watch::{Change, Event, Watch};
+use crate::{unix_ns, SearchConfig};
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long after a change the file is written, at the soonest.
+const WRITE_AFTER: Duration = Duration::from_secs(1);
+
+/// How long the file goes unwritten, at the most, when nothing changes.
+const REWRITE_EVERY: Duration = Duration::from_secs(10 * 60);
+
+/// Build the index of the tree `search` describes, write it to `file`, and
+/// keep it up to date; it only returns if that fails.
+pub(super) async fn build_and_keep_up(search: &SearchConfig, file: &Path) -> Result<()> {
+    // Watching from before the walk, so nothing changed during it is missed
+    let mut config = search.clone();
+    config.root_path = std::fs::canonicalize(&search.root_path)?;
+    let custom = Custom {
+        emit: EntryKinds::ALL,
+        ..Custom::default()
+    };
+    let mut watch = Watch::start(&config, &custom)?.with_all_removals();
+    let (mut index, _) = build(&config).await?;
+    index.write(file)?;
+    eprintln!(
+        "Indexed {} entries under {}; keeping {} up to date",
+        index.entries.len(),
+        index.root.display(),
+        file.display()
+    );
+
+    let mut changed = false;
+    let mut written = Instant::now();
+    let mut tick = tokio::time::interval(WRITE_AFTER);
+    loop {
+        tokio::select! {
+            event = watch.next() => match event {
+                Some(Ok(event)) => {
+                    index.apply(&event);
+                    changed = true;
+                }
+                Some(Err(e)) => eprintln!("Error: {e}"),
+                None => bail!("the file events stopped coming"),
+            },
+            _ = tick.tick() => {
+                if watch.take_lost() {
+                    eprintln!("Warning: file events were lost; indexing the tree again");
+                    (index, _) = build(&config).await?;
+                    changed = true;
+                }
+                if changed || written.elapsed() >= REWRITE_EVERY {
+                    index.built_ns = unix_ns(SystemTime::now());
+                    index.write(file)?;
+                    (changed, written) = (false, Instant::now());
+                }
+            }
+        }
+    }
+}
+
+impl Index {
+    /// Make the change `event` reports, to a path under the root.
+    pub fn apply(&mut self, event: &Event) {
+        let path = relative(&event.path, &self.root);
+        match event.change {
+            Change::Created | Change::Modified => {
+                let Ok(metadata) = event.path.symlink_metadata() else {
+                    return;
+                };
+                self.insert(Entry {
+                    path,
+                    kind: event.kind,
+                    size: metadata.len(),
+                    mtime_ns: metadata.modified().map_or(0, unix_ns),
+                });
+            }
+            Change::Removed => self.remove(&path),
+        }
+    }
+
+    /// Add `entry`, or replace what's there at its path.
+    fn insert(&mut self, entry: Entry) {
+        match self.position(&entry.path) {
+            Ok(at) => self.entries[at] = entry,
+            Err(at) => self.entries.insert(at, entry),
+        }
+    }
+
+    /// Take out `path`, and if it was a directory everything in it.
+    fn remove(&mut self, path: &str) {
+        let start = self.position(path).unwrap_or_else(|at| at);
+        let end = match self.entries.get(start) {
+            Some(entry) if entry.path == path => start + 1,
+            _ => start,
+        };
+        // What's below it sorts together, right after it or not far off
+        let below = format!("{path}/");
+        let from = end + self.entries[end..].partition_point(|e| e.path.as_str() < below.as_str());
+        let to = from + self.entries[from..].partition_point(|e| e.path.starts_with(&below));
+        self.entries.drain(from..to);
+        self.entries.drain(start..end);
+    }
+
+    fn position(&self, path: &str) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| entry.path.as_str().cmp(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntryKind;
+
+    #[test]
+    fn test_changes_are_made_in_place() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        let entry = |path: &str, kind| Entry {
+            path: path.into(),
+            kind,
+            size: 0,
+            mtime_ns: 0,
+        };
+        let mut index = Index {
+            root: root.to_path_buf(),
+            built_ns: 0,
+            entries: vec![
+                entry("a", EntryKind::Dir),
+                entry("a b", EntryKind::File),
+                entry("a/x", EntryKind::File),
+                entry("a/y", EntryKind::Dir),
+                entry("a/y/z", EntryKind::File),
+                entry("b", EntryKind::File),
+            ],
+        };
+        std::fs::write(root.join("a b"), "grown")?;
+        std::fs::write(root.join("ab"), "")?;
+        let event = |change, path: &str, kind| Event {
+            change,
+            path: root.join(path),
+            kind,
+        };
+        index.apply(&event(Change::Modified, "a b", EntryKind::File));
+        index.apply(&event(Change::Created, "ab", EntryKind::File));
+        // Gone before its event was handled
+        index.apply(&event(Change::Created, "c", EntryKind::File));
+        index.apply(&event(Change::Removed, "a", EntryKind::File));
+
+        let paths: Vec<_> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a b", "ab", "b"]);
+        assert_eq!(index.entries[0].size, 5);
+        assert!(index.entries[0].mtime_ns > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! JSON-RPC 2.0 over a pair of byte streams, one message per line, as `fs
+//! mcp` and `fs --rpc` speak it over stdin and stdout. Requests are read one
+//! at a time; what's sent back goes through a [`Sender`], from whichever task
+//! has it, and is written in the order sent.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// The longest line read as a message; a request needs far less.
+const MAX_MESSAGE: u64 = 4 << 20;
+
+/// A request, or a notification if it has no id.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Request {
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Request {
+    /// Its params as `T`, or the error to answer with if they aren't.
+    pub fn params<T: for<'de> Deserialize<'de>>(&self) -> Result<T, Error> {
+        let params = match &self.params {
+            // Leaving them out is the same as giving none
+            Value::Null => json!({}),
+            params => params.clone(),
+        };
+        serde_json::from_value(params).map_err(Error::invalid_params)
+    }
+}
+
+/// What a request is answered with when it can't be done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Error {
+    pub code: i64,
+    pub message: String,
+}
+
+impl Error {
+    pub fn parse_error(e: impl fmt::Display) -> Self {
+        Error::new(-32700, format!("not JSON: {e}"))
+    }
+
+    pub fn invalid_request(e: impl fmt::Display) -> Self {
+        Error::new(-32600, format!("not a request: {e}"))
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Error::new(-32601, format!("there's no method {method}"))
+    }
+
+    pub fn invalid_params(e: impl fmt::Display) -> Self {
+        Error::new(-32602, e.to_string())
+    }
+
+    /// The answer to a request cancelled before it was done; LSP's code.
+    pub fn request_cancelled() -> Self {
+        Error::new(-32800, "cancelled".to_string())
+    }
+
+    fn new(code: i64, message: String) -> Self {
+        Error { code, message }
+    }
+}
+
+/// Sends responses and notifications, each written as a line.
+#[derive(Debug, Clone)]
+pub(crate) struct Sender(mpsc::UnboundedSender<Value>);
+
+impl Sender {
+    /// Answer the request `id` with `result`.
+    pub fn respond(&self, id: Value, result: Result<Value, Error>) {
+        let message = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": e.code, "message": e.message },
+            }),
+        };
+        // Only gone if writing failed, which the writer's task reports
+        let _ = self.0.send(message);
+    }
+
+    /// Tell the other side something, needing no answer.
+    pub fn notify(&self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        let _ = self.0.send(message);
+    }
+}
+
+/// A [`Sender`] writing to `output`, and the task doing it, which finishes
+/// once every sender is dropped.
+pub(crate) fn writer<W>(mut output: W) -> (Sender, JoinHandle<io::Result<()>>)
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel::<Value>();
+    let task = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let mut line = serde_json::to_vec(&message)?;
+            line.push(b'\n');
+            output.write_all(&line).await?;
+            // Each message as it's ready, not when a buffer fills
+            if rx.is_empty() {
+                output.flush().await?;
+            }
+        }
+        Ok(())
+    });
+    (Sender(tx), task)
+}
+
+/// The next message on `input`, or the error to answer a line that isn't a
+/// request with; None once it's closed.
+pub(crate) async fn read<R>(input: &mut R) -> io::Result<Option<Result<Request, Error>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let mut limited = (&mut *input).take(MAX_MESSAGE + 1);
+        if limited.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        if line.len() as u64 > MAX_MESSAGE && line.last() != Some(&b'\n') {
+            skip_line(input).await?;
+            let e = format!("it's longer than {MAX_MESSAGE} bytes");
+            return Ok(Some(Err(Error::invalid_request(e))));
+        }
+        if !line.trim_ascii().is_empty() {
+            break;
+        }
+    }
+    let value: Value = match serde_json::from_slice(&line) {
+        Ok(value) => value,
+        Err(e) => return Ok(Some(Err(Error::parse_error(e)))),
+    };
+    if value.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Ok(Some(Err(Error::invalid_request("it isn't JSON-RPC 2.0"))));
+    }
+    Ok(Some(
+        serde_json::from_value(value).map_err(Error::invalid_request),
+    ))
+}
+
+/// Read past the rest of the line, without keeping it.
+async fn skip_line<R>(input: &mut R) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        let buf = input.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                input.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                input.consume(len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, BufReader};
+
+    #[tokio::test]
+    async fn test_messages_are_read_and_written_a_line_each() -> anyhow::Result<()> {
+        let lines = [
+            "{not json",
+            "",
+            r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":1}}"#,
+            r#"{"jsonrpc":"1.0","id":2,"method":"search"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"search"}"#,
+        ];
+        let text = lines.join("\n");
+        let mut input = BufReader::new(text.as_bytes());
+        let unreadable = read(&mut input).await?.unwrap().unwrap_err();
+        assert_eq!(unreadable.code, -32700);
+        // Blank lines are skipped, and a notification has no id
+        let notification = read(&mut input).await?.unwrap().unwrap();
+        assert_eq!(
+            (notification.id, notification.method.as_str()),
+            (None, "$/cancelRequest")
+        );
+        assert_eq!(read(&mut input).await?.unwrap().unwrap_err().code, -32600);
+        let request = read(&mut input).await?.unwrap().unwrap();
+        assert_eq!(request.id, Some(json!(3)));
+        assert_eq!(request.params::<Value>().unwrap(), json!({}));
+        assert!(read(&mut input).await?.is_none());
+
+        // A line longer than any request is refused without being kept
+        let long = format!("[{}]\n{}", "0,".repeat(MAX_MESSAGE as usize), lines[4]);
+        let mut input = BufReader::new(long.as_bytes());
+        assert_eq!(read(&mut input).await?.unwrap().unwrap_err().code, -32600);
+        assert!(read(&mut input).await?.unwrap().is_ok());
+
+        // What can't be read is answered with a null id, as there isn't one
+        let (client, server) = tokio::io::duplex(4096);
+        let (sender, writing) = writer(server);
+        sender.respond(Value::Null, Err(unreadable));
+        sender.notify("search/progress", json!({ "id": 3 }));
+        drop(sender);
+        writing.await??;
+        let mut written = String::new();
+        BufReader::new(client).read_to_string(&mut written).await?;
+        let messages: Vec<Value> = written
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(messages[0]["id"], Value::Null);
+        assert_eq!(messages[0]["error"]["code"], -32700);
+        assert_eq!(
+            messages[1],
+            json!({ "jsonrpc": "2.0", "method": "search/progress", "params": { "id": 3 } })
+        );
+        assert_eq!(messages.len(), 2);
+        Ok(())
+    }
+}
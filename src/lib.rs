@@ -0,0 +1,926 @@
+//! Finding files: a .gitignore-aware walk of a tree, breadth-first or on many
+//! threads, and everything the `fs` command does with what it finds.
+//!
+//! [`SearchBuilder`] sets a search up and [`Search::run`] starts it, handing
+//! back [`Results`] as they're found. The other modules are the command
+//! line's, public so that the `fs` binary can use them.
+//!
+//! All of that is the default `tokio` feature. Without it, the walk itself
+//! needs no runtime: [`Search::visit`] and, with the `blocking` feature,
+//! [`Search::iter`] read the tree on the caller's thread, and the command
+//! line and its dependencies aren't built at all.
+
+#[cfg(feature = "tokio")]
+pub mod actions;
+#[cfg(feature = "tokio")]
+pub mod bench;
+#[cfg(feature = "tokio")]
+pub mod color;
+#[cfg(feature = "tokio")]
+pub mod config;
+#[cfg(feature = "tokio")]
+pub mod content;
+pub mod crawl;
+#[cfg(feature = "tokio")]
+pub mod du;
+#[cfg(feature = "tokio")]
+pub mod dup;
+mod error;
+#[cfg(feature = "tokio")]
+pub mod explain;
+#[cfg(any(feature = "sqlite", feature = "parquet"))]
+pub mod export;
+#[cfg(feature = "tokio")]
+pub mod grep;
+#[cfg(feature = "tokio")]
+mod hashing;
+#[cfg(feature = "tokio")]
+pub mod index;
+#[cfg(feature = "tokio")]
+mod jsonrpc;
+#[cfg(feature = "tokio")]
+pub mod logging;
+#[cfg(feature = "tokio")]
+pub mod manifest;
+#[cfg(feature = "tokio")]
+pub mod manpage;
+#[cfg(feature = "tokio")]
+pub mod mcp;
+#[cfg(feature = "tokio")]
+pub mod repl;
+#[cfg(feature = "tokio")]
+pub mod roots;
+#[cfg(feature = "tokio")]
+pub mod rpc;
+mod search;
+#[cfg(feature = "tokio")]
+pub mod serve;
+#[cfg(feature = "tokio")]
+pub mod tui;
+#[cfg(feature = "tokio")]
+pub mod vpath;
+#[cfg(feature = "tokio")]
+pub mod watch;
+
+use clap::Args;
+use crawl::{Files, IgnoreFiles};
+use search::Custom;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub use crawl::{
+    ByKey, Descend, EntryKind, EntryKinds, FileEntry, FileSystem, Filter, MemFs, ProgressEvent,
+    Rank, Results, StdFs, Totals,
+};
+pub use error::Error;
+#[cfg(any(feature = "tokio", feature = "blocking"))]
+pub use search::Iter;
+pub use search::{Search, SearchBuilder};
+pub use tokio_util::sync::CancellationToken;
+
+/// The search the command line's flags describe. It can also be read from a
+/// config document with [`Search::from_config`], where each setting is named
+/// as its flag is (`show-hidden = true`), the crawl's go in a `crawl` table of
+/// their own, and what isn't given is as the flag's default.
+#[derive(Args, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SearchConfig {
+    /// The root directory to start the search from
+    #[arg(default_value = ".")]
+    pub root_path: PathBuf,
+
+    /// Search pattern to match against file names (use '*' wildcard; naive only)
+    #[arg(short, long, default_value = "*")]
+    pub pattern: String,
+
+    /// Maximum depth to search (unlimited if not provided)
+    #[arg(short, long)]
+    pub max_depth: Option<usize>,
+
+    /// Only search files with these extensions (comma-separated)
+    #[arg(short, long, value_delimiter = ',')]
+    pub extensions: Option<Vec<String>>,
+
+    /// Show hidden files and directories (Unix: name starts with '.', Windows: hidden attribute set)
+    #[arg(short = 'H', long, default_value_t = false)]
+    pub show_hidden: bool,
+
+    /// By default, we read the .gitignore in each directory and ignore those paths.
+    /// If set, we do NOT ignore them (i.e., we include gitignored files).
+    #[arg(long, default_value_t = false)]
+    pub include_gitignored: bool,
+
+    /// Also leave out what FILE says to, in .gitignore's format with paths
+    /// relative to the root, even with --include-gitignored (repeatable)
+    #[arg(long = "ignore-file", value_name = "FILE")]
+    #[serde(rename = "ignore-file")]
+    pub ignore_files: Vec<PathBuf>,
+
+    /// Also look inside zip and tar archives; matching members are shown as archive.zip!inner/path
+    #[arg(long)]
+    pub search_archives: bool,
+
+    #[command(flatten)]
+    pub crawl: crawl::CrawlOptions,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            root_path: ".".into(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: crawl::CrawlOptions::default(),
+        }
+    }
+}
+
+/// Creates a channel of result batches and spawns the crawl.
+#[cfg(feature = "tokio")]
+pub(crate) async fn search_files(config: &SearchConfig) -> Results {
+    start_search(config, &Custom::default())
+}
+
+/// [`search_files`] for callers that aren't async themselves, with what the
+/// library's caller set up besides `config`; it must still be called within a
+/// tokio runtime.
+#[cfg(feature = "tokio")]
+pub(crate) fn start_search(config: &SearchConfig, custom: &Custom) -> Results {
+    let (tx, rx) = crawl::channel(config.crawl.channel_capacity);
+
+    let root = config.root_path.clone();
+    let options = config.crawl.clone();
+    let filters = crawl_filters(config, custom);
+
+    tokio::spawn(async move {
+        // Once cancelled, the crawl stops between directories; but a read
+        // stuck on a dead network mount never returns, so stop waiting for it
+        let cancelled = options.cancel.clone();
+        tokio::select! {
+            result = crawl::crawl(root.clone(), filters, &options, &tx) => {
+                if let Err(e) = result {
+                    let _ = tx.send(vec![Err(Error::from_crawl(e, &root))]).await;
+                }
+            }
+            () = cancelled.cancelled() => {}
+        }
+        drop(tx);
+    });
+
+    rx
+}
+
+/// What the crawl filters by, as `config` and the library's `custom` setup
+/// have it, reading the tree from the custom file system, or else the real one.
+pub(crate) fn crawl_filters(config: &SearchConfig, custom: &Custom) -> crawl::Filters {
+    let files = custom
+        .fs
+        .as_ref()
+        .map_or_else(Files::default, |fs| Files::new(Arc::clone(fs)));
+    let ignore_files = IgnoreFiles::read(&files, &config.root_path, &config.ignore_files);
+    crawl::Filters {
+        max_depth: config.max_depth.unwrap_or(usize::MAX),
+        pattern: config.pattern.clone(),
+        extensions: config.extensions.clone(),
+        show_hidden: config.show_hidden,
+        include_gitignored: config.include_gitignored,
+        ignore_files,
+        search_archives: config.search_archives,
+        emit: custom.emit,
+        custom: custom.filters.clone(),
+        rank: custom.rank.clone(),
+        files,
+    }
+}
+
+/// Cross-platform hidden detection: a leading dot everywhere but Windows,
+/// WASI included
+#[cfg(not(windows))]
+fn is_hidden(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => name.to_str().map(|s| s.starts_with('.')).unwrap_or(false),
+        None => false,
+    }
+}
+
+#[cfg(windows)]
+fn is_hidden(path: &Path) -> bool {
+    use std::os::windows::prelude::MetadataExt;
+
+    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+        if name.starts_with('.') {
+            return true;
+        }
+    }
+    match path.metadata() {
+        Ok(meta) => (meta.file_attributes() & 0x2) != 0, // FILE_ATTRIBUTE_HIDDEN = 0x2
+        Err(_) => false,
+    }
+}
+
+/// Pattern and extension checks
+fn file_matches(path: &Path, pattern: &str, extensions: Option<&[String]>) -> bool {
+    let file_name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    if pattern != "*" && !naive_pattern_match(file_name, pattern) {
+        return false;
+    }
+
+    if let Some(exts) = extensions {
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            if !exts.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext)) {
+                return false;
+            }
+        } else {
+            // If there's no extension but user wants some, skip
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Nanoseconds since the Unix epoch, negative before it.
+pub(crate) fn unix_ns(time: std::time::SystemTime) -> i64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(after) => after.as_nanos() as i64,
+        Err(before) => -(before.duration().as_nanos() as i64),
+    }
+}
+
+/// Parse a byte count with an optional binary unit suffix: 512, 64K, 1.5M, 2G, 1T.
+pub(crate) fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let scale: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{text}'")),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{text}'"))?;
+    Ok((number * scale as f64) as u64)
+}
+
+/// Naive '*' pattern => substring match
+fn naive_pattern_match(name: &str, pat: &str) -> bool {
+    if pat == "*" {
+        return true;
+    }
+    name.contains(&pat.replace('*', ""))
+}
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs as stdfs;
+    use tempfile::tempdir;
+
+    /// Collect all successful PathBuf results
+    async fn collect_results(mut rx: Results) -> Vec<PathBuf> {
+        let mut v = Vec::new();
+        while let Some(item) = rx.recv().await {
+            if let Ok(entry) = item {
+                v.push(entry.into_path());
+            }
+        }
+        v.sort();
+        v
+    }
+
+    // -- 1) BASIC TESTS --
+
+    /// Basic BFS search with no .gitignore
+    #[tokio::test]
+    async fn test_search_basic() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // Create sub dir + files
+        let sub_dir = tmp_path.join("sub");
+        stdfs::create_dir_all(&sub_dir)?;
+        let file1 = sub_dir.join("file1.txt");
+        let file2 = sub_dir.join("file2.rs");
+        let file3 = tmp_path.join("data.bin");
+
+        stdfs::write(&file1, "hello")?;
+        stdfs::write(&file2, "world")?;
+        stdfs::write(&file3, "data")?;
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+
+        let mut expected = vec![file1, file2, file3];
+        expected.sort();
+        assert_eq!(found, expected);
+
+        Ok(())
+    }
+
+    /// Searching an empty directory yields no files
+    #[tokio::test]
+    async fn test_empty_dir() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert_eq!(found.len(), 0, "Expected no files in empty directory");
+        Ok(())
+    }
+
+    /// If the directory doesn't exist, we should get an error, but no results
+    #[tokio::test]
+    async fn test_non_existent_directory() -> Result<()> {
+        let non_existent = PathBuf::from("X:/some-non-existent-1234");
+        let config = SearchConfig {
+            root_path: non_existent,
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let rx = search_files(&config).await;
+
+        let mut files = Vec::new();
+        let mut errors = 0usize;
+
+        let mut chan = rx;
+        while let Some(msg) = chan.recv().await {
+            match msg {
+                Ok(path) => files.push(path),
+                Err(_) => errors += 1,
+            }
+        }
+
+        assert_eq!(files.len(), 0);
+        assert!(errors >= 1, "Should have at least one error from non-existent dir");
+
+        Ok(())
+    }
+
+    // -- 2) HIDDEN FILES --
+
+    /// We skip hidden files by default, show them if show_hidden = true
+    #[tokio::test]
+    async fn test_hidden_files() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // Create hidden file
+        let hidden_file = tmp_path.join(".hidden.txt");
+        stdfs::write(&hidden_file, "secret")?;
+
+        // By default, show_hidden = false => we won't see it
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert!(
+            !found.contains(&hidden_file),
+            "Should NOT see hidden_file when show_hidden is false"
+        );
+
+        // If we set show_hidden = true => we do see it
+        let config2 = SearchConfig {
+            show_hidden: true,
+            ..config
+        };
+        let found2 = collect_results(search_files(&config2).await).await;
+        assert!(
+            found2.contains(&hidden_file),
+            "Expected to see hidden_file when show_hidden is true"
+        );
+
+        Ok(())
+    }
+
+    // -- 3) EXTENSIONS --
+
+    #[tokio::test]
+    async fn test_search_extensions() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        let file_txt = tmp_path.join("hello.txt");
+        let file_md = tmp_path.join("readme.md");
+        stdfs::write(&file_txt, "hello")?;
+        stdfs::write(&file_md, "# readme")?;
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: Some(vec!["txt".into()]),
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+
+        // Expect to see only hello.txt
+        assert!(found.contains(&file_txt), "Expected to see .txt file");
+        assert!(!found.contains(&file_md), "Should NOT see .md file");
+        Ok(())
+    }
+
+    // -- 4) MAX DEPTH --
+
+    #[tokio::test]
+    async fn test_max_depth() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // Create a multi-level directory
+        // root
+        //   level1/
+        //     level2/
+        //       file.txt
+        let level1 = tmp_path.join("level1");
+        let level2 = level1.join("level2");
+        stdfs::create_dir_all(&level2)?;
+
+        let file_txt = level2.join("deep_file.txt");
+        stdfs::write(&file_txt, "deep")?;
+
+        // max_depth = 1 => we see items in root, but not in level1/level2
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: Some(1),
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert!(!found.contains(&file_txt), "Should not see file at depth 2");
+
+        // max_depth = 2 => we can see file_txt
+        let config2 = SearchConfig {
+            max_depth: Some(2),
+            ..config
+        };
+        let found2 = collect_results(search_files(&config2).await).await;
+        assert!(found2.contains(&file_txt), "Should see file at depth 2");
+        Ok(())
+    }
+
+    // -- 5) GITIGNORE SCENARIOS --
+
+    /// .gitignore ignores *.log by default
+    #[tokio::test]
+    async fn test_gitignore_default() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // Create .gitignore that ignores *.log
+        let gitignore_path = tmp_path.join(".gitignore");
+        stdfs::write(&gitignore_path, "*.log\n")?;
+
+        // Create files
+        let file_txt = tmp_path.join("notes.txt");
+        let file_log = tmp_path.join("debug.log");
+        stdfs::write(&file_txt, "hello")?;
+        stdfs::write(&file_log, "some logs")?;
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+
+        // We expect to see notes.txt, but NOT debug.log
+        assert!(found.contains(&file_txt), "Expected to find notes.txt");
+        assert!(
+            !found.contains(&file_log),
+            "Should NOT find debug.log if it's .gitignored"
+        );
+
+        Ok(())
+    }
+
+    /// If --include-gitignored is set, we see all files
+    #[tokio::test]
+    async fn test_include_gitignored() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // .gitignore
+        let gitignore_path = tmp_path.join(".gitignore");
+        stdfs::write(&gitignore_path, "*.log\n")?;
+
+        // Create files
+        let file_txt = tmp_path.join("notes.txt");
+        let file_log = tmp_path.join("debug.log");
+        stdfs::write(&file_txt, "hello")?;
+        stdfs::write(&file_log, "some logs")?;
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: true, // override ignoring
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+
+        assert!(found.contains(&file_txt));
+        assert!(
+            found.contains(&file_log),
+            "Expected to see log file, ignoring .gitignore"
+        );
+        Ok(())
+    }
+
+    /// Multiple lines in .gitignore, plus blank lines and comments
+    #[tokio::test]
+    async fn test_gitignore_multi_line() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+    
+        // IMPORTANT: Remove leading spaces so the ignore crate parses them correctly
+        let content = r#"# This is a comment
+*.log
+secret_*
+
+# blank line above
+*.tmp
+"#;
+        let gitignore_path = tmp_path.join(".gitignore");
+        stdfs::write(&gitignore_path, content)?;
+    
+        // Create files
+        let f_log = tmp_path.join("debug.log");
+        let f_secret = tmp_path.join("secret_file.txt");
+        let f_tmp = tmp_path.join("random.tmp");
+        let f_txt = tmp_path.join("notes.txt");
+    
+        stdfs::write(&f_log, "log")?;
+        stdfs::write(&f_secret, "secret")?;
+        stdfs::write(&f_tmp, "tmp data")?;
+        stdfs::write(&f_txt, "notes")?;
+    
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+    
+        // Debug output
+        println!("Found files: {:?}", found);
+        println!("Gitignore content:\n{}", content);
+        
+        // We expect to see only notes.txt and the .gitignore itself
+        let expected: Vec<_> = vec![
+            tmp_path.join(".gitignore"),
+            tmp_path.join("notes.txt"),
+        ];
+        
+        assert_eq!(
+            found.len(),
+            expected.len(),
+            "Expected exactly {} files, found {}",
+            expected.len(),
+            found.len()
+        );
+        for path in &expected {
+            assert!(found.contains(path), "Expected to find: {}", path.display());
+        }
+        
+        // Additional specific checks
+        assert!(!found.contains(&f_log), "Should ignore *.log");
+        assert!(!found.contains(&f_secret), "Should ignore secret_*");
+        assert!(!found.contains(&f_tmp), "Should ignore *.tmp");
+    
+        Ok(())
+    }
+
+    /// .gitignore that doesn't exist => no ignoring
+    #[tokio::test]
+    async fn test_no_gitignore_file() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // There's no .gitignore
+        let file_log = tmp_path.join("debug.log");
+        stdfs::write(&file_log, "some logs")?;
+
+        // Should see debug.log
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert!(found.contains(&file_log));
+        Ok(())
+    }
+
+    /// A .gitignore below the root applies to its own subtree, on top of the root's
+    #[tokio::test]
+    async fn test_nested_gitignore() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        // root/.gitignore ignores *.log; app/.gitignore adds target/ and
+        // lets keep.log back in
+        let app = tmp_path.join("app");
+        stdfs::create_dir_all(app.join("target"))?;
+        stdfs::write(tmp_path.join(".gitignore"), "*.log\n")?;
+        stdfs::write(app.join(".gitignore"), "target/\n!keep.log\n")?;
+        let top_log = tmp_path.join("debug.log");
+        let app_log = app.join("debug.log");
+        let kept = app.join("keep.log");
+        let built = app.join("target/out.bin");
+        let target_at_top = tmp_path.join("target");
+        stdfs::create_dir_all(&target_at_top)?;
+        let top_built = target_at_top.join("out.bin");
+        for file in [&top_log, &app_log, &kept, &built, &top_built] {
+            stdfs::write(file, "x")?;
+        }
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert_eq!(found, vec![kept, top_built]);
+        Ok(())
+    }
+
+    /// --ignore-file rules apply below every .gitignore, and with
+    /// --include-gitignored too
+    #[tokio::test]
+    async fn test_ignore_file() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+        let rules = tmp.path().join("rules");
+        stdfs::write(&rules, "*.bak\n*.log\nbuild/\n")?;
+        stdfs::create_dir(tmp_path.join("build"))?;
+        stdfs::write(tmp_path.join(".gitignore"), "!keep.log\n")?;
+        for file in ["a.bak", "a.log", "keep.log", "notes.txt", "build/out"] {
+            stdfs::write(tmp_path.join(file), "x")?;
+        }
+
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            ignore_files: vec![rules.clone()],
+            ..SearchConfig::default()
+        };
+        let found = collect_results(search_files(&config).await).await;
+        let expected = ["keep.log", "notes.txt", "rules"].map(|file| tmp_path.join(file));
+        assert_eq!(found, expected);
+
+        // Without the .gitignore, nothing lets keep.log back in
+        let config = SearchConfig {
+            include_gitignored: true,
+            ..config
+        };
+        let found = collect_results(search_files(&config).await).await;
+        assert_eq!(found, expected[1..]);
+
+        // One that can't be read stops the search before it starts
+        let config = SearchConfig {
+            ignore_files: vec![tmp_path.join("missing")],
+            ..config
+        };
+        let mut rx = search_files(&config).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(Err(Error::IgnoreParse { path, .. })) if path == tmp_path.join("missing")
+        ));
+        assert!(rx.recv().await.is_none());
+        Ok(())
+    }
+
+    // -- 6) PATTERN SPECIFICS --
+
+    #[tokio::test]
+    async fn test_pattern_substring() -> Result<()> {
+        let tmp = tempdir()?;
+        let tmp_path = tmp.path();
+
+        let abc = tmp_path.join("abc-file.txt");
+        let xyz = tmp_path.join("xyz-file.txt");
+        stdfs::write(&abc, "abc")?;
+        stdfs::write(&xyz, "xyz")?;
+
+        // Pattern "abc*" => naive substring check => matches "abc-file.txt"
+        let config = SearchConfig {
+            root_path: tmp_path.to_path_buf(),
+            pattern: "abc*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: true,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+        let found = collect_results(search_files(&config).await).await;
+
+        assert!(found.contains(&abc));
+        assert!(!found.contains(&xyz));
+        Ok(())
+    }
+
+    // -- 7) PERMISSION ERRORS --
+
+    #[tokio::test]
+    async fn test_permission_denied() -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let tmp = tempdir()?;
+            let tmp_path = tmp.path();
+
+            // We create an unreadable directory
+            let locked_dir = tmp_path.join("locked");
+            stdfs::create_dir_all(&locked_dir)?;
+
+            // Then remove read permissions
+            let mut perms = stdfs::metadata(&locked_dir)?.permissions();
+            perms.set_mode(0o000); // no permissions
+            stdfs::set_permissions(&locked_dir, perms)?;
+
+            // BFS should return an error for locked_dir, but it won't crash
+            let config = SearchConfig {
+                root_path: tmp_path.to_path_buf(),
+                pattern: "*".into(),
+                max_depth: None,
+                extensions: None,
+                show_hidden: true,
+                include_gitignored: false,
+                ignore_files: Vec::new(),
+                search_archives: false,
+                crawl: Default::default(),
+            };
+            let rx = search_files(&config).await;
+
+            let mut files_found = Vec::new();
+            let mut errors = 0;
+            let mut channel = rx;
+            while let Some(item) = channel.recv().await {
+                match item {
+                    Ok(p) => files_found.push(p),
+                    Err(_) => errors += 1,
+                }
+            }
+
+            // We didn't create any files, so no found paths
+            // We do expect at least 1 error from locked_dir
+            assert_eq!(files_found.len(), 0);
+            assert!(
+                errors >= 1,
+                "Expected at least one error from permission-denied directory"
+            );
+
+            // Reset permissions so tempdir can clean up
+            let mut perms2 = stdfs::metadata(&locked_dir)?.permissions();
+            perms2.set_mode(0o755);
+            stdfs::set_permissions(&locked_dir, perms2)?;
+        }
+
+        // On Windows, setting read-only to a directory doesn't yield the same error pattern.
+        // We'll skip this scenario on Windows or handle with other approaches.
+        Ok(())
+    }
+    
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+    
+    /// Arbitrary string generator for QuickCheck
+    /// This example just generates ASCII strings of modest length;
+    /// tweak as needed for your use cases.
+    #[derive(Clone, Debug)]
+    struct RandomString(pub String);
+
+    impl Arbitrary for RandomString {
+        fn arbitrary(g: &mut Gen) -> Self {
+            // Generate ASCII characters only, up to 50 in length
+            let size = usize::arbitrary(g) % 50;
+            let s: String = (0..size)
+                .map(|_| {
+                    let c = u8::arbitrary(g) % 128; // ASCII range
+                    c as char
+                })
+                .collect();
+            RandomString(s)
+        }
+    }
+
+    /// Property: If the pattern is "*", then naive_pattern_match() should
+    /// always return true for any input string.
+    #[test]
+    fn prop_star_matches_all_strings() {
+            fn prop(s: RandomString) -> TestResult {
+                let pat = "*";
+                let matched = naive_pattern_match(&s.0, pat);
+                // This should *always* be true
+                TestResult::from_bool(matched)
+            }
+            QuickCheck::new().quickcheck(prop as fn(RandomString) -> TestResult);
+        }
+
+    /// Property: If the pattern does not contain '*', then `naive_pattern_match`
+    /// is effectively `string.contains(pat)`.
+    #[test]
+    fn prop_substring_equivalent() {
+        fn inner(s: RandomString, pat: RandomString) -> TestResult {
+            // We artificially remove '*' from `pat` to test substring logic
+            let pat_no_star = pat.0.replace('*', "");
+            let direct_contains = s.0.contains(&pat_no_star);
+            let our_match = naive_pattern_match(&s.0, &pat_no_star);
+
+            TestResult::from_bool(direct_contains == our_match)
+        }
+        QuickCheck::new().quickcheck(inner as fn(RandomString, RandomString) -> TestResult);
+    }
+}
@@ -0,0 +1,95 @@
+//! `-v` and `--log-format`: what the search does, logged to stderr through
+//! `tracing`. Each search is a span with its root, each directory read one
+//! inside it, and at the most verbose, every path the walk looks at says
+//! whether it matched or why it was skipped.
+
+use clap::{Args, ValueEnum};
+use std::io::{self, IsTerminal};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// Log what the search does to stderr: -v each search, -vv each
+    /// directory read and .gitignore parsed, -vvv why each path was or
+    /// wasn't a result
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// How to write the log
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t,
+        global = true
+    )]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// A line for people to read
+    #[default]
+    Text,
+    /// A JSON object a line, for log systems to ingest
+    Json,
+}
+
+impl LogArgs {
+    /// The most detailed events logged.
+    pub fn level(&self) -> LevelFilter {
+        match self.verbose {
+            0 => LevelFilter::OFF,
+            1 => LevelFilter::INFO,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Start logging as `args` say, if they ask for it at all.
+pub fn init(args: &LogArgs) {
+    let level = args.level();
+    if level == LevelFilter::OFF {
+        return;
+    }
+    // How long each directory read took, as it closes
+    let spans = if level >= LevelFilter::DEBUG {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_max_level(level)
+        .with_span_events(spans);
+    let _ = match args.log_format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        log: LogArgs,
+    }
+
+    #[test]
+    fn test_each_v_logs_more() {
+        let level = |args: &[&str]| Cli::parse_from(args).log.level();
+        assert_eq!(level(&["fs"]), LevelFilter::OFF);
+        assert_eq!(level(&["fs", "-v"]), LevelFilter::INFO);
+        assert_eq!(level(&["fs", "-vv"]), LevelFilter::DEBUG);
+        assert_eq!(level(&["fs", "-v", "-v", "-v", "-v"]), LevelFilter::TRACE);
+        let json = Cli::parse_from(["fs", "--log-format", "json"]).log;
+        assert_eq!(json.log_format, LogFormat::Json);
+    }
+}
@@ -1,761 +1,512 @@
-use anyhow::Result;
-use clap::Parser;
+//! The `fs` command line, over the library's search.
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use fs::actions::{self, ActionConfig};
+use fs::color::{Colors, When};
+use fs::{bench, config, content, crawl, du, dup, explain, grep, index, logging, manifest};
+use fs::{manpage, mcp, repl, roots, rpc, serve, tui, vpath, watch};
+use fs::{EntryKinds, FileEntry, Results, SearchConfig};
+use serde::Serialize;
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use tokio::fs;
-use tokio::sync::mpsc;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "A file system search tool that supports .gitignore")]
-pub struct SearchConfig {
-    /// The root directory to start the search from
-    #[arg(default_value = ".")]
-    pub root_path: PathBuf,
-
-    /// Search pattern to match against file names (use '*' wildcard; naive only)
-    #[arg(short, long, default_value = "*")]
-    pub pattern: String,
-
-    /// Maximum depth to search (unlimited if not provided)
-    #[arg(short, long)]
-    pub max_depth: Option<usize>,
-
-    /// Only search files with these extensions (comma-separated)
-    #[arg(short, long, value_delimiter = ',')]
-    pub extensions: Option<Vec<String>>,
-
-    /// Show hidden files and directories (Unix: name starts with '.', Windows: hidden attribute set)
-    #[arg(short = 'H', long, default_value_t = false)]
-    pub show_hidden: bool,
-
-    /// By default, we read .gitignore in the root directory and ignore those paths.
-    /// If set, we do NOT ignore them (i.e., we include gitignored files).
-    #[arg(long, default_value_t = false)]
-    pub include_gitignored: bool,
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand, `fs` is `fs find`
+    #[command(flatten)]
+    find: FindArgs,
+
+    #[command(flatten)]
+    log: logging::LogArgs,
+}
+
+#[derive(Args, Debug)]
+struct FindArgs {
+    #[command(flatten)]
+    search: SearchConfig,
+
+    /// More directories to search besides the first; one inside another
+    /// given is only searched once
+    #[arg(value_name = "ROOT")]
+    more_roots: Vec<PathBuf>,
+
+    /// Search the paths listed in FILE ('-' for stdin), one per line or
+    /// NUL-separated, instead of roots: directories are searched, and other
+    /// files are results if their names pass the filters
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["root_path", "more_roots"])]
+    paths_from: Option<PathBuf>,
+
+    /// Walk nothing: read paths from stdin as --paths-from does, and pass on
+    /// those the search from the root would have found, as they come
+    #[arg(long, conflicts_with_all = ["more_roots", "paths_from"])]
+    filter_stdin: bool,
+
+    /// Search nothing, but explain why this search would or wouldn't find
+    /// PATH, as `fs explain` does
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["more_roots", "paths_from"])]
+    why: Option<PathBuf>,
+
+    /// Search nothing yet: answer searches over JSON-RPC on stdin and stdout,
+    /// for an editor plugin to keep running, with these flags as defaults
+    #[arg(long, conflicts_with_all = ["more_roots", "paths_from", "filter_stdin", "why"])]
+    rpc: bool,
+
+    #[command(flatten)]
+    content: content::ContentFilter,
+
+    #[command(flatten)]
+    actions: ActionConfig,
+
+    /// Flush printed paths after every N, not only when the search has no more
+    /// ready, for pipelines that act on each path as soon as it's found
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    flush_every: Option<usize>,
+
+    /// Print each result as a line of JSON: its path, type, depth, size and
+    /// modification time
+    #[arg(long)]
+    json: bool,
+
+    /// When to color the printed paths, as FS_COLORS (or else LS_COLORS) says
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = When::Auto)]
+    color: When,
+
+    /// Stop the whole search at the first match, and exit with failure if
+    /// there isn't one, to check whether anything matches
+    #[arg(short = '1', long)]
+    first: bool,
+
+    /// Choose among the results as they're found, typing to fuzzy-match
+    /// them and Tab to mark several, and print only the paths chosen
+    #[arg(long, conflicts_with_all = ["json", "first", "flush_every"])]
+    pick: bool,
+
+    /// Stop searching after this long (e.g. 30s, 5m, 1h), act on or print
+    /// what was found by then, and exit with status 124
+    #[arg(long, value_name = "DURATION", value_parser = actions::touch::parse_duration)]
+    timeout: Option<Duration>,
+}
+
+/// The exit status when --timeout stops the search, as timeout(1) uses.
+const TIMED_OUT: u8 = 124;
+
+/// The exit status after Ctrl-C, as shells report a process it killed.
+const INTERRUPTED: u8 = 130;
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Search for files by name, printing their paths or acting on them (the default)
+    Find(Box<FindArgs>),
+    /// Search file contents with a regex, printing path:line:text
+    Grep(grep::GrepArgs),
+    /// Replace regex matches inside the matched files, after previewing the diffs
+    Replace(actions::ReplaceArgs),
+    /// Find files with identical contents and how much space the copies waste
+    Dup(dup::DupArgs),
+    /// Total up the size of the files in each directory, biggest first
+    Du(du::DuArgs),
+    /// Record the path, size, modification time and hash of every file in a tree
+    Manifest(manifest::ManifestArgs),
+    /// Check a tree against a manifest, listing added, removed, modified and corrupted files
+    Verify(manifest::VerifyArgs),
+    /// Compare a manifest with a later one or the tree as it is now, finding renames by contents
+    Diff(manifest::diff::DiffArgs),
+    /// Build or inspect the index of a tree that `fs locate` answers from
+    Index(index::IndexArgs),
+    /// Look a name up in the index, in milliseconds, instead of walking the tree
+    Locate(index::LocateArgs),
+    /// Print each change to the files a search would find, as it happens
+    Watch(watch::WatchArgs),
+    /// Hold the index in memory and answer `fs locate --daemon` over a socket
+    Daemon(index::daemon::DaemonArgs),
+    /// Answer searches over HTTP, streaming the results as JSON lines
+    Serve(serve::ServeArgs),
+    /// Offer the search to coding agents as tools, over the Model Context Protocol on stdio
+    Mcp(mcp::McpArgs),
+    /// Time the search over a tree with each backend and thread count, warm and cold
+    Bench(bench::BenchArgs),
+    /// Search one tree again and again from a prompt, keeping what's read between searches
+    Repl(repl::ReplArgs),
+    /// Browse the results as they're found, narrowing them down as you type
+    Tui(tui::TuiArgs),
+    /// Explain why a search would or wouldn't find a path: which rule, filter or limit decided
+    Explain(explain::ExplainArgs),
+    /// Print the man page, or write one for each subcommand too, for packagers
+    Manpage(manpage::ManpageArgs),
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Parse CLI
-    let config = SearchConfig::parse();
-
-    // Start BFS-based search, get a channel of results
-    let mut rx = search_files(&config).await;
-
-    // Drain the channel and print out each path
-    while let Some(path_result) = rx.recv().await {
-        match path_result {
-            Ok(path) => println!("Found: {}", path.display()),
-            Err(e)   => eprintln!("Error: {e}"),
-        }
+async fn main() -> Result<ExitCode> {
+    // A helper started by --clipboard, not a user invocation
+    if let Some(code) = actions::hold_clipboard_if_requested() {
+        return code;
     }
 
-    Ok(())
+    // Parse CLI, over the defaults from the config file and environment
+    let cmd = Cli::command();
+    let args = config::with_defaults(&cmd, std::env::args_os().collect())?;
+    let cli = Cli::from_arg_matches(&cmd.get_matches_from(args)).unwrap_or_else(|e| e.exit());
+    logging::init(&cli.log);
+
+    match cli.command {
+        None => find(cli.find).await,
+        Some(Command::Find(args)) => find(*args).await,
+        Some(Command::Grep(args)) => grep::run(&args).await,
+        Some(Command::Replace(args)) => actions::replace(&args).await,
+        Some(Command::Dup(args)) => dup::run(&args).await,
+        Some(Command::Du(args)) => du::run(&args).await,
+        Some(Command::Manifest(args)) => manifest::write(&args).await,
+        Some(Command::Verify(args)) => manifest::verify(&args).await,
+        Some(Command::Diff(args)) => manifest::diff::run(&args).await,
+        Some(Command::Index(args)) => index::run(&args).await,
+        Some(Command::Locate(args)) => index::locate(&args).await,
+        Some(Command::Watch(args)) => watch::run(&args).await,
+        Some(Command::Daemon(args)) => index::daemon::run(&args).await,
+        Some(Command::Serve(args)) => serve::run(&args).await,
+        Some(Command::Mcp(args)) => mcp::run(&args).await,
+        Some(Command::Bench(args)) => bench::run(&args).await,
+        Some(Command::Repl(args)) => repl::run(&args).await,
+        Some(Command::Tui(args)) => tui::run(&args).await,
+        Some(Command::Explain(args)) => explain::run(&args).await,
+        Some(Command::Manpage(args)) => manpage::run(&args, Cli::command()),
+    }
 }
 
-/// Creates an mpsc channel and spawns the BFS task.
-async fn search_files(config: &SearchConfig) -> mpsc::Receiver<Result<PathBuf>> {
-    let (tx, rx) = mpsc::channel(100);
-
-    let root = config.root_path.clone();
-    let pattern = config.pattern.clone();
-    let exts = config.extensions.clone();
-    let show_hidden = config.show_hidden;
-    let max_depth = config.max_depth.unwrap_or(usize::MAX);
-    let include_gitignored = config.include_gitignored;
+/// `fs find`: search, then print or act on what's found.
+async fn find(mut args: FindArgs) -> Result<ExitCode> {
+    // Undoing a rename doesn't involve a search at all
+    if let Some(journal) = &args.actions.undo_rename {
+        return actions::undo_rename(journal);
+    }
+    if let Some(path) = &args.why {
+        return explain::explain(&args.search, path);
+    }
+    if args.rpc {
+        return rpc::run(&args.search).await;
+    }
 
-    // Build the Gitignore matcher (only from root/.gitignore)
-    let gitignore = build_gitignore(&root);
+    if args.actions.interactive && args.actions.is_empty() {
+        bail!("--interactive needs an action to confirm, e.g. --delete or --exec");
+    }
+    if args.search.search_archives && !args.actions.is_empty() {
+        bail!("--search-archives can't be combined with an action: archive members aren't files on disk");
+    }
+    if args.search.search_archives && !args.content.is_empty() {
+        bail!("--contains and --not-contains can't be combined with --search-archives");
+    }
+    if args.search.search_archives && args.first {
+        bail!("--first can't be combined with --search-archives");
+    }
+    if args.json && (args.search.search_archives || !args.actions.is_empty()) {
+        bail!("--json prints the search's results, so it can't be combined with --search-archives or an action");
+    }
+    if args.pick && (args.search.search_archives || !args.actions.is_empty()) {
+        bail!("--pick can't be combined with --search-archives or an action");
+    }
 
-    tokio::spawn(async move {
-        if let Err(e) = crawl_bfs(
-            &root,
-            max_depth,
-            &pattern,
-            exts.as_deref(),
-            show_hidden,
-            include_gitignored,
-            &gitignore,
-            &tx,
-        )
-        .await
-        {
-            let _ = tx.send(Err(e)).await;
+    let (given, files) = match &args.paths_from {
+        Some(list) => roots::split_dirs(roots::read_paths(&read_input(list)?)),
+        None => {
+            let mut given = vec![args.search.root_path.clone()];
+            given.append(&mut args.more_roots);
+            (given, Vec::new())
         }
-        drop(tx);
-    });
-
-    rx
-}
-
-/// Performs BFS without recursion, respecting .gitignore, hidden, patterns, etc.
-async fn crawl_bfs(
-    root_dir: &Path,
-    max_depth: usize,
-    pattern: &str,
-    extensions: Option<&[String]>,
-    show_hidden: bool,
-    include_gitignored: bool,
-    gitignore: &Option<ignore::gitignore::Gitignore>,
-    tx: &mpsc::Sender<Result<PathBuf>>,
-) -> Result<()> {
-    use std::collections::VecDeque;
-    let mut queue = VecDeque::new();
-    queue.push_back((root_dir.to_path_buf(), 0));
-
-    while let Some((dir, depth)) = queue.pop_front() {
-        if depth > max_depth {
-            continue;
+    };
+    let roots = roots::distinct(given);
+    match (roots.as_slice(), files.is_empty()) {
+        ([root], true) => args.search.root_path = root.clone(),
+        _ if !args.actions.is_empty() => {
+            bail!("an action works on the tree under one root, so it can't be given several paths");
         }
+        _ => {}
+    }
 
-        let mut entries = match fs::read_dir(&dir).await {
-            Ok(e) => e,
-            Err(e) => {
-                // e.g., permission denied or path doesn't exist
-                let _ = tx.send(Err(e.into())).await;
-                continue;
-            }
-        };
+    // --delete totals up the size of what it's about to remove, and --json
+    // prints everything
+    args.search.crawl.stat = crawl::Fields {
+        kind: args.json,
+        size: args.json || args.actions.delete,
+        modified: args.json,
+    };
 
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
+    let deadline = args
+        .timeout
+        .map(|limit| stop_after(limit, args.search.crawl.cancel.clone()));
+    // An action half done isn't worth keeping, so Ctrl-C only stops the
+    // search gracefully when its results are just being printed
+    let interrupted = args
+        .actions
+        .is_empty()
+        .then(|| stop_on_interrupt(args.search.crawl.cancel.clone()));
+
+    // --delete-dirs also removes matching directories that are empty, which
+    // a content filter would only fail to read
+    let emit = match args.actions.delete_dirs && args.content.is_empty() {
+        true => EntryKinds::default() | EntryKinds::DIRS,
+        false => EntryKinds::default(),
+    };
 
-            // If user does NOT want to include gitignored, skip if matched
-            if !include_gitignored && is_gitignored(&path, gitignore) {
-                continue;
-            }
+    // Start BFS-based search of each root, get a channel of results, or
+    // take them from stdin
+    let mut rx = match args.filter_stdin {
+        true => roots::filter(&args.search, io::stdin()),
+        false => roots::search(&args.search, &roots, &files, emit).await,
+    };
 
-            let metadata = match entry.metadata().await {
-                Ok(m) => m,
-                Err(e) => {
-                    let _ = tx.send(Err(e.into())).await;
-                    continue;
-                }
-            };
+    // Content filters run last, on files that passed every other filter
+    let by_content = !args.content.is_empty();
+    if by_content {
+        rx = content::filter(rx, args.content, args.actions.jobs());
+    }
 
-            // hidden check
-            if !show_hidden && is_hidden(&path) {
-                continue;
-            }
+    if args.first {
+        rx = first_match(rx, args.search.crawl.cancel.clone());
+    }
 
-            // BFS queue subdirectories
-            if metadata.is_dir() {
-                if depth < max_depth {
-                    queue.push_back((path, depth + 1));
-                }
-            } else {
-                // If it's a file, check pattern / extension
-                if file_matches(&path, pattern, extensions) {
-                    tx.send(Ok(path)).await?;
+    let code = if args.search.search_archives {
+        // Archives are opened up and their matching members listed alongside other results
+        let mut rx = vpath::expand_archives(rx, &args.search);
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(path) => println!("Found: {path}"),
+                Err(e)   => eprintln!("Error: {e}"),
+            }
+        }
+        ExitCode::SUCCESS
+    } else if args.pick {
+        match tui::pick(rx).await? {
+            Some(chosen) => {
+                for path in chosen {
+                    println!("{}", path.display());
                 }
+                ExitCode::SUCCESS
             }
+            // Given up on, as fzf has it
+            None => ExitCode::from(INTERRUPTED),
         }
-    }
-
-    Ok(())
-}
-
-/// Build a Gitignore object from "root_dir/.gitignore", if it exists.
-fn build_gitignore(root_dir: &Path) -> Option<ignore::gitignore::Gitignore> {
-    use ignore::gitignore::GitignoreBuilder;
-    let gitignore_path = root_dir.join(".gitignore");
-
-    if !gitignore_path.is_file() {
-        return None;
-    }
+    } else if !args.actions.is_empty() {
+        // Hand the results to an action if one was requested
+        actions::run(rx, &args.actions, &args.search, by_content).await?
+    } else {
+        // Drain the channel and print out each path
+        let colors = Colors::for_stdout(args.color);
+        let json = args.json.then_some(roots.as_slice());
+        let printed = match print_results(rx, args.flush_every, json, colors).await {
+            // Whatever reads the output stopped early, e.g. `| head`
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 1,
+            result => result?,
+        };
+        if args.first && printed == 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    };
 
-    let mut builder = GitignoreBuilder::new(root_dir);
-    if builder.add(gitignore_path).is_some() {
-        return None;
+    if interrupted.is_some_and(|pressed| pressed.is_cancelled()) {
+        eprintln!("Warning: interrupted; these results are incomplete");
+        std::process::exit(INTERRUPTED.into());
     }
-
-    match builder.build() {
-        Ok(gi) => Some(gi),
-        Err(_) => None,
+    if let (Some(limit), Some(deadline)) = (args.timeout, deadline) {
+        if deadline.is_finished() {
+            eprintln!(
+                "Warning: the search timed out after {limit:?}; these results are incomplete"
+            );
+            // Not a return: shutting down the runtime waits for blocking
+            // reads, and one may be stuck for good
+            std::process::exit(TIMED_OUT.into());
+        }
+        deadline.abort();
     }
+    Ok(code)
 }
 
-/// Check if path is matched by the .gitignore (and thus should be ignored).
-fn is_gitignored(path: &Path, gitignore: &Option<ignore::gitignore::Gitignore>) -> bool {
-    if let Some(ref gi) = gitignore {
-        let matched = gi.matched_path_or_any_parents(path, path.is_dir());
-        matched.is_ignore()
-    } else {
-        false
+/// The whole of `path`, or of stdin for `-`.
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut input = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut input)
+            .context("can't read stdin")?;
+        return Ok(input);
     }
+    std::fs::read(path).with_context(|| format!("can't read {}", path.display()))
 }
 
-/// Cross-platform hidden detection
-#[cfg(unix)]
-fn is_hidden(path: &Path) -> bool {
-    match path.file_name() {
-        Some(name) => name.to_str().map(|s| s.starts_with('.')).unwrap_or(false),
-        None => false,
-    }
+/// Stop the search with `cancel` once `limit` has passed. The task finishes
+/// only if it did.
+fn stop_after(limit: Duration, cancel: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::time::sleep(limit).await;
+        cancel.cancel();
+    })
 }
 
-#[cfg(windows)]
-fn is_hidden(path: &Path) -> bool {
-    use std::os::windows::prelude::MetadataExt;
-
-    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-        if name.starts_with('.') {
-            return true;
+/// Stop the search with `cancel` at the first Ctrl-C, so that what it found
+/// by then is still printed; a second one quits at once. The token returned
+/// is cancelled once Ctrl-C has been pressed.
+fn stop_on_interrupt(cancel: CancellationToken) -> CancellationToken {
+    let interrupted = CancellationToken::new();
+    let pressed = interrupted.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
         }
-    }
-    match path.metadata() {
-        Ok(meta) => (meta.file_attributes() & 0x2) != 0, // FILE_ATTRIBUTE_HIDDEN = 0x2
-        Err(_) => false,
-    }
+        pressed.cancel();
+        cancel.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(INTERRUPTED.into());
+        }
+    });
+    interrupted
 }
 
-/// Pattern and extension checks
-fn file_matches(path: &Path, pattern: &str, extensions: Option<&[String]>) -> bool {
-    let file_name = match path.file_name().and_then(|n| n.to_str()) {
-        Some(name) => name,
-        None => return false,
-    };
-
-    if pattern != "*" && !naive_pattern_match(file_name, pattern) {
-        return false;
-    }
+/// Pass on results up to the first file, then stop the search with `cancel`.
+/// Errors before it are passed on too.
+fn first_match(mut rx: Results, cancel: CancellationToken) -> Results {
+    let (tx, first) = crawl::channel(1);
+    tokio::spawn(async move {
+        while let Some(item) = rx.recv().await {
+            let found = item.is_ok();
+            if tx.send(vec![item]).await.is_err() || found {
+                break;
+            }
+        }
+        cancel.cancel();
+    });
+    first
+}
 
-    if let Some(exts) = extensions {
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            if !exts.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext)) {
-                return false;
+/// Print each path through one locked, buffered stdout. It's flushed whenever
+/// the search has nothing more ready, so a slow search still shows paths as
+/// it finds them, and after every `flush_every` paths if given. Paths are
+/// colored with `colors`, if given, or printed as JSON with the one of the
+/// `json` roots they're under. Returns how many were printed.
+async fn print_results(
+    mut rx: Results,
+    flush_every: Option<usize>,
+    json: Option<&[PathBuf]>,
+    colors: Option<Colors>,
+) -> io::Result<usize> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let (mut printed, mut unflushed) = (0, 0);
+    while let Some(batch) = rx.recv_batch().await {
+        for path_result in batch {
+            match path_result {
+                Ok(entry) => {
+                    if let Some(roots) = json {
+                        let root = roots::root_of(roots, entry.path());
+                        serde_json::to_writer(
+                            &mut out,
+                            &Tagged {
+                                entry: &entry,
+                                root,
+                            },
+                        )?;
+                        writeln!(out)?;
+                    } else if let Some(colors) = &colors {
+                        writeln!(out, "Found: {}", colors.paint(entry.path()))?;
+                    } else {
+                        writeln!(out, "Found: {}", entry.path().display())?;
+                    }
+                    printed += 1;
+                }
+                Err(e) => eprintln!("Error: {e}"),
             }
-        } else {
-            // If there's no extension but user wants some, skip
-            return false;
+            unflushed += 1;
+            if flush_every.is_some_and(|n| unflushed >= n) {
+                out.flush()?;
+                unflushed = 0;
+            }
+        }
+        if rx.is_empty() {
+            out.flush()?;
+            unflushed = 0;
         }
     }
-
-    true
+    out.flush()?;
+    Ok(printed)
 }
 
-/// Naive '*' pattern => substring match
-fn naive_pattern_match(name: &str, pat: &str) -> bool {
-    if pat == "*" {
-        return true;
-    }
-    name.contains(&pat.replace('*', ""))
+/// A result printed by --json, with the root it was found under.
+#[derive(Serialize)]
+struct Tagged<'a> {
+    #[serde(flatten)]
+    entry: &'a FileEntry,
+    root: Option<&'a Path>,
 }
-// -----------------------------------------------------------------------------
-// Tests
-// -----------------------------------------------------------------------------
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use fs::SearchBuilder;
     use std::fs as stdfs;
+    use std::path::PathBuf;
     use tempfile::tempdir;
-    use tokio::sync::mpsc::Receiver;
 
     /// Collect all successful PathBuf results
-    async fn collect_results(mut rx: Receiver<Result<PathBuf>>) -> Vec<PathBuf> {
+    async fn collect_results(mut rx: Results) -> Vec<PathBuf> {
         let mut v = Vec::new();
         while let Some(item) = rx.recv().await {
-            if let Ok(path) = item {
-                v.push(path);
+            if let Ok(entry) = item {
+                v.push(entry.into_path());
             }
         }
         v.sort();
         v
     }
 
-    // -- 1) BASIC TESTS --
-
-    /// Basic BFS search with no .gitignore
-    #[tokio::test]
-    async fn test_search_basic() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // Create sub dir + files
-        let sub_dir = tmp_path.join("sub");
-        stdfs::create_dir_all(&sub_dir)?;
-        let file1 = sub_dir.join("file1.txt");
-        let file2 = sub_dir.join("file2.rs");
-        let file3 = tmp_path.join("data.bin");
-
-        stdfs::write(&file1, "hello")?;
-        stdfs::write(&file2, "world")?;
-        stdfs::write(&file3, "data")?;
-
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: false,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-
-        let mut expected = vec![file1, file2, file3];
-        expected.sort();
-        assert_eq!(found, expected);
-
-        Ok(())
-    }
-
-    /// Searching an empty directory yields no files
-    #[tokio::test]
-    async fn test_empty_dir() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: false,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-        assert_eq!(found.len(), 0, "Expected no files in empty directory");
-        Ok(())
-    }
-
-    /// If the directory doesn't exist, we should get an error, but no results
-    #[tokio::test]
-    async fn test_non_existent_directory() -> Result<()> {
-        let non_existent = PathBuf::from("X:/some-non-existent-1234");
-        let config = SearchConfig {
-            root_path: non_existent,
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let rx = search_files(&config).await;
-
-        let mut files = Vec::new();
-        let mut errors = 0usize;
-
-        let mut chan = rx;
-        while let Some(msg) = chan.recv().await {
-            match msg {
-                Ok(path) => files.push(path),
-                Err(_) => errors += 1,
-            }
-        }
-
-        assert_eq!(files.len(), 0);
-        assert!(errors >= 1, "Should have at least one error from non-existent dir");
-
-        Ok(())
-    }
-
-    // -- 2) HIDDEN FILES --
-
-    /// We skip hidden files by default, show them if show_hidden = true
-    #[tokio::test]
-    async fn test_hidden_files() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // Create hidden file
-        let hidden_file = tmp_path.join(".hidden.txt");
-        stdfs::write(&hidden_file, "secret")?;
-
-        // By default, show_hidden = false => we won't see it
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: false,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-        assert!(
-            !found.contains(&hidden_file),
-            "Should NOT see hidden_file when show_hidden is false"
-        );
-
-        // If we set show_hidden = true => we do see it
-        let config2 = SearchConfig {
-            show_hidden: true,
-            ..config
-        };
-        let found2 = collect_results(search_files(&config2).await).await;
-        assert!(
-            found2.contains(&hidden_file),
-            "Expected to see hidden_file when show_hidden is true"
-        );
-
-        Ok(())
-    }
-
-    // -- 3) EXTENSIONS --
-
-    #[tokio::test]
-    async fn test_search_extensions() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        let file_txt = tmp_path.join("hello.txt");
-        let file_md = tmp_path.join("readme.md");
-        stdfs::write(&file_txt, "hello")?;
-        stdfs::write(&file_md, "# readme")?;
-
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: Some(vec!["txt".into()]),
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-
-        // Expect to see only hello.txt
-        assert!(found.contains(&file_txt), "Expected to see .txt file");
-        assert!(!found.contains(&file_md), "Should NOT see .md file");
-        Ok(())
-    }
-
-    // -- 4) MAX DEPTH --
-
+    /// --first: one match, then the search is stopped
     #[tokio::test]
-    async fn test_max_depth() -> Result<()> {
+    async fn test_first_match_cancels_the_search() -> Result<()> {
         let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // Create a multi-level directory
-        // root
-        //   level1/
-        //     level2/
-        //       file.txt
-        let level1 = tmp_path.join("level1");
-        let level2 = level1.join("level2");
-        stdfs::create_dir_all(&level2)?;
-
-        let file_txt = level2.join("deep_file.txt");
-        stdfs::write(&file_txt, "deep")?;
-
-        // max_depth = 1 => we see items in root, but not in level1/level2
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: Some(1),
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-        assert!(!found.contains(&file_txt), "Should not see file at depth 2");
-
-        // max_depth = 2 => we can see file_txt
-        let config2 = SearchConfig {
-            max_depth: Some(2),
-            ..config
-        };
-        let found2 = collect_results(search_files(&config2).await).await;
-        assert!(found2.contains(&file_txt), "Should see file at depth 2");
-        Ok(())
-    }
-
-    // -- 5) GITIGNORE SCENARIOS --
-
-    /// .gitignore ignores *.log by default
-    #[tokio::test]
-    async fn test_gitignore_default() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // Create .gitignore that ignores *.log
-        let gitignore_path = tmp_path.join(".gitignore");
-        stdfs::write(&gitignore_path, "*.log\n")?;
-
-        // Create files
-        let file_txt = tmp_path.join("notes.txt");
-        let file_log = tmp_path.join("debug.log");
-        stdfs::write(&file_txt, "hello")?;
-        stdfs::write(&file_log, "some logs")?;
-
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-
-        // We expect to see notes.txt, but NOT debug.log
-        assert!(found.contains(&file_txt), "Expected to find notes.txt");
-        assert!(
-            !found.contains(&file_log),
-            "Should NOT find debug.log if it's .gitignored"
-        );
-
-        Ok(())
-    }
-
-    /// If --include-gitignored is set, we see all files
-    #[tokio::test]
-    async fn test_include_gitignored() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // .gitignore
-        let gitignore_path = tmp_path.join(".gitignore");
-        stdfs::write(&gitignore_path, "*.log\n")?;
-
-        // Create files
-        let file_txt = tmp_path.join("notes.txt");
-        let file_log = tmp_path.join("debug.log");
-        stdfs::write(&file_txt, "hello")?;
-        stdfs::write(&file_log, "some logs")?;
-
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: true, // override ignoring
-        };
-        let found = collect_results(search_files(&config).await).await;
-
-        assert!(found.contains(&file_txt));
-        assert!(
-            found.contains(&file_log),
-            "Expected to see log file, ignoring .gitignore"
-        );
-        Ok(())
-    }
-
-    /// Multiple lines in .gitignore, plus blank lines and comments
-    #[tokio::test]
-    async fn test_gitignore_multi_line() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-    
-        // IMPORTANT: Remove leading spaces so the ignore crate parses them correctly
-        let content = r#"# This is a comment
-*.log
-secret_*
-
-# blank line above
-*.tmp
-"#;
-        let gitignore_path = tmp_path.join(".gitignore");
-        stdfs::write(&gitignore_path, content)?;
-    
-        // Create files
-        let f_log = tmp_path.join("debug.log");
-        let f_secret = tmp_path.join("secret_file.txt");
-        let f_tmp = tmp_path.join("random.tmp");
-        let f_txt = tmp_path.join("notes.txt");
-    
-        stdfs::write(&f_log, "log")?;
-        stdfs::write(&f_secret, "secret")?;
-        stdfs::write(&f_tmp, "tmp data")?;
-        stdfs::write(&f_txt, "notes")?;
-    
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-    
-        // Debug output
-        println!("Found files: {:?}", found);
-        println!("Gitignore content:\n{}", content);
-        
-        // We expect to see only notes.txt and the .gitignore itself
-        let expected: Vec<_> = vec![
-            tmp_path.join(".gitignore"),
-            tmp_path.join("notes.txt"),
-        ];
-        
-        assert_eq!(
-            found.len(),
-            expected.len(),
-            "Expected exactly {} files, found {}",
-            expected.len(),
-            found.len()
-        );
-        for path in &expected {
-            assert!(found.contains(path), "Expected to find: {}", path.display());
+        for i in 0..20 {
+            stdfs::write(tmp.path().join(format!("{i}.txt")), "x")?;
         }
-        
-        // Additional specific checks
-        assert!(!found.contains(&f_log), "Should ignore *.log");
-        assert!(!found.contains(&f_secret), "Should ignore secret_*");
-        assert!(!found.contains(&f_tmp), "Should ignore *.tmp");
-    
-        Ok(())
-    }
 
-    /// .gitignore that doesn't exist => no ignoring
-    #[tokio::test]
-    async fn test_no_gitignore_file() -> Result<()> {
-        let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        // There's no .gitignore
-        let file_log = tmp_path.join("debug.log");
-        stdfs::write(&file_log, "some logs")?;
-
-        // Should see debug.log
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-        assert!(found.contains(&file_log));
+        let cancel = CancellationToken::new();
+        let search = SearchBuilder::new(tmp.path())
+            .cancel_token(cancel.clone())
+            .build();
+        let rx = first_match(search.run().await, cancel.clone());
+        assert_eq!(collect_results(rx).await.len(), 1);
+        assert!(cancel.is_cancelled());
         Ok(())
     }
 
-    // -- 6) PATTERN SPECIFICS --
-
+    /// --timeout: past the deadline, the search stops and the timer says so
     #[tokio::test]
-    async fn test_pattern_substring() -> Result<()> {
+    async fn test_timeout_stops_the_search() -> Result<()> {
         let tmp = tempdir()?;
-        let tmp_path = tmp.path();
-
-        let abc = tmp_path.join("abc-file.txt");
-        let xyz = tmp_path.join("xyz-file.txt");
-        stdfs::write(&abc, "abc")?;
-        stdfs::write(&xyz, "xyz")?;
-
-        // Pattern "abc*" => naive substring check => matches "abc-file.txt"
-        let config = SearchConfig {
-            root_path: tmp_path.to_path_buf(),
-            pattern: "abc*".into(),
-            max_depth: None,
-            extensions: None,
-            show_hidden: true,
-            include_gitignored: false,
-        };
-        let found = collect_results(search_files(&config).await).await;
-
-        assert!(found.contains(&abc));
-        assert!(!found.contains(&xyz));
+        stdfs::write(tmp.path().join("a.txt"), "x")?;
+        let cancel = CancellationToken::new();
+        let search = SearchBuilder::new(tmp.path())
+            .cancel_token(cancel.clone())
+            .build();
+
+        let waiting = stop_after(Duration::from_secs(3600), cancel.clone());
+        assert_eq!(collect_results(search.run().await).await.len(), 1);
+        assert!(!waiting.is_finished());
+        waiting.abort();
+
+        let deadline = stop_after(Duration::ZERO, cancel);
+        deadline.await?;
+        assert!(collect_results(search.run().await).await.is_empty());
         Ok(())
     }
-
-    // -- 7) PERMISSION ERRORS --
-
-    #[tokio::test]
-    async fn test_permission_denied() -> Result<()> {
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-
-            let tmp = tempdir()?;
-            let tmp_path = tmp.path();
-
-            // We create an unreadable directory
-            let locked_dir = tmp_path.join("locked");
-            stdfs::create_dir_all(&locked_dir)?;
-
-            // Then remove read permissions
-            let mut perms = stdfs::metadata(&locked_dir)?.permissions();
-            perms.set_mode(0o000); // no permissions
-            stdfs::set_permissions(&locked_dir, perms)?;
-
-            // BFS should return an error for locked_dir, but it won't crash
-            let config = SearchConfig {
-                root_path: tmp_path.to_path_buf(),
-                pattern: "*".into(),
-                max_depth: None,
-                extensions: None,
-                show_hidden: true,
-                include_gitignored: false,
-            };
-            let rx = search_files(&config).await;
-
-            let mut files_found = Vec::new();
-            let mut errors = 0;
-            let mut channel = rx;
-            while let Some(item) = channel.recv().await {
-                match item {
-                    Ok(p) => files_found.push(p),
-                    Err(_) => errors += 1,
-                }
-            }
-
-            // We didn't create any files, so no found paths
-            // We do expect at least 1 error from locked_dir
-            assert_eq!(files_found.len(), 0);
-            assert!(
-                errors >= 1,
-                "Expected at least one error from permission-denied directory"
-            );
-
-            // Reset permissions so tempdir can clean up
-            let mut perms2 = stdfs::metadata(&locked_dir)?.permissions();
-            perms2.set_mode(0o755);
-            stdfs::set_permissions(&locked_dir, perms2)?;
-        }
-
-        // On Windows, setting read-only to a directory doesn't yield the same error pattern.
-        // We'll skip this scenario on Windows or handle with other approaches.
-        Ok(())
-    }
-    
-    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
-    
-    /// Arbitrary string generator for QuickCheck
-    /// This example just generates ASCII strings of modest length;
-    /// tweak as needed for your use cases.
-    #[derive(Clone, Debug)]
-    struct RandomString(pub String);
-
-    impl Arbitrary for RandomString {
-        fn arbitrary(g: &mut Gen) -> Self {
-            // Generate ASCII characters only, up to 50 in length
-            let size = usize::arbitrary(g) % 50;
-            let s: String = (0..size)
-                .map(|_| {
-                    let c = u8::arbitrary(g) % 128; // ASCII range
-                    c as char
-                })
-                .collect();
-            RandomString(s)
-        }
-    }
-
-    /// Property: If the pattern is "*", then naive_pattern_match() should
-    /// always return true for any input string.
-    #[test]
-    fn prop_star_matches_all_strings() {
-            fn prop(s: RandomString) -> TestResult {
-                let pat = "*";
-                let matched = naive_pattern_match(&s.0, pat);
-                // This should *always* be true
-                TestResult::from_bool(matched)
-            }
-            QuickCheck::new().quickcheck(prop as fn(RandomString) -> TestResult);
-        }
-
-    /// Property: If the pattern does not contain '*', then `naive_pattern_match`
-    /// is effectively `string.contains(pat)`.
-    #[test]
-    fn prop_substring_equivalent() {
-        fn inner(s: RandomString, pat: RandomString) -> TestResult {
-            // We artificially remove '*' from `pat` to test substring logic
-            let pat_no_star = pat.0.replace('*', "");
-            let direct_contains = s.0.contains(&pat_no_star);
-            let our_match = naive_pattern_match(&s.0, &pat_no_star);
-
-            TestResult::from_bool(direct_contains == our_match)
-        }
-        QuickCheck::new().quickcheck(inner as fn(RandomString, RandomString) -> TestResult);
-    }
 }
@@ -0,0 +1,250 @@
+//! Comparing two snapshots of a tree (`fs diff`): two manifests, or a manifest
+//! and the tree as it is now.
+//!
+//! Files that left one path and turned up at another with the same contents
+//! are reported as renames, not as a removal and an addition.
+
+use super::{build, jobs, read_manifest, Entry};
+use crate::SearchConfig;
+use anyhow::{bail, Result};
+use clap::Args;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The earlier snapshot, a manifest written by `fs manifest`
+    pub old: PathBuf,
+
+    /// The later snapshot is ROOT_PATH: another manifest, or a directory to
+    /// search and hash now (with the same options the manifest was made with)
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Number of files to hash at once when comparing with a directory
+    /// [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+/// One difference between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    /// Same path, different contents
+    Modified(String),
+    /// Same contents, different path
+    Renamed {
+        from: String,
+        to: String,
+    },
+}
+
+impl Change {
+    /// The path the change is listed under: where the file is now, if anywhere.
+    fn path(&self) -> &str {
+        match self {
+            Change::Added(path) | Change::Removed(path) | Change::Modified(path) => path,
+            Change::Renamed { to, .. } => to,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "added: {path}"),
+            Change::Removed(path) => write!(f, "removed: {path}"),
+            Change::Modified(path) => write!(f, "modified: {path}"),
+            Change::Renamed { from, to } => write!(f, "renamed: {from} -> {to}"),
+        }
+    }
+}
+
+/// Loads both snapshots and prints their differences and a summary. Exits 1
+/// if there are any, like diff(1).
+pub async fn run(args: &DiffArgs) -> Result<ExitCode> {
+    if args.search.search_archives {
+        bail!("manifests record files on disk; drop --search-archives");
+    }
+    let old = read_manifest(&args.old)?;
+    let (new, errors) = if args.search.root_path.is_file() {
+        (read_manifest(&args.search.root_path)?, 0)
+    } else {
+        let exclude = std::fs::canonicalize(&args.old).ok();
+        build(&args.search, jobs(args.jobs), exclude.as_deref()).await
+    };
+
+    let changes = diff(&old, &new);
+    let mut counts = [0usize; 4];
+    for change in &changes {
+        println!("{change}");
+        counts[match change {
+            Change::Added(_) => 0,
+            Change::Removed(_) => 1,
+            Change::Modified(_) => 2,
+            Change::Renamed { .. } => 3,
+        }] += 1;
+    }
+    let [added, removed, modified, renamed] = counts;
+    println!("{added} added, {removed} removed, {modified} modified, {renamed} renamed");
+
+    Ok(if errors > 0 || !changes.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Every difference between the `old` and `new` entries, sorted by path.
+/// Modification times alone don't count: a file touched but not changed is
+/// the same file.
+pub fn diff(old: &[Entry], new: &[Entry]) -> Vec<Change> {
+    let old_paths: HashMap<&str, &Entry> = old.iter().map(|e| (e.path.as_str(), e)).collect();
+    let new_paths: HashMap<&str, &Entry> = new.iter().map(|e| (e.path.as_str(), e)).collect();
+
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    for entry in old {
+        match new_paths.get(entry.path.as_str()) {
+            None => removed.push(entry),
+            Some(now) if now.size != entry.size || now.blake3 != entry.blake3 => {
+                changes.push(Change::Modified(entry.path.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    let added: Vec<&Entry> = new
+        .iter()
+        .filter(|e| !old_paths.contains_key(e.path.as_str()))
+        .collect();
+
+    let (renames, removed, added) = match_renames(removed, added);
+    changes.extend(renames.into_iter().map(|(from, to)| Change::Renamed {
+        from: from.path.clone(),
+        to: to.path.clone(),
+    }));
+    changes.extend(removed.into_iter().map(|e| Change::Removed(e.path.clone())));
+    changes.extend(added.into_iter().map(|e| Change::Added(e.path.clone())));
+    changes.sort_by(|a, b| {
+        a.path()
+            .cmp(b.path())
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+    changes
+}
+
+/// Pair removed files with added files of the same size and hash, and return
+/// the pairs and whatever is left unpaired on each side.
+///
+/// When several added files could be a removed one's new name, the closest
+/// path wins: the same file name (moved to another directory) over the same
+/// directory (renamed in place) over the longest shared prefix, with ties
+/// going to the first path. Empty files all hash alike, so they never pair.
+fn match_renames<'a>(
+    removed: Vec<&'a Entry>,
+    added: Vec<&'a Entry>,
+) -> (Vec<(&'a Entry, &'a Entry)>, Vec<&'a Entry>, Vec<&'a Entry>) {
+    let mut by_contents: HashMap<(u64, &str), Vec<&Entry>> = HashMap::new();
+    let mut unmatched_added = Vec::new();
+    for entry in added {
+        if entry.size == 0 {
+            unmatched_added.push(entry);
+        } else {
+            by_contents
+                .entry((entry.size, entry.blake3.as_str()))
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let mut unmatched_removed = Vec::new();
+    for entry in removed {
+        let candidates = by_contents
+            .get_mut(&(entry.size, entry.blake3.as_str()))
+            .filter(|candidates| !candidates.is_empty() && entry.size > 0);
+        let Some(candidates) = candidates else {
+            unmatched_removed.push(entry);
+            continue;
+        };
+        let best = (0..candidates.len())
+            .rev()
+            .max_by_key(|&i| closeness(&entry.path, &candidates[i].path))
+            .unwrap_or(0);
+        pairs.push((entry, candidates.remove(best)));
+    }
+    unmatched_added.extend(by_contents.into_values().flatten());
+    (pairs, unmatched_removed, unmatched_added)
+}
+
+/// How alike two paths are, for ranking rename candidates.
+fn closeness(a: &str, b: &str) -> (bool, bool, usize) {
+    let (a, b) = (Path::new(a), Path::new(b));
+    let prefix = a
+        .components()
+        .zip(b.components())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (
+        a.file_name() == b.file_name(),
+        a.parent() == b.parent(),
+        prefix,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, contents: &str) -> Entry {
+        Entry {
+            path: path.into(),
+            size: contents.len() as u64,
+            mtime_ns: 0,
+            blake3: blake3::hash(contents.as_bytes()).to_hex().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff_finds_renames_by_contents() {
+        let old = [
+            entry("a/report.pdf", "report"),
+            entry("a/x.txt", "copy"),
+            entry("a/y.txt", "copy"),
+            entry("empty", ""),
+            entry("gone.txt", "bye"),
+            entry("notes.txt", "v1"),
+            entry("same.txt", "same"),
+        ];
+        let mut new = vec![
+            entry("b/report.pdf", "report"),
+            entry("a/z.txt", "copy"),
+            entry("b/y.txt", "copy"),
+            entry("empty2", ""),
+            entry("new.txt", "hi"),
+            entry("notes.txt", "v2"),
+            entry("same.txt", "same"),
+        ];
+        new[6].mtime_ns = 42;
+
+        let changes: Vec<String> = diff(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            [
+                // x.txt stayed in a/, y.txt kept its name in b/
+                "renamed: a/x.txt -> a/z.txt",
+                "renamed: a/report.pdf -> b/report.pdf",
+                "renamed: a/y.txt -> b/y.txt",
+                "removed: empty",
+                "added: empty2",
+                "removed: gone.txt",
+                "added: new.txt",
+                "modified: notes.txt",
+            ]
+        );
+    }
+}
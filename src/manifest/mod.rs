@@ -0,0 +1,529 @@
+//! Manifests of a tree (`fs manifest`) and checking a tree against one later
+//! (`fs verify`), for validating backups and catching bitrot.
+//!
+//! A manifest is JSON Lines: one object per regular file (symlinks are left
+//! out) with its path relative to the root, size, modification time and
+//! BLAKE3 hash, sorted by path.
+
+pub mod diff;
+
+use crate::crawl::FileEntry;
+use crate::hashing;
+use crate::{search_files, unix_ns, Error, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::sync::mpsc;
+
+#[derive(Args, Debug)]
+pub struct ManifestArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Write the manifest to FILE instead of standard output
+    #[arg(short = 'o', long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Number of files to hash at once [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The manifest written by `fs manifest`
+    pub manifest: PathBuf,
+
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// Only look for silent corruption: print a JSON line for each file whose
+    /// contents changed while its modification time didn't, ignoring files
+    /// added, removed or edited since the manifest was written
+    #[arg(long)]
+    pub bitrot: bool,
+
+    /// Number of files to hash at once [default: number of CPUs]
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+/// One file as recorded in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Relative to the root, with `/` separators
+    pub path: String,
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch
+    pub mtime_ns: i64,
+    pub blake3: String,
+}
+
+impl Entry {
+    /// Stat and hash the file at `path`, recording it relative to `root`.
+    pub fn read(path: &Path, root: &Path) -> io::Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        Ok(Entry {
+            path: relative(path, root),
+            size: meta.len(),
+            mtime_ns: mtime_ns(&meta),
+            blake3: hashing::blake3_file(path)?.to_hex().to_string(),
+        })
+    }
+}
+
+pub(crate) fn mtime_ns(meta: &std::fs::Metadata) -> i64 {
+    meta.modified().map_or(0, unix_ns)
+}
+
+/// `path` relative to `root`, with `/` separators on every platform so a
+/// manifest can be checked on another system.
+pub(crate) fn relative(path: &Path, root: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    let parts: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// How a file compares to its manifest entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    /// On disk but not in the manifest
+    Added,
+    /// In the manifest but gone from disk
+    Removed,
+    /// Contents changed, and so did the modification time
+    Modified,
+    /// Contents changed while the modification time stayed the same, which
+    /// normal writes don't do: bitrot, or tampering
+    Corrupted,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "ok",
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Modified => "modified",
+            Status::Corrupted => "corrupted",
+        })
+    }
+}
+
+/// Compare the file an entry describes with what is on disk under `root` now.
+pub fn check(entry: &Entry, root: &Path) -> io::Result<Status> {
+    check_hash(entry, root).map(|(status, _)| status)
+}
+
+/// Like [`check`], also returning the file's hash now unless it's gone.
+fn check_hash(entry: &Entry, root: &Path) -> io::Result<(Status, Option<blake3::Hash>)> {
+    let path = root.join(&entry.path);
+    let meta = match std::fs::metadata(&path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Status::Removed, None)),
+        Err(e) => return Err(e),
+    };
+    let hash = hashing::blake3_file(&path)?;
+    let status = if hash.to_hex().as_str() == entry.blake3 {
+        Status::Ok
+    } else if mtime_ns(&meta) == entry.mtime_ns {
+        Status::Corrupted
+    } else {
+        Status::Modified
+    };
+    Ok((status, Some(hash)))
+}
+
+/// A file `--bitrot` found corrupted: a line of its report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Corruption {
+    pub path: String,
+    /// Size and modification time, from the manifest
+    pub size: u64,
+    pub mtime_ns: i64,
+    /// The hash in the manifest, and the one the file has now
+    pub expected: String,
+    pub actual: String,
+}
+
+fn jobs(jobs: Option<usize>) -> usize {
+    jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    })
+}
+
+/// Whether `path` is the file at `canonical`, checked only when the names match.
+fn is_file(path: &Path, canonical: Option<&Path>) -> bool {
+    canonical.is_some_and(|canonical| {
+        path.file_name() == canonical.file_name()
+            && std::fs::canonicalize(path).is_ok_and(|p| p == canonical)
+    })
+}
+
+/// Searches and hashes the tree, then writes the manifest sorted by path.
+pub async fn write(args: &ManifestArgs) -> Result<ExitCode> {
+    if args.search.search_archives {
+        bail!("manifests record files on disk; drop --search-archives");
+    }
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("creating {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    // The manifest being written isn't part of the tree it describes
+    let exclude = args
+        .output
+        .as_ref()
+        .and_then(|path| std::fs::canonicalize(path).ok());
+
+    let (entries, errors) = build(&args.search, jobs(args.jobs), exclude.as_deref()).await;
+    for entry in &entries {
+        serde_json::to_writer(&mut out, entry)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    if let Some(path) = &args.output {
+        eprintln!("Wrote {} entries to {}", entries.len(), path.display());
+    }
+
+    Ok(if errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Every searched file's entry, sorted by path, and how many couldn't be read.
+async fn build(search: &SearchConfig, jobs: usize, exclude: Option<&Path>) -> (Vec<Entry>, usize) {
+    let root = search.root_path.clone();
+    let rx = search_files(search).await;
+    let exclude = exclude.map(Path::to_path_buf);
+    let mut hashed = hashing::spawn_pool(rx, jobs, move |item: &Result<FileEntry, Error>| {
+        let found = item.as_ref().ok()?;
+        // Only regular files have contents to record; what a symlink points
+        // to is recorded where it's in the tree itself
+        if found.is_file().is_ok_and(|is_file| !is_file) {
+            return None;
+        }
+        let path = found.path();
+        (!is_file(path, exclude.as_deref())).then(|| Entry::read(path, &root))
+    });
+
+    let mut entries = Vec::new();
+    let mut errors = 0usize;
+    while let Some((item, entry)) = hashed.recv().await {
+        match (item, entry) {
+            (Ok(_), Some(Ok(entry))) => entries.push(entry),
+            (Ok(found), Some(Err(e))) => {
+                eprintln!("Error: {}: {e}", found.path().display());
+                errors += 1;
+            }
+            (Ok(_), None) => {}
+            (Err(e), _) => {
+                eprintln!("Error: {e}");
+                errors += 1;
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    (entries, errors)
+}
+
+/// Read a manifest written by `fs manifest`.
+pub fn read_manifest(path: &Path) -> Result<Vec<Entry>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: not a manifest entry", path.display(), i + 1))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Checks every manifest entry against the tree and looks for files the
+/// manifest doesn't know, printing each difference and a summary.
+pub async fn verify(args: &VerifyArgs) -> Result<ExitCode> {
+    if args.search.search_archives {
+        bail!("manifests record files on disk; drop --search-archives");
+    }
+    let entries = read_manifest(&args.manifest)?;
+    if args.bitrot {
+        return bitrot(entries, &args.search.root_path, jobs(args.jobs)).await;
+    }
+    let exclude = std::fs::canonicalize(&args.manifest).ok();
+    let (results, errors) =
+        compare(entries, &args.search, jobs(args.jobs), exclude.as_deref()).await;
+
+    let mut counts = [0usize; 5];
+    for (path, status) in &results {
+        counts[*status as usize] += 1;
+        if *status != Status::Ok {
+            println!("{status}: {path}");
+        }
+    }
+    let [ok, added, removed, modified, corrupted] = counts;
+    println!(
+        "{ok} ok, {added} added, {removed} removed, {modified} modified, {corrupted} corrupted"
+    );
+
+    Ok(if errors > 0 || ok < results.len() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Hashes every file in the manifest and prints a JSON line for each that is
+/// corrupted, with a summary on stderr so stdout stays machine-readable.
+async fn bitrot(entries: Vec<Entry>, root: &Path, jobs: usize) -> Result<ExitCode> {
+    let checked = entries.len();
+    let (found, skipped, errors) = scan_bitrot(entries, root, jobs).await;
+    for corruption in &found {
+        println!("{}", serde_json::to_string(corruption)?);
+    }
+    eprintln!(
+        "Checked {checked} file(s): {} corrupted, {skipped} changed or removed since the manifest, {errors} unreadable",
+        found.len()
+    );
+
+    Ok(if errors > 0 || !found.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The corrupted files among the manifest's, sorted by path, how many were
+/// edited or removed since (and so can't be judged), and how many couldn't be read.
+async fn scan_bitrot(
+    entries: Vec<Entry>,
+    root: &Path,
+    jobs: usize,
+) -> (Vec<Corruption>, usize, usize) {
+    let (tx, rx) = mpsc::channel(jobs.max(1) * 2);
+    tokio::spawn(async move {
+        for entry in entries {
+            if tx.send(entry).await.is_err() {
+                return;
+            }
+        }
+    });
+    let root = root.to_path_buf();
+    let mut checked = hashing::spawn_pool(rx, jobs, move |entry: &Entry| check_hash(entry, &root));
+
+    let mut found = Vec::new();
+    let (mut skipped, mut errors) = (0usize, 0usize);
+    while let Some((entry, result)) = checked.recv().await {
+        match result {
+            Ok((Status::Corrupted, Some(hash))) => found.push(Corruption {
+                path: entry.path,
+                size: entry.size,
+                mtime_ns: entry.mtime_ns,
+                expected: entry.blake3,
+                actual: hash.to_hex().to_string(),
+            }),
+            Ok((Status::Ok, _)) => {}
+            Ok(_) => skipped += 1,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", entry.path);
+                errors += 1;
+            }
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    (found, skipped, errors)
+}
+
+/// The status of every file in the manifest or the tree, sorted by path, and
+/// how many couldn't be checked.
+async fn compare(
+    entries: Vec<Entry>,
+    search: &SearchConfig,
+    jobs: usize,
+    exclude: Option<&Path>,
+) -> (Vec<(String, Status)>, usize) {
+    let root = search.root_path.clone();
+    let known: HashSet<String> = entries.iter().map(|e| e.path.clone()).collect();
+
+    let (tx, rx) = mpsc::channel(jobs.max(1) * 2);
+    tokio::spawn(async move {
+        for entry in entries {
+            if tx.send(entry).await.is_err() {
+                return;
+            }
+        }
+    });
+    let check_root = root.clone();
+    let mut checked = hashing::spawn_pool(rx, jobs, move |entry: &Entry| check(entry, &check_root));
+
+    // Look for additions while the entries are being hashed
+    let mut added = Vec::new();
+    let mut errors = 0usize;
+    let mut rx = search_files(search).await;
+    while let Some(item) = rx.recv().await {
+        match item {
+            Ok(entry) if is_file(entry.path(), exclude) => {}
+            Ok(entry) => {
+                let rel = relative(entry.path(), &root);
+                if !known.contains(&rel) {
+                    added.push((rel, Status::Added));
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                errors += 1;
+            }
+        }
+    }
+
+    let mut results = added;
+    while let Some((entry, status)) = checked.recv().await {
+        match status {
+            Ok(status) => results.push((entry.path, status)),
+            Err(e) => {
+                eprintln!("Error: {}: {e}", entry.path);
+                errors += 1;
+            }
+        }
+    }
+    results.sort();
+    (results, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manifest_then_verify() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        std::fs::create_dir(root.join("docs"))?;
+        for (name, contents) in [
+            ("keep.txt", "unchanged"),
+            ("edit.txt", "before"),
+            ("rot.bin", "abcdef"),
+            ("gone.txt", "bye"),
+            ("docs/readme.md", "# hi"),
+        ] {
+            std::fs::write(root.join(name), contents)?;
+        }
+        let search = SearchConfig {
+            root_path: root.to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: None,
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: false,
+            crawl: Default::default(),
+        };
+
+        let (entries, errors) = build(&search, 2, None).await;
+        assert_eq!(errors, 0);
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "docs/readme.md",
+                "edit.txt",
+                "gone.txt",
+                "keep.txt",
+                "rot.bin"
+            ]
+        );
+        assert_eq!(
+            entries[3].blake3,
+            blake3::hash(b"unchanged").to_hex().as_str()
+        );
+
+        // Round trip through the file format
+        let manifest = tmp.path().join("manifest.jsonl");
+        let mut out = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut out, entry)?;
+            out.push(b'\n');
+        }
+        std::fs::write(&manifest, out)?;
+        assert_eq!(read_manifest(&manifest)?, entries);
+
+        std::fs::write(root.join("edit.txt"), "after, and longer")?;
+        std::fs::remove_file(root.join("gone.txt"))?;
+        std::fs::write(root.join("new.txt"), "hello")?;
+        // Same size and mtime, different bytes
+        let rot = root.join("rot.bin");
+        let mtime = filetime::FileTime::from_last_modification_time(&std::fs::metadata(&rot)?);
+        std::fs::write(&rot, "abcdeF")?;
+        filetime::set_file_mtime(&rot, mtime)?;
+
+        let (found, skipped, errors) = scan_bitrot(entries.clone(), root, 2).await;
+        assert_eq!((skipped, errors), (2, 0));
+        assert_eq!(
+            found,
+            [Corruption {
+                path: "rot.bin".into(),
+                size: 6,
+                mtime_ns: entries[4].mtime_ns,
+                expected: entries[4].blake3.clone(),
+                actual: blake3::hash(b"abcdeF").to_hex().to_string(),
+            }]
+        );
+
+        let exclude = std::fs::canonicalize(&manifest)?;
+        let (results, errors) = compare(entries, &search, 2, Some(&exclude)).await;
+        assert_eq!(errors, 0);
+        let results: Vec<_> = results.iter().map(|(p, s)| (p.as_str(), *s)).collect();
+        assert_eq!(
+            results,
+            [
+                ("docs/readme.md", Status::Ok),
+                ("edit.txt", Status::Modified),
+                ("gone.txt", Status::Removed),
+                ("keep.txt", Status::Ok),
+                ("new.txt", Status::Added),
+                ("rot.bin", Status::Corrupted),
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_are_left_out() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        std::fs::create_dir(root.join("sub"))?;
+        std::fs::write(root.join("sub/a.txt"), "a")?;
+        std::os::unix::fs::symlink("sub", root.join("link"))?;
+        std::os::unix::fs::symlink("sub/a.txt", root.join("alias.txt"))?;
+        let search = SearchConfig {
+            root_path: root.to_path_buf(),
+            ..SearchConfig::default()
+        };
+
+        let (entries, errors) = build(&search, 2, None).await;
+        assert_eq!(errors, 0);
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["sub/a.txt"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! Man pages for packagers (`fs manpage`): one for `fs` and one for each of
+//! its subcommands (`fs-grep(1)` and so on), rendered from the same clap
+//! definitions as `--help`, with examples of the commands in use.
+
+use anyhow::{Context, Result};
+use clap::{Args, Command};
+use clap_mangen::Man;
+use roff::{bold, roman, Roff};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Args, Debug)]
+pub struct ManpageArgs {
+    /// Write fs.1 and a page for each subcommand (fs-grep.1, ...) into DIR,
+    /// instead of printing fs.1
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+}
+
+/// Examples for each page, by its name: the command, then what it does.
+const EXAMPLES: &[(&str, &str, &str)] = &[
+    (
+        "fs",
+        "fs -e rs,toml",
+        "List the .rs and .toml files under the current directory.",
+    ),
+    (
+        "fs",
+        "fs ~/Downloads -p IMG -m 2",
+        "Find files with IMG in their names, at most two directories down.",
+    ),
+    (
+        "fs",
+        "fs src tests vendor -e rs",
+        "List the Rust sources under three directories at once.",
+    ),
+    (
+        "fs",
+        "git diff --name-only | fs --filter-stdin -e rs",
+        "Keep the changed files that are Rust sources the search would find.",
+    ),
+    (
+        "fs",
+        "fs -vvv -p main 2>&1 | grep skipped",
+        "See why files named main were left out.",
+    ),
+    (
+        "fs",
+        "fs -e rs --why build/gen.rs",
+        "Show which .gitignore rule or filter keeps build/gen.rs out of the results.",
+    ),
+    (
+        "fs",
+        "fs -H --include-gitignored",
+        "Include hidden files, and those .gitignore files leave out.",
+    ),
+    (
+        "fs",
+        "fs src -e rs --not-contains SPDX-License-Identifier",
+        "Find source files missing a license header.",
+    ),
+    (
+        "fs",
+        "fs -e tmp --delete --dry-run",
+        "Show what deleting every .tmp file would remove, and how much space it would free.",
+    ),
+    (
+        "fs",
+        "fs -1 -e lock",
+        "Exit successfully if there's a lock file anywhere, stopping at the first.",
+    ),
+    (
+        "fs",
+        "vim $(fs src -e rs --pick)",
+        "Pick Rust sources to edit from a fuzzy-matched list.",
+    ),
+    (
+        "fs",
+        "fs --rpc ~/src/project -H",
+        "Answer an editor plugin's searches of ~/src/project as JSON-RPC on stdin and stdout.",
+    ),
+    (
+        "fs-find",
+        "fs find -e log --json",
+        "Print each log file as a line of JSON.",
+    ),
+    (
+        "fs-grep",
+        "fs grep 'TODO|FIXME' src -e rs",
+        "Print each line of the Rust sources mentioning TODO or FIXME.",
+    ),
+    (
+        "fs-replace",
+        "fs replace 'colour' 'color' docs -e md",
+        "Preview replacing colour with color in the Markdown files, then ask before writing.",
+    ),
+    (
+        "fs-dup",
+        "fs dup ~/Pictures -e jpg,png",
+        "List sets of identical pictures, the most space wasted first.",
+    ),
+    (
+        "fs-dup",
+        "fs dup ~/Downloads --delete-duplicates keep-newest --dry-run",
+        "Show which copies deleting all but the newest of each set would remove.",
+    ),
+    (
+        "fs-du",
+        "fs du ~/src --depth 2 --include-gitignored",
+        "Show which directories two levels down take the most space.",
+    ),
+    (
+        "fs-manifest",
+        "fs manifest /srv/data -o data.manifest",
+        "Record the size, modification time and hash of every file under /srv/data.",
+    ),
+    (
+        "fs-verify",
+        "fs verify data.manifest /srv/data",
+        "List what was added, removed, modified or corrupted since the manifest was made.",
+    ),
+    (
+        "fs-diff",
+        "fs diff before.manifest after.manifest",
+        "Compare two manifests, finding renamed files by their contents.",
+    ),
+    (
+        "fs-index",
+        "fs index build ~ -H",
+        "Index everything in the home directory, hidden files included, for fs locate.",
+    ),
+    (
+        "fs-index",
+        "fs index build ~/src --watch",
+        "Index ~/src and keep the index up to date from file events until stopped.",
+    ),
+    (
+        "fs-locate",
+        "fs locate -b -i readme",
+        "Find files named like readme in the index, whatever their case.",
+    ),
+    (
+        "fs-daemon",
+        "fs daemon --socket /run/user/1000/fs.sock",
+        "Answer fs locate --daemon --socket /run/user/1000/fs.sock from the index in memory.",
+    ),
+    (
+        "fs-serve",
+        "fs serve /srv/data --listen 0.0.0.0:7878",
+        "Answer GET /search?ext=log&limit=100 and GET /stats about /srv/data from any host.",
+    ),
+    (
+        "fs-mcp",
+        "fs mcp ~/src/project -H",
+        "Let a coding agent search ~/src/project, hidden files included, through MCP tools.",
+    ),
+    (
+        "fs-watch",
+        "fs watch src -e rs",
+        "Print each Rust source created, modified or removed under src as it happens.",
+    ),
+    (
+        "fs-bench",
+        "fs bench /usr --threads 1,4,auto --cold",
+        "Time the walk of /usr with each backend and thread count, warm and cold.",
+    ),
+    (
+        "fs-repl",
+        "fs repl ~/src --limit 50",
+        "Type searches of ~/src one after another, walking it only once.",
+    ),
+    (
+        "fs-tui",
+        "fs tui ~/Downloads -H",
+        "Browse everything in ~/Downloads, hidden files included, sorting and narrowing it down.",
+    ),
+    (
+        "fs-explain",
+        "fs explain node_modules/x/index.js",
+        "Show whether each directory down to index.js is entered, and the rule that stops the walk.",
+    ),
+    (
+        "fs-manpage",
+        "fs manpage --dir /usr/share/man/man1",
+        "Install the man pages.",
+    ),
+];
+
+/// Print the man page for `cmd`, the whole command line, or with `--dir`
+/// write it and its subcommands' pages.
+pub fn run(args: &ManpageArgs, cmd: Command) -> Result<ExitCode> {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    let subpages: Vec<_> = pages(&cmd).map(name).collect();
+    match &args.dir {
+        None => render(&cmd, &subpages, &mut io::stdout().lock())?,
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("can't create {}", dir.display()))?;
+            let pages = std::iter::once((&cmd, subpages.clone()))
+                .chain(pages(&cmd).map(|sub| (sub, vec![name(&cmd)])));
+            for (page, see_also) in pages {
+                let path = dir.join(format!("{}.1", name(page)));
+                let mut file = io::BufWriter::new(
+                    std::fs::File::create(&path)
+                        .with_context(|| format!("can't create {}", path.display()))?,
+                );
+                render(page, &see_also, &mut file)?;
+                file.flush()?;
+                eprintln!("Wrote {}", path.display());
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The subcommands that get pages of their own.
+fn pages(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|sub| !sub.is_hide_set())
+}
+
+/// The page's name: `fs`, or `fs-grep` for a subcommand.
+fn name(cmd: &Command) -> &str {
+    cmd.get_display_name().unwrap_or_else(|| cmd.get_name())
+}
+
+/// `cmd`'s page: clap_mangen's sections, with the examples and the pages in
+/// `see_also` before the version and authors.
+fn render(cmd: &Command, see_also: &[&str], out: &mut dyn Write) -> io::Result<()> {
+    let man = Man::new(cmd.clone()).manual("fs manual");
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    if cmd.get_arguments().any(|arg| !arg.is_hide_set()) {
+        man.render_options_section(out)?;
+    }
+    if cmd.has_subcommands() {
+        man.render_subcommands_section(out)?;
+    }
+
+    let mut roff = Roff::new();
+    let examples: Vec<_> = EXAMPLES
+        .iter()
+        .filter(|(page, ..)| *page == name(cmd))
+        .collect();
+    if !examples.is_empty() {
+        roff.control("SH", ["EXAMPLES"]);
+        for (_, command, what) in examples {
+            roff.control("TP", [])
+                .text([bold(*command)])
+                .text([roman(*what)]);
+        }
+    }
+    roff.control("SH", ["SEE ALSO"]);
+    let mut pages = Vec::new();
+    for (i, page) in see_also.iter().enumerate() {
+        if i > 0 {
+            pages.push(roman(", "));
+        }
+        pages.extend([bold(*page), roman("(1)")]);
+    }
+    roff.text(pages);
+    roff.to_writer(out)?;
+
+    if cmd.get_version().is_some() {
+        man.render_version_section(out)?;
+    }
+    if cmd.get_author().is_some() {
+        man.render_authors_section(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_page_for_each_subcommand() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cmd = Command::new("fs")
+            .version("1.0")
+            .subcommand(Command::new("du").about("Total up sizes"))
+            .subcommand(Command::new("secret").hide(true));
+        let args = ManpageArgs {
+            dir: Some(tmp.path().join("man1")),
+        };
+        run(&args, cmd)?;
+
+        let mut written: Vec<_> = std::fs::read_dir(tmp.path().join("man1"))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<_>>()?;
+        written.sort();
+        assert_eq!(written, ["fs-du.1", "fs.1"]);
+
+        let du = std::fs::read_to_string(tmp.path().join("man1/fs-du.1"))?;
+        assert!(du.starts_with(".ie"), "{du}");
+        assert!(du.contains(".SH EXAMPLES\n.TP\n\\fBfs du ~/src"), "{du}");
+        assert!(du.ends_with(".SH \"SEE ALSO\"\n\\fBfs\\fR(1)\n"), "{du}");
+        let fs = std::fs::read_to_string(tmp.path().join("man1/fs.1"))?;
+        assert!(fs.contains("\\fBfs\\-du\\fR(1)\n"), "{fs}");
+        assert!(fs.contains(".SH VERSION\nv1.0"), "{fs}");
+        Ok(())
+    }
+}
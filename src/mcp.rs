@@ -0,0 +1,722 @@
+//! `fs mcp`: the search as tools for coding agents, served over the Model
+//! Context Protocol on stdin and stdout. There are three, all read-only:
+//! `search_files` lists the files a search finds, `read_matches` the lines in
+//! them a regex matches, as `fs grep` prints them, and `stat_path` what a
+//! path is and whether a search would find it.
+//!
+//! Nothing outside the root `fs mcp` was started on is read, through symlinks
+//! or `..` either, and searches leave out what `fs` would with the same
+//! flags: hidden files and whatever .gitignore files ignore, unless a call
+//! asks for them. Paths go in and come out relative to the root. Each call
+//! runs in a task of its own, and a client can cancel it.
+
+use crate::crawl::EntryKind;
+use crate::grep::FileSearcher;
+use crate::jsonrpc::{self, Error, Request};
+use crate::manifest::relative;
+use crate::search::Custom;
+use crate::{search_files, unix_ns, SearchConfig};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// The protocol's versions this speaks, newest first.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// How many paths or matching lines a call returns, unless it asks for more.
+const DEFAULT_LIMIT: usize = 200;
+
+/// How much of a line `read_matches` returns, in characters.
+const MAX_LINE: usize = 500;
+
+#[derive(Args, Debug)]
+pub struct McpArgs {
+    /// The tree the tools may look at, and the defaults for their searches
+    #[command(flatten)]
+    pub search: SearchConfig,
+}
+
+pub async fn run(args: &McpArgs) -> Result<ExitCode> {
+    let mut config = args.search.clone();
+    config.root_path = std::fs::canonicalize(&config.root_path)
+        .with_context(|| format!("can't serve {}", config.root_path.display()))?;
+    let input = BufReader::new(tokio::io::stdin());
+    serve(config, input, tokio::io::stdout()).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Answer the requests on `input` about the tree `config` describes, until
+/// it's closed.
+async fn serve<R, W>(config: SearchConfig, mut input: R, output: W) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, writer) = jsonrpc::writer(output);
+    let server = Arc::new(Server {
+        config,
+        calls: Mutex::new(HashMap::new()),
+    });
+    while let Some(message) = jsonrpc::read(&mut input).await? {
+        let request = match message {
+            Ok(request) => request,
+            Err(e) => {
+                sender.respond(Value::Null, Err(e));
+                continue;
+            }
+        };
+        let Some(id) = request.id.clone() else {
+            server.notified(&request);
+            continue;
+        };
+        match request.method.as_str() {
+            "initialize" => sender.respond(id, initialize(&request)),
+            "ping" => sender.respond(id, Ok(json!({}))),
+            "tools/list" => sender.respond(id, Ok(json!({ "tools": tools() }))),
+            "tools/call" => {
+                let cancel = server.started(&id);
+                let (server, sender) = (Arc::clone(&server), sender.clone());
+                tokio::spawn(async move {
+                    let result = server.call(&request, &cancel).await;
+                    // A cancelled call isn't answered
+                    if server.finished(&id) {
+                        sender.respond(id, result);
+                    }
+                });
+            }
+            method => sender.respond(id, Err(Error::method_not_found(method))),
+        }
+    }
+    // No one's left to answer
+    server.cancel_all();
+    drop(sender);
+    writer.await??;
+    Ok(())
+}
+
+fn initialize(request: &Request) -> Result<Value, Error> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Initialize {
+        protocol_version: String,
+    }
+    let asked: Initialize = request.params()?;
+    // The version asked for if it's one this speaks, else the newest
+    let version = PROTOCOL_VERSIONS
+        .into_iter()
+        .find(|&version| version == asked.protocol_version)
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    Ok(json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "fs", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Search the repository by file name with search_files and by \
+            contents with read_matches, and check a path with stat_path. Hidden and \
+            gitignored files are left out unless asked for.",
+    }))
+}
+
+/// The tools, as `tools/list` describes them.
+fn tools() -> Value {
+    let scope = json!({
+        "path": {
+            "type": "string",
+            "description": "A directory below the root to search instead of all of it",
+        },
+        "pattern": {
+            "type": "string",
+            "description": "Only file names containing this; '*' matches anything",
+        },
+        "extensions": {
+            "type": "array",
+            "items": { "type": "string" },
+            "description": "Only files with these extensions, like [\"rs\", \"toml\"]",
+        },
+        "max_depth": {
+            "type": "integer",
+            "minimum": 0,
+            "description": "Go at most this many directories down",
+        },
+        "hidden": { "type": "boolean", "description": "Include hidden files" },
+        "include_gitignored": {
+            "type": "boolean",
+            "description": "Include what .gitignore files leave out",
+        },
+    });
+    let with = |more: Value| {
+        let mut properties = scope.clone();
+        properties
+            .as_object_mut()
+            .unwrap()
+            .extend(more.as_object().unwrap().clone());
+        properties
+    };
+    json!([
+        {
+            "name": "search_files",
+            "description": "List the files in the repository whose names match, as paths \
+                relative to its root.",
+            "inputSchema": {
+                "type": "object",
+                "properties": with(json!({
+                    "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": format!(
+                            "Return at most this many [default: {DEFAULT_LIMIT}]"
+                        ),
+                    },
+                })),
+                "additionalProperties": false,
+            },
+            "annotations": { "readOnlyHint": true, "openWorldHint": false },
+        },
+        {
+            "name": "read_matches",
+            "description": "Search the contents of the files for a regex and return the \
+                matching lines as path:line:text, with context lines as path-line-text. \
+                Binary files are skipped.",
+            "inputSchema": {
+                "type": "object",
+                "properties": with(json!({
+                    "regex": { "type": "string", "description": "The regex to search for" },
+                    "ignore_case": { "type": "boolean" },
+                    "context": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "description": "Lines of context around each match",
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": format!(
+                            "Stop after this many matching lines [default: {DEFAULT_LIMIT}]"
+                        ),
+                    },
+                })),
+                "required": ["regex"],
+                "additionalProperties": false,
+            },
+            "annotations": { "readOnlyHint": true, "openWorldHint": false },
+        },
+        {
+            "name": "stat_path",
+            "description": "Say what a path is (type, size, modification time, and for a \
+                symlink its target) and whether a search would find it, or what it skips \
+                it for.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Relative to the root" },
+                },
+                "required": ["path"],
+                "additionalProperties": false,
+            },
+            "annotations": { "readOnlyHint": true, "openWorldHint": false },
+        },
+    ])
+}
+
+/// Where a tool searches, narrowing down the server's own search.
+#[derive(Debug, Default, Deserialize)]
+struct Scope {
+    path: Option<String>,
+    pattern: Option<String>,
+    extensions: Option<Vec<String>>,
+    max_depth: Option<usize>,
+    hidden: Option<bool>,
+    include_gitignored: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchFiles {
+    #[serde(flatten)]
+    scope: Scope,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadMatches {
+    regex: String,
+    #[serde(flatten)]
+    scope: Scope,
+    #[serde(default)]
+    ignore_case: bool,
+    #[serde(default)]
+    context: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatPath {
+    path: String,
+}
+
+struct Server {
+    /// The root, absolute, and the defaults for searches
+    config: SearchConfig,
+    /// The calls in progress, by id, for cancelling
+    calls: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl Server {
+    fn started(&self, id: &Value) -> CancellationToken {
+        let cancel = CancellationToken::new();
+        let mut calls = self.calls.lock().unwrap();
+        calls.insert(id.to_string(), cancel.clone());
+        cancel
+    }
+
+    /// Whether the call `id` is still wanted, now that it's done.
+    fn finished(&self, id: &Value) -> bool {
+        let cancel = self.calls.lock().unwrap().remove(&id.to_string());
+        cancel.is_some_and(|cancel| !cancel.is_cancelled())
+    }
+
+    fn cancel_all(&self) {
+        self.calls
+            .lock()
+            .unwrap()
+            .values()
+            .for_each(CancellationToken::cancel);
+    }
+
+    fn notified(&self, notification: &Request) {
+        if notification.method != "notifications/cancelled" {
+            return;
+        }
+        if let Some(id) = notification.params.get("requestId") {
+            if let Some(cancel) = self.calls.lock().unwrap().get(&id.to_string()) {
+                cancel.cancel();
+            }
+        }
+    }
+
+    async fn call(&self, request: &Request, cancel: &CancellationToken) -> Result<Value, Error> {
+        #[derive(Deserialize)]
+        struct Call {
+            name: String,
+            #[serde(default)]
+            arguments: Option<Value>,
+        }
+        let call: Call = request.params()?;
+        let arguments = call.arguments.unwrap_or_else(|| json!({}));
+        let output = match call.name.as_str() {
+            "search_files" => match arguments_of("search_files", arguments) {
+                Ok(args) => self.search_files(args, cancel).await,
+                Err(e) => Err(e),
+            },
+            "read_matches" => match arguments_of("read_matches", arguments) {
+                Ok(args) => self.read_matches(args, cancel).await,
+                Err(e) => Err(e),
+            },
+            "stat_path" => match arguments_of("stat_path", arguments) {
+                Ok(args) => self.stat_path(args),
+                Err(e) => Err(e),
+            },
+            name => return Err(Error::invalid_params(format!("there's no tool {name}"))),
+        };
+        // What went wrong goes back to the model, to try again differently
+        let (text, is_error) = match output {
+            Ok(text) => (text, false),
+            Err(e) => (format!("{e:#}"), true),
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        }))
+    }
+
+    async fn search_files(&self, args: SearchFiles, cancel: &CancellationToken) -> Result<String> {
+        let config = self.search(&args.scope, cancel)?;
+        let _stop = config.crawl.cancel.clone().drop_guard();
+        let limit = args.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut results = search_files(&config).await;
+        let (mut paths, mut more, mut errors) = (Vec::new(), false, 0);
+        while let Some(item) = results.recv().await {
+            match item {
+                Ok(_) if paths.len() == limit => {
+                    more = true;
+                    break;
+                }
+                // A symlink out of the root isn't shown either
+                Ok(entry) if !resolves_inside(entry.path(), &self.config.root_path) => {}
+                Ok(entry) => paths.push(self.shown(entry.path())),
+                Err(_) => errors += 1,
+            }
+        }
+        if cancel.is_cancelled() {
+            bail!("cancelled");
+        }
+        paths.sort_unstable();
+        let mut text = paths.join("\n");
+        if paths.is_empty() {
+            text.push_str("Nothing found.");
+        }
+        if more {
+            text += &format!("\n(only the first {limit}; narrow the search or raise the limit)");
+        }
+        if errors > 0 {
+            text += &format!("\n({errors} entries couldn't be read)");
+        }
+        Ok(text)
+    }
+
+    async fn read_matches(&self, args: ReadMatches, cancel: &CancellationToken) -> Result<String> {
+        let searcher = Arc::new(FileSearcher::new(
+            &args.regex,
+            args.ignore_case,
+            args.context,
+        )?);
+        let config = self.search(&args.scope, cancel)?;
+        let _stop = config.crawl.cancel.clone().drop_guard();
+        let limit = args.limit.unwrap_or(DEFAULT_LIMIT);
+        let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let prefix = format!(
+            "{}{}",
+            self.config.root_path.display(),
+            std::path::MAIN_SEPARATOR
+        );
+
+        let mut results = search_files(&config).await;
+        let mut running = JoinSet::new();
+        let (mut text, mut matches, mut errors) = (String::new(), 0, 0);
+        let mut more = false;
+        loop {
+            // Keeping the workers busy, until there are enough matches
+            while running.len() < jobs && !more {
+                match results.recv().await {
+                    Some(Ok(entry)) => {
+                        let searcher = Arc::clone(&searcher);
+                        let root = self.config.root_path.clone();
+                        let path = entry.into_path();
+                        running.spawn_blocking(move || {
+                            // Nor is a file read through a symlink out of it
+                            if !resolves_inside(&path, &root) {
+                                return Ok((String::new(), 0));
+                            }
+                            searcher.search(&path)
+                        });
+                    }
+                    Some(Err(_)) => errors += 1,
+                    None => break,
+                }
+            }
+            let Some(done) = running.join_next().await else {
+                break;
+            };
+            let Ok((output, found)) = done? else {
+                errors += 1;
+                continue;
+            };
+            if found == 0 || more {
+                continue;
+            }
+            if !text.is_empty() && args.context > 0 {
+                text.push_str("--\n");
+            }
+            for line in output.lines() {
+                let line = line.strip_prefix(&prefix).unwrap_or(line);
+                match line.char_indices().nth(MAX_LINE) {
+                    Some((cut, _)) => text += &format!("{}…\n", &line[..cut]),
+                    None => text += &format!("{line}\n"),
+                }
+            }
+            matches += found;
+            more = matches >= limit;
+        }
+        if cancel.is_cancelled() {
+            bail!("cancelled");
+        }
+        if text.is_empty() {
+            text.push_str("No matches.");
+        }
+        if more {
+            text += &format!(
+                "(stopped at {matches} matching lines; narrow the search or raise the limit)"
+            );
+        }
+        if errors > 0 {
+            text += &format!("\n({errors} files couldn't be read)");
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    fn stat_path(&self, args: StatPath) -> Result<String> {
+        let root = &self.config.root_path;
+        let path = self.inside(&args.path, false)?;
+        let metadata = path
+            .symlink_metadata()
+            .with_context(|| format!("can't read {}", args.path))?;
+        let kind = EntryKind::from(metadata.file_type());
+        let mut stat = json!({
+            "path": self.shown(&path),
+            "type": match kind {
+                EntryKind::File => "file",
+                EntryKind::Dir => "dir",
+                EntryKind::Symlink => "symlink",
+                EntryKind::Other => "other",
+            },
+            "size": metadata.len(),
+            "modified_ns": metadata.modified().ok().map(unix_ns),
+            "readonly": metadata.permissions().readonly(),
+        });
+        if kind == EntryKind::Symlink {
+            stat["target"] = json!(std::fs::read_link(&path)?.to_string_lossy());
+        }
+        if kind == EntryKind::Dir {
+            stat["entries"] = json!(std::fs::read_dir(&path)?.count());
+        }
+        // What a search from the root makes of it, as `fs explain` says
+        let filters = crate::crawl_filters(&self.config, &Custom::default());
+        let steps = filters.walk_to(root, &path, kind).unwrap_or_default();
+        stat["search"] = json!(match steps.last() {
+            None => "the root".to_string(),
+            Some(last) if last.path == path && last.result => "found".to_string(),
+            Some(last) if last.path == path => last.why.to_string(),
+            Some(last) => format!("not reached: {}: {}", self.shown(&last.path), last.why),
+        });
+        Ok(serde_json::to_string_pretty(&stat)?)
+    }
+
+    /// The search a tool asks for.
+    fn search(&self, scope: &Scope, cancel: &CancellationToken) -> Result<SearchConfig> {
+        let mut config = self.config.clone();
+        if let Some(path) = &scope.path {
+            config.root_path = self.inside(path, true)?;
+        }
+        if let Some(pattern) = &scope.pattern {
+            config.pattern.clone_from(pattern);
+        }
+        if let Some(extensions) = &scope.extensions {
+            config.extensions = Some(extensions.clone());
+        }
+        config.max_depth = scope.max_depth.or(config.max_depth);
+        config.show_hidden = scope.hidden.unwrap_or(config.show_hidden);
+        config.include_gitignored = scope
+            .include_gitignored
+            .unwrap_or(config.include_gitignored);
+        // Stopped with the call, or once the tool has what it needs
+        config.crawl.cancel = cancel.child_token();
+        crate::crawl_filters(&config, &Custom::default()).check()?;
+        Ok(config)
+    }
+
+    /// `path`, relative to the root, as it is on disk; an error if that's
+    /// outside the root. With `follow`, a symlink is taken to be where it
+    /// points, and that must be inside too.
+    fn inside(&self, path: &str, follow: bool) -> Result<PathBuf> {
+        let root = &self.config.root_path;
+        let joined = root.join(path);
+        let resolved = match (follow, joined.parent(), joined.file_name()) {
+            (false, Some(parent), Some(name)) => {
+                std::fs::canonicalize(parent).map(|p| p.join(name))
+            }
+            _ => std::fs::canonicalize(&joined),
+        };
+        let resolved = resolved.with_context(|| format!("can't find {path}"))?;
+        if !resolved.starts_with(root) {
+            bail!("{path} is outside the root, which is as far as these tools go");
+        }
+        Ok(resolved)
+    }
+
+    /// How a path under the root is shown: relative to it.
+    fn shown(&self, path: &Path) -> String {
+        relative(path, &self.config.root_path)
+    }
+}
+
+/// Whether `path`, once symlinks are followed, is below `root`, which is
+/// absolute. The crawl lists symlinks as it finds them, wherever they point.
+/// The arguments of a call to `tool`, refusing any its schema doesn't list:
+/// serde can't for the structs that flatten a `Scope` in.
+fn arguments_of<T: DeserializeOwned>(tool: &str, arguments: Value) -> Result<T> {
+    let tools = tools();
+    let properties = tools
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|schema| schema["name"] == tool)
+        .and_then(|schema| schema["inputSchema"]["properties"].as_object())
+        .with_context(|| format!("there's no schema for {tool}"))?;
+    if let Some(arguments) = arguments.as_object() {
+        if let Some(key) = arguments.keys().find(|key| !properties.contains_key(*key)) {
+            let known: Vec<_> = properties.keys().map(|key| format!("`{key}`")).collect();
+            bail!(
+                "unknown field `{key}`, expected one of {}",
+                known.join(", ")
+            );
+        }
+    }
+    Ok(serde_json::from_value(arguments)?)
+}
+
+fn resolves_inside(path: &Path, root: &Path) -> bool {
+    std::fs::canonicalize(path).is_ok_and(|resolved| resolved.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, DuplexStream, Lines, ReadHalf, WriteHalf};
+
+    /// The client's end of a connection to the server.
+    struct Client {
+        id: u64,
+        input: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        output: WriteHalf<DuplexStream>,
+    }
+
+    impl Client {
+        async fn ask(&mut self, method: &str, params: Value) -> Result<Value> {
+            self.id += 1;
+            let request =
+                json!({ "jsonrpc": "2.0", "id": self.id, "method": method, "params": params });
+            self.output
+                .write_all(format!("{request}\n").as_bytes())
+                .await?;
+            let line = self.input.next_line().await?.context("no answer")?;
+            let answer: Value = serde_json::from_str(&line)?;
+            assert_eq!(answer["id"], self.id);
+            Ok(answer)
+        }
+
+        /// What calling the tool `name` returns, and whether it's an error.
+        async fn call(&mut self, name: &str, arguments: Value) -> Result<(String, bool)> {
+            let answer = self
+                .ask(
+                    "tools/call",
+                    json!({ "name": name, "arguments": arguments }),
+                )
+                .await?;
+            let result = &answer["result"];
+            let text = result["content"][0]["text"].as_str().context("no text")?;
+            Ok((text.to_string(), result["isError"] == true))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_the_tools_answer_about_the_tree_and_only_it() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("repo");
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(root.join(".gitignore"), "target/\n")?;
+        std::fs::create_dir(root.join("target"))?;
+        std::fs::write(root.join("target/out.rs"), "fn main() {}\n")?;
+        std::fs::write(root.join("src/main.rs"), "fn main() {\n    run();\n}\n")?;
+        std::fs::write(root.join("src/lib.rs"), "pub fn run() {}\n")?;
+        std::fs::write(tmp.path().join("secret.txt"), "fn main\n")?;
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(tmp.path(), root.join("out"))?;
+            std::os::unix::fs::symlink("../secret.txt", root.join("leak.txt"))?;
+        }
+
+        let config = SearchConfig {
+            root_path: root.canonicalize()?,
+            ..SearchConfig::default()
+        };
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_in, server_out) = tokio::io::split(server);
+        let serving = tokio::spawn(serve(config, BufReader::new(server_in), server_out));
+        let (input, output) = tokio::io::split(client);
+        let mut client = Client {
+            id: 0,
+            input: BufReader::new(input).lines(),
+            output,
+        };
+
+        let answer = client
+            .ask("initialize", json!({ "protocolVersion": "2024-11-05" }))
+            .await?;
+        assert_eq!(answer["result"]["protocolVersion"], "2024-11-05");
+        let answer = client.ask("tools/list", json!({})).await?;
+        let names: Vec<_> = answer["result"]["tools"]
+            .as_array()
+            .context("no tools")?
+            .iter()
+            .map(|tool| tool["name"].clone())
+            .collect();
+        assert_eq!(names, ["search_files", "read_matches", "stat_path"]);
+
+        let found = client
+            .call("search_files", json!({ "extensions": ["rs"] }))
+            .await?;
+        assert_eq!(found, ("src/lib.rs\nsrc/main.rs".into(), false));
+
+        let (found, _) = client
+            .call("read_matches", json!({ "regex": "run\\(", "path": "src" }))
+            .await?;
+        let mut lines: Vec<_> = found.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            ["src/lib.rs:1:pub fn run() {}", "src/main.rs:2:    run();"]
+        );
+
+        let (stat, _) = client
+            .call("stat_path", json!({ "path": "target/out.rs" }))
+            .await?;
+        let stat: Value = serde_json::from_str(&stat)?;
+        assert_eq!(stat["type"], "file");
+        assert_eq!(
+            stat["search"],
+            "not reached: target: skipped: ignored by a .gitignore"
+        );
+
+        // Nothing outside the root, however it's asked for
+        let (_, failed) = client
+            .call("stat_path", json!({ "path": "../secret.txt" }))
+            .await?;
+        assert!(failed);
+        let (_, failed) = client.call("search_files", json!({ "path": ".." })).await?;
+        assert!(failed);
+        #[cfg(unix)]
+        {
+            let arguments = json!({ "regex": "main", "path": "out" });
+            let (_, failed) = client.call("read_matches", arguments).await?;
+            assert!(failed);
+            // Nor through a symlink the search comes across
+            let found = client
+                .call("read_matches", json!({ "regex": "fn main" }))
+                .await?;
+            assert_eq!(found, ("src/main.rs:1:fn main() {".into(), false));
+            let found = client
+                .call("search_files", json!({ "pattern": "leak" }))
+                .await?;
+            assert_eq!(found, ("Nothing found.".into(), false));
+        }
+
+        // Nor with arguments the schema doesn't have, which would be ignored
+        let arguments = json!({ "extention": ["rs"] });
+        let (refused, failed) = client.call("search_files", arguments).await?;
+        assert!(failed);
+        assert!(
+            refused.starts_with("unknown field `extention`"),
+            "{refused}"
+        );
+        let arguments = json!({ "regex": "main", "ignorecase": true });
+        let (_, failed) = client.call("read_matches", arguments).await?;
+        assert!(failed);
+
+        let answer = client.ask("tools/call", json!({ "name": "rm" })).await?;
+        assert_eq!(answer["error"]["code"], -32602);
+        let answer = client.ask("resources/list", json!({})).await?;
+        assert_eq!(answer["error"]["code"], -32601);
+
+        drop(client);
+        serving.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+//! `fs repl`: one tree, searched over and over. Each line typed is a search,
+//! with the flags `fs` takes, and the directory listings and .gitignore files
+//! read for one are kept for the next, so trying another pattern on a huge
+//! tree takes as long as filtering what's in memory, not another walk of the
+//! disk. `:refresh` drops them, for changes made since.
+
+use crate::crawl::{DirEntry, FileSystem, Metadata, ReadDir, StdFs};
+use crate::search::Custom;
+use crate::{start_search, SearchConfig};
+use anyhow::Result;
+use clap::parser::ValueSource;
+use clap::{Args, Command, FromArgMatches};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Args, Debug)]
+pub struct ReplArgs {
+    /// The directory every search is in; a search naming a root of its own
+    /// looks there, relative to this
+    #[arg(default_value = ".")]
+    pub root: PathBuf,
+
+    /// Print at most this many paths for each search, besides how many there
+    /// were in all
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+}
+
+const HELP: &str = "\
+Type a search as you would after `fs` (e.g. `-e rs -p test`, or `src -H`);
+an empty line runs the last one again.
+  :limit N   print at most N paths for each search
+  :refresh   forget the directories read so far, to see changes since
+  :help      this
+  :quit      leave (or Ctrl-D)";
+
+pub async fn run(args: &ReplArgs) -> Result<ExitCode> {
+    let query = SearchConfig::augment_args(Command::new("fs"))
+        .no_binary_name(true)
+        .disable_version_flag(true);
+    let mut limit = args.limit;
+    let mut cache = Arc::new(CachedFs::default());
+    let mut last = None;
+
+    let mut editor = DefaultEditor::new()?;
+    eprintln!("Searching {}; :help for help", args.root.display());
+    loop {
+        let line = match editor.readline("fs> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if !line.is_empty() {
+            editor.add_history_entry(line)?;
+        }
+        let words = match line {
+            "" => match &last {
+                Some(words) => Vec::clone(words),
+                None => continue,
+            },
+            ":q" | ":quit" => break,
+            ":help" => {
+                println!("{HELP}");
+                continue;
+            }
+            ":refresh" => {
+                cache = Arc::new(CachedFs::default());
+                continue;
+            }
+            _ if line.starts_with(":limit") => {
+                match line[":limit".len()..].trim().parse() {
+                    Ok(n) => limit = n,
+                    Err(_) => eprintln!("Error: :limit takes a number"),
+                }
+                continue;
+            }
+            _ if line.starts_with(':') => {
+                eprintln!("Error: no command {line}; :help lists them");
+                continue;
+            }
+            _ => match shell_words::split(line) {
+                Ok(words) => words,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    continue;
+                }
+            },
+        };
+
+        let matches = match query.clone().try_get_matches_from(&words) {
+            Ok(matches) => matches,
+            Err(e) => {
+                e.print()?;
+                continue;
+            }
+        };
+        let mut config = SearchConfig::from_arg_matches(&matches)?;
+        config.root_path = match matches.value_source("root_path") {
+            Some(ValueSource::CommandLine) => args.root.join(&config.root_path),
+            _ => args.root.clone(),
+        };
+        last = Some(words);
+        search(config, &cache, limit).await;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Run one search, reading the tree through `cache`, and print the first
+/// `limit` paths, any errors, and how many paths there were. Ctrl-C stops
+/// it, but not the REPL.
+async fn search(mut config: SearchConfig, cache: &Arc<CachedFs>, limit: usize) {
+    let cancel = CancellationToken::new();
+    config.crawl.cancel = cancel.clone();
+    let custom = Custom {
+        fs: Some(Arc::clone(cache) as Arc<dyn FileSystem>),
+        ..Custom::default()
+    };
+
+    let started = Instant::now();
+    let mut rx = start_search(&config, &custom);
+    let (mut found, mut errors) = (0, 0);
+    let collect = async {
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => {
+                    if found < limit {
+                        println!("{}", entry.path().display());
+                    }
+                    found += 1;
+                }
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    errors += 1;
+                }
+            }
+        }
+    };
+    let interrupted = tokio::select! {
+        () = collect => false,
+        _ = tokio::signal::ctrl_c() => {
+            cancel.cancel();
+            true
+        }
+    };
+
+    let mut summary = format!("{found} found in {:.1?}", started.elapsed());
+    if found > limit {
+        summary += &format!(", {} not shown", found - limit);
+    }
+    if errors > 0 {
+        summary += &format!(", {errors} errors");
+    }
+    if interrupted {
+        summary += " (interrupted)";
+    }
+    println!("-- {summary}");
+}
+
+/// The real file system, with what's been read from it kept for the next
+/// search. Only what was read successfully is kept, so a directory that
+/// couldn't be read is tried again each time.
+#[derive(Debug, Default)]
+struct CachedFs {
+    listings: Mutex<HashMap<PathBuf, Arc<[DirEntry]>>>,
+    metadata: Mutex<HashMap<PathBuf, Metadata>>,
+    symlink_metadata: Mutex<HashMap<PathBuf, Metadata>>,
+    contents: Mutex<HashMap<PathBuf, Arc<[u8]>>>,
+}
+
+/// What's kept in `cache` for `key`, or else `read`, kept if it's `Ok`.
+fn cached<V: Clone>(
+    cache: &Mutex<HashMap<PathBuf, V>>,
+    key: &Path,
+    read: impl FnOnce() -> io::Result<V>,
+) -> io::Result<V> {
+    if let Some(value) = cache.lock().unwrap().get(key) {
+        return Ok(value.clone());
+    }
+    let value = read()?;
+    cache
+        .lock()
+        .unwrap()
+        .insert(key.to_path_buf(), value.clone());
+    Ok(value)
+}
+
+impl FileSystem for CachedFs {
+    fn read_dir(&self, dir: &Path) -> io::Result<ReadDir<'_>> {
+        let entries = cached(&self.listings, dir, || {
+            StdFs.read_dir(dir)?.collect::<io::Result<Arc<[_]>>>()
+        })?;
+        Ok(Box::new(
+            (0..entries.len()).map(move |i| Ok(entries[i].clone())),
+        ))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        cached(&self.metadata, path, || StdFs.metadata(path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        cached(&self.symlink_metadata, path, || {
+            StdFs.symlink_metadata(path)
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let contents = cached(&self.contents, path, || std::fs::read(path).map(Arc::from))?;
+        Ok(Box::new(io::Cursor::new(contents)))
+    }
+
+    fn is_hidden(&self, path: &Path) -> bool {
+        StdFs.is_hidden(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::EntryKind;
+
+    #[test]
+    fn test_what_was_read_is_kept() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::write(tmp.path().join("a.rs"), "")?;
+        std::fs::write(tmp.path().join(".gitignore"), "*.log\n")?;
+        let cache = CachedFs::default();
+        let names = |cache: &CachedFs| -> io::Result<Vec<_>> {
+            let mut names: Vec<_> = cache
+                .read_dir(tmp.path())?
+                .map(|entry| entry.map(|entry| (entry.name, entry.kind)))
+                .collect::<io::Result<_>>()?;
+            names.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Ok(names)
+        };
+        let before = names(&cache)?;
+        let mut ignore = String::new();
+        cache
+            .open(&tmp.path().join(".gitignore"))?
+            .read_to_string(&mut ignore)?;
+
+        // Changes on disk aren't seen until the cache is dropped
+        std::fs::write(tmp.path().join("b.rs"), "")?;
+        std::fs::write(tmp.path().join(".gitignore"), "")?;
+        assert_eq!(names(&cache)?, before);
+        assert_eq!(before[1], ("a.rs".into(), Some(EntryKind::File)));
+        let mut again = String::new();
+        cache
+            .open(&tmp.path().join(".gitignore"))?
+            .read_to_string(&mut again)?;
+        assert_eq!(again, ignore);
+        assert_eq!(names(&CachedFs::default())?.len(), 3);
+
+        // Failures aren't kept
+        let missing = tmp.path().join("missing");
+        assert!(cache.read_dir(&missing).is_err());
+        std::fs::create_dir(&missing)?;
+        assert_eq!(cache.read_dir(&missing)?.count(), 0);
+        Ok(())
+    }
+}
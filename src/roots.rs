@@ -0,0 +1,367 @@
+//! Searching several roots at once (`fs src tests vendor`). A root inside
+//! another one given is left out, since the other's search already covers
+//! it, and the rest are searched side by side, their results merged into one
+//! stream as they're found. Paths another tool lists (`--paths-from`) are
+//! searched the same way if they're directories, while any other path is a
+//! candidate result of its own, run through the name filters. With
+//! `--filter-stdin` nothing is walked at all: each path listed is a result if
+//! the walk would have given it.
+
+use crate::search::Custom;
+use crate::{crawl, EntryKinds, Error, FileEntry, Results, SearchConfig};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// `roots` without those another one (or an earlier copy of the same one)
+/// already covers, in the order given. Roots are compared as their canonical
+/// paths, so `src` and `./src/../src` are the same; one that can't be
+/// resolved is kept as given, for its search to report.
+pub fn distinct(roots: Vec<PathBuf>) -> Vec<PathBuf> {
+    let resolved: Vec<_> = roots
+        .iter()
+        .map(|root| root.canonicalize().unwrap_or_else(|_| root.clone()))
+        .collect();
+    roots
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            !resolved.iter().enumerate().any(|(j, other)| {
+                let inside = resolved[*i].starts_with(other);
+                j != *i && inside && (j < *i || resolved[*i] != *other)
+            })
+        })
+        .map(|(_, root)| root)
+        .collect()
+}
+
+/// The paths listed in `input`: separated by NULs if there are any, as
+/// `find -print0` and `git ls-files -z` print them, since a file name can
+/// hold a newline, and otherwise one per line. Blank entries are skipped.
+pub fn read_paths(input: &[u8]) -> Vec<PathBuf> {
+    let separator = if input.contains(&0) { b'\0' } else { b'\n' };
+    input
+        .split(|&byte| byte == separator)
+        .map(|path| match separator {
+            b'\n' => path.strip_suffix(b"\r").unwrap_or(path),
+            _ => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+/// The paths listed on `input`, one at a time as they arrive, so a stage in
+/// the middle of a pipeline passes each on without waiting for the end. They
+/// are separated as [`read_paths`] has them, except that it's settled by the
+/// first read: NULs if there's one in it, and otherwise lines.
+pub fn stream_paths(input: impl Read) -> impl Iterator<Item = io::Result<PathBuf>> {
+    let mut input = input;
+    let mut pending = Vec::new();
+    let mut separator = None;
+    let mut done = false;
+    std::iter::from_fn(move || loop {
+        if let Some(separator) = separator {
+            let end = pending.iter().position(|&byte| byte == separator);
+            let entry: Vec<u8> = match end {
+                Some(end) => pending.drain(..=end).take(end).collect(),
+                None if done && !pending.is_empty() => std::mem::take(&mut pending),
+                None if done => return None,
+                None => Vec::new(),
+            };
+            let entry = match separator {
+                b'\n' => entry.strip_suffix(b"\r").unwrap_or(&entry),
+                _ => &entry,
+            };
+            if !entry.is_empty() {
+                return Some(Ok(path_from_bytes(entry)));
+            }
+            if end.is_some() || done {
+                continue;
+            }
+        }
+        let mut chunk = [0; 8 * 1024];
+        match input.read(&mut chunk) {
+            Ok(0) => done = true,
+            Ok(n) => pending.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Some(Err(e)),
+        }
+        if separator.is_none() && (done || pending.iter().any(|&b| b == b'\0' || b == b'\n')) {
+            separator = Some(if pending.contains(&0) { b'\0' } else { b'\n' });
+        }
+    })
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+/// `paths` split into the directories among them, to be searched, and the
+/// rest, to be filtered as they are.
+pub fn split_dirs(paths: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    paths.into_iter().partition(|path| path.is_dir())
+}
+
+/// The root among `roots` that `path`, a result of searching them or one
+/// listed, was found under. They're compared as absolute paths when they
+/// aren't spelled alike, so `./src/a.rs` is under `src`.
+pub fn root_of<'a>(roots: &'a [PathBuf], path: &Path) -> Option<&'a Path> {
+    let mut roots = roots.iter().map(PathBuf::as_path);
+    if let Some(root) = roots.clone().find(|root| path.starts_with(root)) {
+        return Some(root);
+    }
+    let path = std::path::absolute(path).ok()?;
+    roots.find(|root| std::path::absolute(root).is_ok_and(|root| path.starts_with(root)))
+}
+
+/// Search each of `roots` as `config` says, all at once, with one stream of
+/// results for them all, after those of `files` that pass its name filters
+/// and aren't under one of the roots, giving the `emit` kinds of entries.
+/// The searches share `config`'s cancellation token, so cancelling it stops
+/// every one.
+pub async fn search(
+    config: &SearchConfig,
+    roots: &[PathBuf],
+    files: &[PathBuf],
+    emit: EntryKinds,
+) -> Results {
+    let custom = Custom {
+        emit,
+        ..Custom::default()
+    };
+    if let ([root], []) = (roots, files) {
+        let config = SearchConfig {
+            root_path: root.clone(),
+            ..config.clone()
+        };
+        return crate::start_search(&config, &custom);
+    }
+    let (tx, merged) = crawl::channel(config.crawl.channel_capacity);
+    let found: Vec<_> = files
+        .iter()
+        .filter(|file| root_of(roots, file).is_none())
+        .filter_map(|file| candidate(config, file))
+        .collect();
+    if !found.is_empty() {
+        tx.try_send(found)
+            .expect("the channel has room for a batch");
+    }
+    for root in roots {
+        let config = SearchConfig {
+            root_path: root.clone(),
+            ..config.clone()
+        };
+        let mut rx = crate::start_search(&config, &custom);
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(batch) = rx.recv_batch().await {
+                if tx.send(batch).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    merged
+}
+
+/// The paths listed on `input` that searching `config.root_path` would have
+/// found, as they're read, without walking anything: each must pass the
+/// filters, and so must the directories between the root and it. Paths that
+/// don't exist are reported as errors.
+pub fn filter(config: &SearchConfig, input: impl Read + Send + 'static) -> Results {
+    let (tx, results) = crawl::channel(config.crawl.channel_capacity);
+    let filters = crate::crawl_filters(config, &Custom::default());
+    let root = config.root_path.clone();
+    let cancel = config.crawl.cancel.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = filters.check() {
+            let _ = tx.blocking_send(vec![Err(e)]);
+            return;
+        }
+        for path in stream_paths(input) {
+            let found = match path {
+                Ok(path) => match path.symlink_metadata() {
+                    Ok(metadata) => {
+                        let kind = metadata.file_type().into();
+                        if !filters.admits(&root, &path, kind) {
+                            continue;
+                        }
+                        Ok(FileEntry::new(path))
+                    }
+                    Err(e) => Err(Error::io(&path, e)),
+                },
+                Err(e) => Err(Error::io(Path::new("-"), e)),
+            };
+            if cancel.is_cancelled() || tx.blocking_send(vec![found]).is_err() {
+                break;
+            }
+        }
+    });
+    results
+}
+
+/// `file` as a result, if the name filters of `config` let it through.
+fn candidate(config: &SearchConfig, file: &Path) -> Option<Result<FileEntry, Error>> {
+    if let Err(e) = file.symlink_metadata() {
+        return Some(Err(Error::io(file, e)));
+    }
+    if !config.show_hidden && crate::is_hidden(file) {
+        return None;
+    }
+    crate::file_matches(file, &config.pattern, config.extensions.as_deref())
+        .then(|| Ok(FileEntry::new(file.to_path_buf())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_paths_are_read_a_line_or_a_nul_apart() {
+        let paths = |input: &[u8]| read_paths(input);
+        assert_eq!(
+            paths(b"a\r\nb c\n\nd"),
+            ["a", "b c", "d"].map(PathBuf::from)
+        );
+        assert_eq!(paths(b"a\nb\0c\0"), ["a\nb", "c"].map(PathBuf::from));
+        assert!(paths(b"").is_empty());
+
+        // Streamed, the same, across reads, once the first read has a
+        // separator to settle on
+        let streamed = |input: &[u8]| {
+            let input = input.chunks(5).fold(
+                Box::new(io::empty()) as Box<dyn Read + '_>,
+                |before, chunk| Box::new(before.chain(chunk)),
+            );
+            stream_paths(input).collect::<io::Result<Vec<_>>>().unwrap()
+        };
+        assert_eq!(streamed(b"a\r\nb c\n\nd"), paths(b"a\r\nb c\n\nd"));
+        assert_eq!(streamed(b"a\nb\0c\0"), paths(b"a\nb\0c\0"));
+        assert!(streamed(b"").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_listed_paths_are_filtered_as_the_walk_would() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        for dir in ["src/.cache", "target/debug"] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        std::fs::write(root.join(".gitignore"), "target\n")?;
+        let listed = [
+            "src/lib.rs",
+            "src/notes.txt",
+            "src/.cache/old.rs",
+            "target/debug/build.rs",
+            "gone.rs",
+        ];
+        for file in &listed[..4] {
+            std::fs::write(root.join(file), "")?;
+        }
+        let input: Vec<u8> = listed
+            .iter()
+            .flat_map(|file| [root.join(file).to_str().unwrap().as_bytes(), b"\n"].concat())
+            .collect();
+
+        let config = SearchConfig {
+            root_path: root.to_path_buf(),
+            extensions: Some(vec!["rs".into()]),
+            ..SearchConfig::default()
+        };
+        let mut rx = filter(&config, io::Cursor::new(input));
+        let (mut found, mut errors) = (Vec::new(), 0);
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => found.push(entry.into_path()),
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(found, [root.join("src/lib.rs")]);
+        assert_eq!(errors, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_roots_are_searched_once() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        for dir in ["src/inner", "tests"] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        for file in ["src/lib.rs", "src/inner/mod.rs", "tests/it.rs"] {
+            std::fs::write(root.join(file), "")?;
+        }
+
+        let given = [
+            "src/inner",
+            "tests",
+            "src",
+            "src/../src",
+            "tests",
+            "missing",
+        ]
+        .map(|dir| root.join(dir));
+        let roots = distinct(given.to_vec());
+        assert_eq!(
+            roots,
+            [root.join("tests"), root.join("src"), root.join("missing")]
+        );
+
+        // Files listed besides are filtered by name, unless a root has them
+        let files = [
+            "tests/it.rs",
+            "notes.txt",
+            "top.rs",
+            ".hidden.rs",
+            "gone.rs",
+        ]
+        .map(|file| root.join(file));
+        for file in &files[1..4] {
+            std::fs::write(file, "")?;
+        }
+        let config = SearchConfig {
+            extensions: Some(vec!["rs".into()]),
+            ..SearchConfig::default()
+        };
+        let mut rx = search(&config, &roots, &files, EntryKinds::default()).await;
+        let mut found = Vec::new();
+        let mut errors = 0;
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(entry) => {
+                    let from = root_of(&roots, entry.path()).map(Path::to_path_buf);
+                    found.push((entry.into_path(), from));
+                }
+                Err(_) => errors += 1,
+            }
+        }
+        found.sort();
+        let src = Some(root.join("src"));
+        assert_eq!(
+            found,
+            [
+                (root.join("src/inner/mod.rs"), src.clone()),
+                (root.join("src/lib.rs"), src),
+                (root.join("tests/it.rs"), Some(root.join("tests"))),
+                (root.join("top.rs"), None),
+            ]
+        );
+        // The missing root and file
+        assert_eq!(errors, 2);
+
+        // However the paths are spelled
+        let relative = [PathBuf::from("src")];
+        let found = root_of(&relative, Path::new("./src/a.rs"));
+        assert_eq!(found, Some(Path::new("src")));
+        assert_eq!(root_of(&[".".into()], Path::new("/elsewhere/a.rs")), None);
+        Ok(())
+    }
+}
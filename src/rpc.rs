@@ -0,0 +1,434 @@
+//! `fs --rpc`: the search kept running as a child process of an editor
+//! plugin, answering JSON-RPC 2.0 on stdin and stdout, a message per line,
+//! so that a search as the user types costs no process starting up.
+//!
+//! `search`, with any of `root`, `pattern`, `extensions`, `max_depth`,
+//! `hidden`, `include_gitignored` and `limit` (each as the flags `fs --rpc`
+//! was started with if left out), sends what it finds in `search/results`
+//! notifications, `{"id": ..., "entries": [...], "errors": [...]}`, a batch
+//! at a time, and the crawl's totals in `search/progress` notifications a
+//! few times a second. It's answered once it's done with `{"found": ...,
+//! "errors": ..., "limited": ...}`.
+//!
+//! Searches run side by side. `cancel` with the `id` of one, or LSP's
+//! `$/cancelRequest` notification, stops it, and it's answered with error
+//! -32800; a plugin cancels the last search as the next keystroke starts
+//! another.
+
+use crate::jsonrpc::{self, Error};
+use crate::search::Custom;
+use crate::{search_files, ProgressEvent, SearchConfig, Totals};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+use tokio::sync::broadcast;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+/// How often results found since the last batch, and progress, are sent.
+const EVERY: Duration = Duration::from_millis(100);
+
+/// The most results sent in one notification.
+const BATCH: usize = 500;
+
+pub async fn run(config: &SearchConfig) -> Result<ExitCode> {
+    let input = BufReader::new(tokio::io::stdin());
+    serve(config.clone(), input, tokio::io::stdout()).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// What a `search` asks for, narrowing down the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchParams {
+    root: Option<PathBuf>,
+    pattern: Option<String>,
+    extensions: Option<Vec<String>>,
+    max_depth: Option<usize>,
+    hidden: Option<bool>,
+    include_gitignored: Option<bool>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+/// The searches in progress, by id, for cancelling.
+type Searches = Arc<Mutex<HashMap<String, CancellationToken>>>;
+
+/// Answer the requests on `input`, searching as `config` describes unless
+/// they say otherwise, until it's closed.
+async fn serve<R, W>(config: SearchConfig, mut input: R, output: W) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, writer) = jsonrpc::writer(output);
+    let searches = Searches::default();
+    while let Some(message) = jsonrpc::read(&mut input).await? {
+        let request = match message {
+            Ok(request) => request,
+            Err(e) => {
+                sender.respond(Value::Null, Err(e));
+                continue;
+            }
+        };
+        let Some(id) = request.id.clone() else {
+            if request.method == "$/cancelRequest" {
+                if let Ok(params) = request.params::<CancelParams>() {
+                    cancel(&searches, &params.id);
+                }
+            }
+            continue;
+        };
+        match request.method.as_str() {
+            "search" => {
+                let params = match request.params() {
+                    Ok(params) => params,
+                    Err(e) => {
+                        sender.respond(id, Err(e));
+                        continue;
+                    }
+                };
+                let cancel = CancellationToken::new();
+                let key = id.to_string();
+                searches.lock().unwrap().insert(key.clone(), cancel.clone());
+                let (config, searches, sender) =
+                    (config.clone(), Arc::clone(&searches), sender.clone());
+                tokio::spawn(async move {
+                    let result = search(config, params, &id, &sender, &cancel).await;
+                    searches.lock().unwrap().remove(&key);
+                    sender.respond(id, result);
+                });
+            }
+            "cancel" => {
+                let result = request
+                    .params::<CancelParams>()
+                    .map(|params| json!({ "cancelled": cancel(&searches, &params.id) }));
+                sender.respond(id, result);
+            }
+            method => sender.respond(id, Err(Error::method_not_found(method))),
+        }
+    }
+    // Nothing more will be asked, but what was is still answered
+    drop(sender);
+    writer.await??;
+    Ok(())
+}
+
+/// Cancel the search `id`; whether it was still running.
+fn cancel(searches: &Searches, id: &Value) -> bool {
+    let searches = searches.lock().unwrap();
+    let cancel = searches.get(&id.to_string());
+    cancel.inspect(|cancel| cancel.cancel()).is_some()
+}
+
+/// Run the search `params` asks for, sending what it finds and its progress
+/// as notifications about `id`.
+async fn search(
+    mut config: SearchConfig,
+    params: SearchParams,
+    id: &Value,
+    sender: &jsonrpc::Sender,
+    cancel: &CancellationToken,
+) -> Result<Value, Error> {
+    if let Some(root) = params.root {
+        config.root_path = root;
+    }
+    if let Some(pattern) = params.pattern {
+        config.pattern = pattern;
+    }
+    if let Some(extensions) = params.extensions {
+        config.extensions = Some(extensions);
+    }
+    config.max_depth = params.max_depth.or(config.max_depth);
+    config.show_hidden = params.hidden.unwrap_or(config.show_hidden);
+    config.include_gitignored = params
+        .include_gitignored
+        .unwrap_or(config.include_gitignored);
+    crate::crawl_filters(&config, &Custom::default())
+        .check()
+        .map_err(Error::invalid_params)?;
+    // Stopped when cancelled, or once there are enough results
+    config.crawl.cancel = cancel.child_token();
+    let _stop = config.crawl.cancel.clone().drop_guard();
+    let (tx, mut progress) = broadcast::channel(64);
+    config.crawl.progress = Some(tx);
+    let mut results = search_files(&config).await;
+    drop(config);
+
+    let limit = params.limit.unwrap_or(usize::MAX);
+    let mut batch = Batch::default();
+    let (mut found, mut errors, mut limited) = (0, 0, false);
+    let mut totals = None;
+    let mut tick = tokio::time::interval(EVERY);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(Error::request_cancelled()),
+            item = results.recv() => match item {
+                Some(Ok(_)) if found == limit => {
+                    limited = true;
+                    break;
+                }
+                Some(Ok(entry)) => {
+                    found += 1;
+                    batch.entries.push(json!(entry));
+                }
+                Some(Err(e)) => {
+                    errors += 1;
+                    batch.errors.push(json!({ "message": e.to_string(), "path": e.path() }));
+                }
+                None => break,
+            },
+            Ok(event) = progress.recv() => {
+                if let ProgressEvent::Totals(now) = event {
+                    totals = Some(now);
+                }
+            }
+            _ = tick.tick() => {
+                batch.send(id, sender);
+                send_progress(totals.take(), id, sender);
+            }
+        }
+        if batch.len() >= BATCH {
+            batch.send(id, sender);
+        }
+    }
+    // The totals the crawl got to, which are in the channel by now
+    while let Ok(event) = progress.try_recv() {
+        if let ProgressEvent::Totals(now) = event {
+            totals = Some(now);
+        }
+    }
+    batch.send(id, sender);
+    send_progress(totals, id, sender);
+    Ok(json!({ "found": found, "errors": errors, "limited": limited }))
+}
+
+/// What's been found since the last `search/results`.
+#[derive(Default)]
+struct Batch {
+    entries: Vec<Value>,
+    errors: Vec<Value>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.entries.len() + self.errors.len()
+    }
+
+    /// Send what's in the batch, if anything, and empty it.
+    fn send(&mut self, id: &Value, sender: &jsonrpc::Sender) {
+        if self.len() == 0 {
+            return;
+        }
+        let Batch { entries, errors } = std::mem::take(self);
+        let params = json!({ "id": id, "entries": entries, "errors": errors });
+        sender.notify("search/results", params);
+    }
+}
+
+fn send_progress(totals: Option<Totals>, id: &Value, sender: &jsonrpc::Sender) {
+    if let Some(totals) = totals {
+        let params = json!({
+            "id": id,
+            "dirs": totals.dirs,
+            "entries": totals.entries,
+            "matched": totals.matched,
+            "errors": totals.errors,
+        });
+        sender.notify("search/progress", params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, DuplexStream, Lines, ReadHalf, WriteHalf};
+    use tokio::task::JoinHandle;
+
+    /// The other end of the pipe `serve` answers on.
+    struct Client {
+        input: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        output: WriteHalf<DuplexStream>,
+    }
+
+    impl Client {
+        fn start(config: SearchConfig) -> (Self, JoinHandle<Result<()>>) {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (server_in, server_out) = tokio::io::split(server);
+            let serving = tokio::spawn(serve(config, BufReader::new(server_in), server_out));
+            let (input, output) = tokio::io::split(client);
+            let input = BufReader::new(input).lines();
+            (Client { input, output }, serving)
+        }
+
+        async fn send(&mut self, messages: &[Value]) -> Result<()> {
+            for message in messages {
+                let line = format!("{message}\n");
+                self.output.write_all(line.as_bytes()).await?;
+            }
+            Ok(())
+        }
+
+        /// The notifications sent before the next answer, and the answer.
+        async fn answer(&mut self) -> Result<(Vec<Value>, Value)> {
+            let mut notifications = Vec::new();
+            loop {
+                let line = self.input.next_line().await?.context("no answer")?;
+                let message: Value = serde_json::from_str(&line)?;
+                if message.get("id").is_some() {
+                    return Ok((notifications, message));
+                }
+                notifications.push(message);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_searches_stream_their_results_and_can_be_cancelled() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        std::fs::create_dir(root.join("src"))?;
+        std::fs::write(root.join("src/lib.rs"), "")?;
+        std::fs::write(root.join("src/main.rs"), "")?;
+        for n in 0..1000 {
+            std::fs::write(root.join(format!("{n}.txt")), "")?;
+        }
+
+        let (mut client, serving) = Client::start(SearchConfig::default());
+        client
+            .send(&[json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "search",
+                "params": { "root": root, "extensions": ["rs"] },
+            })])
+            .await?;
+        let (notifications, answer) = client.answer().await?;
+        let (mut paths, mut progress) = (Vec::new(), None);
+        for message in notifications {
+            match message["method"].as_str() {
+                Some("search/results") => {
+                    assert_eq!(message["params"]["id"], 1);
+                    for entry in message["params"]["entries"].as_array().unwrap() {
+                        paths.push(entry["path"].as_str().unwrap().to_string());
+                    }
+                }
+                Some("search/progress") => progress = Some(message["params"].clone()),
+                method => panic!("unexpected notification {method:?}"),
+            }
+        }
+        assert_eq!(answer["id"], 1);
+        assert_eq!(
+            answer["result"],
+            json!({ "found": 2, "errors": 0, "limited": false })
+        );
+        paths.sort_unstable();
+        let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+        assert_eq!(paths, [path("src/lib.rs"), path("src/main.rs")]);
+        assert_eq!(progress.context("no progress")?["matched"], 2);
+
+        // Cancelled before it's got going, it stops and says so
+        client
+            .send(&[
+                json!({ "jsonrpc": "2.0", "id": "two", "method": "search",
+                    "params": { "root": root } }),
+                json!({ "jsonrpc": "2.0", "method": "$/cancelRequest",
+                    "params": { "id": "two" } }),
+            ])
+            .await?;
+        let (_, answer) = client.answer().await?;
+        assert_eq!(answer["id"], "two");
+        assert_eq!(answer["error"]["code"], -32800);
+
+        client
+            .send(&[json!({ "jsonrpc": "2.0", "id": 3, "method": "cancel",
+                "params": { "id": "two" } })])
+            .await?;
+        let (_, answer) = client.answer().await?;
+        assert_eq!(answer["result"]["cancelled"], false);
+
+        client
+            .send(&[json!({ "jsonrpc": "2.0", "id": 4, "method": "search",
+                "params": { "colour": "red" } })])
+            .await?;
+        let (_, answer) = client.answer().await?;
+        assert_eq!(answer["error"]["code"], -32602);
+
+        client.output.shutdown().await?;
+        serving.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_searches_are_limited_batched_cancelled_and_checked() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        for n in 0..1200 {
+            std::fs::write(root.join(format!("{n}.txt")), "")?;
+        }
+        let (mut client, serving) = Client::start(SearchConfig::default());
+
+        // Past its limit a search stops, sending at most a batch at a time
+        client
+            .send(&[json!({ "jsonrpc": "2.0", "id": 1, "method": "search",
+                "params": { "root": root, "limit": 1100 } })])
+            .await?;
+        let (notifications, answer) = client.answer().await?;
+        assert_eq!(
+            answer["result"],
+            json!({ "found": 1100, "errors": 0, "limited": true })
+        );
+        let batches: Vec<usize> = notifications
+            .iter()
+            .filter(|message| message["method"] == "search/results")
+            .map(|message| message["params"]["entries"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(batches.iter().sum::<usize>(), 1100);
+        assert!(batches.len() >= 3, "{batches:?}");
+        assert!(batches.iter().all(|&len| len <= BATCH), "{batches:?}");
+
+        // A search still running is cancelled with `cancel`
+        client
+            .send(&[
+                json!({ "jsonrpc": "2.0", "id": 2, "method": "search",
+                    "params": { "root": root } }),
+                json!({ "jsonrpc": "2.0", "id": 3, "method": "cancel",
+                    "params": { "id": 2 } }),
+            ])
+            .await?;
+        let (_, first) = client.answer().await?;
+        let (_, second) = client.answer().await?;
+        let (cancel, search) = match first["id"] == 3 {
+            true => (first, second),
+            false => (second, first),
+        };
+        assert_eq!(cancel["result"]["cancelled"], true);
+        assert_eq!(search["error"]["code"], -32800);
+
+        // What a search overrides is checked like the flags are
+        client
+            .send(&[json!({ "jsonrpc": "2.0", "id": 4, "method": "search",
+                "params": { "root": root, "pattern": "src/lib" } })])
+            .await?;
+        let (notifications, answer) = client.answer().await?;
+        assert_eq!(answer["error"]["code"], -32602);
+        assert!(notifications.is_empty());
+
+        client.output.shutdown().await?;
+        serving.await??;
+        Ok(())
+    }
+}
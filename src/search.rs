@@ -0,0 +1,904 @@
+//! The library's way into a search: [`SearchBuilder`] says where to look and
+//! what for, and [`Search::run`] walks the tree, or [`Search::iter`] for
+//! callers without a tokio runtime of their own. Built without the `tokio`
+//! feature, only [`Search::visit`] is left, and with `blocking`,
+//! [`Search::iter`] walking on the caller's thread.
+//!
+//! ```no_run
+//! # #[cfg(feature = "tokio")]
+//! # async fn example() {
+//! let search = fs::SearchBuilder::new("/var/log")
+//!     .pattern("error")
+//!     .extensions(["log", "txt"])
+//!     .max_depth(3)
+//!     .build();
+//! let mut results = search.run().await;
+//! while let Some(item) = results.recv().await {
+//!     match item {
+//!         Ok(entry) => println!("{}", entry.path().display()),
+//!         Err(e) => eprintln!("{e}"),
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::crawl::{
+    self, CrawlOptions, Descend, EntryKinds, FileEntry, FileSystem, Filter, Order, ProgressEvent,
+    Rank, Threads,
+};
+use crate::Error;
+use crate::{crawl_filters, SearchConfig};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+#[cfg(feature = "tokio")]
+use {crate::crawl::Results, crate::start_search, futures_core::Stream, tokio::runtime::Runtime};
+
+/// Sets up a [`Search`]. What isn't set is as the command line has it by
+/// default: every file, at any depth, leaving out hidden and gitignored ones.
+#[derive(Debug, Clone)]
+pub struct SearchBuilder {
+    config: SearchConfig,
+    custom: Custom,
+}
+
+impl SearchBuilder {
+    /// A search of the tree under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        SearchBuilder {
+            config: SearchConfig {
+                root_path: root.into(),
+                ..SearchConfig::default()
+            },
+            custom: Custom::default(),
+        }
+    }
+
+    /// Only files whose names contain `pattern`, where `*` matches anything.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.config.pattern = pattern.into();
+        self
+    }
+
+    /// Only files with one of these extensions, compared without case.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.extensions = Some(extensions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Go at most `depth` directories below the root.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.config.max_depth = Some(depth);
+        self
+    }
+
+    /// Whether to include hidden files and look inside hidden directories.
+    pub fn hidden(mut self, show: bool) -> Self {
+        self.config.show_hidden = show;
+        self
+    }
+
+    /// Whether to leave out what .gitignore files say to ignore.
+    pub fn gitignore(mut self, respect: bool) -> Self {
+        self.config.include_gitignored = !respect;
+        self
+    }
+
+    /// How many directories to read at once.
+    pub fn threads(mut self, threads: Threads) -> Self {
+        self.config.crawl.threads = threads;
+        self
+    }
+
+    /// Level by level, or each subtree in turn.
+    pub fn order(mut self, order: Order) -> Self {
+        self.config.crawl.order = order;
+        self
+    }
+
+    /// Stop the search once `token` is cancelled, e.g. from a Ctrl-C
+    /// handler: the walk checks it between directories, and any results
+    /// already found are still delivered. Also stops [`Search::visit`].
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.config.crawl.cancel = token;
+        self
+    }
+
+    /// Report the walk's progress on `tx`, apart from the results: each
+    /// directory entered, each error, and after each directory read, running
+    /// [`crawl::Totals`]. A subscriber that falls behind misses events rather
+    /// than slowing the walk. Counting bytes means reading each result's size
+    /// with the listing, which on Linux is one `statx` per result. Not for
+    /// [`Search::visit`], whose callback sees all of this anyway.
+    pub fn progress(mut self, tx: broadcast::Sender<ProgressEvent>) -> Self {
+        self.config.crawl.progress = Some(tx);
+        self
+    }
+
+    /// Everything else about the walk, as the command line's crawl flags set
+    /// it. Replaces what [`SearchBuilder::threads`],
+    /// [`SearchBuilder::order`], [`SearchBuilder::cancel_token`] and
+    /// [`SearchBuilder::progress`] set.
+    pub fn crawl(mut self, options: CrawlOptions) -> Self {
+        self.config.crawl = options;
+        self
+    }
+
+    /// Only files `filter` matches, and only directories it descends into,
+    /// on top of everything else. Each filter added must agree.
+    pub fn with_filter(mut self, filter: Box<dyn Filter>) -> Self {
+        self.custom.filters.push(filter.into());
+        self
+    }
+
+    /// Decide on each directory before it's read: `hook` is handed every one
+    /// the walk would otherwise enter, and [`Descend::Skip`] leaves out
+    /// everything under it, e.g. what an earlier snapshot says is unchanged.
+    /// Like a filter's, it's called on the crawl's blocking threads.
+    pub fn on_enter_dir(
+        self,
+        hook: impl Fn(&FileEntry) -> Descend + Send + Sync + 'static,
+    ) -> Self {
+        self.with_filter(Box::new(EnterDir(hook)))
+    }
+
+    /// Read the tree from `fs` instead of the real file system, e.g. a
+    /// [`crate::MemFs`] in tests. `--backend` doesn't apply: it's read as the
+    /// `blocking` backend reads a real disk.
+    pub fn file_system(mut self, fs: impl FileSystem + 'static) -> Self {
+        self.custom.fs = Some(Arc::new(fs));
+        self
+    }
+
+    /// Which kinds of entries are results, e.g. [`EntryKinds::ALL`] for a
+    /// tree view. By default, everything but directories, as the command line
+    /// lists. Directories are matched by name like files, and entered either
+    /// way; [`Search::visit`] still hands over each one it enters just once.
+    pub fn emit(mut self, kinds: EntryKinds) -> Self {
+        self.custom.emit = kinds;
+        self
+    }
+
+    /// Read directories, and send each one's results, in `rank`'s order
+    /// instead of [`SearchBuilder::order`]'s: a comparator closure, or a
+    /// [`crate::ByKey`] priority. Keeping one order means reading one
+    /// directory at a time, so `--threads` and `--max-frontier` don't apply.
+    pub fn rank(mut self, rank: impl Rank + 'static) -> Self {
+        self.custom.rank = Some(Arc::new(rank));
+        self
+    }
+
+    pub fn build(self) -> Search {
+        Search {
+            config: self.config,
+            custom: self.custom,
+        }
+    }
+}
+
+/// [`SearchBuilder::on_enter_dir`]'s hook, as a filter that keeps every file.
+struct EnterDir<F>(F);
+
+impl<F: Fn(&FileEntry) -> Descend + Send + Sync> Filter for EnterDir<F> {
+    fn matches(&self, _: &FileEntry) -> bool {
+        true
+    }
+
+    fn should_descend(&self, dir: &FileEntry) -> bool {
+        (self.0)(dir) == Descend::Yes
+    }
+}
+
+/// What a library caller sets up that the command line can't.
+#[derive(Clone, Default)]
+pub(crate) struct Custom {
+    /// Added with [`SearchBuilder::with_filter`]
+    pub filters: Vec<Arc<dyn Filter>>,
+    pub rank: Option<Arc<dyn Rank>>,
+    pub emit: EntryKinds,
+    /// The file system to read instead of the real one
+    pub fs: Option<Arc<dyn FileSystem>>,
+}
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Custom")
+            .field("filters", &self.filters.len())
+            .field("rank", &self.rank.is_some())
+            .field("emit", &self.emit)
+            .field("fs", &self.fs)
+            .finish()
+    }
+}
+
+/// A search, ready to run as many times as wanted.
+#[derive(Debug, Clone)]
+pub struct Search {
+    config: SearchConfig,
+    custom: Custom,
+}
+
+impl Search {
+    /// A search set up by the command line's flags.
+    pub fn new(config: SearchConfig) -> Self {
+        Search {
+            config,
+            custom: Custom::default(),
+        }
+    }
+
+    /// A search described by a config document, in whatever format
+    /// `document` deserializes (JSON, TOML, ...): see [`SearchConfig`].
+    ///
+    /// ```
+    /// let document = r#"{ "root-path": "src", "extensions": ["rs"], "crawl": { "threads": 4 } }"#;
+    /// let search = fs::Search::from_config(&mut serde_json::Deserializer::from_str(document))?;
+    /// # Ok::<_, serde_json::Error>(())
+    /// ```
+    pub fn from_config<'de, D: Deserializer<'de>>(document: D) -> Result<Self, D::Error> {
+        SearchConfig::deserialize(document).map(Search::new)
+    }
+
+    pub fn builder(root: impl Into<PathBuf>) -> SearchBuilder {
+        SearchBuilder::new(root)
+    }
+
+    /// Start walking the tree on the current tokio runtime. Results arrive as
+    /// they're found; dropping the receiver stops the walk.
+    #[cfg(feature = "tokio")]
+    pub async fn run(&self) -> Results {
+        start_search(&self.config, &self.custom)
+    }
+
+    /// [`Search::run`], as a [`Stream`] of results for the combinators of
+    /// `futures` or `tokio-stream`.
+    #[cfg(feature = "tokio")]
+    pub async fn stream(&self) -> impl Stream<Item = Result<FileEntry, Error>> + Unpin {
+        self.run().await
+    }
+
+    /// Walk the tree on a runtime of its own, handing results over as a
+    /// plain iterator, for callers that don't use tokio. Not for async code:
+    /// waiting for the next result blocks the thread.
+    #[cfg(feature = "tokio")]
+    pub fn iter(&self) -> std::io::Result<Iter> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let results = {
+            let _entered = runtime.enter();
+            start_search(&self.config, &self.custom)
+        };
+        Ok(Iter {
+            results,
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Walk the tree lazily on the caller's thread, reading the next
+    /// directory whenever the last one's results run out. Everything the
+    /// filters allow is entered, one directory at a time whatever
+    /// `--threads` says; dropping the iterator stops the walk. Never fails:
+    /// the `Result` is for the same signature as with `tokio`.
+    #[cfg(all(feature = "blocking", not(feature = "tokio")))]
+    pub fn iter(&self) -> std::io::Result<Iter> {
+        let filters = crawl_filters(&self.config, &self.custom);
+        let root = self.config.root_path.clone();
+        Ok(Iter(crawl::Entries::new(
+            root,
+            filters,
+            self.config.crawl.clone(),
+        )))
+    }
+
+    /// Walk the tree on this thread, calling `f` with each matching file,
+    /// each directory before it's read, and each error, with no channel or
+    /// runtime in between. `f` continues with [`Descend::Yes`], leaves out a
+    /// directory's contents with [`Descend::Skip`] (meaningless for a file),
+    /// or stops the walk by breaking, whose value is returned. `--threads`
+    /// doesn't apply: everything happens in `f`'s own time.
+    pub fn visit<B>(
+        &self,
+        f: impl FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend>,
+    ) -> Option<B> {
+        let filters = crawl_filters(&self.config, &self.custom);
+        let root = self.config.root_path.clone();
+        crawl::visit(root, &filters, &self.config.crawl, f)
+    }
+
+    /// [`Search::visit`] on as many threads as [`SearchBuilder::threads`]
+    /// says (one per core for `Auto`), this one among them, each calling a
+    /// callback of its own made by `new_visitor`, as `ignore`'s `WalkParallel`
+    /// does: per-file work like hashing or parsing then spreads over the
+    /// workers instead of waiting on one consumer. A directory's entries all
+    /// go to the callback of the worker that read it. Breaking stops every
+    /// worker, and what the first to break broke with is returned. With a
+    /// custom rank, only each directory's results are in its order.
+    ///
+    /// ```no_run
+    /// use std::ops::ControlFlow;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let bytes = AtomicU64::new(0);
+    /// let search = fs::SearchBuilder::new("/srv").build();
+    /// search.visit_parallel(|| {
+    ///     let bytes = &bytes;
+    ///     move |item: Result<fs::FileEntry, fs::Error>| {
+    ///         if let Ok(entry) = item {
+    ///             bytes.fetch_add(entry.size().unwrap_or(0), Ordering::Relaxed);
+    ///         }
+    ///         ControlFlow::<(), _>::Continue(fs::Descend::Yes)
+    ///     }
+    /// });
+    /// ```
+    pub fn visit_parallel<B, F>(&self, new_visitor: impl FnMut() -> F) -> Option<B>
+    where
+        B: Send,
+        F: FnMut(Result<FileEntry, Error>) -> ControlFlow<B, Descend> + Send,
+    {
+        let filters = crawl_filters(&self.config, &self.custom);
+        let root = self.config.root_path.clone();
+        crawl::visit_parallel(root, &filters, &self.config.crawl, new_visitor)
+    }
+}
+
+/// The results of [`Search::iter`]. Dropping it stops the walk.
+#[cfg(feature = "tokio")]
+pub struct Iter {
+    results: Results,
+    runtime: Option<Runtime>,
+}
+
+#[cfg(feature = "tokio")]
+impl Iterator for Iter {
+    type Item = Result<FileEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.results.blocking_recv()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for Iter {
+    fn drop(&mut self) {
+        // Without waiting for a read that may be stuck on a dead mount
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// The results of [`Search::iter`]. Dropping it stops the walk.
+#[cfg(all(feature = "blocking", not(feature = "tokio")))]
+pub struct Iter(crawl::Entries);
+
+#[cfg(all(feature = "blocking", not(feature = "tokio")))]
+impl Iterator for Iter {
+    type Item = Result<FileEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::crawl::{Backend, ByKey, EntryKind, MemFs};
+    use std::panic::AssertUnwindSafe;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_builder_sets_up_the_search() {
+        let mut tree = MemFs::new();
+        for path in ["/t/a.rs", "/t/b.txt", "/t/.hidden.rs", "/t/deep/er/c.rs"] {
+            tree = tree.with_file(path, "x");
+        }
+
+        let search = Search::builder("/t")
+            .file_system(tree)
+            .extensions(["RS"])
+            .max_depth(1);
+        let found = |search: SearchBuilder| async move {
+            let mut results = search.build().run().await;
+            let mut names = Vec::new();
+            while let Some(item) = results.recv().await {
+                let entry = item.unwrap();
+                names.push(entry.path().file_name().unwrap().to_owned());
+            }
+            names.sort();
+            names
+        };
+        assert_eq!(found(search.clone()).await, ["a.rs"]);
+        assert_eq!(
+            found(search.hidden(true).max_depth(2)).await,
+            [".hidden.rs", "a.rs", "c.rs"]
+        );
+    }
+
+    #[test]
+    fn test_iter_without_a_runtime() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for i in 0..300 {
+            std::fs::write(tmp.path().join(format!("{i}.txt")), "x")?;
+        }
+        let search = Search::builder(tmp.path())
+            .threads(Threads::Fixed(2))
+            .build();
+        let found: Vec<FileEntry> = search.iter()?.collect::<Result<_, Error>>()?;
+        assert_eq!(found.len(), 300);
+        // Stopping early just drops the rest
+        assert_eq!(search.iter()?.take(5).count(), 5);
+        Ok(())
+    }
+
+    /// A filter that keeps even-sized files and stays out of `skip` folders
+    struct EvenSizes;
+
+    impl Filter for EvenSizes {
+        fn matches(&self, entry: &FileEntry) -> bool {
+            entry.size().is_ok_and(|size| size % 2 == 0)
+        }
+
+        fn should_descend(&self, dir: &FileEntry) -> bool {
+            dir.path().file_name() != Some("skip".as_ref())
+        }
+    }
+
+    #[test]
+    fn test_custom_filters_prune_the_walk() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for (path, contents) in [
+            ("a.txt", "ab"),
+            ("b.txt", "abc"),
+            ("sub/c.txt", "abcd"),
+            ("sub/d.rs", "ab"),
+            ("sub/skip/e.txt", "ab"),
+        ] {
+            let path = tmp.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, contents)?;
+        }
+
+        for backend in [Backend::Tokio, Backend::Blocking] {
+            let options = CrawlOptions {
+                backend,
+                ..CrawlOptions::default()
+            };
+            let search = Search::builder(tmp.path())
+                .crawl(options)
+                .with_filter(Box::new(EvenSizes))
+                .with_filter(Box::new(|entry: &FileEntry| {
+                    entry.path().extension() == Some("txt".as_ref())
+                }))
+                .build();
+            let mut found: Vec<PathBuf> = search
+                .iter()?
+                .map(|item| Ok(item?.path().strip_prefix(tmp.path())?.to_owned()))
+                .collect::<anyhow::Result<_>>()?;
+            found.sort();
+            assert_eq!(found, [PathBuf::from("a.txt"), PathBuf::from("sub/c.txt")]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_visit_skips_and_stops() {
+        let mut tree = MemFs::new();
+        for path in [
+            "a.txt",
+            "keep/b.txt",
+            "keep/deeper/c.txt",
+            "skip/d.txt",
+            "z.txt",
+        ] {
+            tree = tree.with_file(Path::new("/t").join(path), "x");
+        }
+        let options = CrawlOptions {
+            order: Order::Dfs,
+            ..CrawlOptions::default()
+        };
+        let search = Search::builder("/t")
+            .file_system(tree)
+            .crawl(options)
+            .build();
+
+        let mut seen = Vec::new();
+        let stopped = search.visit(|item| {
+            let entry = item.unwrap();
+            let path = entry.path().strip_prefix("/t").unwrap().to_owned();
+            if path.ends_with("c.txt") {
+                return ControlFlow::Break(path);
+            }
+            seen.push(path);
+            if entry.path().ends_with("skip") {
+                ControlFlow::Continue(Descend::Skip)
+            } else {
+                ControlFlow::Continue(Descend::Yes)
+            }
+        });
+        assert_eq!(stopped, Some(PathBuf::from("keep/deeper/c.txt")));
+        // Each directory is seen before it's read, and skip's never is
+        seen.sort();
+        let expected = [
+            "a.txt",
+            "keep",
+            "keep/b.txt",
+            "keep/deeper",
+            "skip",
+            "z.txt",
+        ];
+        assert_eq!(seen, expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_visit_parallel_gives_each_worker_a_visitor() {
+        let tree = MemFs::new().with_file("/t/skip/hidden.txt", "x");
+        let mut expected = Vec::new();
+        for dir in 0..20 {
+            for file in 0..5 {
+                let path = PathBuf::from(format!("d{dir}/f{file}.txt"));
+                tree.write(Path::new("/t").join(&path), "x");
+                expected.push(path);
+            }
+            expected.push(PathBuf::from(format!("d{dir}")));
+        }
+        expected.push(PathBuf::from("skip"));
+        let options = CrawlOptions {
+            threads: Threads::Fixed(4),
+            ..CrawlOptions::default()
+        };
+        let search = Search::builder("/t")
+            .file_system(tree)
+            .crawl(options)
+            .build();
+
+        let (visitors, seen) = (Mutex::new(0), Mutex::new(Vec::new()));
+        let stopped = search.visit_parallel(|| {
+            *visitors.lock().unwrap() += 1;
+            |item: Result<FileEntry, Error>| {
+                let entry = item.unwrap();
+                let path = entry.path().strip_prefix("/t").unwrap().to_owned();
+                let skip = path.ends_with("skip");
+                seen.lock().unwrap().push(path);
+                ControlFlow::<(), _>::Continue(if skip { Descend::Skip } else { Descend::Yes })
+            }
+        });
+        assert_eq!(stopped, None);
+        assert_eq!(visitors.into_inner().unwrap(), 4);
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        // One visitor breaking stops them all, and says why
+        let stopped = search.visit_parallel(|| {
+            |item: Result<FileEntry, Error>| match item {
+                Ok(entry) if entry.path().ends_with("d7/f3.txt") => ControlFlow::Break(7),
+                _ => ControlFlow::Continue(Descend::Yes),
+            }
+        });
+        assert_eq!(stopped, Some(7));
+
+        // And one panicking, once they have
+        let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            search.visit_parallel(|| {
+                |item: Result<FileEntry, Error>| match item {
+                    Ok(entry) if entry.path().ends_with("d7/f3.txt") => panic!("bad file"),
+                    _ => ControlFlow::<(), _>::Continue(Descend::Yes),
+                }
+            })
+        }));
+        assert!(panicked.is_err());
+    }
+
+    #[test]
+    fn test_rank_orders_the_walk_and_its_results() -> anyhow::Result<()> {
+        let mut tree = MemFs::new();
+        for path in ["a/1.txt", "b/2.txt", "c/3.txt", "x.txt", "y.txt"] {
+            tree = tree.with_file(Path::new("/t").join(path), "x");
+        }
+        let names = |entries: Vec<FileEntry>| -> Vec<String> {
+            let name =
+                |entry: FileEntry| entry.path().file_name().unwrap().to_string_lossy().into();
+            entries.into_iter().map(name).collect()
+        };
+
+        // Last name first, however many threads were asked for
+        let search = Search::builder("/t")
+            .file_system(tree)
+            .threads(Threads::Fixed(4))
+            .rank(|a: &FileEntry, b: &FileEntry| b.path().cmp(a.path()));
+        let found = search.clone().build().iter()?.collect::<Result<_, _>>()?;
+        assert_eq!(names(found), ["y.txt", "x.txt", "3.txt", "2.txt", "1.txt"]);
+
+        // The same as a priority, for the directories visit hands over too
+        let by_name = ByKey(|entry: &FileEntry| entry.path().to_owned());
+        let mut seen = Vec::new();
+        search.rank(by_name).build().visit(|item| {
+            seen.push(item.unwrap());
+            ControlFlow::<(), _>::Continue(Descend::Yes)
+        });
+        let expected = ["y.txt", "x.txt", "c", "b", "a", "3.txt", "2.txt", "1.txt"];
+        assert_eq!(names(seen), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_emit_chooses_the_kinds_of_entries() -> anyhow::Result<()> {
+        let tree = MemFs::new()
+            .with_file("/t/a.txt", "x")
+            .with_file("/t/sub/b.txt", "x");
+        tree.symlink("sub", "/t/link");
+        let search = Search::builder("/t").file_system(tree);
+        let found = |kinds| -> anyhow::Result<Vec<(String, EntryKind)>> {
+            let mut found = Vec::new();
+            for item in search.clone().emit(kinds).build().iter()? {
+                let entry = item?;
+                let name = entry
+                    .path()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                found.push((name, entry.kind()?));
+            }
+            found.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(found)
+        };
+        let kinds = |found: Vec<(String, EntryKind)>| found.into_iter().map(|(_, kind)| kind);
+        let names = |found: Vec<(String, EntryKind)>| -> Vec<String> {
+            found.into_iter().map(|(name, _)| name).collect()
+        };
+
+        // Everything but directories by default, as the command line lists
+        assert_eq!(
+            names(found(EntryKinds::default())?),
+            ["a.txt", "b.txt", "link"]
+        );
+        let all = found(EntryKinds::ALL)?;
+        assert!(kinds(all.clone()).eq([
+            EntryKind::File,
+            EntryKind::File,
+            EntryKind::Symlink,
+            EntryKind::Dir
+        ]));
+        assert_eq!(names(all), ["a.txt", "b.txt", "link", "sub"]);
+        // Directories are still entered when they aren't results
+        assert_eq!(names(found(EntryKinds::FILES)?), ["a.txt", "b.txt"]);
+        assert_eq!(names(found(EntryKinds::DIRS)?), ["sub"]);
+
+        // visit hands over each directory once, before reading it
+        let mut seen = Vec::new();
+        search.emit(EntryKinds::ALL).build().visit(|item| {
+            seen.push(item.unwrap().into_path());
+            ControlFlow::<(), _>::Continue(Descend::Yes)
+        });
+        assert_eq!(seen.iter().filter(|path| path.ends_with("sub")).count(), 1);
+        assert_eq!(seen.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_enter_dir_prunes_before_reading() -> anyhow::Result<()> {
+        let mut tree = MemFs::new();
+        for path in ["a.txt", "src/b.txt", "src/cache/c.txt", "cache/d.txt"] {
+            tree = tree.with_file(Path::new("/t").join(path), "x");
+        }
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook = {
+            let asked = Arc::clone(&asked);
+            move |dir: &FileEntry| {
+                asked
+                    .lock()
+                    .unwrap()
+                    .push((dir.path().to_owned(), dir.depth()));
+                if dir.path().ends_with("cache") {
+                    Descend::Skip
+                } else {
+                    Descend::Yes
+                }
+            }
+        };
+        let search = Search::builder("/t").file_system(tree).on_enter_dir(hook);
+        let mut found: Vec<PathBuf> = search
+            .build()
+            .iter()?
+            .map(|item| Ok(item?.into_path()))
+            .collect::<anyhow::Result<_>>()?;
+        found.sort();
+        assert_eq!(found, ["/t/a.txt", "/t/src/b.txt"].map(PathBuf::from));
+
+        let mut asked = asked.lock().unwrap().clone();
+        asked.sort();
+        let expected = [("/t/cache", 1), ("/t/src", 1), ("/t/src/cache", 2)];
+        assert_eq!(
+            asked,
+            expected.map(|(path, depth)| (PathBuf::from(path), depth))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_a_cancelled_token_stops_the_search() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::write(tmp.path().join("a.txt"), "x")?;
+        let cancel = CancellationToken::new();
+        let search = Search::builder(tmp.path())
+            .cancel_token(cancel.clone())
+            .build();
+        assert!(search.run().await.recv().await.is_some());
+
+        cancel.cancel();
+        assert!(search.run().await.recv().await.is_none());
+        let mut visited = 0;
+        search.visit(|_| {
+            visited += 1;
+            ControlFlow::<(), _>::Continue(Descend::Yes)
+        });
+        assert_eq!(visited, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_on_the_side() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for (path, contents) in [("a.txt", "ab"), ("b.rs", "abc"), ("sub/c.txt", "abcd")] {
+            let path = tmp.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, contents)?;
+        }
+
+        for backend in [Backend::Tokio, Backend::Blocking] {
+            let (tx, mut rx) = broadcast::channel(64);
+            let options = CrawlOptions {
+                backend,
+                ..CrawlOptions::default()
+            };
+            let search = Search::builder(tmp.path())
+                .extensions(["txt"])
+                .crawl(options)
+                .progress(tx)
+                .build();
+            let mut results = search.run().await;
+            while results.recv().await.is_some() {}
+
+            let (mut entered, mut last) = (Vec::new(), None);
+            while let Ok(event) = rx.try_recv() {
+                match event {
+                    ProgressEvent::DirEntered { path, depth } => entered.push((path, depth)),
+                    ProgressEvent::Totals(totals) => last = Some(totals),
+                    ProgressEvent::Error { message } => panic!("{message}"),
+                }
+            }
+            assert_eq!(
+                entered,
+                [(tmp.path().into(), 0), (tmp.path().join("sub").into(), 1)]
+            );
+            let last = last.unwrap();
+            assert_eq!((last.dirs, last.entries, last.matched), (2, 4, 2));
+            if cfg!(target_os = "linux") {
+                assert_eq!(last.bytes, 6);
+            }
+        }
+
+        // What can't be read is reported both ways
+        let (tx, mut rx) = broadcast::channel(64);
+        let search = Search::builder(tmp.path().join("missing"))
+            .progress(tx)
+            .build();
+        assert!(search.run().await.recv().await.unwrap().is_err());
+        let errors = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|event| matches!(event, ProgressEvent::Error { .. }))
+            .count();
+        assert_eq!(errors, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_a_config_document() -> anyhow::Result<()> {
+        let document = r#"{
+            "root-path": "/srv",
+            "pattern": "report*",
+            "extensions": ["pdf"],
+            "show-hidden": true,
+            "crawl": { "threads": 8, "order": "dfs", "throttle": "10MB/s" }
+        }"#;
+        let search = Search::from_config(&mut serde_json::Deserializer::from_str(document))?;
+        let config = &search.config;
+        assert_eq!(config.root_path, Path::new("/srv"));
+        assert_eq!(config.pattern, "report*");
+        assert_eq!(config.extensions, Some(vec!["pdf".into()]));
+        assert!(config.show_hidden && !config.include_gitignored);
+        assert_eq!(config.crawl.threads, Threads::Fixed(8));
+        assert_eq!(config.crawl.order, Order::Dfs);
+        assert_eq!(
+            config.crawl.throttle,
+            Some(crawl::Rate::Bytes((10 << 20) as f64))
+        );
+        // What isn't given is as the flags have it
+        assert_eq!(config.max_depth, None);
+        assert_eq!(config.crawl.channel_capacity, 100);
+
+        let config = |document: &str| {
+            let search = Search::from_config(&mut serde_json::Deserializer::from_str(document));
+            search.map(|search| search.config)
+        };
+        assert_eq!(
+            config(r#"{ "crawl": { "threads": "auto" } }"#)?
+                .crawl
+                .threads,
+            Threads::Auto
+        );
+        assert_eq!(config("{}")?.root_path, Path::new("."));
+        // A misspelt setting is an error, not a default
+        let typo = config(r#"{ "show_hidden": true }"#).unwrap_err();
+        assert!(typo.to_string().contains("show_hidden"), "{typo}");
+        let invalid = config(r#"{ "crawl": { "threads": "lots" } }"#).unwrap_err();
+        assert!(invalid.to_string().contains("'lots'"), "{invalid}");
+        // Checked as the flag is, rather than left to panic making the channel
+        let empty = config(r#"{ "crawl": { "channel-capacity": 0 } }"#).unwrap_err();
+        assert!(empty.to_string().contains("at least 1"), "{empty}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_say_what_and_where() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("a.txt");
+        std::fs::write(&file, "x")?;
+        std::fs::write(tmp.path().join(".gitignore"), "[unclosed\n")?;
+        let errors = |search: SearchBuilder| -> anyhow::Result<Vec<Error>> {
+            Ok(search.build().iter()?.filter_map(Result::err).collect())
+        };
+
+        let missing = tmp.path().join("missing");
+        let found = errors(Search::builder(&missing))?;
+        assert!(matches!(
+            &found[..],
+            [Error::Io { path, source }]
+                if *path == missing && source.kind() == std::io::ErrorKind::NotFound
+        ));
+        let found = errors(Search::builder(&file))?;
+        assert!(matches!(&found[..], [Error::NotADirectory { path }] if *path == file));
+        let found = errors(Search::builder(tmp.path()).pattern("sub/a"))?;
+        assert!(
+            matches!(&found[..], [Error::PatternInvalid { pattern, .. }] if pattern == "sub/a")
+        );
+        let found = errors(Search::builder(tmp.path()))?;
+        assert!(matches!(&found[..], [Error::IgnoreParse { .. }]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_results_as_a_stream() -> std::io::Result<()> {
+        use futures_util::StreamExt;
+
+        let tmp = tempfile::tempdir()?;
+        for i in 0..10 {
+            std::fs::write(tmp.path().join(format!("{i}.txt")), "x")?;
+        }
+        std::fs::write(tmp.path().join("skip.rs"), "x")?;
+
+        let stream = Search::builder(tmp.path()).build().stream().await;
+        let found: Vec<PathBuf> = stream
+            .filter_map(|item| async move { item.ok() })
+            .filter(|entry| std::future::ready(entry.path().extension() == Some("txt".as_ref())))
+            .map(FileEntry::into_path)
+            .take(4)
+            .collect()
+            .await;
+        assert_eq!(found.len(), 4);
+        assert!(found.iter().all(|path| path.starts_with(tmp.path())));
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! The `fs.v1.Search` gRPC service, as proto/fs.proto declares it, for
+//! callers in other languages: one server-streaming `Search` call mirroring
+//! [`SearchBuilder`](crate::SearchBuilder), sending what's found as it's
+//! found. A search runs for as long as its call, and a client that cancels
+//! or hangs up stops it, as with the HTTP API.
+//!
+//! The messages are declared here by hand rather than generated, so building
+//! needs no `protoc`; they must match the .proto.
+
+use super::{Running, SearchQuery, Served};
+use anyhow::Result;
+use axum::body::HttpBody;
+use axum::http::uri::PathAndQuery;
+use axum::BoxError;
+use futures_core::Stream;
+use std::convert::Infallible;
+use std::future::{Future, Ready};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tonic::server::ServerStreamingService;
+use tonic::transport::Channel;
+use tonic::{Status, Streaming};
+use tonic_prost::ProstCodec;
+use tower_service::Service;
+
+/// The one method's path.
+pub(super) const PATH: &str = "/fs.v1.Search/Search";
+
+/// What to search for; whatever's left unset is as `fs serve` was started.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SearchRequest {
+    /// Only names containing this, where `*` matches anything
+    #[prost(string, tag = "1")]
+    pub pattern: String,
+    /// Only files with one of these extensions, compared without case
+    #[prost(string, repeated, tag = "2")]
+    pub extensions: Vec<String>,
+    /// A directory below the root to search instead of all of it
+    #[prost(string, tag = "3")]
+    pub dir: String,
+    #[prost(uint32, optional, tag = "4")]
+    pub max_depth: Option<u32>,
+    #[prost(bool, optional, tag = "5")]
+    pub hidden: Option<bool>,
+    #[prost(bool, optional, tag = "6")]
+    pub gitignored: Option<bool>,
+    /// Send at most this many results
+    #[prost(uint64, optional, tag = "7")]
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SearchResult {
+    #[prost(oneof = "search_result::Item", tags = "1, 2")]
+    pub item: Option<search_result::Item>,
+}
+
+pub mod search_result {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Item {
+        #[prost(message, tag = "1")]
+        Entry(super::Entry),
+        #[prost(message, tag = "2")]
+        Error(super::SearchError),
+    }
+}
+
+/// Something the search found, as `fs --format json` prints it.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Entry {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    /// "file", "dir", "symlink" or "other"; empty if it couldn't be read
+    #[prost(string, tag = "2")]
+    pub file_type: String,
+    #[prost(uint32, tag = "3")]
+    pub depth: u32,
+    #[prost(uint64, optional, tag = "4")]
+    pub size: Option<u64>,
+    /// Nanoseconds since the Unix epoch
+    #[prost(int64, optional, tag = "5")]
+    pub mtime_ns: Option<i64>,
+}
+
+/// Something the search couldn't read; it carries on past it.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SearchError {
+    #[prost(string, tag = "1")]
+    pub message: String,
+    /// The file or directory it's about, if any
+    #[prost(string, tag = "2")]
+    pub path: String,
+}
+
+/// A client of the service, for Rust callers.
+#[derive(Debug, Clone)]
+pub struct Client(tonic::client::Grpc<Channel>);
+
+impl Client {
+    /// Connect to the `fs serve` at `uri`, like `http://127.0.0.1:7878`.
+    pub async fn connect(uri: &str) -> Result<Self> {
+        let channel = Channel::from_shared(uri.to_string())?.connect().await?;
+        Ok(Client::new(channel))
+    }
+
+    /// A client on a channel set up by the caller, with TLS or timeouts, say.
+    pub fn new(channel: Channel) -> Self {
+        Client(tonic::client::Grpc::new(channel))
+    }
+
+    /// What `request` finds, as it's found. Dropping the stream stops the
+    /// search.
+    pub async fn search(
+        &mut self,
+        request: SearchRequest,
+    ) -> Result<Streaming<SearchResult>, Status> {
+        self.0
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let path = PathAndQuery::from_static(PATH);
+        let request = tonic::Request::new(request);
+        let response = self
+            .0
+            .server_streaming(request, path, ProstCodec::default())
+            .await?;
+        Ok(response.into_inner())
+    }
+}
+
+/// The service, as the HTTP server routes calls to it.
+#[derive(Clone)]
+pub(super) struct SearchService(pub(super) Arc<Served>);
+
+impl<B> Service<axum::http::Request<B>> for SearchService
+where
+    B: HttpBody + Send + 'static,
+    B::Error: Into<BoxError> + Send,
+{
+    type Response = axum::http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: axum::http::Request<B>) -> Self::Future {
+        let search = Search(Arc::clone(&self.0));
+        Box::pin(async move {
+            let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+            Ok(grpc.server_streaming(search, request).await)
+        })
+    }
+}
+
+struct Search(Arc<Served>);
+
+impl ServerStreamingService<SearchRequest> for Search {
+    type Response = SearchResult;
+    type ResponseStream = Results;
+    type Future = Ready<Result<tonic::Response<Results>, Status>>;
+
+    fn call(&mut self, request: tonic::Request<SearchRequest>) -> Self::Future {
+        let request = request.into_inner();
+        let query = SearchQuery {
+            pattern: Some(request.pattern).filter(|p| !p.is_empty()),
+            ext: Some(request.extensions.join(",")).filter(|e| !e.is_empty()),
+            dir: Some(PathBuf::from(request.dir)).filter(|d| !d.as_os_str().is_empty()),
+            max_depth: request.max_depth.map(|depth| depth as usize),
+            hidden: request.hidden,
+            gitignored: request.gitignored,
+            limit: request
+                .limit
+                .map(|limit| limit.try_into().unwrap_or(usize::MAX)),
+        };
+        let started = self.0.start(query).map_err(Status::invalid_argument);
+        std::future::ready(started.map(|running| tonic::Response::new(Results(running))))
+    }
+}
+
+/// A search's results as messages.
+struct Results(Running);
+
+impl Stream for Results {
+    type Item = Result<SearchResult, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = match ready!(Pin::new(&mut self.0).poll_next(cx)) {
+            Some(Ok(entry)) => search_result::Item::Entry(Entry {
+                path: entry.path().to_string_lossy().into_owned(),
+                file_type: entry.type_name().unwrap_or_default().to_string(),
+                depth: entry.depth().try_into().unwrap_or(u32::MAX),
+                size: entry.size().ok(),
+                mtime_ns: entry.modified().ok().map(crate::unix_ns),
+            }),
+            Some(Err(e)) => search_result::Item::Error(SearchError {
+                message: e.to_string(),
+                path: e
+                    .path()
+                    .map_or_else(String::new, |p| p.to_string_lossy().into_owned()),
+            }),
+            None => return Poll::Ready(None),
+        };
+        Poll::Ready(Some(Ok(SearchResult { item: Some(item) })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchConfig;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_a_search_streams_and_stops_when_the_client_does() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        std::fs::create_dir(root.join("src"))?;
+        std::fs::write(root.join("src/lib.rs"), "")?;
+        std::fs::write(root.join("README.md"), "# tree\n")?;
+        for n in 0..5000 {
+            std::fs::write(root.join(format!("src/{n:0200}.txt")), "")?;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let served = Served::new(SearchConfig {
+            root_path: root.clone(),
+            ..SearchConfig::default()
+        });
+        let app = super::super::router(Arc::clone(&served));
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+        // A window small enough that the server can't send everything
+        // before the client reads it
+        let channel = Channel::from_shared(format!("http://{addr}"))?
+            .initial_stream_window_size(1 << 16)
+            .initial_connection_window_size(1 << 16)
+            .connect()
+            .await?;
+        let mut client = Client::new(channel);
+
+        let request = SearchRequest {
+            extensions: vec!["rs".into()],
+            ..SearchRequest::default()
+        };
+        let mut results = client.search(request).await?;
+        let Some(search_result::Item::Entry(entry)) = results.message().await?.unwrap().item else {
+            panic!("not an entry");
+        };
+        assert_eq!(entry.path, root.join("src/lib.rs").to_string_lossy());
+        assert_eq!((entry.file_type.as_str(), entry.depth), ("file", 2));
+        assert_eq!(entry.size, Some(0));
+        assert!(results.message().await?.is_none());
+
+        let outside = SearchRequest {
+            dir: "..".into(),
+            ..SearchRequest::default()
+        };
+        let status = client.search(outside).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // Hanging up partway through stops the search
+        let mut results = client.search(SearchRequest::default()).await?;
+        assert!(results.message().await?.is_some());
+        drop(results);
+        let stopped = async {
+            while served.running.load(Ordering::Relaxed) > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), stopped).await?;
+        assert!(served.entries.load(Ordering::Relaxed) < 1 + 5002);
+        server.abort();
+        Ok(())
+    }
+}
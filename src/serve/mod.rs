@@ -0,0 +1,371 @@
+//! `fs serve`: searches over HTTP, for dashboards and services that would
+//! otherwise shell out to `fs`. The tree is the one given on the command line,
+//! with its flags as the defaults each request starts from:
+//!
+//! - `GET /search?pattern=*.log&ext=log,txt&dir=var&max_depth=3&hidden=true
+//!   &gitignored=true&limit=100` streams what's found as it's found, one JSON
+//!   object per line, as `--format json` prints them, with `{"error": ...}`
+//!   lines for what couldn't be read. Every parameter is optional; `dir` is a
+//!   directory below the root to search instead of all of it. A client that
+//!   hangs up stops its search.
+//! - `GET /stats` says what the server has done since it started.
+//!
+//! Built with the `grpc` feature, the same port answers the `fs.v1.Search`
+//! service too, over HTTP/2; see [`grpc`].
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+use crate::crawl::{FileEntry, Results};
+use crate::search::Custom;
+use crate::{start_search, SearchConfig};
+use anyhow::{Context, Result};
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use clap::Args;
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// How much of the results is sent at once, at most, when they come quickly.
+const CHUNK: usize = 64 * 1024;
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The tree to serve, and the defaults for each request's search
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// The address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7878")]
+    pub listen: SocketAddr,
+}
+
+/// What a request to `/search` may change about the search.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SearchQuery {
+    pattern: Option<String>,
+    /// Comma-separated
+    ext: Option<String>,
+    /// Below the root
+    dir: Option<PathBuf>,
+    max_depth: Option<usize>,
+    hidden: Option<bool>,
+    gitignored: Option<bool>,
+    limit: Option<usize>,
+}
+
+/// The server's tree, and what it's done.
+struct Served {
+    search: SearchConfig,
+    started: Instant,
+    searches: AtomicU64,
+    running: AtomicU64,
+    entries: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// What `/stats` answers.
+#[derive(Debug, Serialize, Deserialize)]
+struct Stats {
+    root: PathBuf,
+    uptime_secs: u64,
+    /// Requests to `/search` so far
+    searches: u64,
+    /// Of which still sending results
+    running: u64,
+    /// Results sent
+    entries: u64,
+    /// Errors sent
+    errors: u64,
+}
+
+pub async fn run(args: &ServeArgs) -> Result<ExitCode> {
+    let mut search = args.search.clone();
+    search.root_path = std::fs::canonicalize(&search.root_path)
+        .with_context(|| format!("can't serve {}", search.root_path.display()))?;
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("can't listen on {}", args.listen))?;
+    eprintln!(
+        "Serving searches of {} on http://{}; Ctrl-C to stop",
+        search.root_path.display(),
+        listener.local_addr()?
+    );
+    tokio::select! {
+        result = serve(listener, search) => result?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Answer requests on `listener` about the tree `config` describes.
+async fn serve(listener: TcpListener, config: SearchConfig) -> Result<()> {
+    axum::serve(listener, router(Served::new(config))).await?;
+    Ok(())
+}
+
+fn router(served: Arc<Served>) -> Router {
+    let app = Router::new()
+        .route("/search", get(search))
+        .route("/stats", get(stats));
+    #[cfg(feature = "grpc")]
+    let app = app.route_service(grpc::PATH, grpc::SearchService(Arc::clone(&served)));
+    app.with_state(served)
+}
+
+impl Served {
+    fn new(search: SearchConfig) -> Arc<Self> {
+        Arc::new(Served {
+            search,
+            started: Instant::now(),
+            searches: AtomicU64::new(0),
+            running: AtomicU64::new(0),
+            entries: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Start the search `query` asks for, or say why it can't be done.
+    fn start(self: &Arc<Self>, query: SearchQuery) -> Result<Running, String> {
+        let mut config = self.search.clone();
+        if let Some(dir) = &query.dir {
+            // Resolved, so neither `..` nor a symlink leads out of the root
+            let resolved = std::fs::canonicalize(config.root_path.join(dir))
+                .map_err(|e| format!("can't search {}: {e}", dir.display()))?;
+            if !resolved.starts_with(&config.root_path) {
+                return Err(format!("dir must be below the root, not {}", dir.display()));
+            }
+            config.root_path = resolved;
+        }
+        if let Some(pattern) = query.pattern {
+            config.pattern = pattern;
+        }
+        if let Some(ext) = &query.ext {
+            config.extensions = Some(ext.split(',').map(String::from).collect());
+        }
+        config.max_depth = query.max_depth.or(config.max_depth);
+        config.show_hidden = query.hidden.unwrap_or(config.show_hidden);
+        config.include_gitignored = query.gitignored.unwrap_or(config.include_gitignored);
+        let custom = Custom::default();
+        crate::crawl_filters(&config, &custom)
+            .check()
+            .map_err(|e| e.to_string())?;
+
+        config.crawl.cancel = CancellationToken::new();
+        let cancel = config.crawl.cancel.clone().drop_guard();
+        self.searches.fetch_add(1, Ordering::Relaxed);
+        self.running.fetch_add(1, Ordering::Relaxed);
+        Ok(Running {
+            results: start_search(&config, &custom),
+            left: query.limit.unwrap_or(usize::MAX),
+            served: Arc::clone(self),
+            _cancel: cancel,
+        })
+    }
+}
+
+async fn search(State(served): State<Arc<Served>>, Query(query): Query<SearchQuery>) -> Response {
+    match served.start(query) {
+        Ok(running) => (
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(Lines(running)),
+        )
+            .into_response(),
+        Err(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+    }
+}
+
+async fn stats(State(served): State<Arc<Served>>) -> Json<Stats> {
+    let count = |n: &AtomicU64| n.load(Ordering::Relaxed);
+    Json(Stats {
+        root: served.search.root_path.clone(),
+        uptime_secs: served.started.elapsed().as_secs(),
+        searches: count(&served.searches),
+        running: count(&served.running),
+        entries: count(&served.entries),
+        errors: count(&served.errors),
+    })
+}
+
+/// A search started for a request, counted in the stats: its results, up to
+/// as many as were asked for. Dropped, as it is when the client hangs up, it
+/// stops the search, finished or not.
+struct Running {
+    results: Results,
+    /// How many more to hand on
+    left: usize,
+    served: Arc<Served>,
+    _cancel: DropGuard,
+}
+
+impl Stream for Running {
+    type Item = Result<FileEntry, crate::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        if self.left == 0 {
+            return Poll::Ready(None);
+        }
+        let item = ready!(Pin::new(&mut self.results).poll_next(cx));
+        let counter = match &item {
+            Some(Ok(_)) => &self.served.entries,
+            Some(Err(_)) => &self.served.errors,
+            None => return Poll::Ready(None),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.left -= 1;
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.served.running.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A search's results as JSON lines, gathered into chunks of what's ready.
+struct Lines(Running);
+
+impl Stream for Lines {
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let mut chunk = Vec::new();
+        let mut finished = false;
+        while chunk.len() < CHUNK {
+            match Pin::new(&mut self.0).poll_next(cx) {
+                Poll::Ready(Some(item)) => push_line(&mut chunk, item),
+                Poll::Ready(None) => {
+                    finished = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+        if !chunk.is_empty() {
+            Poll::Ready(Some(Ok(chunk.into())))
+        } else if finished {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn push_line(chunk: &mut Vec<u8>, item: Result<FileEntry, crate::Error>) {
+    let written = match item {
+        Ok(entry) => serde_json::to_writer(&mut *chunk, &entry),
+        Err(e) => {
+            let path = e.path().map(Path::to_string_lossy);
+            let error = serde_json::json!({ "error": e.to_string(), "path": path });
+            serde_json::to_writer(&mut *chunk, &error)
+        }
+    };
+    // Writing to a Vec can't fail, and the values always serialize
+    written.expect("a result serializes");
+    chunk.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// The status line and body of the answer to GET `target`, asked over
+    /// HTTP/1.0 so the body comes whole rather than in chunks.
+    async fn get(addr: SocketAddr, target: &str) -> Result<(String, String)> {
+        let mut stream = TcpStream::connect(addr).await?;
+        let request = format!("GET {target} HTTP/1.0\r\n\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        let (head, body) = response.split_once("\r\n\r\n").context("no body")?;
+        let status = head.lines().next().unwrap_or_default().to_string();
+        Ok((status, body.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_searches_stream_json_lines() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        std::fs::create_dir_all(root.join("src/bin"))?;
+        std::fs::write(root.join("src/lib.rs"), "")?;
+        std::fs::write(root.join("src/bin/main.rs"), "")?;
+        std::fs::write(root.join("src/.hidden.rs"), "")?;
+        std::fs::write(root.join("README.md"), "# tree\n")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let search = SearchConfig {
+            root_path: root.clone(),
+            ..SearchConfig::default()
+        };
+        let server = tokio::spawn(serve(listener, search));
+
+        let paths = |body: &str| -> Result<Vec<PathBuf>> {
+            let mut paths = body
+                .lines()
+                .map(|line| {
+                    let entry: serde_json::Value = serde_json::from_str(line)?;
+                    Ok(PathBuf::from(entry["path"].as_str().unwrap_or_default()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            paths.sort();
+            Ok(paths)
+        };
+        let (status, body) = get(addr, "/search?ext=rs").await?;
+        assert!(status.contains("200"), "{status}");
+        assert_eq!(
+            paths(&body)?,
+            [root.join("src/bin/main.rs"), root.join("src/lib.rs")]
+        );
+        let (_, body) = get(addr, "/search?ext=rs&hidden=true&dir=src&max_depth=0").await?;
+        assert_eq!(
+            paths(&body)?,
+            [root.join("src/.hidden.rs"), root.join("src/lib.rs")]
+        );
+        let (_, body) = get(addr, "/search?pattern=*&limit=1").await?;
+        assert_eq!(body.lines().count(), 1);
+
+        // Nothing outside the root, and nothing that can't match
+        let (status, _) = get(addr, "/search?dir=../..").await?;
+        assert!(status.contains("400"), "{status}");
+        let (status, _) = get(addr, "/search?pattern=a/b").await?;
+        assert!(status.contains("400"), "{status}");
+        let (status, _) = get(addr, "/search?colour=red").await?;
+        assert!(status.contains("400"), "{status}");
+
+        let (_, body) = get(addr, "/stats").await?;
+        let stats: Stats = serde_json::from_str(&body)?;
+        assert_eq!(stats.root, root);
+        assert_eq!((stats.searches, stats.running), (3, 0));
+        assert_eq!(stats.entries, 5);
+
+        // Nor through a symlink out of it
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("..", root.join("up"))?;
+            let (status, _) = get(addr, "/search?dir=up").await?;
+            assert!(status.contains("400"), "{status}");
+        }
+        server.abort();
+        Ok(())
+    }
+}
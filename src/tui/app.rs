@@ -0,0 +1,405 @@
+//! What the TUI shows: every result so far, and which of them pass the
+//! filter typed in, in the order chosen, with one of them selected. The
+//! picker matches what's typed fuzzily instead, best match first, and has
+//! results marked to be chosen together.
+
+use super::fuzzy;
+use crate::crawl::EntryKind;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A result, with what the list shows and sorts it by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Row {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+/// The order results are listed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Sort {
+    /// As the search found them
+    #[default]
+    Found,
+    Name,
+    /// Biggest first
+    Size,
+    /// Newest first
+    Modified,
+}
+
+impl Sort {
+    pub fn next(self) -> Self {
+        match self {
+            Sort::Found => Sort::Name,
+            Sort::Name => Sort::Size,
+            Sort::Size => Sort::Modified,
+            Sort::Modified => Sort::Found,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Sort::Found => "as found",
+            Sort::Name => "by name",
+            Sort::Size => "by size",
+            Sort::Modified => "by modification time",
+        }
+    }
+}
+
+/// Which kinds of results are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Show {
+    #[default]
+    All,
+    Files,
+    Dirs,
+}
+
+impl Show {
+    pub fn next(self) -> Self {
+        match self {
+            Show::All => Show::Files,
+            Show::Files => Show::Dirs,
+            Show::Dirs => Show::All,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Show::All => "everything",
+            Show::Files => "files",
+            Show::Dirs => "directories",
+        }
+    }
+
+    fn keeps(self, kind: EntryKind) -> bool {
+        match self {
+            Show::All => true,
+            Show::Files => kind != EntryKind::Dir,
+            Show::Dirs => kind == EntryKind::Dir,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct App {
+    rows: Vec<Row>,
+    /// Indexes into `rows` of those listed, in order
+    shown: Vec<usize>,
+    /// Index into `shown`
+    selected: usize,
+    /// Whether `shown` needs working out again
+    stale: bool,
+    /// Whether that's because the filter changed, which selects the first
+    /// result again
+    refiltered: bool,
+    filter: String,
+    /// Whether it's the picker's, matching the filter fuzzily and marking
+    /// results to be chosen
+    picker: bool,
+    sort: Sort,
+    show: Show,
+    /// Indexes into `rows` of those marked
+    marked: BTreeSet<usize>,
+    /// Whether the search is over
+    pub finished: bool,
+    pub errors: usize,
+    /// A message for the status line, until the next key
+    pub status: Option<String>,
+}
+
+impl App {
+    /// The picker's: what's typed is matched fuzzily.
+    pub fn picker() -> Self {
+        App {
+            picker: true,
+            ..App::default()
+        }
+    }
+
+    pub fn is_picker(&self) -> bool {
+        self.picker
+    }
+
+    /// Add a result the search found. In the order found, it's listed at
+    /// once; otherwise the list is sorted again by [`App::update`].
+    pub fn add(&mut self, row: Row) {
+        let i = self.rows.len();
+        let listed = self.keeps(&row);
+        self.rows.push(row);
+        let ranked = self.picker && !self.filter.is_empty();
+        if self.sort == Sort::Found && !ranked && !self.stale {
+            if listed {
+                self.shown.push(i);
+            }
+        } else {
+            self.stale = true;
+        }
+    }
+
+    /// Work out which results are listed again, if anything changed, keeping
+    /// the same one selected if it still is.
+    pub fn update(&mut self) {
+        if !self.stale {
+            return;
+        }
+        let selected = self.shown.get(self.selected).copied();
+        let selected = selected.filter(|_| !self.refiltered);
+        self.shown = (0..self.rows.len())
+            .filter(|&i| self.keeps(&self.rows[i]))
+            .collect();
+        let rows = &self.rows;
+        match self.sort {
+            // The best matches first
+            _ if self.picker && !self.filter.is_empty() => {
+                let score = |i: usize| fuzzy::score(&self.filter, &rows[i].path.to_string_lossy());
+                self.shown.sort_by_cached_key(|&i| {
+                    (Reverse(score(i)), rows[i].path.as_os_str().len(), i)
+                });
+            }
+            Sort::Found => {}
+            Sort::Name => self.shown.sort_by(|&a, &b| {
+                let name = |i: usize| rows[i].path.file_name();
+                name(a).cmp(&name(b)).then(rows[a].path.cmp(&rows[b].path))
+            }),
+            Sort::Size => self.shown.sort_by_key(|&i| Reverse(rows[i].size)),
+            Sort::Modified => self.shown.sort_by_key(|&i| Reverse(rows[i].modified)),
+        }
+        self.selected = selected
+            .and_then(|row| self.shown.iter().position(|&i| i == row))
+            .unwrap_or(0);
+        self.stale = false;
+        self.refiltered = false;
+    }
+
+    fn keeps(&self, row: &Row) -> bool {
+        if !self.show.keeps(row.kind) {
+            return false;
+        }
+        if self.picker {
+            return fuzzy::score(&self.filter, &row.path.to_string_lossy()).is_some();
+        }
+        // Each word typed, anywhere in the path, ignoring case
+        let path = row.path.to_string_lossy().to_lowercase();
+        self.filter
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| path.contains(word))
+    }
+
+    /// The results listed, in order.
+    pub fn shown(&self) -> impl ExactSizeIterator<Item = &Row> {
+        self.shown.iter().map(|&i| &self.rows[i])
+    }
+
+    /// How many results there are in all, listed or not.
+    pub fn total(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// The position of the selected result in the list.
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<&Row> {
+        self.shown.get(self.selected).map(|&i| &self.rows[i])
+    }
+
+    /// Move the selection `by` places, stopping at either end.
+    pub fn move_by(&mut self, by: isize) {
+        let last = self.shown.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(by).min(last);
+    }
+
+    /// Select the result at `index` in the list, or the last one.
+    pub fn move_to(&mut self, index: usize) {
+        self.selected = index.min(self.shown.len().saturating_sub(1));
+    }
+
+    /// Take the selected result out, once it's been deleted.
+    pub fn remove_selected(&mut self) {
+        if let Some(&i) = self.shown.get(self.selected) {
+            self.rows.remove(i);
+            self.shown.remove(self.selected);
+            let after = |j: usize| if j > i { j - 1 } else { j };
+            for j in &mut self.shown {
+                *j = after(*j);
+            }
+            self.marked = self
+                .marked
+                .iter()
+                .filter(|&&j| j != i)
+                .map(|&j| after(j))
+                .collect();
+            self.move_by(0);
+        }
+    }
+
+    /// Mark the selected result, or unmark it, and move on to the next.
+    pub fn toggle_mark(&mut self) {
+        if let Some(&i) = self.shown.get(self.selected) {
+            if !self.marked.remove(&i) {
+                self.marked.insert(i);
+            }
+            self.move_by(1);
+        }
+    }
+
+    /// Whether the result at `position` in the list is marked.
+    pub fn is_marked(&self, position: usize) -> bool {
+        self.shown
+            .get(position)
+            .is_some_and(|i| self.marked.contains(i))
+    }
+
+    pub fn marked_count(&self) -> usize {
+        self.marked.len()
+    }
+
+    /// What the picker chose: the results marked, in the order found, or
+    /// else the one selected.
+    pub fn chosen(&self) -> Vec<PathBuf> {
+        if self.marked.is_empty() {
+            return self
+                .selected()
+                .map(|row| row.path.clone())
+                .into_iter()
+                .collect();
+        }
+        self.marked
+            .iter()
+            .map(|&i| self.rows[i].path.clone())
+            .collect()
+    }
+
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        self.filter.push(c);
+        self.stale = true;
+        self.refiltered = true;
+    }
+
+    pub fn erase_char(&mut self) {
+        if self.filter.pop().is_some() {
+            self.stale = true;
+            self.refiltered = true;
+        }
+    }
+
+    pub fn sort(&self) -> Sort {
+        self.sort
+    }
+
+    pub fn next_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.stale = true;
+    }
+
+    pub fn show(&self) -> Show {
+        self.show
+    }
+
+    pub fn next_show(&mut self) {
+        self.show = self.show.next();
+        self.stale = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn row(path: &str, kind: EntryKind, size: u64) -> Row {
+        Row {
+            path: path.into(),
+            kind,
+            size: Some(size),
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(size)),
+        }
+    }
+
+    fn paths(app: &App) -> Vec<&str> {
+        app.shown().map(|row| row.path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_the_list_follows_the_filter_sort_and_kind() {
+        let mut app = App::default();
+        app.add(row("src/main.rs", EntryKind::File, 30));
+        app.add(row("src", EntryKind::Dir, 0));
+        app.add(row("README.md", EntryKind::File, 50));
+        assert_eq!(paths(&app), ["src/main.rs", "src", "README.md"]);
+
+        app.move_by(2);
+        app.next_sort();
+        app.next_sort();
+        app.update();
+        assert_eq!(app.sort(), Sort::Size);
+        assert_eq!(paths(&app), ["README.md", "src/main.rs", "src"]);
+        // Still on the same result
+        assert_eq!(app.selected_index(), 0);
+
+        // Sorted as they come in
+        app.add(row("Cargo.toml", EntryKind::File, 40));
+        app.update();
+        assert_eq!(
+            paths(&app),
+            ["README.md", "Cargo.toml", "src/main.rs", "src"]
+        );
+
+        app.next_show();
+        for c in "SRC .r".chars() {
+            app.type_char(c);
+        }
+        app.update();
+        assert_eq!(paths(&app), ["src/main.rs"]);
+        app.erase_char();
+        app.erase_char();
+        app.next_show();
+        app.update();
+        assert_eq!(paths(&app), ["src"]);
+
+        app.remove_selected();
+        assert_eq!(app.total(), 3);
+        assert!(app.selected().is_none());
+    }
+
+    #[test]
+    fn test_the_picker_ranks_and_marks() {
+        let mut app = App::picker();
+        for path in ["docs/main.md", "src/domain.rs", "src/main.rs", "tests/x.rs"] {
+            app.add(row(path, EntryKind::File, 1));
+        }
+        for c in "main".chars() {
+            app.type_char(c);
+        }
+        app.update();
+        assert_eq!(
+            paths(&app),
+            ["src/main.rs", "docs/main.md", "src/domain.rs"]
+        );
+        assert_eq!(app.chosen(), [PathBuf::from("src/main.rs")]);
+
+        app.toggle_mark();
+        app.move_by(1);
+        app.toggle_mark();
+        assert!(app.is_marked(0) && !app.is_marked(1) && app.is_marked(2));
+        assert_eq!(app.marked_count(), 2);
+        // In the order found
+        assert_eq!(
+            app.chosen(),
+            [PathBuf::from("src/domain.rs"), PathBuf::from("src/main.rs")]
+        );
+    }
+}
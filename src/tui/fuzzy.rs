@@ -0,0 +1,109 @@
+//! Fuzzy matching for the picker, as fzf's first algorithm has it: the
+//! pattern's characters must all appear in the path, in order, and the
+//! tighter the stretch they're found in, and the more of them that start a
+//! word or follow one another, the better the match. Matching ignores case
+//! unless the pattern has an uppercase letter in it.
+
+/// Points for each character matched.
+const MATCH: i64 = 16;
+/// Extra for one right after the previous match.
+const CONSECUTIVE: i64 = 8;
+/// Extra for one that starts a word: after a separator, or an uppercase
+/// letter after a lowercase one.
+const WORD_START: i64 = 10;
+/// Extra for one in the file name, rather than the directories above it.
+const IN_NAME: i64 = 4;
+/// Taken off for each character skipped between matches.
+const GAP: i64 = 1;
+
+/// How well `pattern` matches `text`, if it does at all; higher is better.
+pub(crate) fn score(pattern: &str, text: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let ignore_case = !pattern.iter().any(|c| c.is_uppercase());
+    let chars: Vec<char> = text.chars().collect();
+    let same = |a: char, b: char| {
+        if ignore_case {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    };
+
+    // The first stretch that has them all, in order, then shrunk from its
+    // end back to the last place it could start
+    let mut at = 0;
+    let mut end = 0;
+    for &p in &pattern {
+        at += chars[at..].iter().position(|&c| same(c, p))?;
+        end = at;
+        at += 1;
+    }
+    let mut start = end;
+    let mut left = pattern.len();
+    for i in (0..=end).rev() {
+        if same(chars[i], pattern[left - 1]) {
+            left -= 1;
+            if left == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let name_start = chars
+        .iter()
+        .rposition(|&c| c == '/' || c == '\\')
+        .map_or(0, |i| i + 1);
+    let mut score = 0;
+    let mut next = 0;
+    let mut last = None;
+    for (i, &c) in chars.iter().enumerate().take(end + 1).skip(start) {
+        if next == pattern.len() || !same(c, pattern[next]) {
+            continue;
+        }
+        score += MATCH;
+        if last.is_some_and(|last| last + 1 == i) {
+            score += CONSECUTIVE;
+        } else if let Some(last) = last {
+            score -= GAP * (i - last - 1) as i64;
+        }
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        let word_start = match before {
+            None => true,
+            Some(b) => !b.is_alphanumeric() || (b.is_lowercase() && c.is_uppercase()),
+        };
+        if word_start {
+            score += WORD_START;
+        }
+        if i >= name_start {
+            score += IN_NAME;
+        }
+        last = Some(i);
+        next += 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tighter_and_better_placed_matches_score_higher() {
+        assert_eq!(score("", "anything"), Some(0));
+        assert_eq!(score("xyz", "src/main.rs"), None);
+        assert_eq!(score("sm", "ms"), None);
+        // Ignoring case unless asked
+        assert!(score("MAIN", "src/main.rs").is_none());
+        assert!(score("main", "src/MAIN.rs").is_some());
+
+        let better = |pattern, a, b| score(pattern, a) > score(pattern, b);
+        assert!(better("main", "src/main.rs", "src/my_archive_index.rs"));
+        assert!(better("lib", "src/lib.rs", "src/calibrate.rs"));
+        assert!(better("fs", "src/fs.rs", "fs/src/other.rs"));
+        assert!(better("tv", "src/tuiView.rs", "src/throttle_overview.rs"));
+    }
+}
@@ -0,0 +1,262 @@
+//! `fs tui`: the search's results in a full-screen list that fills in as the
+//! crawl finds them, narrowed down by what's typed, sorted and filtered by
+//! kind with a key, and opened, copied or deleted from where they're listed.
+//! `fs --pick` is the same list as a picker, fuzzy-matching what's typed
+//! against the results, and printing the ones chosen. Both show a preview of
+//! the selected result beside the list, loaded in the background.
+//!
+//! The crawl runs on the runtime as usual, handing rows to the UI over a
+//! channel; the UI runs on a blocking thread of its own, taking whatever has
+//! arrived between keys and drawing on stderr, so that stdout stays free.
+
+mod app;
+mod fuzzy;
+mod preview;
+mod view;
+
+use crate::crawl::{EntryKinds, Fields};
+use crate::search::Custom;
+use crate::{start_search, Results, SearchConfig};
+use anyhow::{Context, Result};
+use app::{App, Row};
+use clap::Args;
+use preview::Previewer;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::Terminal;
+use std::io::{self, Stderr};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver};
+
+#[derive(Args, Debug)]
+pub struct TuiArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+}
+
+/// How long to wait for a key before taking in new results and drawing.
+const TICK: Duration = Duration::from_millis(50);
+
+/// The most results taken in between draws, so keys are still answered
+/// while a fast crawl floods the channel.
+const BATCH: usize = 20_000;
+
+pub async fn run(args: &TuiArgs) -> Result<ExitCode> {
+    let mut config = args.search.clone();
+    // Sizes and times to sort by, and directories to list
+    config.crawl.stat = Fields {
+        kind: true,
+        size: true,
+        modified: true,
+    };
+    let custom = Custom {
+        emit: EntryKinds::ALL,
+        ..Custom::default()
+    };
+    let cancel = config.crawl.cancel.clone();
+
+    let rows = forward(start_search(&config, &custom), true);
+    let result = tokio::task::spawn_blocking(move || ui(rows, App::default())).await?;
+    cancel.cancel();
+    result?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Let the user choose among `results` with the picker. The paths chosen,
+/// or None if the user gave up.
+pub async fn pick(results: Results) -> Result<Option<Vec<PathBuf>>> {
+    let rows = forward(results, false);
+    tokio::task::spawn_blocking(move || ui(rows, App::picker())).await?
+}
+
+/// `results` as rows for the UI, as they come, with their sizes and
+/// modification times if `stat`, which may take a stat each.
+fn forward(mut results: Results, stat: bool) -> UnboundedReceiver<Result<Row, String>> {
+    let (tx, rows) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(item) = results.recv().await {
+            let item = item.map(|entry| Row {
+                kind: entry.kind().unwrap_or(crate::EntryKind::Other),
+                size: entry.size().ok().filter(|_| stat),
+                modified: entry.modified().ok().filter(|_| stat),
+                path: entry.into_path(),
+            });
+            if tx.send(item.map_err(|e| e.to_string())).is_err() {
+                break;
+            }
+        }
+    });
+    rows
+}
+
+/// The UI's loop, until the user quits, or with the picker chooses (the
+/// paths chosen) or gives up (None).
+fn ui(
+    mut rows: UnboundedReceiver<Result<Row, String>>,
+    mut app: App,
+) -> Result<Option<Vec<PathBuf>>> {
+    let mut screen = Screen::enter()?;
+    let mut previewer = Previewer::start();
+    let mut previewing = true;
+    loop {
+        take_in(&mut app, &mut rows);
+        app.update();
+        if previewing {
+            previewer.want(app.selected().map(|row| row.path.as_path()));
+            previewer.take_in();
+        }
+        let preview = previewing.then_some(&previewer);
+        screen
+            .terminal
+            .draw(|frame| view::draw(frame, &app, preview))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        // Deleting is asked about first, and any other key is a no
+        if app.status.take().as_deref() == Some(DELETE_QUESTION) {
+            if key.code == KeyCode::Char('y') {
+                delete_selected(&mut app);
+            }
+            continue;
+        }
+        match key.code {
+            KeyCode::Esc => break,
+            KeyCode::Char('c') if ctrl => break,
+            KeyCode::Enter if app.is_picker() => {
+                let chosen = app.chosen();
+                if !chosen.is_empty() {
+                    return Ok(Some(chosen));
+                }
+            }
+            KeyCode::Tab if app.is_picker() => app.toggle_mark(),
+            KeyCode::Up => app.move_by(-1),
+            KeyCode::Char('p') if ctrl => app.move_by(-1),
+            KeyCode::Down => app.move_by(1),
+            KeyCode::Char('n') if ctrl => app.move_by(1),
+            KeyCode::PageUp => app.move_by(-page(&screen)?),
+            KeyCode::PageDown => app.move_by(page(&screen)?),
+            KeyCode::Home => app.move_to(0),
+            KeyCode::End => app.move_to(usize::MAX),
+            KeyCode::Char('s') if ctrl => app.next_sort(),
+            KeyCode::Char('t') if ctrl => app.next_show(),
+            KeyCode::Char('v') if ctrl => previewing = !previewing,
+            KeyCode::Char('y') if ctrl && !app.is_picker() => {
+                if let Some(row) = app.selected() {
+                    let path = row.path.to_string_lossy().into_owned();
+                    app.status = Some(match crate::actions::clipboard::set_text(path) {
+                        Ok(()) => "Copied the path".into(),
+                        Err(e) => format!("Error: {e:#}"),
+                    });
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(row) = app.selected() {
+                    if let Err(e) = open(&row.path) {
+                        app.status = Some(format!("Error: can't open it: {e}"));
+                    }
+                }
+            }
+            KeyCode::Delete if !app.is_picker() && app.selected().is_some() => {
+                app.status = Some(DELETE_QUESTION.into());
+            }
+            KeyCode::Backspace => app.erase_char(),
+            KeyCode::Char(c) if !ctrl => app.type_char(c),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+const DELETE_QUESTION: &str = "Move it to the trash? [y/N]";
+
+/// Take in what the search has found since last time, up to [`BATCH`].
+fn take_in(app: &mut App, rows: &mut UnboundedReceiver<Result<Row, String>>) {
+    for _ in 0..BATCH {
+        match rows.try_recv() {
+            Ok(Ok(row)) => app.add(row),
+            Ok(Err(_)) => app.errors += 1,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                app.finished = true;
+                return;
+            }
+        }
+    }
+}
+
+/// How many rows a page up or down moves.
+fn page(screen: &Screen) -> io::Result<isize> {
+    let height = screen.terminal.size()?.height;
+    Ok(usize::from(height.saturating_sub(4).max(1)) as isize)
+}
+
+/// Move the selected result to the trash, and take it off the list.
+fn delete_selected(app: &mut App) {
+    let Some(path) = app.selected().map(|row| row.path.clone()) else {
+        return;
+    };
+    match trash::delete(&path) {
+        Ok(()) => {
+            app.status = Some(format!("Moved {} to the trash", path.display()));
+            app.remove_selected();
+        }
+        Err(e) => app.status = Some(format!("Error: can't move it to the trash: {e}")),
+    }
+}
+
+/// Open `path` as the desktop would, without waiting.
+fn open(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = std::process::Command::new("xdg-open");
+    command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(drop)
+}
+
+/// The terminal in raw mode on the alternate screen, drawn on through
+/// stderr, until it's dropped, panics included.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        let terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+        terminal::enable_raw_mode().context("can't put the terminal in raw mode")?;
+        let screen = Screen { terminal };
+        execute!(io::stderr(), EnterAlternateScreen)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        let _ = self.terminal.show_cursor();
+    }
+}
@@ -0,0 +1,349 @@
+//! The preview pane: a look inside the selected result, worked out on a
+//! thread of its own so that moving through the list never waits on the
+//! disk. Text files show their first lines, highlighted for their syntax;
+//! other files what's known about them; directories how much is in them.
+
+use crate::actions::human_size;
+use crate::grep::binary::{has_binary_extension, looks_binary};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// The most of a text file read for its preview.
+const HEAD_BYTES: u64 = 64 * 1024;
+
+/// The most lines of it previewed, more than any pane is tall.
+const HEAD_LINES: usize = 200;
+
+/// What the pane shows for a result.
+#[derive(Debug)]
+pub(crate) enum Preview {
+    /// The start of a text file, highlighted, and whether there's more
+    Text {
+        lines: Vec<Line<'static>>,
+        more: bool,
+    },
+    /// What's known about a file that isn't text, a name and a value each
+    Facts(Vec<(&'static str, String)>),
+    /// How many entries a directory has of each kind
+    Dir {
+        dirs: usize,
+        files: usize,
+        other: usize,
+        hidden: usize,
+    },
+    /// Why there's nothing to show
+    Error(String),
+}
+
+/// Previews loaded in the background: the UI says which path it wants, and
+/// picks the preview up once it's there. Only the latest path asked for is
+/// loaded, so scrolling past results doesn't queue up work for each.
+pub(crate) struct Previewer {
+    requests: Sender<PathBuf>,
+    loaded: Receiver<(PathBuf, Preview)>,
+    /// The path last asked for
+    wanted: Option<PathBuf>,
+    /// The last preview loaded, and what it's of
+    current: Option<(PathBuf, Preview)>,
+}
+
+impl Previewer {
+    /// Start the thread loading previews, which ends with the previewer.
+    pub fn start() -> Self {
+        let (requests, asked) = mpsc::channel();
+        let (done, loaded) = mpsc::channel();
+        thread::spawn(move || serve(asked, done));
+        Previewer {
+            requests,
+            loaded,
+            wanted: None,
+            current: None,
+        }
+    }
+
+    /// Ask for the preview of `path`, unless it's been asked for already.
+    pub fn want(&mut self, path: Option<&Path>) {
+        if self.wanted.as_deref() == path {
+            return;
+        }
+        self.wanted = path.map(Path::to_path_buf);
+        if let Some(path) = &self.wanted {
+            let _ = self.requests.send(path.clone());
+        }
+    }
+
+    /// Take in the previews loaded since last time.
+    pub fn take_in(&mut self) {
+        while let Ok(loaded) = self.loaded.try_recv() {
+            self.current = Some(loaded);
+        }
+    }
+
+    /// The preview of the path wanted, once it's loaded.
+    pub fn get(&self) -> Option<&Preview> {
+        let (path, preview) = self.current.as_ref()?;
+        (self.wanted.as_ref() == Some(path)).then_some(preview)
+    }
+}
+
+/// Load the previews asked for on `asked`, skipping any that are already out
+/// of date, until the previewer is gone.
+fn serve(asked: Receiver<PathBuf>, done: Sender<(PathBuf, Preview)>) {
+    let highlighter = Highlighter::new();
+    while let Ok(mut path) = asked.recv() {
+        while let Ok(newer) = asked.try_recv() {
+            path = newer;
+        }
+        let preview = load(&path, &highlighter);
+        if done.send((path, preview)).is_err() {
+            break;
+        }
+    }
+}
+
+/// The preview of `path`.
+pub(crate) fn load(path: &Path, highlighter: &Highlighter) -> Preview {
+    match try_load(path, highlighter) {
+        Ok(preview) => preview,
+        Err(e) => Preview::Error(format!("can't read it: {e}")),
+    }
+}
+
+fn try_load(path: &Path, highlighter: &Highlighter) -> io::Result<Preview> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        return count_entries(path);
+    }
+    if !metadata.is_file() || has_binary_extension(path) {
+        return Ok(facts(path, &metadata));
+    }
+    let mut head = Vec::new();
+    File::open(path)?.take(HEAD_BYTES).read_to_end(&mut head)?;
+    if looks_binary(&head) {
+        return Ok(facts(path, &metadata));
+    }
+    let mut more = metadata.len() > HEAD_BYTES;
+    if more {
+        // Not the line cut off partway
+        if let Some(end) = head.iter().rposition(|&b| b == b'\n') {
+            head.truncate(end + 1);
+        }
+    }
+    let text = String::from_utf8_lossy(&head);
+    more |= text.lines().nth(HEAD_LINES).is_some();
+    Ok(Preview::Text {
+        lines: highlighter.highlight(path, &text),
+        more,
+    })
+}
+
+fn count_entries(dir: &Path) -> io::Result<Preview> {
+    let (mut dirs, mut files, mut other, mut hidden) = (0, 0, 0, 0);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            hidden += 1;
+        }
+        match entry.file_type()? {
+            kind if kind.is_dir() => dirs += 1,
+            kind if kind.is_file() => files += 1,
+            _ => other += 1,
+        }
+    }
+    Ok(Preview::Dir {
+        dirs,
+        files,
+        other,
+        hidden,
+    })
+}
+
+fn facts(path: &Path, metadata: &Metadata) -> Preview {
+    let kind = if metadata.is_file() {
+        "binary file"
+    } else {
+        "special file"
+    };
+    let mut facts = vec![
+        ("Type", kind.to_string()),
+        (
+            "Size",
+            format!("{} ({} bytes)", human_size(metadata.len()), metadata.len()),
+        ),
+    ];
+    if let Ok(modified) = metadata.modified() {
+        let modified = time::OffsetDateTime::from(modified);
+        facts.push((
+            "Modified",
+            format!(
+                "{} {:02}:{:02} UTC",
+                modified.date(),
+                modified.hour(),
+                modified.minute()
+            ),
+        ));
+    }
+    facts.push(("Permissions", permissions(metadata)));
+    if let Ok(target) = fs::read_link(path) {
+        facts.push(("Links to", target.display().to_string()));
+    }
+    Preview::Facts(facts)
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = metadata.permissions().mode();
+    (0..9)
+        .map(|i| {
+            let on = mode & (0o400 >> i) != 0;
+            match (on, i % 3) {
+                (false, _) => '-',
+                (true, 0) => 'r',
+                (true, 1) => 'w',
+                (true, _) => 'x',
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> String {
+    match metadata.permissions().readonly() {
+        true => "read-only".into(),
+        false => "writable".into(),
+    }
+}
+
+/// Syntax highlighting, with the syntaxes and theme it needs loaded once.
+pub(crate) struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    pub fn new() -> Self {
+        let mut themes = ThemeSet::load_defaults();
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes
+                .themes
+                .remove("base16-ocean.dark")
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The first lines of `text`, from `path`, highlighted as its extension,
+    /// name or first line says, or else as plain text.
+    fn highlight(&self, path: &Path, text: &str) -> Vec<Line<'static>> {
+        let by_name = |name: Option<&std::ffi::OsStr>| {
+            self.syntaxes.find_syntax_by_extension(name?.to_str()?)
+        };
+        let syntax = by_name(path.extension())
+            .or_else(|| by_name(path.file_name()))
+            .or_else(|| {
+                self.syntaxes
+                    .find_syntax_by_first_line(text.lines().next()?)
+            })
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        LinesWithEndings::from(text)
+            .take(HEAD_LINES)
+            .map(
+                |line| match highlighter.highlight_line(line, &self.syntaxes) {
+                    Ok(ranges) => Line::from(
+                        ranges
+                            .into_iter()
+                            .map(|(style, text)| {
+                                let color = style.foreground;
+                                let style = Style::new().fg(Color::Rgb(color.r, color.g, color.b));
+                                Span::styled(printable(text), style)
+                            })
+                            .collect::<Vec<_>>(),
+                    ),
+                    Err(_) => Line::from(printable(line)),
+                },
+            )
+            .collect()
+    }
+}
+
+/// `text` as it can be drawn: tabs as spaces, and without line endings or
+/// other control characters.
+fn printable(text: &str) -> String {
+    text.replace('\t', "    ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(preview: &Preview) -> Vec<String> {
+        let Preview::Text { lines, .. } = preview else {
+            panic!("not text: {preview:?}");
+        };
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_each_kind_of_result_is_previewed() -> io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let highlighter = Highlighter::new();
+        let main = tmp.path().join("main.rs");
+        fs::write(&main, "fn main() {\n\tprintln!(\"hi\");\n}\n")?;
+        let preview = load(&main, &highlighter);
+        assert_eq!(
+            text(&preview),
+            ["fn main() {", "    println!(\"hi\");", "}"]
+        );
+        // In more than one color
+        let Preview::Text { lines, more } = &preview else {
+            unreachable!()
+        };
+        assert!(!more);
+        assert!(lines[0].spans.len() > 1);
+
+        let long = tmp.path().join("long.txt");
+        fs::write(&long, "line\n".repeat(HEAD_LINES + 1))?;
+        let preview = load(&long, &highlighter);
+        assert_eq!(text(&preview).len(), HEAD_LINES);
+        assert!(matches!(preview, Preview::Text { more: true, .. }));
+
+        let binary = tmp.path().join("data");
+        fs::write(&binary, b"\x7fELF\0\0\0\0")?;
+        let Preview::Facts(facts) = load(&binary, &highlighter) else {
+            panic!("no facts for a binary file");
+        };
+        assert_eq!(facts[0], ("Type", "binary file".into()));
+        assert_eq!(facts[1], ("Size", "8 B (8 bytes)".into()));
+
+        fs::create_dir(tmp.path().join("sub"))?;
+        fs::write(tmp.path().join(".hidden"), "")?;
+        assert!(matches!(
+            load(tmp.path(), &highlighter),
+            Preview::Dir {
+                dirs: 1,
+                files: 4,
+                other: 0,
+                hidden: 1
+            }
+        ));
+        assert!(matches!(
+            load(&tmp.path().join("missing"), &highlighter),
+            Preview::Error(_)
+        ));
+        Ok(())
+    }
+}
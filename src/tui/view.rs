@@ -0,0 +1,153 @@
+//! Drawing the TUI: the results, with a preview of the selected one beside
+//! them, the filter being typed, and a status line with what the keys do.
+
+use super::app::App;
+use super::preview::{Preview, Previewer};
+use crate::actions::human_size;
+use crate::crawl::EntryKind;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+const KEYS: &str =
+    "↑↓ move  Enter open  ^Y copy path  Del delete  ^S sort  ^T type  ^V preview  Esc quit";
+const PICKER_KEYS: &str =
+    "↑↓ move  Tab mark  Enter choose  ^S sort  ^T type  ^V preview  Esc cancel";
+
+/// The narrowest the screen can be with the preview beside the list.
+const PREVIEW_MIN_WIDTH: u16 = 60;
+
+/// Draw `app`, and beside it the preview of the selected result from
+/// `previewer`, if there's one and there's room.
+pub(crate) fn draw(frame: &mut Frame, app: &App, previewer: Option<&Previewer>) {
+    let [mut list, input, status] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    if let Some(previewer) = previewer.filter(|_| list.width >= PREVIEW_MIN_WIDTH) {
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(list);
+        list = left;
+        draw_preview(frame, app, previewer.get(), right);
+    }
+
+    let progress = if app.finished { "" } else { ", searching…" };
+    let order = if app.is_picker() && !app.filter().is_empty() {
+        "best match first"
+    } else {
+        app.sort().label()
+    };
+    let mut title = format!(
+        " {} of {}{progress}, {}, {order}",
+        app.shown().len(),
+        app.total(),
+        app.show().label(),
+    );
+    if app.marked_count() > 0 {
+        title += &format!(", {} marked", app.marked_count());
+    }
+    title.push(' ');
+    // Only the rows that fit are turned into items
+    let height = usize::from(list.height.saturating_sub(2)).max(1);
+    let first = app.selected_index().saturating_sub(height - 1);
+    let items: Vec<_> = app
+        .shown()
+        .enumerate()
+        .skip(first)
+        .take(height)
+        .map(|(position, row)| {
+            // Marks in the picker, sizes otherwise
+            let lead = match (row.kind, row.size) {
+                _ if app.is_picker() => {
+                    let mark = if app.is_marked(position) { "*" } else { " " };
+                    format!("{mark} ")
+                }
+                (EntryKind::Dir, _) | (_, None) => format!("{:10}  ", ""),
+                (_, Some(size)) => format!("{:>10}  ", human_size(size)),
+            };
+            let path = row.path.display().to_string();
+            let path = match row.kind {
+                EntryKind::Dir => Span::styled(path + "/", Style::new().blue().bold()),
+                _ => Span::raw(path),
+            };
+            ListItem::new(Line::from(vec![Span::raw(lead), path]))
+        })
+        .collect();
+    let mut state =
+        ListState::default().with_selected(app.selected().map(|_| app.selected_index() - first));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        list,
+        &mut state,
+    );
+
+    frame.render_widget(Paragraph::new(format!("> {}", app.filter())), input);
+    frame.set_cursor_position((input.x + 2 + app.filter().chars().count() as u16, input.y));
+
+    let keys = if app.is_picker() { PICKER_KEYS } else { KEYS };
+    let status_line = match &app.status {
+        Some(message) => Line::from(message.as_str()).bold(),
+        None if app.errors > 0 => Line::from(format!("{} errors  {keys}", app.errors)).dim(),
+        None => Line::from(keys).dim(),
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+fn draw_preview(frame: &mut Frame, app: &App, preview: Option<&Preview>, area: Rect) {
+    let Some(row) = app.selected() else {
+        frame.render_widget(Block::bordered(), area);
+        return;
+    };
+    let name = row.path.file_name().unwrap_or(row.path.as_os_str());
+    let mut title = format!(" {}", name.to_string_lossy());
+    let lines = match preview {
+        None => vec![Line::from("Loading…").dim()],
+        Some(Preview::Text { lines, more }) => {
+            if *more {
+                title += ", first lines";
+            }
+            // Only what fits
+            lines.iter().take(area.height.into()).cloned().collect()
+        }
+        Some(Preview::Facts(facts)) => facts
+            .iter()
+            .map(|(name, value)| {
+                Line::from(vec![
+                    Span::raw(format!("{name:12}")).bold(),
+                    Span::raw(value),
+                ])
+            })
+            .collect(),
+        Some(Preview::Dir {
+            dirs,
+            files,
+            other,
+            hidden,
+        }) => {
+            let mut counts = vec![
+                Line::from(format!("{dirs} directories")),
+                Line::from(format!("{files} files")),
+            ];
+            if *other > 0 {
+                counts.push(Line::from(format!("{other} other")));
+            }
+            if *hidden > 0 {
+                counts.push(Line::from(format!("{hidden} of them hidden")).dim());
+            }
+            counts
+        }
+        Some(Preview::Error(message)) => vec![Line::from(message.as_str()).red()],
+    };
+    title.push(' ');
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
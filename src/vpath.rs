@@ -0,0 +1,249 @@
+//! Virtual paths: results that live inside a zip or tar archive rather than
+//! directly on disk (`--search-archives`), written as `archive.zip!inner/path`.
+
+use crate::actions::archive::Format;
+use crate::crawl::Results;
+use crate::{file_matches, SearchConfig};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Separates an archive's path from the path of a member inside it.
+pub const SEPARATOR: char = '!';
+
+/// A search result that is either a file on disk or a member of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VirtualPath {
+    Disk(PathBuf),
+    Member { archive: PathBuf, member: PathBuf },
+}
+
+impl fmt::Display for VirtualPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualPath::Disk(path) => write!(f, "{}", path.display()),
+            VirtualPath::Member { archive, member } => {
+                write!(f, "{}{SEPARATOR}{}", archive.display(), member.display())
+            }
+        }
+    }
+}
+
+/// Call `f` with the path and contents of every regular file in an archive.
+/// Members whose names would escape the archive (absolute, or with `..`) are skipped.
+pub(crate) fn for_each_member(
+    archive: &Path,
+    format: Format,
+    mut f: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let file = File::open(archive)?;
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file)).map_err(io::Error::other)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+                if !entry.is_file() {
+                    continue;
+                }
+                if let Some(name) = entry.enclosed_name() {
+                    f(&name, &mut entry)?;
+                }
+            }
+            Ok(())
+        }
+        Format::Tar => for_each_tar_member(file, f),
+        Format::TarGz => for_each_tar_member(flate2::read::GzDecoder::new(file), f),
+        Format::TarZst => for_each_tar_member(zstd::stream::read::Decoder::new(file)?, f),
+    }
+}
+
+fn for_each_tar_member(
+    reader: impl Read,
+    mut f: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+            continue;
+        }
+        f(&path, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Replace each archive coming out of the crawl with its members whose names
+/// pass the search's name filters, keeping the archive itself only if its own
+/// name passes them. Other results go through unchanged.
+pub fn expand_archives(
+    mut rx: Results,
+    config: &SearchConfig,
+) -> mpsc::Receiver<Result<VirtualPath>> {
+    let (tx, out) = mpsc::channel(100);
+    let pattern = config.pattern.clone();
+    let exts = config.extensions.clone();
+
+    tokio::spawn(async move {
+        while let Some(item) = rx.recv().await {
+            let path = match item {
+                Ok(entry) => entry.into_path(),
+                Err(e) => {
+                    if tx.send(Err(e.into())).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let Some(format) = Format::detect(&path) else {
+                if tx.send(Ok(VirtualPath::Disk(path))).await.is_err() {
+                    return;
+                }
+                continue;
+            };
+            if file_matches(&path, &pattern, exts.as_deref())
+                && tx.send(Ok(VirtualPath::Disk(path.clone()))).await.is_err()
+            {
+                return;
+            }
+
+            let (archive, pattern, exts) = (path.clone(), pattern.clone(), exts.clone());
+            let members = tokio::task::spawn_blocking(move || {
+                let mut members = Vec::new();
+                for_each_member(&archive, format, |member, _| {
+                    if file_matches(member, &pattern, exts.as_deref()) {
+                        members.push(member.to_path_buf());
+                    }
+                    Ok(())
+                })
+                .map(|()| members)
+            })
+            .await;
+
+            let sent = match members {
+                Ok(Ok(members)) => {
+                    let mut sent = true;
+                    for member in members {
+                        let archive = path.clone();
+                        sent &= tx
+                            .send(Ok(VirtualPath::Member { archive, member }))
+                            .await
+                            .is_ok();
+                    }
+                    sent
+                }
+                Ok(Err(e)) => tx
+                    .send(Err(e).with_context(|| format!("reading {}", path.display())))
+                    .await
+                    .is_ok(),
+                Err(e) => tx.send(Err(e.into())).await.is_ok(),
+            };
+            if !sent {
+                return;
+            }
+        }
+    });
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A zip with `docs/readme.md`, `src/lib.rs` and a `src/` directory entry.
+    fn write_zip(path: &Path) -> Result<()> {
+        let mut zip = zip::ZipWriter::new(File::create(path)?);
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("src/", options)?;
+        zip.start_file("src/lib.rs", options)?;
+        zip.write_all(b"pub fn hello() {}\n")?;
+        zip.start_file("docs/readme.md", options)?;
+        zip.write_all(b"# hello\n")?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_for_each_member_zip_and_tar() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let zip_path = tmp.path().join("code.zip");
+        write_zip(&zip_path)?;
+
+        let tar_path = tmp.path().join("code.tar.gz");
+        let gz = flate2::write::GzEncoder::new(File::create(&tar_path)?, Default::default());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "notes/todo.txt", &b"fix it"[..])?;
+        tar.into_inner()?.finish()?;
+
+        let mut seen = Vec::new();
+        for (path, format) in [(&zip_path, Format::Zip), (&tar_path, Format::TarGz)] {
+            for_each_member(path, format, |member, contents| {
+                let mut text = String::new();
+                contents.read_to_string(&mut text)?;
+                seen.push((member.to_path_buf(), text));
+                Ok(())
+            })?;
+        }
+        assert_eq!(
+            seen,
+            vec![
+                (PathBuf::from("src/lib.rs"), "pub fn hello() {}\n".into()),
+                (PathBuf::from("docs/readme.md"), "# hello\n".into()),
+                (PathBuf::from("notes/todo.txt"), "fix it".into()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expand_archives_filters_members() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let zip_path = tmp.path().join("code.zip");
+        write_zip(&zip_path)?;
+        let plain = tmp.path().join("main.rs");
+
+        let (tx, rx) = crate::crawl::channel(2);
+        tx.send(vec![Ok(zip_path.clone().into())]).await?;
+        tx.send(vec![Ok(plain.clone().into())]).await?;
+        drop(tx);
+
+        let config = SearchConfig {
+            root_path: tmp.path().to_path_buf(),
+            pattern: "*".into(),
+            max_depth: None,
+            extensions: Some(vec!["rs".into()]),
+            show_hidden: false,
+            include_gitignored: false,
+            ignore_files: Vec::new(),
+            search_archives: true,
+            crawl: Default::default(),
+        };
+        let mut out = expand_archives(rx, &config);
+        let mut results = Vec::new();
+        while let Some(item) = out.recv().await {
+            results.push(item?.to_string());
+        }
+
+        assert_eq!(
+            results,
+            vec![
+                format!("{}!src/lib.rs", zip_path.display()),
+                plain.display().to_string(),
+            ]
+        );
+        Ok(())
+    }
+}
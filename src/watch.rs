@@ -0,0 +1,351 @@
+//! `fs watch`: the changes to a tree as they happen, from the operating
+//! system's file events (inotify, FSEvents, ReadDirectoryChangesW) rather
+//! than by walking it again. Each changed path goes through the same checks
+//! as a search's results, so only what `fs` with the same flags would list is
+//! reported: created, modified or removed, as text or JSON lines.
+//!
+//! A directory created or moved into the tree is read through at once, since
+//! what's in it when it arrives gets no events of its own; and what's found
+//! that way isn't reported again if events for it come in after all.
+
+use crate::crawl::{EntryKind, Filters};
+use crate::dup::OutputFormat;
+use crate::search::Custom;
+use crate::SearchConfig;
+use anyhow::{Context, Result};
+use clap::Args;
+use notify::event::{ModifyKind, RemoveKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver};
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    /// How to print each change
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+}
+
+/// What happened to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// Created, or moved in
+    Created,
+    Modified,
+    /// Removed, or moved out
+    Removed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::Created => "created",
+            Change::Modified => "modified",
+            Change::Removed => "removed",
+        })
+    }
+}
+
+/// A change to a path a search would list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub change: Change,
+    /// Under the root as it was given, as a search's results are
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// The changes under a root that pass a search's filters, as they happen.
+pub(crate) struct Watch {
+    /// Watching until dropped
+    _watcher: notify::RecommendedWatcher,
+    raw: UnboundedReceiver<notify::Result<notify::Event>>,
+    filters: Filters,
+    /// The root, absolute, as the events name paths
+    root: PathBuf,
+    /// The root as it was given
+    shown: PathBuf,
+    /// Changes sorted out of the last raw event and not yet handed on
+    ready: VecDeque<Event>,
+    /// What reading new directories found, since the events stopped coming
+    scanned: HashSet<PathBuf>,
+    /// Whether to report every path removed, not only those a search would
+    /// have listed
+    all_removals: bool,
+    /// Whether events were lost since [`Watch::take_lost`] was last asked
+    lost: bool,
+}
+
+impl Watch {
+    /// Start watching the tree `config` searches, for the entries it and
+    /// `custom` would have it find.
+    pub(crate) fn start(config: &SearchConfig, custom: &Custom) -> Result<Self> {
+        let root = std::fs::canonicalize(&config.root_path)
+            .with_context(|| format!("can't watch {}", config.root_path.display()))?;
+        let filters = crate::crawl_filters(config, custom);
+        filters.check()?;
+        let (tx, raw) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("can't watch {}", root.display()))?;
+        Ok(Watch {
+            _watcher: watcher,
+            raw,
+            filters,
+            root,
+            shown: config.root_path.clone(),
+            ready: VecDeque::new(),
+            scanned: HashSet::new(),
+            all_removals: false,
+            lost: false,
+        })
+    }
+
+    /// Report every path removed, whether or not a search would have listed
+    /// it: once it's gone there's no telling what it was, and a caller that
+    /// keeps a list of its own can drop what it doesn't have.
+    pub(crate) fn with_all_removals(mut self) -> Self {
+        self.all_removals = true;
+        self
+    }
+
+    /// Whether the system lost events, so that changes may be missing, since
+    /// last asked.
+    pub(crate) fn take_lost(&mut self) -> bool {
+        std::mem::take(&mut self.lost)
+    }
+
+    /// The next change, or an error from the watcher.
+    pub(crate) async fn next(&mut self) -> Option<Result<Event>> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Some(Ok(event));
+            }
+            let raw = match self.raw.try_recv() {
+                Ok(raw) => raw,
+                Err(TryRecvError::Empty) => {
+                    // A burst of events is over, and with it the chance of
+                    // one for a path already found by reading its directory
+                    self.scanned.clear();
+                    self.raw.recv().await?
+                }
+                Err(TryRecvError::Disconnected) => return None,
+            };
+            match raw {
+                Ok(event) => self.sort(event),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    /// Queue the changes a raw event stands for.
+    fn sort(&mut self, event: notify::Event) {
+        if event.need_rescan() {
+            tracing::warn!("events were lost; changes may be missing");
+            self.lost = true;
+        }
+        let removed_kind = |kind| match kind {
+            RemoveKind::Folder => EntryKind::Dir,
+            _ => EntryKind::File,
+        };
+        match (event.kind, event.paths.as_slice()) {
+            (EventKind::Create(_), paths)
+            | (EventKind::Modify(ModifyKind::Name(RenameMode::To)), paths) => {
+                paths.iter().for_each(|path| self.created(path, false));
+            }
+            // The backends that send this send From and To as well
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), _) => {}
+            (EventKind::Modify(ModifyKind::Name(RenameMode::From)), paths) => {
+                paths
+                    .iter()
+                    .for_each(|path| self.removed(path, EntryKind::File));
+            }
+            // Moved, one way or the other
+            (EventKind::Modify(ModifyKind::Name(_)), paths) => {
+                for path in paths {
+                    match path.symlink_metadata() {
+                        Ok(_) => self.created(path, false),
+                        Err(_) => self.removed(path, EntryKind::File),
+                    }
+                }
+            }
+            (EventKind::Modify(_), paths) => paths.iter().for_each(|path| self.modified(path)),
+            (EventKind::Remove(kind), paths) => {
+                paths
+                    .iter()
+                    .for_each(|path| self.removed(path, removed_kind(kind)));
+            }
+            _ => {}
+        }
+    }
+
+    /// `path` arrived, and if it's a directory the walk enters, everything in
+    /// it with it; `scanning` if it was found by reading its directory.
+    fn created(&mut self, path: &Path, scanning: bool) {
+        let seen = match scanning {
+            true => !self.scanned.insert(path.to_path_buf()),
+            false => self.scanned.remove(path),
+        };
+        if seen {
+            return;
+        }
+        let Ok(metadata) = path.symlink_metadata() else {
+            return;
+        };
+        let kind = EntryKind::from(metadata.file_type());
+        let Some(step) = self.decide(path, kind) else {
+            return;
+        };
+        if step.result {
+            self.push(Change::Created, path, kind);
+        }
+        if !step.entered {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            self.created(&entry.path(), true);
+        }
+    }
+
+    fn modified(&mut self, path: &Path) {
+        let Ok(metadata) = path.symlink_metadata() else {
+            return;
+        };
+        let kind = EntryKind::from(metadata.file_type());
+        if self.decide(path, kind).is_some_and(|step| step.result) {
+            self.push(Change::Modified, path, kind);
+        }
+    }
+
+    fn removed(&mut self, path: &Path, kind: EntryKind) {
+        if self.all_removals || self.decide(path, kind).is_some_and(|step| step.result) {
+            self.push(Change::Removed, path, kind);
+        }
+    }
+
+    /// What a walk of the tree would make of `path`: None unless it gets as
+    /// far as `path` itself.
+    fn decide(&self, path: &Path, kind: EntryKind) -> Option<crate::crawl::Step> {
+        let below = path.strip_prefix(&self.root).ok()?;
+        let steps = self.filters.walk_to(&self.root, path, kind)?;
+        (steps.len() == below.iter().count()).then(|| steps.into_iter().last())?
+    }
+
+    fn push(&mut self, change: Change, path: &Path, kind: EntryKind) {
+        let below = path.strip_prefix(&self.root).unwrap_or(path);
+        self.ready.push_back(Event {
+            change,
+            path: self.shown.join(below),
+            kind,
+        });
+    }
+}
+
+/// A change as `--format json` prints it.
+#[derive(Serialize)]
+struct JsonEvent<'a> {
+    change: Change,
+    path: &'a Path,
+    file_type: &'static str,
+}
+
+pub async fn run(args: &WatchArgs) -> Result<ExitCode> {
+    let mut watch = Watch::start(&args.search, &Custom::default())?;
+    eprintln!(
+        "Watching {}; Ctrl-C to stop",
+        args.search.root_path.display()
+    );
+    while let Some(event) = watch.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                continue;
+            }
+        };
+        match args.format {
+            OutputFormat::Text => println!("{} {}", event.change, event.path.display()),
+            OutputFormat::Json => {
+                let file_type = match event.kind {
+                    EntryKind::File => "file",
+                    EntryKind::Dir => "dir",
+                    EntryKind::Symlink => "symlink",
+                    EntryKind::Other => "other",
+                };
+                let json = JsonEvent {
+                    change: event.change,
+                    path: &event.path,
+                    file_type,
+                };
+                println!("{}", serde_json::to_string(&json)?);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_changes_are_filtered_as_a_search_would() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        std::fs::write(root.join(".gitignore"), "build/\n")?;
+        std::fs::write(root.join("old.rs"), "")?;
+        let config = SearchConfig {
+            root_path: root.to_path_buf(),
+            extensions: Some(vec!["rs".into()]),
+            ..SearchConfig::default()
+        };
+        let mut watch = Watch::start(&config, &Custom::default())?;
+
+        std::fs::write(root.join("new.rs"), "fn main() {}\n")?;
+        std::fs::write(root.join("notes.txt"), "")?;
+        std::fs::write(root.join(".hidden.rs"), "")?;
+        std::fs::create_dir(root.join("build"))?;
+        std::fs::write(root.join("build/out.rs"), "")?;
+        // Moved in with what's already in it
+        let outside = tempfile::tempdir_in(root.parent().unwrap())?;
+        std::fs::create_dir(outside.path().join("lib"))?;
+        std::fs::write(outside.path().join("lib/mod.rs"), "")?;
+        std::fs::rename(outside.path().join("lib"), root.join("lib"))?;
+        std::fs::remove_file(root.join("old.rs"))?;
+
+        let mut seen = Vec::new();
+        let wanted = [
+            (Change::Created, root.join("new.rs")),
+            (Change::Created, root.join("lib/mod.rs")),
+            (Change::Removed, root.join("old.rs")),
+        ];
+        while !wanted.iter().all(|change| seen.contains(change)) {
+            let next = tokio::time::timeout(Duration::from_secs(10), watch.next());
+            let event = next.await?.unwrap()?;
+            seen.push((event.change, event.path));
+        }
+        assert!(
+            seen.iter()
+                .all(|(_, path)| path.extension() == Some("rs".as_ref())
+                    && !path.starts_with(root.join("build"))
+                    && !path.ends_with(".hidden.rs")),
+            "{seen:?}"
+        );
+        Ok(())
+    }
+}